@@ -1,6 +1,6 @@
 use crate::class::{
     field_descriptor_parser, method_descriptor_parser, FieldDescriptor, FieldId, MethodDescriptor,
-    MethodId,
+    MethodId, FIELD_DESCRIPTORS, METHOD_DESCRIPTORS,
 };
 use classfile_parser::constant_info::{ConstantInfo, NameAndTypeConstant};
 use std::mem::take;
@@ -62,8 +62,9 @@ pub struct ConstantPool {
 /// These are functions that take a single index, check the constant at that index matches the
 /// expected type, then return a (cheap) clone of the value.
 ///
-/// If a third parser combinator argument is provided, the `String` constant is lazily parsed using
-/// the combinator on first access, updating the constant pool.
+/// If parser combinator and [`Interner`](crate::class::Interner) arguments are provided, the
+/// `String` constant is lazily parsed using the combinator on first access, interned, then updates
+/// the constant pool. This means equal descriptors in different constant pools share an [`Arc`].
 macro_rules! const_index {
     ($name:ident, $variant:path => $return:ty) => {
         pub fn $name(&self, index: u16) -> $return {
@@ -78,7 +79,7 @@ macro_rules! const_index {
         }
     };
     // Lazily parsed string constant
-    ($name:ident, $variant:path => Arc<$return:ty>, $parser:ident) => {
+    ($name:ident, $variant:path => Arc<$return:ty>, $parser:ident, $interner:ident) => {
         pub fn $name(&self, index: u16) -> Arc<$return> {
             let mut inner = self.inner.write().unwrap();
             let info = &mut inner[index as usize];
@@ -90,7 +91,7 @@ macro_rules! const_index {
                     let (_, descriptor) = $parser(&string_value).unwrap_or_else(|_| {
                         panic!("Unable to parse {} {}", stringify!($name), string_value)
                     });
-                    let descriptor = Arc::new(descriptor);
+                    let descriptor = $interner.intern(descriptor);
                     *info = $variant(Arc::clone(&descriptor));
                     descriptor
                 }
@@ -117,9 +118,9 @@ impl ConstantPool {
     const_index!(str, Constant::String => Arc<String>);
     const_index!(num, Constant::Number => NumericConstant);
     const_index!(class_name, Constant::Class => Arc<String>);
-    const_index!(field_descriptor, Constant::FieldDescriptor => Arc<FieldDescriptor>, field_descriptor_parser);
+    const_index!(field_descriptor, Constant::FieldDescriptor => Arc<FieldDescriptor>, field_descriptor_parser, FIELD_DESCRIPTORS);
     const_index!(field, Constant::Field => FieldId);
-    const_index!(method_descriptor, Constant::MethodDescriptor => Arc<MethodDescriptor>, method_descriptor_parser);
+    const_index!(method_descriptor, Constant::MethodDescriptor => Arc<MethodDescriptor>, method_descriptor_parser, METHOD_DESCRIPTORS);
     const_index!(method, Constant::Method => MethodId);
 
     /// Helper function for [`ConstantPool::new`] to avoid having to explicitly acquiring the write
@@ -212,6 +213,12 @@ impl ConstantPool {
         pool
     }
 
+    /// Returns a (cheap) clone of the constant at `index`, for instructions like `ldc` that accept
+    /// constants of several types.
+    pub fn get(&self, index: u16) -> Constant {
+        self.inner.read().unwrap()[index as usize].clone()
+    }
+
     /// Returns an iterator over all `Constant`s in this pool in index order
     pub fn iter(&self) -> RwLockIter<Constant> {
         let inner = self.inner.read().unwrap();
@@ -241,12 +248,142 @@ mod tests {
         Constant, ConstantPool, FieldDescriptor, MethodDescriptor, NumericConstant,
         ReturnDescriptor,
     };
-    use crate::tests::load_code;
+    use crate::function::NaNBehaviour;
+    use crate::tests::{load_code, load_many_code};
     use crate::Function;
     use classfile_parser::code_attribute::Instruction as JVMInstruction;
     use classfile_parser::constant_info::{ConstantInfo, Utf8Constant};
+    use std::cmp::Ordering;
     use std::sync::Arc;
 
+    impl NumericConstant {
+        /// Applies `int_op`, `long_op`, `float_op` or `double_op` to `self` and `other` depending
+        /// on their type, returning `None` if their types differ or the operation can't be
+        /// performed.
+        fn binary_op(
+            self,
+            other: Self,
+            int_op: impl FnOnce(i32, i32) -> Option<i32>,
+            long_op: impl FnOnce(i64, i64) -> Option<i64>,
+            float_op: impl FnOnce(f32, f32) -> f32,
+            double_op: impl FnOnce(f64, f64) -> f64,
+        ) -> Option<Self> {
+            match (self, other) {
+                (NumericConstant::Integer(a), NumericConstant::Integer(b)) => {
+                    int_op(a, b).map(NumericConstant::Integer)
+                }
+                (NumericConstant::Long(a), NumericConstant::Long(b)) => {
+                    long_op(a, b).map(NumericConstant::Long)
+                }
+                (NumericConstant::Float(a), NumericConstant::Float(b)) => {
+                    Some(NumericConstant::Float(float_op(a, b)))
+                }
+                (NumericConstant::Double(a), NumericConstant::Double(b)) => {
+                    Some(NumericConstant::Double(double_op(a, b)))
+                }
+                _ => None,
+            }
+        }
+
+        /// Returns `self + other` (`iadd`, `ladd`, `fadd` or `dadd`), wrapping on integer overflow.
+        fn add(self, other: Self) -> Option<Self> {
+            self.binary_op(
+                other,
+                |a, b| Some(a.wrapping_add(b)),
+                |a, b| Some(a.wrapping_add(b)),
+                |a, b| a + b,
+                |a, b| a + b,
+            )
+        }
+
+        /// Returns `self - other` (`isub`, `lsub`, `fsub` or `dsub`), wrapping on integer overflow.
+        fn sub(self, other: Self) -> Option<Self> {
+            self.binary_op(
+                other,
+                |a, b| Some(a.wrapping_sub(b)),
+                |a, b| Some(a.wrapping_sub(b)),
+                |a, b| a - b,
+                |a, b| a - b,
+            )
+        }
+
+        /// Returns `self * other` (`imul`, `lmul`, `fmul` or `dmul`), wrapping on integer overflow.
+        fn mul(self, other: Self) -> Option<Self> {
+            self.binary_op(
+                other,
+                |a, b| Some(a.wrapping_mul(b)),
+                |a, b| Some(a.wrapping_mul(b)),
+                |a, b| a * b,
+                |a, b| a * b,
+            )
+        }
+
+        /// Returns `self / other` (`idiv`, `ldiv`, `fdiv` or `ddiv`). Integer division rounds
+        /// towards zero, and `MIN_VALUE / -1` wraps to `MIN_VALUE`. Returns `None` for integer
+        /// division by zero, as this throws an `ArithmeticException` at runtime.
+        fn div(self, other: Self) -> Option<Self> {
+            self.binary_op(
+                other,
+                |a, b| (b != 0).then(|| a.wrapping_div(b)),
+                |a, b| (b != 0).then(|| a.wrapping_div(b)),
+                |a, b| a / b,
+                |a, b| a / b,
+            )
+        }
+
+        /// Returns `self % other` (`irem`, `lrem`, `frem` or `drem`). The result has the same sign
+        /// as `self` for all types, like C's `fmod` for floating point values. Returns `None` for
+        /// integer remainder by zero, as this throws an `ArithmeticException` at runtime.
+        fn rem(self, other: Self) -> Option<Self> {
+            self.binary_op(
+                other,
+                |a, b| (b != 0).then(|| a.wrapping_rem(b)),
+                |a, b| (b != 0).then(|| a.wrapping_rem(b)),
+                |a, b| a % b,
+                |a, b| a % b,
+            )
+        }
+
+        /// Compares `self` and `other` (`lcmp`, `fcmpl`, `fcmpg`, `dcmpl` or `dcmpg`), returning an
+        /// `Integer` of -1 if `self < other`, 0 if `self = other` and 1 if `self > other`. If
+        /// either is NaN, the result is determined by `nan_behaviour`. Returns `None` if types
+        /// differ.
+        fn cmp(self, other: Self, nan_behaviour: NaNBehaviour) -> Option<Self> {
+            let ordering = match (self, other) {
+                (NumericConstant::Integer(a), NumericConstant::Integer(b)) => Some(a.cmp(&b)),
+                (NumericConstant::Long(a), NumericConstant::Long(b)) => Some(a.cmp(&b)),
+                (NumericConstant::Float(a), NumericConstant::Float(b)) => a.partial_cmp(&b),
+                (NumericConstant::Double(a), NumericConstant::Double(b)) => a.partial_cmp(&b),
+                _ => return None,
+            };
+            let result = match ordering {
+                Some(Ordering::Less) => -1,
+                Some(Ordering::Equal) => 0,
+                Some(Ordering::Greater) => 1,
+                None => match nan_behaviour {
+                    NaNBehaviour::Greater => 1,
+                    NaNBehaviour::Lesser => -1,
+                },
+            };
+            Some(NumericConstant::Integer(result))
+        }
+    }
+
+    impl ConstantPool {
+        /// Returns the names of all classes referenced by this pool in index order, i.e. the
+        /// classes its class depends on. This includes the class itself, its superclass, and array
+        /// classes (e.g. `[I`).
+        fn referenced_class_names(&self) -> Vec<Arc<String>> {
+            (&self.iter())
+                .into_iter()
+                .filter_map(|constant| match constant {
+                    Constant::Class(class_name) => Some(Arc::clone(class_name)),
+                    _ => None,
+                })
+                .collect()
+        }
+    }
+
     /// Helper function for finding constant index of first LDC instruction in implicit constructor.
     fn ldc_index(method: &Function) -> u16 {
         assert_eq!(*method.id.name, "<init>");
@@ -294,6 +431,56 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn numeric_integer_overflow() {
+        use NumericConstant::{Integer, Long};
+        let (max, min) = (Integer(i32::MAX), Integer(i32::MIN));
+        assert_eq!(max.add(Integer(1)), Some(min));
+        assert_eq!(min.sub(Integer(1)), Some(max));
+        assert_eq!(max.mul(Integer(2)), Some(Integer(-2)));
+        assert_eq!(min.div(Integer(-1)), Some(min));
+        assert_eq!(min.rem(Integer(-1)), Some(Integer(0)));
+        assert_eq!(Long(i64::MAX).add(Long(1)), Some(Long(i64::MIN)));
+
+        // Check mismatched types can't be combined
+        assert_eq!(Integer(1).add(Long(1)), None);
+    }
+
+    #[test]
+    fn numeric_long_division() {
+        use NumericConstant::Long;
+        assert_eq!(Long(7).div(Long(2)), Some(Long(3)));
+        assert_eq!(Long(-7).div(Long(2)), Some(Long(-3))); // Rounds towards zero
+        assert_eq!(Long(-7).rem(Long(2)), Some(Long(-1))); // Same sign as dividend
+        assert_eq!(Long(7).rem(Long(-2)), Some(Long(1)));
+        assert_eq!(Long(i64::MIN).div(Long(-1)), Some(Long(i64::MIN)));
+        // Division by zero throws at runtime, so can't be folded
+        assert_eq!(Long(1).div(Long(0)), None);
+        assert_eq!(Long(1).rem(Long(0)), None);
+    }
+
+    #[test]
+    fn numeric_float_nan_cmp() {
+        use NumericConstant::{Double, Float, Integer};
+        let (greater, lesser) = (NaNBehaviour::Greater, NaNBehaviour::Lesser);
+        assert_eq!(Float(1.0).cmp(Float(2.0), greater), Some(Integer(-1)));
+        assert_eq!(Float(2.0).cmp(Float(2.0), greater), Some(Integer(0)));
+        assert_eq!(Float(0.0).cmp(Float(-0.0), greater), Some(Integer(0)));
+        assert_eq!(Float(f32::NAN).cmp(Float(1.0), greater), Some(Integer(1)));
+        assert_eq!(Float(1.0).cmp(Float(f32::NAN), lesser), Some(Integer(-1)));
+        assert_eq!(
+            Double(f64::NAN).cmp(Double(f64::NAN), lesser),
+            Some(Integer(-1))
+        );
+        assert_eq!(Double(3.0).cmp(Double(2.0), lesser), Some(Integer(1)));
+
+        // Check NaNs propagate through arithmetic, and floating point division by zero doesn't fail
+        let nan = Float(f32::NAN).add(Float(1.0));
+        assert!(matches!(nan, Some(Float(f)) if f.is_nan()));
+        assert_eq!(Double(1.0).div(Double(0.0)), Some(Double(f64::INFINITY)));
+        assert_eq!(Float(5.5).rem(Float(-2.0)), Some(Float(1.5)));
+    }
+
     #[test]
     fn constant_class() -> anyhow::Result<()> {
         let class = load_code("Class c = Test.class;")?;
@@ -302,6 +489,26 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn constant_referenced_class_names() -> anyhow::Result<()> {
+        let classes = load_many_code(
+            "static class A {}
+            static class B {}
+            static Object make(boolean a) { if (a) { return new A(); } return new B(); }",
+        )?;
+        let class_names = classes["Test"].const_pool.referenced_class_names();
+        let class_names = class_names
+            .iter()
+            .map(|name| name.as_str())
+            .collect::<Vec<_>>();
+        assert!(class_names.contains(&"Test$A"));
+        assert!(class_names.contains(&"Test$B"));
+        // Check the class itself and its superclass are included too
+        assert!(class_names.contains(&"Test"));
+        assert!(class_names.contains(&"java/lang/Object"));
+        Ok(())
+    }
+
     #[test]
     fn constant_field_descriptor() {
         let utf8_string = String::from("I");
@@ -400,4 +607,28 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn constant_descriptors_interned() -> anyhow::Result<()> {
+        let classes = load_many_code(
+            "static class A { static long f(int x, double y) { return x; } }
+            static class B { static long g(int x, double y) { return x; } }",
+        )?;
+        let a = &classes["Test$A"];
+        let b = &classes["Test$B"];
+        assert!(!Arc::ptr_eq(&a.const_pool, &b.const_pool));
+
+        // Check identical method descriptors from different constant pools share an Arc...
+        let f = a.methods.iter().find(|m| *m.id.name == "f").unwrap();
+        let g = b.methods.iter().find(|m| *m.id.name == "g").unwrap();
+        assert_eq!(format!("{}", f.descriptor), "(ID)J");
+        assert!(Arc::ptr_eq(&f.descriptor, &g.descriptor));
+        // ...including implicit constructors (`()V`)
+        assert!(Arc::ptr_eq(
+            &a.methods[0].descriptor,
+            &b.methods[0].descriptor
+        ));
+
+        Ok(())
+    }
 }
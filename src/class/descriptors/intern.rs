@@ -0,0 +1,58 @@
+use crate::class::{FieldDescriptor, MethodDescriptor};
+use std::collections::HashSet;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+
+/// Thread-safe set of reference-counted values, ensuring equal values share a single [`Arc`].
+///
+/// Each class has its own [`ConstantPool`](crate::class::ConstantPool), so without interning,
+/// identical descriptors used by many classes (e.g. `()V`) would each be allocated separately.
+pub struct Interner<T> {
+    values: Mutex<HashSet<Arc<T>>>,
+}
+
+impl<T: Eq + Hash> Interner<T> {
+    /// Constructs a new empty `Interner`.
+    pub fn new() -> Self {
+        Self {
+            values: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Returns the interned [`Arc`] equal to `value`, interning `value` if this is the first time
+    /// it's been seen.
+    pub fn intern(&self, value: T) -> Arc<T> {
+        let mut values = self.values.lock().unwrap();
+        match values.get(&value) {
+            Some(interned) => Arc::clone(interned),
+            None => {
+                let value = Arc::new(value);
+                values.insert(Arc::clone(&value));
+                value
+            }
+        }
+    }
+}
+
+lazy_static! {
+    /// Global interner for field descriptors parsed by any constant pool.
+    pub static ref FIELD_DESCRIPTORS: Interner<FieldDescriptor> = Interner::new();
+    /// Global interner for method descriptors parsed by any constant pool.
+    pub static ref METHOD_DESCRIPTORS: Interner<MethodDescriptor> = Interner::new();
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::class::descriptors::intern::Interner;
+    use std::sync::Arc;
+
+    #[test]
+    fn interner_shares_equal_values() {
+        let interner = Interner::new();
+        let a = interner.intern(String::from("a"));
+        let b = interner.intern(String::from("b"));
+        assert!(!Arc::ptr_eq(&a, &b));
+        assert!(Arc::ptr_eq(&a, &interner.intern(String::from("a"))));
+        assert!(Arc::ptr_eq(&b, &interner.intern(String::from("b"))));
+    }
+}
@@ -1,5 +1,7 @@
+mod intern;
 mod parser;
 mod types;
 
+pub use self::intern::*;
 pub use self::parser::*;
 pub use self::types::*;
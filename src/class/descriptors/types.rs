@@ -70,12 +70,20 @@ impl FieldDescriptor {
         }
     }
 
+    /// Returns the number of bytes a field of this type occupies in an instance. `boolean`s, `byte`s,
+    /// `char`s and `short`s are stored narrower than their WebAssembly type.
     pub fn size(&self) -> u32 {
-        let field_type = self.as_type();
-        match field_type {
-            ValType::I32 | ValType::F32 => 4,
-            ValType::I64 | ValType::F64 => 8,
-            _ => unreachable!("{:?}", field_type),
+        match self {
+            FieldDescriptor::Boolean | FieldDescriptor::Byte => 1,
+            FieldDescriptor::Char | FieldDescriptor::Short => 2,
+            _ => {
+                let field_type = self.as_type();
+                match field_type {
+                    ValType::I32 | ValType::F32 => 4,
+                    ValType::I64 | ValType::F64 => 8,
+                    _ => unreachable!("{:?}", field_type),
+                }
+            }
         }
     }
 }
@@ -138,7 +146,7 @@ impl FunctionType {
 }
 
 /// Returns the WebAssembly text format's representation of a value type.
-fn val_type_name(t: ValType) -> &'static str {
+pub fn val_type_name(t: ValType) -> &'static str {
     match t {
         ValType::I32 => "i32",
         ValType::I64 => "i64",
@@ -232,6 +240,39 @@ mod tests {
     use std::cmp::Ordering;
     use wasm_encoder::ValType;
 
+    /// Kind of value a [`FieldDescriptor`] describes, distinguishing references from primitives.
+    /// [`FieldDescriptor::as_type`] represents all references as `i32` pointers, so this is
+    /// required to tell which values point to objects on the heap (e.g. for shadow stacks or
+    /// garbage collection).
+    #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+    enum ReferenceKind {
+        /// Primitive value, not a reference
+        Value,
+        /// Reference to an instance of a class
+        Object,
+        /// Reference to an array
+        Array,
+    }
+
+    impl FieldDescriptor {
+        /// Returns whether this descriptor describes a primitive value, or a reference to an object
+        /// or array. See [`ReferenceKind`] for more details.
+        fn reference_kind(&self) -> ReferenceKind {
+            match self {
+                FieldDescriptor::Byte
+                | FieldDescriptor::Char
+                | FieldDescriptor::Double
+                | FieldDescriptor::Float
+                | FieldDescriptor::Int
+                | FieldDescriptor::Long
+                | FieldDescriptor::Short
+                | FieldDescriptor::Boolean => ReferenceKind::Value,
+                FieldDescriptor::Object(_) => ReferenceKind::Object,
+                FieldDescriptor::Array(_) => ReferenceKind::Array,
+            }
+        }
+    }
+
     // Descriptor `Display` implementations are tested in the `super::parser::tests` module
 
     #[test]
@@ -259,13 +300,48 @@ mod tests {
         );
     }
 
+    #[test]
+    fn field_descriptor_reference_kind() {
+        // Primitive types
+        let primitives = [
+            FieldDescriptor::Boolean,
+            FieldDescriptor::Byte,
+            FieldDescriptor::Char,
+            FieldDescriptor::Short,
+            FieldDescriptor::Int,
+            FieldDescriptor::Long,
+            FieldDescriptor::Float,
+            FieldDescriptor::Double,
+        ];
+        for descriptor in &primitives {
+            assert_eq!(descriptor.reference_kind(), ReferenceKind::Value);
+        }
+
+        // Reference types, including arrays of primitives and arrays of references
+        assert_eq!(
+            FieldDescriptor::Object(String::from("Test")).reference_kind(),
+            ReferenceKind::Object
+        );
+        assert_eq!(
+            FieldDescriptor::Array(Box::new(FieldDescriptor::Int)).reference_kind(),
+            ReferenceKind::Array
+        );
+        assert_eq!(
+            FieldDescriptor::Array(Box::new(FieldDescriptor::Object(String::from("Test"))))
+                .reference_kind(),
+            ReferenceKind::Array
+        );
+    }
+
     #[test]
     fn field_descriptor_size() {
+        // Narrow (1 and 2 byte) types
+        assert_eq!(FieldDescriptor::Boolean.size(), 1);
+        assert_eq!(FieldDescriptor::Byte.size(), 1);
+        assert_eq!(FieldDescriptor::Char.size(), 2);
+        assert_eq!(FieldDescriptor::Short.size(), 2);
+
         // Single word (4 byte) types
-        assert_eq!(FieldDescriptor::Boolean.size(), 4);
-        assert_eq!(FieldDescriptor::Byte.size(), 4);
-        assert_eq!(FieldDescriptor::Char.size(), 4);
-        assert_eq!(FieldDescriptor::Short.size(), 4);
         assert_eq!(FieldDescriptor::Int.size(), 4);
 
         assert_eq!(FieldDescriptor::Float.size(), 4);
@@ -316,6 +392,18 @@ mod tests {
         assert_eq!(d.function_type.params, [ValType::I32]);
         assert_eq!(d.function_type.results, [ValType::F32]);
 
+        // Check computes `FunctionType` with reference returns as pointers
+        let d = MethodDescriptor::new(
+            vec![],
+            ReturnDescriptor::Field(FieldDescriptor::Object(String::from("Test"))),
+        );
+        assert_eq!(d.function_type.results, [ValType::I32]);
+        let d = MethodDescriptor::new(
+            vec![],
+            ReturnDescriptor::Field(FieldDescriptor::Array(Box::new(FieldDescriptor::Long))),
+        );
+        assert_eq!(d.function_type.results, [ValType::I32]);
+
         // Check computes `FunctionType` with void return
         let d = MethodDescriptor::new(vec![], ReturnDescriptor::Void);
         assert_eq!(d.function_type.params, []);
@@ -9,6 +9,7 @@ pub use self::types::*;
 
 use crate::class::parser::parse_class;
 use crate::scheduler::Job;
+use crate::timings::SharedDuration;
 use anyhow::Context;
 use std::fs;
 use std::path::{Path, PathBuf};
@@ -16,22 +17,30 @@ use std::sync::mpsc::Sender;
 
 pub struct LoadClassJob {
     pub path: PathBuf,
+    /// Whether to accept slightly malformed class files. Enabled with `--compat-mode`.
+    pub compat: bool,
     pub result_tx: Sender<anyhow::Result<Class>>,
+    /// Total time spent loading classes, shared with other jobs. Used for `--print-timings`.
+    pub load_duration: SharedDuration,
 }
 
 impl Job for LoadClassJob {
     fn process(&self) {
-        let maybe_class = load_class(&self.path);
+        let maybe_class = self
+            .load_duration
+            .time(|| load_class(&self.path, self.compat));
         self.result_tx.send(maybe_class).unwrap()
     }
 }
 
-pub fn load_class<P: AsRef<Path>>(path: P) -> anyhow::Result<Class> {
+/// Reads and parses the class file at `path`. If `compat` is set, slightly malformed class files
+/// will be accepted (see [`parse_class`]).
+pub fn load_class<P: AsRef<Path>>(path: P, compat: bool) -> anyhow::Result<Class> {
     // Load class from disk
     let path = path.as_ref();
     let display = path.display();
     let data = fs::read(path).with_context(|| format!("Unable to read {}", display))?;
 
     // Parse and return class file
-    parse_class(&data)
+    parse_class(&data, compat)
 }
@@ -1,14 +1,84 @@
-use crate::class::{Class, ConstantPool, MethodId};
-use crate::function::Function;
+use crate::class::{Class, ConstantPool, MethodId, JAVA_LANG_OBJECT};
+use crate::function::{ExceptionHandler, Function, StackMap};
 use anyhow::Context;
-use classfile_parser::attribute_info::code_attribute_parser;
+use classfile_parser::attribute_info::{code_attribute_parser, AttributeInfo, CodeAttribute};
 use classfile_parser::class_parser;
 use classfile_parser::code_attribute::{code_parser, Instruction as JVMInstruction};
 use classfile_parser::field_info::{FieldAccessFlags, FieldInfo};
 use classfile_parser::method_info::{MethodAccessFlags, MethodInfo};
-use std::collections::HashMap;
+use nom::multi::{count, length_count};
+use nom::number::complete::{be_u16, be_u8};
+use nom::IResult;
+use std::borrow::Cow;
+use std::collections::HashSet;
 use std::mem::take;
 use std::sync::{Arc, Mutex};
+use wasm_encoder::ValType;
+
+/// Maximum class file major version known to be supported (Java 17). Newer class files may still
+/// be parsed, but a warning will be logged.
+const MAX_MAJOR_VERSION: u16 = 61;
+
+/// Rewrites constant pool entries introduced after Java 8 that the [`classfile_parser`] crate
+/// can't parse into entries with the same layout it can, returning the (possibly) modified class
+/// file.
+///
+/// None of these entries are used by montera, and all rewritten entries are parsed as
+/// [`Constant::Unusable`](super::Constant::Unusable), so their contents don't matter:
+///
+/// - `CONSTANT_Dynamic` (17) is rewritten to `CONSTANT_InvokeDynamic` (18)
+/// - `CONSTANT_Module` (19) and `CONSTANT_Package` (20) are rewritten to `CONSTANT_MethodType` (16)
+///
+/// See section [4.4] of the Java Virtual Machine Specification for the layout of each entry.
+///
+/// [4.4]: https://docs.oracle.com/javase/specs/jvms/se17/html/jvms-4.html#jvms-4.4
+fn normalise_constant_pool(data: &[u8]) -> anyhow::Result<Cow<'_, [u8]>> {
+    let read_u16 = |i: usize| -> anyhow::Result<u16> {
+        match data.get(i..i + 2) {
+            Some(bytes) => Ok(u16::from_be_bytes([bytes[0], bytes[1]])),
+            None => bail!("Unexpected end of class file"),
+        }
+    };
+
+    // Skip magic and minor version
+    let major_version = read_u16(6)?;
+    if major_version > MAX_MAJOR_VERSION {
+        warn!(
+            "Class file major version {} is newer than {}, parsing may fail...",
+            major_version, MAX_MAJOR_VERSION
+        );
+    }
+
+    let mut data = Cow::Borrowed(data);
+    let const_pool_count = read_u16(8)?;
+    let mut i = 10;
+    // Note the constant pool is 1-indexed
+    let mut index = 1;
+    while index < const_pool_count {
+        let tag = *data
+            .get(i)
+            .ok_or_else(|| anyhow!("Unexpected end of class file"))?;
+        // Number of bytes following the tag, and number of pool entries occupied
+        let (len, entries) = match tag {
+            1 => (2 + read_u16(i + 1)? as usize, 1), // Utf8
+            3 | 4 => (4, 1),                         // Integer, Float
+            5 | 6 => (8, 2),                         // Long, Double
+            7 | 8 | 16 | 19 | 20 => (2, 1),          // Class, String, MethodType, Module, Package
+            9 | 10 | 11 | 12 | 17 | 18 => (4, 1),    // Refs, NameAndType, (Invoke)Dynamic
+            15 => (3, 1),                            // MethodHandle
+            _ => bail!("Unknown constant pool tag {} at index {}", tag, index),
+        };
+        match tag {
+            17 => data.to_mut()[i] = 18,
+            19 | 20 => data.to_mut()[i] = 16,
+            _ => {}
+        }
+        i += 1 + len;
+        index += entries;
+    }
+
+    Ok(data)
+}
 
 /// Parses the contents of a Java `.class` file as defined in [chapter 4] of the Java
 /// Virtual Machine Specification, returning a [`Class`].
@@ -16,9 +86,13 @@ use std::sync::{Arc, Mutex};
 /// This will include the class name, the name of the super class, field offsets, total class
 /// size, the constant pool, method signatures and code.
 ///
+/// If `compat` is set, structures produced by obfuscators or hand-written class files that javac
+/// wouldn't generate, but are still loadable, will be accepted. See [`parse_code`] for details.
+///
 /// [chapter 4]: https://docs.oracle.com/javase/specs/jvms/se7/html/jvms-4.html
-pub fn parse_class(data: &[u8]) -> anyhow::Result<Class> {
-    // Parse class file
+pub fn parse_class(data: &[u8], compat: bool) -> anyhow::Result<Class> {
+    // Parse class file, rewriting constant pool entries from newer Java versions first
+    let data = normalise_constant_pool(data).context("Unable to parse constant pool")?;
     let (_, mut class_file) = class_parser(&data).map_err(|_| anyhow!("Unable to parse class"))?;
 
     // Move constant pool out of class_file, parse it, then make class_file immutable
@@ -26,42 +100,94 @@ pub fn parse_class(data: &[u8]) -> anyhow::Result<Class> {
     let const_pool = Arc::new(ConstantPool::new(const_pool));
     let class_file = class_file;
 
-    // Extract this and super class names
+    // Extract this and super class names. Module descriptors (`module-info`) don't have a super
+    // class, so use Object like other classes without an explicit one.
     let class_name = const_pool.class_name(class_file.this_class);
-    let super_class_name = const_pool.class_name(class_file.super_class);
+    let super_class_name = match class_file.super_class {
+        0 => Arc::new(String::from(JAVA_LANG_OBJECT)),
+        index => const_pool.class_name(index),
+    };
+
+    // Extract class fields, their sizes, and final and volatile fields
+    let ParsedFields {
+        fields,
+        final_fields,
+        volatile_fields,
+    } = parse_fields(&const_pool, &class_file.fields)?;
 
-    // Extract class fields, relative offsets and total class size
-    let (field_offsets, size) = parse_fields(&const_pool, &class_file.fields)?;
+    // Find the name of the source file this class was compiled from, if it was recorded
+    let source_file = parse_source_file(&const_pool, &class_file.attributes)?;
 
     // Parse all instance/static methods`
     let functions = class_file
         .methods
         .iter()
-        .map(|method| parse_function(&class_name, &const_pool, method))
+        .map(|method| parse_function(&class_name, &const_pool, &source_file, method, compat))
         .collect::<anyhow::Result<Vec<_>>>()?;
 
+    // Find classes that must be initialized before this one
+    let static_dependencies = class_file
+        .methods
+        .iter()
+        .find(|method| *const_pool.str(method.name_index) == "<clinit>")
+        .map(|method| parse_static_dependencies(&class_name, &const_pool, method, compat))
+        .unwrap_or_default();
+
     // Build and return Class value
     let class = Class {
         class_name,
+        flags: class_file.access_flags,
         super_class_name,
-        size,
-        field_offsets,
+        fields,
+        final_fields,
+        volatile_fields,
+        static_dependencies,
         const_pool,
         methods: functions,
+        source_file,
     };
     Ok(class)
 }
 
-/// Parses class fields, returning field offsets and the total class size.
+/// Parses the `SourceFile` attribute of a class if any, as defined in section [4.7.10] of the Java
+/// Virtual Machine Specification, returning the name of the source file (without directories) the
+/// class was compiled from, e.g. `Test.java`. Nested classes share their outer class's file.
+///
+/// [4.7.10]: https://docs.oracle.com/javase/specs/jvms/se7/html/jvms-4.html#jvms-4.7.10
+fn parse_source_file(
+    const_pool: &ConstantPool,
+    attributes: &[AttributeInfo],
+) -> anyhow::Result<Option<Arc<String>>> {
+    let source_file_attr_info = attributes
+        .iter()
+        .find(|attr| *const_pool.str(attr.attribute_name_index) == "SourceFile");
+    match source_file_attr_info {
+        // The attribute contains a single constant pool index of the file name
+        Some(source_file_attr_info) => match source_file_attr_info.info[..] {
+            [high, low] => Ok(Some(const_pool.str(u16::from_be_bytes([high, low])))),
+            _ => bail!("Unable to parse source file attribute"),
+        },
+        None => Ok(None),
+    }
+}
+
+/// Instance fields of a class, returned by [`parse_fields`]. See [`Class`] for details on each field.
+struct ParsedFields {
+    fields: Vec<(Arc<String>, u32)>,
+    final_fields: HashSet<Arc<String>>,
+    volatile_fields: HashSet<Arc<String>>,
+}
+
+/// Parses class fields, returning field names and sizes in declaration order, and the names of
+/// `final` and `volatile` fields. Fields are laid out in instances by the renderer, as their offsets
+/// depend on the instance header and superclasses.
 ///
 /// Static fields are currently ignored and dropped. Whilst these are required by assertions, they
 /// are special-cased later on in compilation.
-fn parse_fields(
-    const_pool: &ConstantPool,
-    fields: &[FieldInfo],
-) -> anyhow::Result<(HashMap<Arc<String>, u32>, u32)> {
-    let mut field_offsets = HashMap::new();
-    let mut size = 0;
+fn parse_fields(const_pool: &ConstantPool, fields: &[FieldInfo]) -> anyhow::Result<ParsedFields> {
+    let mut instance_fields = vec![];
+    let mut final_fields = HashSet::new();
+    let mut volatile_fields = HashSet::new();
 
     for field in fields {
         // Extract name and descriptor (lazily parsing) from the constant pool
@@ -77,13 +203,23 @@ fn parse_fields(
             continue;
         }
 
-        // Current size is the offset for this field
-        let offset = size;
-        field_offsets.insert(field_name, offset);
-        size += descriptor.size();
+        // Record final fields for verifying writes later on
+        if field.access_flags.contains(FieldAccessFlags::FINAL) {
+            final_fields.insert(Arc::clone(&field_name));
+        }
+        // Record volatile fields for atomic accesses when rendering with threads
+        if field.access_flags.contains(FieldAccessFlags::VOLATILE) {
+            volatile_fields.insert(Arc::clone(&field_name));
+        }
+
+        instance_fields.push((field_name, descriptor.size()));
     }
 
-    Ok((field_offsets, size))
+    Ok(ParsedFields {
+        fields: instance_fields,
+        final_fields,
+        volatile_fields,
+    })
 }
 
 /// Parses a class static or instance method, including its code if any, returning a [`Function`].
@@ -93,7 +229,9 @@ fn parse_fields(
 fn parse_function(
     class_name: &Arc<String>,
     const_pool: &Arc<ConstantPool>,
+    source_file: &Option<Arc<String>>,
     method: &MethodInfo,
+    compat: bool,
 ) -> anyhow::Result<Arc<Function>> {
     // Extract method name and descriptor from constant pool
     let name = const_pool.str(method.name_index);
@@ -101,14 +239,22 @@ fn parse_function(
 
     // Parse function instructions (if any), ignoring class initializers, which are used by
     // assertions
-    let code = if *name == "<clinit>" {
+    let ParsedCode {
+        code,
+        stack_map,
+        exception_handlers,
+    } = if *name == "<clinit>" {
         warn!(
             "Class initializers fields are not yet supported, ignoring {}'s...",
             class_name
         );
-        Some(vec![(0, JVMInstruction::Nop)])
+        ParsedCode {
+            code: Some(vec![(0, JVMInstruction::Nop)]),
+            stack_map: StackMap::new(),
+            exception_handlers: vec![],
+        }
     } else {
-        parse_code(const_pool, method)
+        parse_code(const_pool, method, compat)
             .with_context(|| format!("Unable to parse code for {}", name))?
     };
 
@@ -123,47 +269,240 @@ fn parse_function(
         flags: method.access_flags,
         descriptor,
         const_pool: Arc::clone(const_pool),
+        source_file: source_file.clone(),
         code: Mutex::new(code),
+        stack_map: Arc::new(stack_map),
+        exception_handlers,
     };
     Ok(Arc::new(function))
 }
 
-/// Parses the code if any for a function.
+/// Parses the `<clinit>` class initializer `method` of the named class, returning the names of
+/// other classes whose static fields it reads with `GETSTATIC`. See [`Class::static_dependencies`].
+///
+/// Class initializers aren't compiled yet (see [`parse_function`]), so if the code can't be parsed
+/// (e.g. it contains exception handlers), a warning is logged and no dependencies are returned.
+fn parse_static_dependencies(
+    class_name: &Arc<String>,
+    const_pool: &ConstantPool,
+    method: &MethodInfo,
+    compat: bool,
+) -> HashSet<Arc<String>> {
+    let code = match parse_code(const_pool, method, compat) {
+        Ok(ParsedCode { code, .. }) => code.unwrap_or_default(),
+        Err(e) => {
+            warn!(
+                "Unable to find {}'s static initializer dependencies: {:#}",
+                class_name, e
+            );
+            vec![]
+        }
+    };
+    code.iter()
+        .filter_map(|(_, instruction)| match instruction {
+            JVMInstruction::Getstatic(n) => Some(const_pool.field(*n).class_name),
+            _ => None,
+        })
+        .filter(|dependency| dependency != class_name)
+        .collect()
+}
+
+/// Code of a function, returned by [`parse_code`]. See [`Function`] for details on each field.
+struct ParsedCode {
+    code: Option<Vec<(usize, JVMInstruction)>>,
+    stack_map: StackMap,
+    exception_handlers: Vec<ExceptionHandler>,
+}
+
+/// Parses the code if any for a function, and its stack map frames.
 ///
 /// Note `native` and `abstract` methods will return [`Option::None`] as they don't have a Java
-/// implementation.
+/// implementation. Other methods must have code, unless `compat` is set, in which case they'll
+/// also return [`Option::None`], and be compiled to stubs that trap when called.
+///
+/// Exception handlers (e.g. from `try`/`catch` or `try`/`finally`) are parsed from the exception
+/// table. Exceptions aren't supported yet, so functions catching them are rejected when compiled,
+/// and `finally` handlers are removed (see
+/// [`remove_finally_handlers`](crate::function::structure::remove_finally_handlers)).
 fn parse_code(
     const_pool: &ConstantPool,
     method: &MethodInfo,
-) -> anyhow::Result<Option<Vec<(usize, JVMInstruction)>>> {
+    compat: bool,
+) -> anyhow::Result<ParsedCode> {
+    let no_code = || ParsedCode {
+        code: None,
+        stack_map: StackMap::new(),
+        exception_handlers: vec![],
+    };
+
     // If this is a native/abstract function, it won't have any Java code
     if method
         .access_flags
         .intersects(MethodAccessFlags::NATIVE | MethodAccessFlags::ABSTRACT)
     {
-        return Ok(None);
+        return Ok(no_code());
     }
 
     // Extract and parse code attribute
     let code_attr_info = method
         .attributes
         .iter()
-        .find(|attr| *const_pool.str(attr.attribute_name_index) == "Code")
-        .ok_or_else(|| anyhow!("Unable to find code"))?;
+        .find(|attr| *const_pool.str(attr.attribute_name_index) == "Code");
+    let code_attr_info = match code_attr_info {
+        Some(code_attr_info) => code_attr_info,
+        None if compat => {
+            warn!(
+                "Unable to find code for {}, compiling to a trapping stub...",
+                const_pool.str(method.name_index)
+            );
+            return Ok(no_code());
+        }
+        None => bail!("Unable to find code"),
+    };
     let (_, code_attr) = code_attribute_parser(&code_attr_info.info)
         .map_err(|_| anyhow!("Unable to parse code attribute"))?;
     let (_, code) = code_parser(&code_attr.code).map_err(|_| anyhow!("Unable to parse code"))?;
+    let stack_map = parse_stack_map(const_pool, &code_attr)?;
+
+    // Extract exception handlers, a catch type of 0 catches all exceptions
+    let exception_handlers = (code_attr.exception_table.iter())
+        .map(|entry| ExceptionHandler {
+            try_range: entry.start_pc as usize..entry.end_pc as usize,
+            handler: entry.handler_pc as usize,
+            catch_type: match entry.catch_type {
+                0 => None,
+                index => Some(const_pool.class_name(index)),
+            },
+        })
+        .collect();
+
+    Ok(ParsedCode {
+        code: Some(code),
+        stack_map,
+        exception_handlers,
+    })
+}
 
-    Ok(Some(code))
+/// Parses a verification type as defined in section [4.7.4] of the Java Virtual Machine
+/// Specification, returning its WebAssembly type. References (including `null` and uninitialized
+/// objects) are `i32` pointers. `top` (unusable) values have no type.
+///
+/// [`classfile_parser`]'s parser doesn't consume the constant pool index or offset following
+/// object and uninitialized types, so we can't use it.
+///
+/// [4.7.4]: https://docs.oracle.com/javase/specs/jvms/se7/html/jvms-4.html#jvms-4.7.4
+fn verification_type_parser(input: &[u8]) -> IResult<&[u8], Option<ValType>> {
+    let (input, tag) = be_u8(input)?;
+    match tag {
+        0 => Ok((input, None)),                   // Top
+        1 => Ok((input, Some(ValType::I32))),     // Integer
+        2 => Ok((input, Some(ValType::F32))),     // Float
+        3 => Ok((input, Some(ValType::F64))),     // Double
+        4 => Ok((input, Some(ValType::I64))),     // Long
+        5 | 6 => Ok((input, Some(ValType::I32))), // Null, UninitializedThis
+        7 | 8 => {
+            // Object (constant pool index of class), Uninitialized (offset of New instruction)
+            let (input, _) = be_u16(input)?;
+            Ok((input, Some(ValType::I32)))
+        }
+        _ => Err(nom::Err::Failure(nom::error::Error::new(
+            input,
+            nom::error::ErrorKind::Tag,
+        ))),
+    }
+}
+
+/// Parses a stack map frame as defined in section [4.7.4] of the Java Virtual Machine
+/// Specification, returning its offset delta and the types of values on the operand stack.
+/// Local variable types are ignored, as these are inferred separately when compiling functions.
+///
+/// [4.7.4]: https://docs.oracle.com/javase/specs/jvms/se7/html/jvms-4.html#jvms-4.7.4
+fn stack_map_frame_parser(input: &[u8]) -> IResult<&[u8], (u16, Vec<ValType>)> {
+    let (input, frame_type) = be_u8(input)?;
+    match frame_type {
+        // same_frame
+        0..=63 => Ok((input, (frame_type as u16, vec![]))),
+        // same_locals_1_stack_item_frame
+        64..=127 => {
+            let (input, t) = verification_type_parser(input)?;
+            Ok((input, (frame_type as u16 - 64, t.into_iter().collect())))
+        }
+        // same_locals_1_stack_item_frame_extended
+        247 => {
+            let (input, offset_delta) = be_u16(input)?;
+            let (input, t) = verification_type_parser(input)?;
+            Ok((input, (offset_delta, t.into_iter().collect())))
+        }
+        // chop_frame, same_frame_extended
+        248..=251 => {
+            let (input, offset_delta) = be_u16(input)?;
+            Ok((input, (offset_delta, vec![])))
+        }
+        // append_frame
+        252..=254 => {
+            let (input, offset_delta) = be_u16(input)?;
+            let locals = (frame_type - 251) as usize;
+            let (input, _) = count(verification_type_parser, locals)(input)?;
+            Ok((input, (offset_delta, vec![])))
+        }
+        // full_frame
+        255 => {
+            let (input, offset_delta) = be_u16(input)?;
+            let (input, _) = length_count(be_u16, verification_type_parser)(input)?;
+            let (input, stack) = length_count(be_u16, verification_type_parser)(input)?;
+            Ok((input, (offset_delta, stack.into_iter().flatten().collect())))
+        }
+        // Reserved for future use
+        _ => Err(nom::Err::Failure(nom::error::Error::new(
+            input,
+            nom::error::ErrorKind::Tag,
+        ))),
+    }
+}
+
+/// Parses the `StackMapTable` attribute of a code attribute if any, as defined in section [4.7.4]
+/// of the Java Virtual Machine Specification, returning the types of values on the operand stack
+/// at each frame's bytecode offset. Frames are only required at branch targets, so this gives the
+/// stack's types at the start of basic blocks where control flow merges.
+///
+/// [4.7.4]: https://docs.oracle.com/javase/specs/jvms/se7/html/jvms-4.html#jvms-4.7.4
+fn parse_stack_map(
+    const_pool: &ConstantPool,
+    code_attr: &CodeAttribute,
+) -> anyhow::Result<StackMap> {
+    let mut stack_map = StackMap::new();
+    let stack_map_attr_info = code_attr
+        .attributes
+        .iter()
+        .find(|attr| *const_pool.str(attr.attribute_name_index) == "StackMapTable");
+    if let Some(stack_map_attr_info) = stack_map_attr_info {
+        let (_, frames) = length_count(be_u16, stack_map_frame_parser)(&stack_map_attr_info.info)
+            .map_err(|_| anyhow!("Unable to parse stack map table"))?;
+        // The first frame's offset is its offset delta, each subsequent frame's offset is the
+        // previous frame's offset plus its offset delta plus 1
+        let mut offset = None;
+        for (offset_delta, stack) in frames {
+            let frame_offset = match offset {
+                None => offset_delta as usize,
+                Some(offset) => offset + offset_delta as usize + 1,
+            };
+            stack_map.insert(frame_offset, stack);
+            offset = Some(frame_offset);
+        }
+    }
+    Ok(stack_map)
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::class::parser::normalise_constant_pool;
     use crate::class::JAVA_LANG_OBJECT;
-    use crate::tests::{load_code, load_many_code};
+    use crate::function::ExceptionHandler;
+    use crate::tests::{load_code, load_many_code, load_many_code_with_args};
     use classfile_parser::code_attribute::Instruction as JVMInstruction;
     use classfile_parser::method_info::MethodAccessFlags;
     use std::sync::Arc;
+    use wasm_encoder::ValType;
 
     #[test]
     fn parse_class_names() -> anyhow::Result<()> {
@@ -186,26 +525,131 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn parse_class_source_file() -> anyhow::Result<()> {
+        let classes = load_many_code("static class A {}")?;
+
+        // Check nested classes record their outer class's source file
+        assert_eq!(
+            classes["Test"].source_file.as_deref(),
+            Some(&String::from("Test.java"))
+        );
+        assert_eq!(
+            classes["Test$A"].source_file.as_deref(),
+            Some(&String::from("Test.java"))
+        );
+
+        // Check the attribute is optional, javac omits it with -g:none
+        let classes = load_many_code_with_args("static class A {}", &["-g:none"])?;
+        assert_eq!(classes["Test"].source_file, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_class_release_versions() -> anyhow::Result<()> {
+        for release in ["8", "11", "17"] {
+            let classes = load_many_code_with_args(
+                "static String greet(String name) { return \"Hello \" + name; }",
+                &["--release", release],
+            )?;
+            let class = &classes["Test"];
+            assert_eq!(*class.class_name, "Test");
+            assert_eq!(class.methods.len(), 1 /* <init> */ + 1);
+            assert_eq!(*class.methods[1].id.name, "greet");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn normalise_constant_pool_rewrites_entries() -> anyhow::Result<()> {
+        #[rustfmt::skip]
+        let data = [
+            0xCA, 0xFE, 0xBA, 0xBE, // Magic
+            0, 0, 0, 61, // Minor, major version (Java 17)
+            0, 6, // Constant pool count
+            1, 0, 1, b'A', // 1: Utf8 "A"
+            5, 0, 0, 0, 0, 0, 0, 0, 42, // 2, 3: Long 42
+            19, 0, 1, // 4: Module #1
+            17, 0, 0, 0, 0, // 5: Dynamic #0:#0
+        ];
+        let normalised = normalise_constant_pool(&data)?;
+        assert_eq!(normalised[..14], data[..14]);
+        assert_eq!(normalised[23], 16);
+        assert_eq!(normalised[26], 18);
+        Ok(())
+    }
+
     #[test]
     fn parse_class_fields() -> anyhow::Result<()> {
         let class = load_code("int a; float b; long c; double d;")?;
-        assert_eq!(class.size, 4 + 4 + 8 + 8);
-        assert_eq!(class.field_offsets[&Arc::new(String::from("a"))], 0);
-        assert_eq!(class.field_offsets[&Arc::new(String::from("b"))], 4);
-        assert_eq!(class.field_offsets[&Arc::new(String::from("c"))], 4 + 4);
-        assert_eq!(class.field_offsets[&Arc::new(String::from("d"))], 4 + 4 + 8);
+        let field = |name: &str, size| (Arc::new(String::from(name)), size);
+        assert_eq!(
+            class.fields,
+            [field("a", 4), field("b", 4), field("c", 8), field("d", 8)]
+        );
         Ok(())
     }
 
     #[test]
     fn parse_class_skips_static_fields() -> anyhow::Result<()> {
         let class = load_code("int a; static long b; float c;")?;
-        assert_eq!(class.size, 4 + 4);
-        assert_eq!(class.field_offsets[&Arc::new(String::from("a"))], 0);
-        assert_eq!(class.field_offsets[&Arc::new(String::from("c"))], 4);
-        assert!(!class
-            .field_offsets
-            .contains_key(&Arc::new(String::from("b"))));
+        let field = |name: &str, size| (Arc::new(String::from(name)), size);
+        assert_eq!(class.fields, [field("a", 4), field("c", 4)]);
+        assert!(!class.declares_field("b"));
+        Ok(())
+    }
+
+    #[test]
+    fn parse_class_final_fields() -> anyhow::Result<()> {
+        let class = load_code("final int a = 1; int b; final float c = 2f;")?;
+        assert_eq!(class.final_fields.len(), 2);
+        assert!(class.final_fields.contains(&Arc::new(String::from("a"))));
+        assert!(class.final_fields.contains(&Arc::new(String::from("c"))));
+        Ok(())
+    }
+
+    #[test]
+    fn parse_class_volatile_fields() -> anyhow::Result<()> {
+        let class = load_code("volatile int a; int b; volatile long c; static volatile int d;")?;
+        assert_eq!(class.volatile_fields.len(), 2);
+        assert!(class.volatile_fields.contains(&Arc::new(String::from("a"))));
+        assert!(class.volatile_fields.contains(&Arc::new(String::from("c"))));
+        Ok(())
+    }
+
+    #[test]
+    fn parse_function_code_exception_handlers() -> anyhow::Result<()> {
+        let class = load_code(
+            "static void sideEffect() {}
+            static int divide(int x) { try { return 1 / x; } catch (ArithmeticException e) { return -1; } }
+            static int run() { try { return 1; } finally { sideEffect(); } }",
+        )?;
+        let handlers = |name: &str| {
+            let method = class.methods.iter().find(|m| *m.id.name == name).unwrap();
+            method.exception_handlers.clone()
+        };
+
+        // Check handlers match `javap -c`'s exception tables:
+        //   from    to  target type
+        //      0     3     4   Class java/lang/ArithmeticException
+        let expected = vec![ExceptionHandler {
+            try_range: 0..3,
+            handler: 4,
+            catch_type: Some(Arc::new(String::from("java/lang/ArithmeticException"))),
+        }];
+        assert_eq!(handlers("divide"), expected);
+        //   from    to  target type
+        //      0     2     7   any
+        let expected = vec![ExceptionHandler {
+            try_range: 0..2,
+            handler: 7,
+            catch_type: None,
+        }];
+        assert_eq!(handlers("run"), expected);
+
+        // Check methods without handlers have empty exception tables
+        assert!(handlers("sideEffect").is_empty());
         Ok(())
     }
 
@@ -265,6 +709,35 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn parse_function_stack_map() -> anyhow::Result<()> {
+        let class = load_code(
+            "static float select(boolean c, float x, float y) { float a, b; a = b = c ? x : y; return a + b; }
+            float f;
+            float store(boolean c, float x, float y) { return this.f = c ? x : y; }",
+        )?;
+
+        // Check frames recorded at branch targets, including values on the stack at merge points
+        let method = &class.methods[1];
+        assert_eq!(format!("{}", method.id), "Test.select(ZFF)F");
+        let expected = hashmap! {
+            8 => vec![],                // false branch: fload_2
+            9 => vec![ValType::F32],    // merge point: dup
+        };
+        assert_eq!(*method.stack_map, expected);
+
+        // Check object types consume their class index, so following types are parsed correctly
+        let method = &class.methods[2];
+        assert_eq!(format!("{}", method.id), "Test.store(ZFF)F");
+        let expected = hashmap! {
+            9 => vec![ValType::I32],                 // false branch: fload_3 (this underneath)
+            10 => vec![ValType::I32, ValType::F32],  // merge point: dup_x1 (full_frame)
+        };
+        assert_eq!(*method.stack_map, expected);
+
+        Ok(())
+    }
+
     #[test]
     fn parse_function_abstract_method() -> anyhow::Result<()> {
         // `abstract` methods must belong to `abstract` `class`es or `interface`s
@@ -1,8 +1,9 @@
 use crate::class::{ConstantPool, FieldDescriptor, MethodDescriptor};
 use crate::function::Function;
+use classfile_parser::ClassAccessFlags;
 use itertools::Itertools;
 use log::Level;
-use std::collections::HashMap;
+use std::collections::HashSet;
 use std::fmt;
 use std::fmt::Formatter;
 use std::ops::Deref;
@@ -15,26 +16,44 @@ use std::sync::Arc;
 pub struct Class {
     /// Name of this class.
     pub class_name: Arc<String>,
+    /// Access flags of this class, e.g. whether it's an interface or annotation type.
+    pub flags: ClassAccessFlags,
     /// Name of this class's superclass, or [`JAVA_LANG_OBJECT`](super::constants::JAVA_LANG_OBJECT)
     /// if this class doesn't explicitly inherit anything.
     pub super_class_name: Arc<String>,
-    /// Number of bytes to allocate on the heap for this class (excluding super classes).
-    pub size: u32,
-    /// Byte offsets from the start of this class (excluding super classes) for each named field.
+    /// Names and sizes in bytes of the instance fields declared by this class (excluding super
+    /// classes), in declaration order.
     ///
-    /// All field offsets will be less than `size`. To get the actual offset relative to a pointer,
-    /// add 4 (for virtual class identifier) + size of super classes.
+    /// Fields are laid out in this order after the instance header and super classes' fields, each
+    /// padded to its natural alignment. See `Renderer::find_field_offset` for the layout.
     ///
     /// See [`parse_fields`](super::parser::parse_fields) for the parser implementation.
-    pub field_offsets: HashMap<Arc<String>, u32>,
+    pub fields: Vec<(Arc<String>, u32)>,
+    /// Names of `final` instance fields declared in this class (excluding super classes). These
+    /// should only be written to in this class's constructors.
+    pub final_fields: HashSet<Arc<String>>,
+    /// Names of `volatile` instance fields declared in this class (excluding super classes). When
+    /// rendering with `--threads`, these are accessed with atomic loads and stores.
+    pub volatile_fields: HashSet<Arc<String>>,
     /// Parsed constant pool associated with this class, containing strings, numbers & descriptors.
     ///
     /// See [`ConstantPool::new`] for the parser implementation.
     pub const_pool: Arc<ConstantPool>,
+    /// Names of other classes with static fields read by this class's `<clinit>` initializer. These
+    /// classes must be initialized before this one.
+    ///
+    /// See [`parse_static_dependencies`](super::parser::parse_static_dependencies) for the parser
+    /// implementation.
+    pub static_dependencies: HashSet<Arc<String>>,
     /// Parsed static and instance methods belonging to this class.
     ///
     /// See [`parse_function`](super::parser::parse_function) for the parser implementation.
     pub methods: Vec<Arc<Function>>,
+    /// Name of the source file this class was compiled from (e.g. `Test.java`), if it was recorded
+    /// in the class file. Used for debug output.
+    ///
+    /// See [`parse_source_file`](super::parser::parse_source_file) for the parser implementation.
+    pub source_file: Option<Arc<String>>,
 }
 
 /// Unique universal identifier for a method in a program consisting of multiple classes.
@@ -72,7 +91,7 @@ impl fmt::Debug for MethodId {
 }
 
 /// Unique universal identifier for a field in a program consisting of multiple classes.
-#[derive(Clone, Eq, PartialEq)]
+#[derive(Clone, Eq, PartialEq, Hash)]
 pub struct FieldId {
     pub class_name: Arc<String>,
     pub name: Arc<String>,
@@ -92,13 +111,34 @@ impl fmt::Debug for FieldId {
 }
 
 impl Class {
+    /// Returns `true` if and only if this is a module (`module-info`) or package (`package-info`)
+    /// descriptor. These don't define any types or code, so shouldn't be compiled.
+    pub fn is_descriptor(&self) -> bool {
+        let simple_name = self.class_name.rsplit('/').next().unwrap();
+        simple_name == "module-info" || simple_name == "package-info"
+    }
+
+    /// Returns `true` if and only if this class (excluding super classes) declares an instance field
+    /// named `name`.
+    pub fn declares_field(&self, name: &str) -> bool {
+        self.fields
+            .iter()
+            .any(|(field_name, _)| field_name.as_str() == name)
+    }
+
+    /// Returns `true` if and only if this is an annotation type (`@interface`). Annotations are only
+    /// read reflectively, so their (abstract or synthetic) methods shouldn't be compiled.
+    pub fn is_annotation(&self) -> bool {
+        self.flags.contains(ClassAccessFlags::ANNOTATION)
+    }
+
     /// Logs the entire class to the console at log level [`Level::Debug`].
     ///
     /// - Class Name
     /// - Super Class
     /// - Constant Pool (at [`Level::Trace`])
-    /// - Field Offsets (at [`Level::Trace`])
-    /// - Size (at [`Level::Trace`])
+    /// - Field Sizes (at [`Level::Trace`])
+    /// - Static Dependencies (at [`Level::Trace`])
     /// - Methods
     /// - Code (at [`Level::Trace`])
     pub fn dump(&self) {
@@ -106,24 +146,31 @@ impl Class {
         if !log_enabled!(Level::Debug) {
             return;
         }
-        debug!(
-            "Class: {} (extends {})",
-            self.class_name, self.super_class_name
-        );
+        match &self.source_file {
+            Some(source_file) => debug!(
+                "Class: {} (extends {}, from {})",
+                self.class_name, self.super_class_name, source_file
+            ),
+            None => debug!(
+                "Class: {} (extends {})",
+                self.class_name, self.super_class_name
+            ),
+        }
         trace!("  Constant Pool:");
         for (i, const_info) in (&self.const_pool.iter()).into_iter().enumerate() {
             trace!("{:>6}: {:?}", i, const_info);
         }
-        if !self.field_offsets.is_empty() {
+        if !self.fields.is_empty() {
             trace!("  Fields:");
-            for (field_name, offset) in self
-                .field_offsets
-                .iter()
-                .sorted_by_key(|(_, offset)| *offset)
-            {
-                trace!("{:>6}: {}", offset, field_name);
+            for (field_name, size) in &self.fields {
+                trace!("{:>6}: {}", size, field_name);
+            }
+        }
+        if !self.static_dependencies.is_empty() {
+            trace!("  Static Dependencies:");
+            for class_name in self.static_dependencies.iter().sorted() {
+                trace!("        {}", class_name);
             }
-            trace!("{:>6}:", self.size);
         }
         for function in &self.methods {
             debug!(
@@ -141,9 +188,76 @@ impl Class {
 
 #[cfg(test)]
 mod tests {
-    use crate::class::{FieldDescriptor, FieldId, MethodDescriptor, MethodId, ReturnDescriptor};
+    use crate::class::{
+        Class, FieldDescriptor, FieldId, MethodDescriptor, MethodId, ReturnDescriptor,
+    };
+    use crate::tests::{load_many_code, str_arc};
+    use itertools::Itertools;
+    use std::collections::{BTreeSet, HashMap, HashSet};
     use std::sync::Arc;
 
+    /// Returns the names of all classes with `<clinit>` initializers in the order they should be
+    /// called, ensuring classes are initialized after any classes whose static fields they read
+    /// (their [`Class::static_dependencies`]). Dependencies without initializers are ignored.
+    ///
+    /// This is a topological sort, breaking ties by class name so the order is deterministic. If
+    /// there are cyclic dependencies, the remaining classes are initialized in class name order.
+    /// Java would initialize these lazily on first access, observing default values for partially
+    /// initialized classes, which we don't support. Nothing calls initializers yet, as `<clinit>`
+    /// bodies and static fields don't compile.
+    fn class_initialization_order(classes: &HashMap<Arc<String>, Class>) -> Vec<Arc<String>> {
+        let initialized = classes
+            .values()
+            .filter(|class| class.methods.iter().any(|m| *m.id.name == "<clinit>"))
+            .map(|class| &class.class_name)
+            .collect::<HashSet<_>>();
+
+        // Count dependencies of each class that must be initialized first, and record dependents
+        let mut remaining = HashMap::new();
+        let mut dependents: HashMap<_, Vec<_>> = HashMap::new();
+        for &class_name in &initialized {
+            let dependencies = classes[class_name]
+                .static_dependencies
+                .iter()
+                .filter(|&dependency| dependency != class_name && initialized.contains(dependency))
+                .collect::<Vec<_>>();
+            remaining.insert(class_name, dependencies.len());
+            for dependency in dependencies {
+                dependents.entry(dependency).or_default().push(class_name);
+            }
+        }
+
+        // Repeatedly initialize the first (by name) class with all dependencies initialized
+        let mut ready = (remaining.iter())
+            .filter(|(_, &count)| count == 0)
+            .map(|(&class_name, _)| class_name)
+            .collect::<BTreeSet<_>>();
+        let mut order = Vec::with_capacity(initialized.len());
+        while let Some(class_name) = ready.iter().next().copied() {
+            ready.remove(class_name);
+            remaining.remove(class_name);
+            order.push(Arc::clone(class_name));
+            for dependent in dependents.get(class_name).into_iter().flatten() {
+                let count = remaining.get_mut(dependent).unwrap();
+                *count -= 1;
+                if *count == 0 {
+                    ready.insert(dependent);
+                }
+            }
+        }
+
+        // Any remaining classes must be part of (or depend on) a cycle
+        if !remaining.is_empty() {
+            let cyclic = remaining.keys().sorted().collect::<Vec<_>>();
+            warn!(
+                "Cyclic static initializer dependencies between {}, initializing in name order...",
+                cyclic.iter().format(", ")
+            );
+            order.extend(cyclic.into_iter().map(|&class_name| Arc::clone(class_name)));
+        }
+        order
+    }
+
     #[test]
     fn method_id_name() {
         let id = MethodId {
@@ -179,4 +293,39 @@ mod tests {
         };
         assert_eq!(format!("{}", id), "Class.fieldI");
     }
+
+    #[test]
+    fn class_initialization_order_dependencies() -> anyhow::Result<()> {
+        let classes = load_many_code(
+            "static class A { static int a = B.b + C.c; }
+            static class B { static int b = C.c * 2; }
+            static class C { static int c = 1; }
+            static class D { static int d = E.e; }
+            static class E { static int e = D.d; }
+            static class F { int f; }",
+        )?;
+        let classes = classes
+            .into_iter()
+            .map(|(name, class)| (Arc::new(name), class))
+            .collect::<HashMap<_, _>>();
+
+        // Check GETSTATIC targets in <clinit> are recorded
+        let a = &classes[&str_arc("Test$A")];
+        let mut dependencies = a.static_dependencies.iter().collect::<Vec<_>>();
+        dependencies.sort();
+        assert_eq!(dependencies, [&str_arc("Test$B"), &str_arc("Test$C")]);
+
+        // Check dependencies are initialized first, cycles are initialized in name order, and
+        // classes without initializers are ignored
+        let order = class_initialization_order(&classes);
+        assert_eq!(
+            order,
+            ["Test$C", "Test$B", "Test$A", "Test$D", "Test$E"]
+                .iter()
+                .map(|&name| str_arc(name))
+                .collect::<Vec<_>>()
+        );
+
+        Ok(())
+    }
 }
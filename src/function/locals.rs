@@ -1,10 +1,12 @@
 use crate::class::FieldDescriptor;
+use crate::function::structure::branch_target;
 use crate::function::Instruction;
 use crate::function::Instruction::I;
 use classfile_parser::code_attribute::Instruction as JVMInstruction;
 use itertools::Itertools;
 use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
 use wasm_encoder::Instruction as WASMInstruction;
 use wasm_encoder::ValType;
 
@@ -72,6 +74,16 @@ fn instruction_local(instruction: &JVMInstruction) -> Option<(u32, ValType)> {
     }
 }
 
+/// Maximum number of locals (including parameters) in a WebAssembly function accepted by
+/// `wasmparser` and Wasmtime, matching the [JavaScript API's implementation limits].
+///
+/// [JavaScript API's implementation limits]: https://webassembly.github.io/spec/js-api/#limits
+const MAX_FUNCTION_LOCALS: usize = 50_000;
+
+/// Maximum number of scratch locals appended when rendering a function, at most one per value type
+/// (`i32`, `i64`, `f32` and `f64`).
+const MAX_RENDER_SCRATCH_LOCALS: usize = 4;
+
 /// Returns the number of JVM words (32-bit integers) occupied by a WebAssembly type.
 fn type_word_count(t: ValType) -> u32 {
     match t {
@@ -106,13 +118,27 @@ fn type_word_count(t: ValType) -> u32 {
 ///
 /// A solution to this problem is to remap unique JVM local variable index and WebAssembly type
 /// pairs to WebAssembly locals (see [`instruction_local`] for extracting these).
-#[derive(Debug)]
+#[derive(Debug, Eq, PartialEq)]
 pub struct LocalInterpretation {
     /// Mapping between JVM stack index and WebAssembly type pairs and their WebAssembly locals.
     map: HashMap<(u32, ValType), u32>,
     /// Index where function parameters end and local variables start for run-length-encoding
     /// WebAssembly locals in the function body.
     local_start: u32,
+    /// Types of additional locals allocated by optimisation passes, not corresponding to any JVM
+    /// stack index. These follow all mapped locals. See [`LocalInterpretation::add_scratch`].
+    scratch: Vec<ValType>,
+}
+
+impl Hash for LocalInterpretation {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // HashMap's don't implement Hash as they have random order, so hash sorted entries instead
+        for entry in self.map.iter().sorted() {
+            entry.hash(state);
+        }
+        self.local_start.hash(state);
+        self.scratch.hash(state);
+    }
 }
 
 impl LocalInterpretation {
@@ -159,7 +185,86 @@ impl LocalInterpretation {
             }
         }
 
-        LocalInterpretation { map, local_start }
+        LocalInterpretation {
+            map,
+            local_start,
+            scratch: vec![],
+        }
+    }
+
+    /// Shares WebAssembly locals between JVM stack index and type pairs of the same type that are
+    /// never live at the same time, reducing the number of locals in the function. Parameters
+    /// always keep their own locals. This must be called before any scratch locals are added.
+    ///
+    /// The live range of each pair is approximated as the range of labels between its first and
+    /// last access in `code`. Values may be carried around loops, so this range is extended to cover
+    /// any backwards branch it overlaps, until no range changes. The JVM verifier ensures each
+    /// variable is definitely assigned before it's read, so a local's previous value is never
+    /// observed by the next pair sharing it.
+    pub fn coalesce(&mut self, code: &[(usize, JVMInstruction)]) {
+        debug_assert!(self.scratch.is_empty());
+
+        // Find the first and last labels accessing each non-parameter pair
+        let mut ranges = BTreeMap::new();
+        for (label, instruction) in code {
+            if let Some(key) = instruction_local(instruction) {
+                if self.map[&key] < self.local_start {
+                    continue;
+                }
+                let range = ranges.entry(key).or_insert((*label, *label));
+                range.1 = *label;
+            }
+        }
+
+        // Extend ranges over overlapping backwards branches
+        let back_edges = code
+            .iter()
+            .filter_map(|(label, instruction)| {
+                let target = branch_target(*label, instruction)?;
+                if target <= *label {
+                    Some((target, *label))
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (start, end) in ranges.values_mut() {
+                for &(target, label) in &back_edges {
+                    if target <= *end && *start <= label && (target < *start || label > *end) {
+                        *start = (*start).min(target);
+                        *end = (*end).max(label);
+                        changed = true;
+                    }
+                }
+            }
+        }
+
+        // Allocate locals in order of range start, reusing the first local of the same type whose
+        // previous pair's range has ended
+        let mut local_ends: Vec<(ValType, usize)> = vec![];
+        for (key, (start, end)) in ranges
+            .into_iter()
+            .sorted_by_key(|(key, (start, _))| (*start, *key))
+        {
+            let (_, t) = key;
+            let reusable = local_ends
+                .iter()
+                .position(|&(local_t, local_end)| local_t == t && local_end < start);
+            let local_index = match reusable {
+                Some(i) => {
+                    local_ends[i].1 = end;
+                    i
+                }
+                None => {
+                    local_ends.push((t, end));
+                    local_ends.len() - 1
+                }
+            };
+            self.map.insert(key, self.local_start + local_index as u32);
+        }
     }
 
     /// Returns the corresponding WebAssembly local index for a unique JVM `stack_index` and
@@ -185,10 +290,34 @@ impl LocalInterpretation {
         out.push(I(WASMInstruction::LocalSet(self.get_local_index(t, index))));
     }
 
-    /// Returns the number of unique (JVM stack index and WebAssembly type pairs)/WebAssembly locals
-    /// (including parameters) mapped to by this interpretation.
+    /// Allocates a new WebAssembly local of type `t` not corresponding to any JVM stack index,
+    /// returning its index. Unlike the scratch locals appended when rendering, this local is
+    /// reserved for the whole function, so its value can be preserved across arbitrary instructions.
+    pub fn add_scratch(&mut self, t: ValType) -> u32 {
+        let local_index = self.len() as u32;
+        self.scratch.push(t);
+        local_index
+    }
+
+    /// Returns the number of WebAssembly locals (including parameters and scratch locals) mapped to
+    /// by this interpretation. Pairs may share locals if they've been coalesced.
     pub fn len(&self) -> usize {
-        self.map.len()
+        self.map.values().unique().count() + self.scratch.len()
+    }
+
+    /// Checks this interpretation's locals, plus any scratch locals appended when rendering, fit
+    /// within WebAssembly's limit on the number of locals in a function. The JVM allows up to
+    /// 65535 local variable words, and each word may be accessed as multiple types, so large
+    /// generated methods may require more WebAssembly locals than are allowed.
+    pub fn check_limits(&self) -> anyhow::Result<()> {
+        let max_len = MAX_FUNCTION_LOCALS - MAX_RENDER_SCRATCH_LOCALS;
+        ensure!(
+            self.len() <= max_len,
+            "Function requires {} locals, but WebAssembly functions are limited to {}",
+            self.len(),
+            max_len
+        );
+        Ok(())
     }
 
     /// Returns the run-length encoding of this functions local variables for the code section
@@ -204,8 +333,12 @@ impl LocalInterpretation {
             .filter(|(_, local_index)| **local_index >= self.local_start)
             // Sort by local index so final ordering is correct (HashMap's have random order)
             .sorted_by_key(|(_, local_index)| **local_index)
+            // Coalesced pairs share locals, so only include each local once
+            .unique_by(|(_, local_index)| **local_index)
             // Extract just the ValType
             .map(|((_, t), _)| *t)
+            // Add scratch locals allocated by optimisation passes
+            .chain(self.scratch.iter().copied())
             // Add any extra types on the end (e.g. scratch for Dup)
             .chain(append.into_iter().copied());
 
@@ -239,8 +372,30 @@ mod tests {
     use crate::class::FieldDescriptor;
     use crate::function::locals::LocalInterpretation;
     use classfile_parser::code_attribute::Instruction as JVMInstruction;
+    use itertools::Itertools;
     use wasm_encoder::ValType;
 
+    impl LocalInterpretation {
+        /// Returns the number of WebAssembly locals that are function parameters, including the
+        /// implicit `this` parameter of instance methods. These are always the first locals.
+        fn parameter_count(&self) -> u32 {
+            self.local_start
+        }
+
+        /// Returns the types of function parameters in order, including the implicit `this`
+        /// parameter of instance methods. See [`LocalInterpretation::parameter_count`].
+        fn parameter_types(&self) -> Vec<ValType> {
+            self.map
+                .iter()
+                .filter(|(_, local_index)| **local_index < self.local_start)
+                // Sort by local index to get parameters in order (HashMap's have random order)
+                .sorted_by_key(|(_, local_index)| **local_index)
+                .unique_by(|(_, local_index)| **local_index)
+                .map(|((_, t), _)| *t)
+                .collect()
+        }
+    }
+
     #[test]
     fn locals_from_static_method() {
         let params = [FieldDescriptor::Int, FieldDescriptor::Double];
@@ -283,6 +438,43 @@ mod tests {
         assert_eq!(locals.map, expected_map);
     }
 
+    #[test]
+    fn locals_parameters() {
+        let params = [
+            FieldDescriptor::Long,
+            FieldDescriptor::Object(String::from("Test")),
+            FieldDescriptor::Double,
+        ];
+        let code = [
+            (0, JVMInstruction::Iconst0),
+            (1, JVMInstruction::Istore(6)), // Check local variables aren't counted
+        ];
+
+        // Check static methods only include their declared parameters...
+        let locals = LocalInterpretation::from_code(true, &params, &code);
+        assert_eq!(locals.parameter_count(), 3);
+        assert_eq!(
+            locals.parameter_types(),
+            [ValType::I64, ValType::I32, ValType::F64]
+        );
+
+        // ...and instance methods include the implicit this parameter first
+        let locals = LocalInterpretation::from_code(false, &params, &code);
+        assert_eq!(locals.parameter_count(), 4);
+        assert_eq!(
+            locals.parameter_types(),
+            [ValType::I32, ValType::I64, ValType::I32, ValType::F64]
+        );
+
+        // Check methods without parameters
+        let locals = LocalInterpretation::from_code(true, &[], &code);
+        assert_eq!(locals.parameter_count(), 0);
+        assert_eq!(locals.parameter_types(), []);
+        let locals = LocalInterpretation::from_code(false, &[], &code);
+        assert_eq!(locals.parameter_count(), 1);
+        assert_eq!(locals.parameter_types(), [ValType::I32]);
+    }
+
     #[test]
     fn locals_with_slot_reuse() {
         let params = [FieldDescriptor::Int, FieldDescriptor::Double];
@@ -337,6 +529,90 @@ mod tests {
         assert_eq!(locals.run_length_encode(&append), expected_rle);
     }
 
+    #[test]
+    fn locals_add_scratch() {
+        let code = [(0, JVMInstruction::Iload0), (1, JVMInstruction::Fload1)];
+        let mut locals = LocalInterpretation::from_code(true, &[], &code);
+        assert_eq!(locals.add_scratch(ValType::F32), 2);
+        assert_eq!(locals.add_scratch(ValType::I32), 3);
+        assert_eq!(locals.len(), 4);
+
+        // Check scratch locals come after mapped locals, but before appended types
+        let expected_rle = vec![(1, ValType::I32), (2, ValType::F32), (2, ValType::I32)];
+        assert_eq!(locals.run_length_encode(&[ValType::I32]), expected_rle);
+    }
+
+    #[test]
+    fn locals_coalesce() {
+        let params = [FieldDescriptor::Int];
+        let code = [
+            (0, JVMInstruction::Iload0),
+            (1, JVMInstruction::Istore1),
+            (2, JVMInstruction::Fconst0),
+            (3, JVMInstruction::Fstore2),
+            (4, JVMInstruction::Iload1),
+            (5, JVMInstruction::Istore3),
+            (6, JVMInstruction::Iload3),
+            (7, JVMInstruction::Istore(4)),
+            (9, JVMInstruction::Iload(4)), // Loop header
+            (11, JVMInstruction::Istore(5)),
+            (13, JVMInstruction::Iload(5)),
+            (15, JVMInstruction::Istore(4)),
+            (17, JVMInstruction::Goto(-8)),
+            (20, JVMInstruction::Iload1),
+        ];
+        let mut locals = LocalInterpretation::from_code(true, &params, &code);
+        assert_eq!(locals.len(), 6);
+        locals.coalesce(&code);
+        let expected_map = hashmap! {
+            (0, ValType::I32) => 0, // Parameters keep their locals
+            (1, ValType::I32) => 1, // Live until the end, so can't be shared
+            (2, ValType::F32) => 2, // Locals are only shared between the same type
+            (3, ValType::I32) => 3,
+            (4, ValType::I32) => 3, // 3 is dead once 4 is stored
+            (5, ValType::I32) => 4, // Both 4 and 5 are carried around the loop
+        };
+        assert_eq!(locals.map, expected_map);
+        assert_eq!(locals.len(), 5);
+        let expected_rle = vec![(1, ValType::I32), (1, ValType::F32), (2, ValType::I32)];
+        assert_eq!(locals.run_length_encode(&[]), expected_rle);
+    }
+
+    #[test]
+    fn locals_check_limits() {
+        // Use every type for each JVM stack index, so each index requires 4 WebAssembly locals
+        let store_all = |n: u16| {
+            [
+                JVMInstruction::IstoreWide(n),
+                JVMInstruction::LstoreWide(n),
+                JVMInstruction::FstoreWide(n),
+                JVMInstruction::DstoreWide(n),
+            ]
+        };
+        let code = |indices: u16| {
+            (0..indices)
+                .flat_map(store_all)
+                .enumerate()
+                .collect::<Vec<_>>()
+        };
+
+        // Check methods just within the limit are accepted...
+        let locals = LocalInterpretation::from_code(true, &[], &code(12_499));
+        assert_eq!(locals.len(), 49_996);
+        locals.check_limits().unwrap();
+
+        // ...but methods requiring too many locals are rejected with a clear error
+        let mut locals = LocalInterpretation::from_code(true, &[], &code(12_499));
+        locals.add_scratch(ValType::I32);
+        assert_eq!(
+            locals.check_limits().unwrap_err().to_string(),
+            "Function requires 49997 locals, but WebAssembly functions are limited to 49996"
+        );
+        let locals = LocalInterpretation::from_code(true, &[], &code(16_384));
+        assert_eq!(locals.len(), 65_536);
+        assert!(locals.check_limits().is_err());
+    }
+
     #[test]
     fn locals_from_all_instructions() {
         fn local_from(instruction: JVMInstruction) -> (u32, ValType) {
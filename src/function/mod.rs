@@ -1,17 +1,28 @@
 mod locals;
+mod optimize;
+mod peephole;
+mod stack;
 pub mod structure;
 mod types;
+mod verify;
 mod visitor;
 
 pub use self::types::*;
+pub use self::verify::verify_final_writes;
 use crate::function::locals::LocalInterpretation;
-use crate::function::structure::structure_code;
-use crate::function::visitor::Visitor;
+use crate::function::optimize::cache_receivers;
+use crate::function::peephole::{fuse_long_comparisons, mark_tail_calls};
+use crate::function::structure::{remove_finally_handlers, structure_code, LOG_TARGET};
+use crate::function::visitor::{select_branches, Visitor};
 use crate::scheduler::Job;
+use crate::timings::SharedDuration;
+use anyhow::Context;
 use classfile_parser::method_info::MethodAccessFlags;
+use std::cell::RefCell;
 use std::path::PathBuf;
 use std::sync::mpsc::Sender;
 use std::sync::Arc;
+use wasm_encoder::{Instruction as WASMInstruction, ValType};
 
 /// Worker thread job for compiling a JVM bytecode function to WebAssembly with pseudo-instructions.
 ///
@@ -19,69 +30,217 @@ use std::sync::Arc;
 ///
 /// If the function doesn't have any code (i.e. `native` or `abstract`), no compilation will take
 /// place, but a `CompiledFunction` will still be sent on the
-/// results channel.
+/// results channel. If the function fails to compile and [`CompileOptions::keep_going`] is set, a
+/// stub that traps when called will be sent instead of the error.
 pub struct CompileFunctionJob {
     /// JVM bytecode function to compile.
     pub function: Arc<Function>,
     /// Render intermediate control flow graphs using Graphviz to the specific directory (if any).
     /// See [`structure_code`] for details on rendered graphs.
     pub graphs_dir: Option<PathBuf>,
+    /// Options controlling how the function is compiled.
+    pub options: CompileOptions,
     /// Channel to send compilation result back to the main thread on.
     pub result_tx: Sender<anyhow::Result<CompiledFunction>>,
+    /// Total time spent compiling functions, shared with other jobs. Used for `--print-timings`.
+    pub compile_duration: SharedDuration,
+}
+
+impl CompileFunctionJob {
+    /// Compiles this job's function, replacing it with a stub if it fails to compile and
+    /// [`CompileOptions::keep_going`] is set.
+    fn compile(&self) -> anyhow::Result<CompiledFunction> {
+        let f = self.function.as_ref();
+        let result = compile_function(f, self.graphs_dir.as_ref(), self.options)
+            .with_context(|| CompileFunctionError { id: f.id.clone() });
+        // If we're keeping going, replace failed functions with stubs so the rest of the module can
+        // still be written
+        match result {
+            Err(e) if self.options.keep_going => {
+                warn!("{:#}, compiling to a stub that traps when called", e);
+                let mut stub = compile_stub(f);
+                stub.unsupported.extend(unsupported_cause(&e));
+                Ok(stub)
+            }
+            result => result,
+        }
+    }
 }
 
 impl Job for CompileFunctionJob {
     fn process(&self) {
-        let result = self.compile_function();
-        self.result_tx.send(result).unwrap();
+        let result = self.compile_duration.time(|| self.compile());
+        // The receiver may have been dropped if the main thread stopped waiting for results after
+        // reaching `--max-errors`, in which case there's no one left to report this result to
+        let _ = self.result_tx.send(result);
     }
 }
 
-impl CompileFunctionJob {
-    /// Compiles this job's JVM bytecode [`Function`] to WebAssembly with pseudo-[`Instruction`]s.
-    fn compile_function(&self) -> anyhow::Result<CompiledFunction> {
-        let f = self.function.as_ref();
+/// Compiles a single JVM bytecode [`Function`] to WebAssembly with pseudo-[`Instruction`]s on the
+/// current thread, using the default [`CompileOptions`] and without rendering control flow graphs.
+/// This allows the visitor's output to be inspected without a [`CompileFunctionJob`] or scheduler.
+///
+/// Note this takes the function's code, so each function can only be compiled once.
+#[cfg(test)]
+pub fn compile_single(function: &Function) -> anyhow::Result<CompiledFunction> {
+    compile_function(function, None, CompileOptions::default())
+}
+
+/// Compiles a JVM bytecode [`Function`] without producing output, returning the unsupported
+/// features it contains, for `montera inspect`. Unsupported instructions are replaced with
+/// placeholders, so all of them are found rather than just the first. Features that can't be
+/// replaced (e.g. `catch` clauses) are returned as the cause of the error instead (see
+/// [`unsupported_cause`]).
+///
+/// Note this takes the function's code, so each function can only be inspected once.
+pub fn inspect_function(function: &Function) -> anyhow::Result<Vec<Unsupported>> {
+    let options = CompileOptions {
+        on_unsupported: UnsupportedBehaviour::Stub,
+        ..CompileOptions::default()
+    };
+    Ok(compile_function(function, None, options)?.unsupported)
+}
+
+/// Compiles a JVM bytecode [`Function`] to WebAssembly with pseudo-[`Instruction`]s, controlled by
+/// `options`. If `graphs_dir` is set, intermediate control flow graphs will be rendered to it.
+fn compile_function(
+    f: &Function,
+    graphs_dir: Option<&PathBuf>,
+    options: CompileOptions,
+) -> anyhow::Result<CompiledFunction> {
+    let (code, locals, unsupported) = match f.code.lock().unwrap().take() {
+        // Compile code if this is a non-native/abstract function
+        Some(code) => {
+            // Exceptions are not yet supported, so trap instead. This means `finally` handlers are
+            // never run, and can be removed. `catch` clauses are rejected once structured.
+            let code = remove_finally_handlers(code, &f.exception_handlers);
+
+            // Remap locals
+            let is_static = f.flags.contains(MethodAccessFlags::STATIC);
+            let mut locals = LocalInterpretation::from_code(is_static, &f.descriptor.params, &code);
+            // Share locals between variables that are never live at the same time if enabled
+            if options.coalesce_locals {
+                locals.coalesce(&code);
+            }
+            let mut locals = Arc::new(locals);
+
+            // Structure the function's code
+            let len = code.len();
+            debug!(target: LOG_TARGET, "Structuring {}...", f.id);
+            let structure = structure_code(
+                code,
+                f.exception_handlers.clone(),
+                graphs_dir,
+                options.graphs_format,
+                options.coalesce_sequences,
+            )?;
+            ensure!(
+                (structure.exception_handlers.iter()).all(|handler| handler.catch_type.is_none()),
+                Unsupported {
+                    description: "Catch clauses",
+                    category: "Exception",
+                }
+            );
+
+            // If enabled, and any 2-way conditionals can be translated into selects, allocate
+            // a scratch local for stashing their conditions (we have the only reference to the
+            // locals until the visitor's constructed)
+            let has_selects = options.prefer_select
+                && (structure.conditionals.iter())
+                    .any(|(header, &follow)| select_branches(&structure, header, follow).is_some());
+            let select_local = if has_selects {
+                let locals = Arc::get_mut(&mut locals).unwrap();
+                Some(locals.add_scratch(ValType::I32))
+            } else {
+                None
+            };
+
+            // Visit control flow graph to produce WebAssembly instructions,
+            // pre-allocating 1.25x the number of JVM instructions for WebAssembly ones
+            let mut out = Vec::with_capacity(((len as f32) * 1.25) as usize);
+            let visitor = Visitor {
+                const_pool: Arc::clone(&f.const_pool),
+                locals: Arc::clone(&locals),
+                code: structure,
+                stack_map: Arc::clone(&f.stack_map),
+                assertions: options.assertions,
+                on_unsupported: options.on_unsupported,
+                loop_rotate: options.loop_rotate,
+                select_local,
+                replaced: RefCell::new(vec![]),
+            };
+            visitor.visit_all(&mut out)?;
+            let unsupported = visitor.replaced.take();
 
-        let (code, locals) = match f.code.lock().unwrap().take() {
-            // Compile code if this is a non-native/abstract function
-            Some(code) => {
-                // Remap locals
-                let is_static = f.flags.contains(MethodAccessFlags::STATIC);
-                let locals = Arc::new(LocalInterpretation::from_code(
-                    is_static,
-                    &f.descriptor.params,
-                    &code,
-                ));
-
-                // Structure the function's code
-                let len = code.len();
-                let structure = structure_code(code, self.graphs_dir.as_ref())?;
-
-                // Visit control flow graph to produce WebAssembly instructions,
-                // pre-allocating 1.25x the number of JVM instructions for WebAssembly ones
-                let mut out = Vec::with_capacity(((len as f32) * 1.25) as usize);
-                let visitor = Visitor {
-                    const_pool: Arc::clone(&f.const_pool),
-                    locals: Arc::clone(&locals),
-                    code: structure,
-                };
-                visitor.visit_all(&mut out)?;
-
-                // TODO (someday): instrument shadow stack here
-
-                (Some(out), Some(locals))
+            // Replace long comparisons immediately used for branching with i64 comparisons
+            fuse_long_comparisons(&mut out);
+
+            // Cache repeated field access receivers if enabled, the visitor's been dropped so
+            // we have the only reference to the locals and can allocate scratch locals
+            if options.cache_receivers {
+                drop(visitor);
+                let locals = Arc::get_mut(&mut locals).unwrap();
+                cache_receivers(&mut out, locals);
+            }
+
+            // Replace calls immediately followed by returns with tail calls if enabled
+            if options.tail_calls {
+                mark_tail_calls(&mut out, &f.descriptor.function_type.results);
             }
-            None => (None, None),
-        };
-
-        // Even if this function doesn't have code, convert it to a compiled function
-        let func = CompiledFunction {
-            id: f.id.clone(),
-            flags: f.flags,
-            descriptor: Arc::clone(&f.descriptor),
-            locals,
-            code,
-        };
-        Ok(func)
+
+            // Make sure we're not using more locals than WebAssembly functions allow
+            locals.check_limits()?;
+
+            // TODO (someday): instrument shadow stack here
+
+            (Some(out), Some(locals), unsupported)
+        }
+        // Concrete methods without code are only loaded with `--compat-mode`, so compile them
+        // to stubs that trap if they're ever called
+        None if !f
+            .flags
+            .intersects(MethodAccessFlags::NATIVE | MethodAccessFlags::ABSTRACT) =>
+        {
+            return Ok(compile_stub(f));
+        }
+        None => (None, None, vec![]),
+    };
+
+    // Even if this function doesn't have code, convert it to a compiled function
+    let func = CompiledFunction {
+        id: f.id.clone(),
+        flags: f.flags,
+        descriptor: Arc::clone(&f.descriptor),
+        source_file: f.source_file.clone(),
+        locals,
+        code,
+        unsupported,
+    };
+    Ok(func)
+}
+
+/// Compiles a JVM bytecode [`Function`] to a stub that traps if it's ever called, ignoring any code
+/// it has.
+fn compile_stub(f: &Function) -> CompiledFunction {
+    let is_static = f.flags.contains(MethodAccessFlags::STATIC);
+    let locals = LocalInterpretation::from_code(is_static, &f.descriptor.params, &[]);
+    let code = vec![
+        Instruction::I(WASMInstruction::Unreachable),
+        Instruction::I(WASMInstruction::End),
+    ];
+    CompiledFunction {
+        id: f.id.clone(),
+        flags: f.flags,
+        descriptor: Arc::clone(&f.descriptor),
+        source_file: f.source_file.clone(),
+        locals: Some(Arc::new(locals)),
+        code: Some(code),
+        unsupported: vec![],
     }
 }
+
+/// Returns the [`Unsupported`] feature that caused a function compilation error, if any.
+pub fn unsupported_cause(e: &anyhow::Error) -> Option<Unsupported> {
+    e.chain()
+        .find_map(|cause| cause.downcast_ref::<Unsupported>().copied())
+}
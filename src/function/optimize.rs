@@ -0,0 +1,276 @@
+use crate::class::FieldId;
+use crate::function::locals::LocalInterpretation;
+use crate::function::Instruction::{self, I};
+use std::collections::HashMap;
+use std::mem;
+use wasm_encoder::{Instruction as WASMInstruction, ValType};
+
+/// Receiver expression consisting of a local followed by a chain of field accesses, e.g. `this.a.b`
+/// is `(0, [a, b])`.
+type Receiver = (u32, Vec<FieldId>);
+
+/// Returns the receiver expression at the start of `window` and the number of instructions it
+/// occupies, if `window` starts with a field access through a chain of at least one other field
+/// access (e.g. `local.get 0; getfield a; getfield x` has receiver `this.a`).
+///
+/// The final field access in the chain uses the receiver rather than being part of it. Receivers
+/// that are just a `local.get` aren't returned, as caching them in a scratch local wouldn't remove
+/// any instructions.
+fn receiver_at(window: &[Instruction<'_>]) -> Option<(Receiver, usize)> {
+    let local_index = match window.first() {
+        Some(I(WASMInstruction::LocalGet(local_index))) => *local_index,
+        _ => return None,
+    };
+    let fields: Vec<_> = window[1..]
+        .iter()
+        .map_while(|instruction| match instruction {
+            Instruction::GetField(id) => Some(id.clone()),
+            _ => None,
+        })
+        .collect();
+    match fields.split_last() {
+        Some((_, chain)) if !chain.is_empty() => {
+            Some(((local_index, chain.to_vec()), 1 + chain.len()))
+        }
+        _ => None,
+    }
+}
+
+/// Returns `true` if `instruction` transfers control, meaning previously evaluated receivers may
+/// not have been evaluated on all paths reaching the next instruction.
+fn is_control(instruction: &WASMInstruction<'_>) -> bool {
+    matches!(
+        instruction,
+        WASMInstruction::Block(_)
+            | WASMInstruction::Loop(_)
+            | WASMInstruction::If(_)
+            | WASMInstruction::Else
+            | WASMInstruction::End
+            | WASMInstruction::Br(_)
+            | WASMInstruction::BrIf(_)
+            | WASMInstruction::BrTable(_, _)
+            | WASMInstruction::Return
+            | WASMInstruction::Unreachable
+    )
+}
+
+/// Common subexpression elimination pass over a function's visited (pseudo-)instructions, caching
+/// receivers of field accesses repeated within a straight-line sequence of instructions in scratch
+/// locals allocated from `locals`.
+///
+/// For example, `a.x + a.y` where `a` is a field of `this` is visited as
+/// `local.get 0; getfield a; getfield x; local.get 0; getfield a; getfield y`. This becomes
+/// `local.get 0; getfield a; local.tee $s; getfield x; local.get $s; getfield y`.
+///
+/// Cached receivers are forgotten when their local is written to, when a field with the same name as
+/// one in their chain is written to on any object (as we don't know whether references alias, and
+/// the same field may be accessed through a reference of a sub- or superclass type), when any method
+/// is called (as it may write to fields), and at control flow instructions. See [`receiver_at`] for
+/// which receivers are cached.
+pub fn cache_receivers(code: &mut Vec<Instruction<'_>>, locals: &mut LocalInterpretation) {
+    // Find the start index and length of each occurrence of each cacheable receiver, grouping
+    // occurrences that evaluate to the same value
+    let mut groups: Vec<Vec<(usize, usize)>> = vec![];
+    let mut available: HashMap<Receiver, usize> = HashMap::new();
+    let mut i = 0;
+    while i < code.len() {
+        if let Some((receiver, len)) = receiver_at(&code[i..]) {
+            let group = *available.entry(receiver).or_insert_with(|| {
+                groups.push(vec![]);
+                groups.len() - 1
+            });
+            groups[group].push((i, len));
+            i += len;
+            continue;
+        }
+
+        // Forget any receivers this instruction may change the value of
+        match &code[i] {
+            I(WASMInstruction::LocalSet(local_index) | WASMInstruction::LocalTee(local_index)) => {
+                available.retain(|(receiver_local_index, _), _| receiver_local_index != local_index)
+            }
+            // Field IDs include the static type of the reference, so compare by name instead
+            Instruction::PutField(id) => {
+                available.retain(|(_, chain), _| chain.iter().all(|field| field.name != id.name))
+            }
+            Instruction::CallStatic(_) | Instruction::CallVirtual(_) => available.clear(),
+            Instruction::Throw => available.clear(),
+            I(instruction) if is_control(instruction) => available.clear(),
+            _ => {}
+        }
+        i += 1;
+    }
+
+    // Allocate a scratch local for each repeated receiver, storing it after its first evaluation
+    // and loading it in place of subsequent evaluations
+    let mut tees = HashMap::new();
+    let mut reuses = HashMap::new();
+    for occurrences in groups
+        .into_iter()
+        .filter(|occurrences| occurrences.len() > 1)
+    {
+        let scratch_local = locals.add_scratch(ValType::I32);
+        let (first_start, first_len) = occurrences[0];
+        tees.insert(first_start + first_len - 1, scratch_local);
+        for &(start, len) in &occurrences[1..] {
+            reuses.insert(start, (len, scratch_local));
+        }
+    }
+    if tees.is_empty() {
+        return;
+    }
+
+    let visited = mem::take(code);
+    let mut skip = 0;
+    for (i, instruction) in visited.into_iter().enumerate() {
+        if skip > 0 {
+            skip -= 1;
+        } else if let Some(&(len, scratch_local)) = reuses.get(&i) {
+            code.push(I(WASMInstruction::LocalGet(scratch_local)));
+            skip = len - 1;
+        } else {
+            code.push(instruction);
+            if let Some(&scratch_local) = tees.get(&i) {
+                code.push(I(WASMInstruction::LocalTee(scratch_local)));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::class::{FieldDescriptor, FieldId};
+    use crate::function::locals::LocalInterpretation;
+    use crate::function::optimize::cache_receivers;
+    use crate::function::Instruction::{self, I};
+    use crate::tests::str_arc;
+    use classfile_parser::code_attribute::Instruction as JVMInstruction;
+    use std::sync::Arc;
+    use wasm_encoder::{BlockType, Instruction as WASMInstruction};
+
+    fn field_of(class_name: &str, name: &str) -> FieldId {
+        FieldId {
+            class_name: str_arc(class_name),
+            name: str_arc(name),
+            descriptor: Arc::new(FieldDescriptor::Int),
+        }
+    }
+
+    fn field(name: &str) -> FieldId {
+        field_of("Test", name)
+    }
+
+    #[test]
+    fn cache_receivers_repeated() {
+        let code = [(0, JVMInstruction::Aload0)];
+        let mut locals = LocalInterpretation::from_code(true, &[], &code);
+        let mut code = vec![
+            I(WASMInstruction::LocalGet(0)),
+            Instruction::GetField(field("a")),
+            Instruction::GetField(field("x")),
+            I(WASMInstruction::LocalGet(0)),
+            Instruction::GetField(field("a")),
+            Instruction::GetField(field("y")),
+            I(WASMInstruction::I32Add),
+            I(WASMInstruction::LocalGet(0)),
+            Instruction::GetField(field("a")),
+            Instruction::GetField(field("x")),
+            I(WASMInstruction::I32Add),
+        ];
+        cache_receivers(&mut code, &mut locals);
+        assert_eq!(
+            code,
+            vec![
+                I(WASMInstruction::LocalGet(0)),
+                Instruction::GetField(field("a")),
+                I(WASMInstruction::LocalTee(1)),
+                Instruction::GetField(field("x")),
+                I(WASMInstruction::LocalGet(1)),
+                Instruction::GetField(field("y")),
+                I(WASMInstruction::I32Add),
+                I(WASMInstruction::LocalGet(1)),
+                Instruction::GetField(field("x")),
+                I(WASMInstruction::I32Add),
+            ]
+        );
+        assert_eq!(locals.len(), 2);
+    }
+
+    #[test]
+    fn cache_receivers_invalidated() {
+        let code = [(0, JVMInstruction::Aload0)];
+        let mut locals = LocalInterpretation::from_code(true, &[], &code);
+        let receiver = || {
+            vec![
+                I(WASMInstruction::LocalGet(0)),
+                Instruction::GetField(field("a")),
+                Instruction::GetField(field("x")),
+                I(WASMInstruction::Drop),
+            ]
+        };
+        let visited = || {
+            let mut code = vec![];
+            // Receivers that are just locals aren't cached
+            code.extend([
+                I(WASMInstruction::LocalGet(0)),
+                Instruction::GetField(field("x")),
+                I(WASMInstruction::LocalGet(0)),
+                Instruction::GetField(field("x")),
+            ]);
+            // Writing to the local, a field in the chain, or branching forgets the receiver
+            code.extend(receiver());
+            code.extend([
+                I(WASMInstruction::I32Const(0)),
+                I(WASMInstruction::LocalSet(0)),
+            ]);
+            code.extend(receiver());
+            code.extend([
+                I(WASMInstruction::LocalGet(0)),
+                I(WASMInstruction::I32Const(0)),
+                Instruction::PutField(field("a")),
+            ]);
+            code.extend(receiver());
+            code.push(I(WASMInstruction::Block(BlockType::Empty)));
+            code.extend(receiver());
+            code.push(I(WASMInstruction::End));
+            code.extend(receiver());
+            code
+        };
+
+        let mut code = visited();
+        cache_receivers(&mut code, &mut locals);
+        assert_eq!(code, visited());
+        assert_eq!(locals.len(), 1);
+    }
+
+    #[test]
+    fn cache_receivers_invalidated_through_other_class() {
+        let code = [(0, JVMInstruction::Aload0), (1, JVMInstruction::Aload1)];
+        let mut locals = LocalInterpretation::from_code(true, &[], &code);
+        let receiver = || {
+            vec![
+                I(WASMInstruction::LocalGet(0)),
+                Instruction::GetField(field("a")),
+                Instruction::GetField(field("x")),
+                I(WASMInstruction::Drop),
+            ]
+        };
+        let visited = || {
+            // Writing to `a` through a reference typed as a subclass of `Test` writes to the same
+            // memory, so forgets the receiver, even though the field IDs differ
+            let mut code = receiver();
+            code.extend([
+                I(WASMInstruction::LocalGet(1)),
+                I(WASMInstruction::I32Const(0)),
+                Instruction::PutField(field_of("Sub", "a")),
+            ]);
+            code.extend(receiver());
+            code
+        };
+
+        let mut code = visited();
+        cache_receivers(&mut code, &mut locals);
+        assert_eq!(code, visited());
+        assert_eq!(locals.len(), 2);
+    }
+}
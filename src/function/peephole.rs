@@ -0,0 +1,183 @@
+use crate::function::Instruction::{self, I};
+use std::iter;
+use wasm_encoder::{Instruction as WASMInstruction, ValType};
+
+/// Returns the single `i64` comparison equivalent to comparing the result of a
+/// [`Instruction::LongCmp`] with `0`, and the number of instructions it replaces, if `window`
+/// starts with such a sequence.
+///
+/// The visitor lowers `Lcmp` to `LongCmp`, producing -1, 0 or 1, then lowers the following
+/// `if<cond>` instruction to a comparison of this result with `0` (e.g. `Iflt` becomes
+/// `i32.const 0; i32.lt_s`). The sign of `LongCmp`'s result matches the ordering of its operands, so
+/// these can be replaced with a direct comparison of the operands (e.g. `i64.lt_s`).
+fn fuse_long_comparison(window: &[Instruction<'_>]) -> Option<(usize, WASMInstruction<'static>)> {
+    match window {
+        [Instruction::LongCmp, I(WASMInstruction::I32Eqz), ..] => Some((2, WASMInstruction::I64Eq)),
+        [Instruction::LongCmp, I(WASMInstruction::I32Const(0)), I(op), ..] => {
+            let fused = match op {
+                WASMInstruction::I32Neq => WASMInstruction::I64Neq,
+                WASMInstruction::I32LtS => WASMInstruction::I64LtS,
+                WASMInstruction::I32GeS => WASMInstruction::I64GeS,
+                WASMInstruction::I32GtS => WASMInstruction::I64GtS,
+                WASMInstruction::I32LeS => WASMInstruction::I64LeS,
+                _ => return None,
+            };
+            Some((3, fused))
+        }
+        _ => None,
+    }
+}
+
+/// Peephole optimisation pass over a function's visited (pseudo-)instructions, replacing
+/// `LongCmp` pseudo-instructions immediately followed by a conditional branch's comparison with a
+/// single `i64` comparison. This avoids calling the `!LongCmp` built-in function for the most
+/// common use of `Lcmp`. See [`fuse_long_comparison`] for more details.
+pub fn fuse_long_comparisons(code: &mut Vec<Instruction<'_>>) {
+    let mut i = 0;
+    while i < code.len() {
+        if let Some((len, fused)) = fuse_long_comparison(&code[i..]) {
+            code.splice(i..i + len, iter::once(I(fused)));
+        }
+        i += 1;
+    }
+}
+
+/// Peephole pass replacing calls immediately followed by a `return` with tail calls (see
+/// [`Instruction::ReturnCallStatic`] and [`Instruction::ReturnCallVirtual`]), so deeply recursive
+/// methods don't overflow the stack. `results` are the result types of the function containing
+/// `code`.
+///
+/// Tail calls must return exactly the same types as the calling function. These always match when
+/// returning a call's result, but calls returning a value may be followed by a `return` in a
+/// `void` method if the value is left on the operand stack, so these are left alone. Calls whose
+/// results are used by another instruction first (e.g. `return f(n) + 1`), or that only reach a
+/// `return` through a branch, aren't in tail position.
+pub fn mark_tail_calls(code: &mut Vec<Instruction<'_>>, results: &[ValType]) {
+    let mut i = 0;
+    while i + 1 < code.len() {
+        if let [call, I(WASMInstruction::Return)] = &code[i..i + 2] {
+            let tail_call = match call {
+                Instruction::CallStatic(id) if id.descriptor.function_type.results == results => {
+                    Some(Instruction::ReturnCallStatic(id.clone()))
+                }
+                Instruction::CallVirtual(id) if id.descriptor.function_type.results == results => {
+                    Some(Instruction::ReturnCallVirtual(id.clone()))
+                }
+                _ => None,
+            };
+            if let Some(tail_call) = tail_call {
+                code.splice(i..i + 2, iter::once(tail_call));
+            }
+        }
+        i += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::class::{FieldDescriptor, MethodDescriptor, MethodId, ReturnDescriptor};
+    use crate::function::peephole::{fuse_long_comparisons, mark_tail_calls};
+    use crate::function::Instruction::{self, I};
+    use crate::tests::str_arc;
+    use std::sync::Arc;
+    use wasm_encoder::{Instruction as WASMInstruction, ValType};
+
+    fn method(name: &str, returns: ReturnDescriptor) -> MethodId {
+        MethodId {
+            class_name: str_arc("Test"),
+            name: str_arc(name),
+            descriptor: Arc::new(MethodDescriptor::new(vec![FieldDescriptor::Int], returns)),
+        }
+    }
+
+    #[test]
+    fn fuse_long_comparisons_only_fuses_branches() {
+        let mut code = vec![
+            I(WASMInstruction::LocalGet(0)),
+            I(WASMInstruction::LocalGet(1)),
+            Instruction::LongCmp,
+            I(WASMInstruction::I32Const(0)),
+            I(WASMInstruction::I32LtS),
+            I(WASMInstruction::LocalGet(0)),
+            I(WASMInstruction::LocalGet(1)),
+            Instruction::LongCmp,
+            I(WASMInstruction::I32Eqz),
+            // Not a comparison with 0, so must be left alone
+            I(WASMInstruction::LocalGet(0)),
+            I(WASMInstruction::LocalGet(1)),
+            Instruction::LongCmp,
+            I(WASMInstruction::I32Const(1)),
+            I(WASMInstruction::I32Add),
+        ];
+        fuse_long_comparisons(&mut code);
+        assert_eq!(
+            code,
+            vec![
+                I(WASMInstruction::LocalGet(0)),
+                I(WASMInstruction::LocalGet(1)),
+                I(WASMInstruction::I64LtS),
+                I(WASMInstruction::LocalGet(0)),
+                I(WASMInstruction::LocalGet(1)),
+                I(WASMInstruction::I64Eq),
+                I(WASMInstruction::LocalGet(0)),
+                I(WASMInstruction::LocalGet(1)),
+                Instruction::LongCmp,
+                I(WASMInstruction::I32Const(1)),
+                I(WASMInstruction::I32Add),
+            ]
+        );
+    }
+
+    #[test]
+    fn mark_tail_calls_only_marks_matching_returns() {
+        let int_method = method("f", ReturnDescriptor::Field(FieldDescriptor::Int));
+        let void_method = method("g", ReturnDescriptor::Void);
+        let code = || {
+            vec![
+                I(WASMInstruction::LocalGet(0)),
+                Instruction::CallStatic(int_method.clone()),
+                I(WASMInstruction::Return),
+                I(WASMInstruction::LocalGet(0)),
+                Instruction::CallVirtual(int_method.clone()),
+                I(WASMInstruction::Return),
+                // Result is used before returning, so not in tail position
+                I(WASMInstruction::LocalGet(0)),
+                Instruction::CallStatic(int_method.clone()),
+                I(WASMInstruction::I32Const(1)),
+                I(WASMInstruction::I32Add),
+                I(WASMInstruction::Return),
+                I(WASMInstruction::LocalGet(0)),
+                Instruction::CallStatic(void_method.clone()),
+                I(WASMInstruction::Return),
+            ]
+        };
+
+        let mut int_code = code();
+        mark_tail_calls(&mut int_code, &[ValType::I32]);
+        assert_eq!(
+            int_code,
+            vec![
+                I(WASMInstruction::LocalGet(0)),
+                Instruction::ReturnCallStatic(int_method.clone()),
+                I(WASMInstruction::LocalGet(0)),
+                Instruction::ReturnCallVirtual(int_method.clone()),
+                I(WASMInstruction::LocalGet(0)),
+                Instruction::CallStatic(int_method.clone()),
+                I(WASMInstruction::I32Const(1)),
+                I(WASMInstruction::I32Add),
+                I(WASMInstruction::Return),
+                // Doesn't return the calling function's result type
+                I(WASMInstruction::LocalGet(0)),
+                Instruction::CallStatic(void_method.clone()),
+                I(WASMInstruction::Return),
+            ]
+        );
+
+        // In a void function, only calls returning nothing can be tail calls
+        let mut void_code = code();
+        mark_tail_calls(&mut void_code, &[]);
+        let mut expected = code();
+        expected.splice(12..14, vec![Instruction::ReturnCallStatic(void_method)]);
+        assert_eq!(void_code, expected);
+    }
+}
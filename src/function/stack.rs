@@ -0,0 +1,459 @@
+use crate::class::{Constant, ConstantPool, NumericConstant, ReturnDescriptor};
+use crate::function::StackMap;
+use classfile_parser::code_attribute::Instruction as JVMInstruction;
+use wasm_encoder::ValType;
+
+/// Effect of a JVM instruction on the operand stack, returned by [`stack_effect`].
+enum StackEffect {
+    /// Pops the specified number of values (regardless of their category), then optionally pushes
+    /// a value of the specified type.
+    PopPush(usize, Option<ValType>),
+    /// Effect depends on the types of values on the stack. See [`StackTypes::update`].
+    Dynamic,
+    /// Effect can't be determined, so all tracked types must be forgotten.
+    Unknown,
+}
+
+/// Returns the effect of a JVM instruction on the operand stack. Category 2 computational types
+/// (`long`s and `double`s) are treated as single values, like WebAssembly values.
+fn stack_effect(const_pool: &ConstantPool, instruction: &JVMInstruction) -> StackEffect {
+    use StackEffect::*;
+    match instruction {
+        // Constants and loads
+        JVMInstruction::Aconstnull
+        | JVMInstruction::Aload(_)
+        | JVMInstruction::AloadWide(_)
+        | JVMInstruction::Aload0
+        | JVMInstruction::Aload1
+        | JVMInstruction::Aload2
+        | JVMInstruction::Aload3
+        | JVMInstruction::Bipush(_)
+        | JVMInstruction::Sipush(_)
+        | JVMInstruction::Iconstm1
+        | JVMInstruction::Iconst0
+        | JVMInstruction::Iconst1
+        | JVMInstruction::Iconst2
+        | JVMInstruction::Iconst3
+        | JVMInstruction::Iconst4
+        | JVMInstruction::Iconst5
+        | JVMInstruction::Iload(_)
+        | JVMInstruction::IloadWide(_)
+        | JVMInstruction::Iload0
+        | JVMInstruction::Iload1
+        | JVMInstruction::Iload2
+        | JVMInstruction::Iload3
+        | JVMInstruction::New(_)
+        | JVMInstruction::Jsr(_)
+        | JVMInstruction::JsrW(_) => PopPush(0, Some(ValType::I32)),
+        JVMInstruction::Lconst0
+        | JVMInstruction::Lconst1
+        | JVMInstruction::Lload(_)
+        | JVMInstruction::LloadWide(_)
+        | JVMInstruction::Lload0
+        | JVMInstruction::Lload1
+        | JVMInstruction::Lload2
+        | JVMInstruction::Lload3 => PopPush(0, Some(ValType::I64)),
+        JVMInstruction::Fconst0
+        | JVMInstruction::Fconst1
+        | JVMInstruction::Fconst2
+        | JVMInstruction::Fload(_)
+        | JVMInstruction::FloadWide(_)
+        | JVMInstruction::Fload0
+        | JVMInstruction::Fload1
+        | JVMInstruction::Fload2
+        | JVMInstruction::Fload3 => PopPush(0, Some(ValType::F32)),
+        JVMInstruction::Dconst0
+        | JVMInstruction::Dconst1
+        | JVMInstruction::Dload(_)
+        | JVMInstruction::DloadWide(_)
+        | JVMInstruction::Dload0
+        | JVMInstruction::Dload1
+        | JVMInstruction::Dload2
+        | JVMInstruction::Dload3 => PopPush(0, Some(ValType::F64)),
+        JVMInstruction::Ldc(n) => ldc_effect(const_pool, *n as u16),
+        JVMInstruction::LdcW(n) | JVMInstruction::Ldc2W(n) => ldc_effect(const_pool, *n),
+
+        // Stores, returns and single operand branches
+        JVMInstruction::Astore(_)
+        | JVMInstruction::AstoreWide(_)
+        | JVMInstruction::Astore0
+        | JVMInstruction::Astore1
+        | JVMInstruction::Astore2
+        | JVMInstruction::Astore3
+        | JVMInstruction::Istore(_)
+        | JVMInstruction::IstoreWide(_)
+        | JVMInstruction::Istore0
+        | JVMInstruction::Istore1
+        | JVMInstruction::Istore2
+        | JVMInstruction::Istore3
+        | JVMInstruction::Lstore(_)
+        | JVMInstruction::LstoreWide(_)
+        | JVMInstruction::Lstore0
+        | JVMInstruction::Lstore1
+        | JVMInstruction::Lstore2
+        | JVMInstruction::Lstore3
+        | JVMInstruction::Fstore(_)
+        | JVMInstruction::FstoreWide(_)
+        | JVMInstruction::Fstore0
+        | JVMInstruction::Fstore1
+        | JVMInstruction::Fstore2
+        | JVMInstruction::Fstore3
+        | JVMInstruction::Dstore(_)
+        | JVMInstruction::DstoreWide(_)
+        | JVMInstruction::Dstore0
+        | JVMInstruction::Dstore1
+        | JVMInstruction::Dstore2
+        | JVMInstruction::Dstore3
+        | JVMInstruction::Areturn
+        | JVMInstruction::Ireturn
+        | JVMInstruction::Lreturn
+        | JVMInstruction::Freturn
+        | JVMInstruction::Dreturn
+        | JVMInstruction::Athrow
+        | JVMInstruction::Ifeq(_)
+        | JVMInstruction::Ifne(_)
+        | JVMInstruction::Iflt(_)
+        | JVMInstruction::Ifge(_)
+        | JVMInstruction::Ifgt(_)
+        | JVMInstruction::Ifle(_)
+        | JVMInstruction::Ifnonnull(_)
+        | JVMInstruction::Ifnull(_)
+        | JVMInstruction::Lookupswitch { .. }
+        | JVMInstruction::Tableswitch { .. }
+        | JVMInstruction::Monitorenter
+        | JVMInstruction::Monitorexit
+        | JVMInstruction::Pop
+        | JVMInstruction::Putstatic(_) => PopPush(1, None),
+
+        // Two operand branches and field writes
+        JVMInstruction::IfAcmpeq(_)
+        | JVMInstruction::IfAcmpne(_)
+        | JVMInstruction::IfIcmpeq(_)
+        | JVMInstruction::IfIcmpne(_)
+        | JVMInstruction::IfIcmplt(_)
+        | JVMInstruction::IfIcmpge(_)
+        | JVMInstruction::IfIcmpgt(_)
+        | JVMInstruction::IfIcmple(_)
+        | JVMInstruction::Putfield(_) => PopPush(2, None),
+
+        // Array stores
+        JVMInstruction::Aastore
+        | JVMInstruction::Bastore
+        | JVMInstruction::Castore
+        | JVMInstruction::Dastore
+        | JVMInstruction::Fastore
+        | JVMInstruction::Iastore
+        | JVMInstruction::Lastore
+        | JVMInstruction::Sastore => PopPush(3, None),
+
+        // Instructions not affecting the stack
+        JVMInstruction::Goto(_)
+        | JVMInstruction::GotoW(_)
+        | JVMInstruction::Iinc { .. }
+        | JVMInstruction::IincWide { .. }
+        | JVMInstruction::Nop
+        | JVMInstruction::Ret(_)
+        | JVMInstruction::RetWide(_)
+        | JVMInstruction::Return => PopPush(0, None),
+
+        // Unary operations, conversions and type checks
+        JVMInstruction::Ineg
+        | JVMInstruction::I2b
+        | JVMInstruction::I2c
+        | JVMInstruction::I2s
+        | JVMInstruction::L2i
+        | JVMInstruction::F2i
+        | JVMInstruction::D2i
+        | JVMInstruction::Arraylength
+        | JVMInstruction::Anewarray(_)
+        | JVMInstruction::Newarray(_)
+        | JVMInstruction::Checkcast(_)
+        | JVMInstruction::Instanceof(_) => PopPush(1, Some(ValType::I32)),
+        JVMInstruction::Lneg | JVMInstruction::I2l | JVMInstruction::F2l | JVMInstruction::D2l => {
+            PopPush(1, Some(ValType::I64))
+        }
+        JVMInstruction::Fneg | JVMInstruction::I2f | JVMInstruction::L2f | JVMInstruction::D2f => {
+            PopPush(1, Some(ValType::F32))
+        }
+        JVMInstruction::Dneg | JVMInstruction::I2d | JVMInstruction::L2d | JVMInstruction::F2d => {
+            PopPush(1, Some(ValType::F64))
+        }
+
+        // Binary operations, comparisons and array loads
+        JVMInstruction::Iadd
+        | JVMInstruction::Isub
+        | JVMInstruction::Imul
+        | JVMInstruction::Idiv
+        | JVMInstruction::Irem
+        | JVMInstruction::Iand
+        | JVMInstruction::Ior
+        | JVMInstruction::Ixor
+        | JVMInstruction::Ishl
+        | JVMInstruction::Ishr
+        | JVMInstruction::Iushr
+        | JVMInstruction::Lcmp
+        | JVMInstruction::Fcmpg
+        | JVMInstruction::Fcmpl
+        | JVMInstruction::Dcmpg
+        | JVMInstruction::Dcmpl
+        | JVMInstruction::Aaload
+        | JVMInstruction::Baload
+        | JVMInstruction::Caload
+        | JVMInstruction::Iaload
+        | JVMInstruction::Saload => PopPush(2, Some(ValType::I32)),
+        JVMInstruction::Ladd
+        | JVMInstruction::Lsub
+        | JVMInstruction::Lmul
+        | JVMInstruction::Ldiv
+        | JVMInstruction::Lrem
+        | JVMInstruction::Land
+        | JVMInstruction::Lor
+        | JVMInstruction::Lxor
+        | JVMInstruction::Lshl
+        | JVMInstruction::Lshr
+        | JVMInstruction::Lushr
+        | JVMInstruction::Laload => PopPush(2, Some(ValType::I64)),
+        JVMInstruction::Fadd
+        | JVMInstruction::Fsub
+        | JVMInstruction::Fmul
+        | JVMInstruction::Fdiv
+        | JVMInstruction::Frem
+        | JVMInstruction::Faload => PopPush(2, Some(ValType::F32)),
+        JVMInstruction::Dadd
+        | JVMInstruction::Dsub
+        | JVMInstruction::Dmul
+        | JVMInstruction::Ddiv
+        | JVMInstruction::Drem
+        | JVMInstruction::Daload => PopPush(2, Some(ValType::F64)),
+        JVMInstruction::Multianewarray { dimensions, .. } => {
+            PopPush(*dimensions as usize, Some(ValType::I32))
+        }
+
+        // Fields and methods
+        JVMInstruction::Getfield(n) => PopPush(1, Some(const_pool.field(*n).descriptor.as_type())),
+        JVMInstruction::Getstatic(n) => PopPush(0, Some(const_pool.field(*n).descriptor.as_type())),
+        JVMInstruction::Invokestatic(n)
+        | JVMInstruction::Invokespecial(n)
+        | JVMInstruction::Invokevirtual(n) => {
+            let id = const_pool.method(*n);
+            // Include implicit this parameter for instance methods
+            let this = !matches!(instruction, JVMInstruction::Invokestatic(_)) as usize;
+            let result = match &id.descriptor.returns {
+                ReturnDescriptor::Void => None,
+                ReturnDescriptor::Field(field_type) => Some(field_type.as_type()),
+            };
+            PopPush(id.descriptor.params.len() + this, result)
+        }
+        JVMInstruction::Invokedynamic(_) | JVMInstruction::Invokeinterface { .. } => Unknown,
+
+        // Stack manipulation
+        JVMInstruction::Dup | JVMInstruction::Dup2 | JVMInstruction::Pop2 => Dynamic,
+        // TODO (someday): track these once the visitor supports them
+        JVMInstruction::Dupx1
+        | JVMInstruction::Dupx2
+        | JVMInstruction::Dup2x1
+        | JVMInstruction::Dup2x2
+        | JVMInstruction::Swap => Unknown,
+    }
+}
+
+/// Returns the number of values popped and the type of value pushed (if any) by `instruction`, if
+/// these don't depend on the stack and `instruction` doesn't transfer control. Used for replacing
+/// unsupported instructions with placeholders that keep the stack balanced.
+pub fn fixed_stack_effect(
+    const_pool: &ConstantPool,
+    instruction: &JVMInstruction,
+) -> Option<(usize, Option<ValType>)> {
+    match instruction {
+        // Subroutines and switches branch, so replacing them with straight-line code would be wrong
+        JVMInstruction::Jsr(_)
+        | JVMInstruction::JsrW(_)
+        | JVMInstruction::Ret(_)
+        | JVMInstruction::RetWide(_)
+        | JVMInstruction::Lookupswitch { .. }
+        | JVMInstruction::Tableswitch { .. } => None,
+        _ => match stack_effect(const_pool, instruction) {
+            StackEffect::PopPush(pops, push) => Some((pops, push)),
+            StackEffect::Dynamic | StackEffect::Unknown => None,
+        },
+    }
+}
+
+/// Returns the types of values left on the operand stack by a straight-line sequence of
+/// `instructions`, with the top of the stack last, if they have fixed stack effects and don't
+/// consume values already on the stack before the sequence. WebAssembly blocks can't access values
+/// outside them (without block parameters), so this is used to check whether a conditional's
+/// branches can be translated into a value-producing `if` block.
+pub fn pushed_types<'a>(
+    const_pool: &ConstantPool,
+    instructions: impl IntoIterator<Item = &'a JVMInstruction>,
+) -> Option<Vec<ValType>> {
+    let mut pushed = vec![];
+    for instruction in instructions {
+        let (pops, push) = fixed_stack_effect(const_pool, instruction)?;
+        let len = pushed.len().checked_sub(pops)?;
+        pushed.truncate(len);
+        pushed.extend(push);
+    }
+    Some(pushed)
+}
+
+/// Returns the effect of loading the numeric constant at `index` in the constant pool. Other
+/// constants aren't supported by the visitor yet.
+fn ldc_effect(const_pool: &ConstantPool, index: u16) -> StackEffect {
+    let t = match const_pool.get(index) {
+        Constant::Number(NumericConstant::Integer(_)) => ValType::I32,
+        Constant::Number(NumericConstant::Long(_)) => ValType::I64,
+        Constant::Number(NumericConstant::Float(_)) => ValType::F32,
+        Constant::Number(NumericConstant::Double(_)) => ValType::F64,
+        // String and Class constants push references
+        Constant::String(_) | Constant::Class(_) => ValType::I32,
+        _ => return StackEffect::Unknown,
+    };
+    StackEffect::PopPush(0, Some(t))
+}
+
+/// Tracks the WebAssembly types of values on top of the JVM operand stack whilst visiting a basic
+/// block, for lowering instructions whose semantics depend on these types (e.g. `Dup`).
+///
+/// Only the types of values on top of the stack are known: at the start of a basic block, the
+/// entire stack is known if the block is a branch target with a frame in the method's
+/// `StackMapTable`, or it's the start of the method, where the stack is empty. Otherwise, values
+/// already on the stack are unknown, and only values pushed within the block are tracked.
+#[derive(Debug, Eq, PartialEq)]
+pub struct StackTypes {
+    /// Types of the known values on top of the stack, with the top of the stack last.
+    known: Vec<ValType>,
+}
+
+impl StackTypes {
+    /// Constructs a new `StackTypes` for the start of a basic block at bytecode `offset`, using the
+    /// block's frame in `stack_map` if any.
+    pub fn at_block(stack_map: &StackMap, offset: usize) -> Self {
+        let known = match stack_map.get(&offset) {
+            Some(frame) => frame.clone(),
+            None => vec![],
+        };
+        StackTypes { known }
+    }
+
+    /// Returns the type of the value on the top of the stack, if known.
+    pub fn top(&self) -> Option<ValType> {
+        self.known.last().copied()
+    }
+
+    /// Pops `n` values off the stack. Popping values beyond the known ones leaves the stack empty,
+    /// as anything underneath them is still unknown.
+    fn pop(&mut self, n: usize) {
+        let len = self.known.len().saturating_sub(n);
+        self.known.truncate(len);
+    }
+
+    /// Updates tracked types to reflect the stack after executing `instruction`.
+    pub fn update(&mut self, const_pool: &ConstantPool, instruction: &JVMInstruction) {
+        match stack_effect(const_pool, instruction) {
+            StackEffect::PopPush(pops, push) => {
+                self.pop(pops);
+                self.known.extend(push);
+            }
+            StackEffect::Dynamic => match (instruction, self.top()) {
+                // If the top of the stack is unknown, so is the duplicated value, and unknown
+                // values can't be tracked above known ones
+                (JVMInstruction::Dup, Some(t)) => self.known.push(t),
+                (JVMInstruction::Dup, None) => self.known.clear(),
+                // Dup2 duplicates a single category 2 value or two category 1 values
+                (JVMInstruction::Dup2, Some(t @ (ValType::I64 | ValType::F64))) => {
+                    self.known.push(t)
+                }
+                (JVMInstruction::Dup2, Some(_)) if self.known.len() >= 2 => {
+                    let len = self.known.len();
+                    self.known.extend_from_within(len - 2..);
+                }
+                // Pop2 pops a single category 2 value or two category 1 values
+                (JVMInstruction::Pop2, Some(ValType::I64 | ValType::F64)) => self.pop(1),
+                (JVMInstruction::Pop2, Some(_)) => self.pop(2),
+                _ => self.known.clear(),
+            },
+            StackEffect::Unknown => self.known.clear(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::class::ConstantPool;
+    use crate::function::stack::{pushed_types, StackTypes};
+    use classfile_parser::code_attribute::Instruction as JVMInstruction;
+    use std::collections::HashMap;
+    use wasm_encoder::ValType;
+
+    #[test]
+    fn stack_types_from_stack_map() {
+        let const_pool = ConstantPool::new(vec![]);
+        let stack_map = hashmap! { 8 => vec![ValType::I32, ValType::F32] };
+
+        // Check frames seed stack at branch targets
+        let mut stack = StackTypes::at_block(&stack_map, 8);
+        assert_eq!(stack.top(), Some(ValType::F32));
+        stack.update(&const_pool, &JVMInstruction::Dup);
+        assert_eq!(stack.known, vec![ValType::I32, ValType::F32, ValType::F32]);
+        stack.update(&const_pool, &JVMInstruction::Fadd);
+        stack.update(&const_pool, &JVMInstruction::F2d);
+        assert_eq!(stack.known, vec![ValType::I32, ValType::F64]);
+        stack.update(&const_pool, &JVMInstruction::Pop2);
+        assert_eq!(stack.top(), Some(ValType::I32));
+        stack.update(&const_pool, &JVMInstruction::Iconst0);
+        stack.update(&const_pool, &JVMInstruction::Pop2);
+        assert_eq!(stack.top(), None);
+
+        // Check Dup2 duplicates category 2 values, or pairs of known category 1 values
+        let mut stack = StackTypes::at_block(&stack_map, 8);
+        stack.update(&const_pool, &JVMInstruction::Dup2);
+        assert_eq!(
+            stack.known,
+            vec![ValType::I32, ValType::F32, ValType::I32, ValType::F32]
+        );
+        stack.update(&const_pool, &JVMInstruction::Lconst0);
+        stack.update(&const_pool, &JVMInstruction::Dup2);
+        assert_eq!(stack.known[4..], [ValType::I64, ValType::I64]);
+        let mut stack = StackTypes::at_block(&HashMap::new(), 4);
+        stack.update(&const_pool, &JVMInstruction::Iconst0);
+        stack.update(&const_pool, &JVMInstruction::Dup2);
+        assert_eq!(stack.top(), None);
+
+        // Check values pushed in blocks without frames are tracked, but nothing underneath
+        let mut stack = StackTypes::at_block(&HashMap::new(), 4);
+        assert_eq!(stack.top(), None);
+        stack.update(&const_pool, &JVMInstruction::Lload0);
+        assert_eq!(stack.top(), Some(ValType::I64));
+        stack.update(&const_pool, &JVMInstruction::Lstore1);
+        stack.update(&const_pool, &JVMInstruction::Dup);
+        assert_eq!(stack.top(), None);
+    }
+
+    #[test]
+    fn pushed_types_sequences() {
+        let const_pool = ConstantPool::new(vec![]);
+
+        // Check values pushed and consumed within the sequence are tracked...
+        let instructions = [
+            JVMInstruction::Iload0,
+            JVMInstruction::Iconst2,
+            JVMInstruction::Imul,
+            JVMInstruction::I2l,
+            JVMInstruction::Goto(3),
+        ];
+        assert_eq!(
+            pushed_types(&const_pool, &instructions),
+            Some(vec![ValType::I64])
+        );
+        assert_eq!(pushed_types(&const_pool, &[]), Some(vec![]));
+
+        // ...but sequences consuming values from before them, or with effects depending on the
+        // stack, are rejected
+        let instructions = [JVMInstruction::Iconst1, JVMInstruction::Iadd];
+        assert_eq!(pushed_types(&const_pool, &instructions), None);
+        let instructions = [JVMInstruction::Iconst1, JVMInstruction::Dup];
+        assert_eq!(pushed_types(&const_pool, &instructions), None);
+    }
+}
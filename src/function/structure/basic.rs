@@ -1,14 +1,18 @@
 use crate::function::structure::ConditionalKind;
-use crate::graph::{remove_element, Graph, NodeId, NodeOrder, Order};
+use crate::function::ExceptionHandler;
+use crate::graph::{remove_element, Graph, Node, NodeId, NodeOrder, NodeSet, Order};
 use classfile_parser::code_attribute::Instruction as JVMInstruction;
+use itertools::Itertools;
 use std::collections::HashMap;
 use std::fmt;
+use std::mem::take;
 
 /// Node value for control flow graphs, either a basic block or compound conditional.
 #[derive(Eq, PartialEq)]
 pub enum Structure {
-    /// Basic block consisting of a sequence of instructions executed in order.
-    Block(Vec<JVMInstruction>),
+    /// Basic block consisting of a sequence of instructions executed in order, with their original
+    /// JVM bytecode labels (offsets).
+    Block(Vec<(usize, JVMInstruction)>),
     /// Short-circuit conditional, `left` is always evaluated, but `right` may not be evaluated
     /// if the result of the conditional can be determined from `left` only.
     CompoundConditional {
@@ -27,12 +31,18 @@ impl Default for Structure {
 }
 
 impl fmt::Debug for Structure {
+    /// Formats this structure for control flow graph visualisation. If the alternate flag is set
+    /// (`{:#?}`), each instruction will be prefixed with its JVM bytecode label, making it easier to
+    /// correlate with `javap -c` output.
     //noinspection RsLiveness
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Structure::Block(instructions) => {
                 let mut iter = instructions.iter().peekable();
-                while let Some(instruction) = iter.next() {
+                while let Some((label, instruction)) = iter.next() {
+                    if f.alternate() {
+                        write!(f, "{}: ", label)?;
+                    }
                     write!(f, "{:?}", instruction)?;
                     if iter.peek().is_some() {
                         write!(f, "\\n")?;
@@ -45,11 +55,15 @@ impl fmt::Debug for Structure {
                 left,
                 right,
             } => {
-                write!(
-                    f,
-                    "{left_negated}{{\n{left:?}\n}} {kind} {{\n{right:?}\n}}",
-                    left_negated = if *left_negated { "! " } else { "" },
-                )?;
+                let left_negated = if *left_negated { "! " } else { "" };
+                if f.alternate() {
+                    write!(
+                        f,
+                        "{left_negated}{{\n{left:#?}\n}} {kind} {{\n{right:#?}\n}}"
+                    )?;
+                } else {
+                    write!(f, "{left_negated}{{\n{left:?}\n}} {kind} {{\n{right:?}\n}}")?;
+                }
             }
         };
         Ok(())
@@ -60,6 +74,40 @@ impl fmt::Debug for Structure {
 /// [`Structure`]s for node values.
 pub type ControlFlowGraph = Graph<Structure>;
 
+/// Accessors for the successors of conditional nodes in [`ControlFlowGraph`]s. By convention,
+/// [`ControlFlowGraph::insert_basic_blocks`] adds the false branch (the instruction following the
+/// conditional) before the true branch (the target of the conditional), so
+/// `successors[0]` is the false branch and `successors[1]` is the true branch.
+impl Node<Structure> {
+    /// Returns whether this node is a 2-way conditional, with false and true branches.
+    #[inline]
+    pub fn is_conditional(&self) -> bool {
+        self.out_degree() == 2
+    }
+
+    /// Returns the successor followed if this node's condition is false, or [`None`] if this node
+    /// isn't a 2-way conditional.
+    #[inline]
+    pub fn false_branch(&self) -> Option<NodeId> {
+        if self.is_conditional() {
+            Some(self.successors[0])
+        } else {
+            None
+        }
+    }
+
+    /// Returns the successor followed if this node's condition is true, or [`None`] if this node
+    /// isn't a 2-way conditional.
+    #[inline]
+    pub fn true_branch(&self) -> Option<NodeId> {
+        if self.is_conditional() {
+            Some(self.successors[1])
+        } else {
+            None
+        }
+    }
+}
+
 /// Macro for matching on conditional, unconditional and not branching JVM instructions, at a
 /// specific label.
 ///
@@ -107,6 +155,105 @@ macro_rules! match_branches {
     };
 }
 
+/// Returns the absolute target label of a conditional or unconditional branch instruction at
+/// `label`, or [`None`] if the instruction isn't branching.
+pub fn branch_target(label: usize, instruction: &JVMInstruction) -> Option<usize> {
+    let mut result = None;
+    match_branches!(label, instruction, {
+        None => {},
+        Unconditional(target) => {
+            result = Some(target);
+        },
+        Conditional(target) => {
+            result = Some(target);
+        },
+    });
+    result
+}
+
+/// Returns whether control never falls through from `instruction` to the next instruction, i.e. it
+/// returns, throws, or unconditionally branches.
+fn is_terminal(instruction: &JVMInstruction) -> bool {
+    matches!(
+        instruction,
+        JVMInstruction::Goto(_)
+            | JVMInstruction::GotoW(_)
+            | JVMInstruction::Ireturn
+            | JVMInstruction::Lreturn
+            | JVMInstruction::Freturn
+            | JVMInstruction::Dreturn
+            | JVMInstruction::Areturn
+            | JVMInstruction::Return
+            | JVMInstruction::Athrow
+    )
+}
+
+/// Returns which instructions in `code` are reachable by following control flow from the
+/// instructions at the `start` labels, indexed like `code`, or [`None`] if this can't be determined
+/// as `code` contains switches or subroutines (which aren't supported yet).
+fn reachable_from(code: &[(usize, JVMInstruction)], start: &[usize]) -> Option<Vec<bool>> {
+    let indices: HashMap<usize, usize> = (code.iter().enumerate())
+        .map(|(i, (label, _))| (*label, i))
+        .collect();
+    let mut reachable = vec![false; code.len()];
+    let mut stack = start.iter().map(|label| indices[label]).collect::<Vec<_>>();
+    while let Some(i) = stack.pop() {
+        if i >= code.len() || reachable[i] {
+            continue;
+        }
+        reachable[i] = true;
+        let (label, instruction) = &code[i];
+        match instruction {
+            JVMInstruction::Tableswitch { .. }
+            | JVMInstruction::Lookupswitch { .. }
+            | JVMInstruction::Jsr(_)
+            | JVMInstruction::JsrW(_)
+            | JVMInstruction::Ret(_)
+            | JVMInstruction::RetWide(_) => return None,
+            _ => {}
+        }
+        if let Some(target) = branch_target(*label, instruction) {
+            stack.push(indices[&target]);
+        }
+        if !is_terminal(instruction) {
+            stack.push(i + 1);
+        }
+    }
+    Some(reachable)
+}
+
+/// Removes the code of `finally` handlers (catching all exceptions) in `handlers` from `code`, so
+/// `try`/`finally` statements can be structured. Handlers catching specific exceptions (`catch`
+/// clauses) aren't supported, so are left in place for the caller to reject.
+///
+/// Exceptions are not yet supported, so throwing (or failing a null check) traps instead, meaning
+/// handlers are never run. `javac` copies `finally` blocks onto every normal exit from the
+/// protected region (including `return`s inside `try`), so these still run the block. Handler code
+/// is only reachable through the exception table, and would otherwise be compiled as unreachable
+/// code expecting a thrown exception on the stack. Instructions reachable from a handler that
+/// aren't also reachable from the entrypoint are removed.
+pub fn remove_finally_handlers(
+    code: Vec<(usize, JVMInstruction)>,
+    handlers: &[ExceptionHandler],
+) -> Vec<(usize, JVMInstruction)> {
+    let handler_labels = (handlers.iter())
+        .filter(|handler| handler.catch_type.is_none())
+        .map(|handler| handler.handler)
+        .collect_vec();
+    if handler_labels.is_empty() {
+        return code;
+    }
+    let reachable = reachable_from(&code, &[0]).zip(reachable_from(&code, &handler_labels));
+    match reachable {
+        Some((from_entry, from_handlers)) => (code.into_iter().enumerate())
+            .filter(|&(i, _)| from_entry[i] || !from_handlers[i])
+            .map(|(_, instruction)| instruction)
+            .collect(),
+        // Switches and subroutines aren't supported, so the function will fail to compile anyway
+        None => code,
+    }
+}
+
 impl ControlFlowGraph {
     /// Helper function for [`ControlFlowGraph::insert_basic_blocks`] that ensures this graph
     /// contains a node for the leader at `label` and this node's ID is stored in the `leaders` map.
@@ -197,7 +344,7 @@ impl ControlFlowGraph {
             });
 
             match &mut self[current_node].value {
-                Structure::Block(instructions) => instructions.push(instruction),
+                Structure::Block(instructions) => instructions.push((label, instruction)),
                 _ => unreachable!("Always inserted with empty Structure::Block"),
             }
         }
@@ -280,6 +427,10 @@ impl ControlFlowGraph {
 
                     // Connect placeholder to header
                     self.add_edge(placeholder, header);
+                    debug!(
+                        "Inserted placeholder {} above header {} for post-tested loop latching {}",
+                        placeholder, header, loop_latching
+                    );
 
                     // Ignore this node when checking for Case 2
                     remove_element(&mut latching, &loop_latching);
@@ -295,6 +446,12 @@ impl ControlFlowGraph {
                 // then connect it to the original header node. This ensures a 2-way conditional's
                 // follow node is never a loop header.
                 let placeholder = self.add_node(Structure::default());
+                debug!(
+                    "Inserted placeholder {} below header {} for 2-way conditional latching {}",
+                    placeholder,
+                    header,
+                    latching.iter().format(", ")
+                );
 
                 // Re-connect all remaining latching node's back edges to placeholder
                 for x in latching {
@@ -307,12 +464,73 @@ impl ControlFlowGraph {
             }
         }
     }
+
+    /// Helper function for [`ControlFlowGraph::coalesce_sequences`] that returns the successor of
+    /// the basic block `id` if they form a linear sequence that can be merged: `id` only branches
+    /// to the successor, which is only reachable from `id`.
+    fn sequence_successor(&self, id: NodeId) -> Option<NodeId> {
+        let node = &self[id];
+        if node.out_degree() != 1 {
+            return None;
+        }
+        let succ = node.successors[0];
+        let succ_node = &self[succ];
+        // The entrypoint has an implicit predecessor, so can never be merged
+        let mergeable = succ != id
+            && succ_node.in_degree() == 1
+            && self.entry != Some(succ)
+            && matches!(node.value, Structure::Block(_))
+            && matches!(succ_node.value, Structure::Block(_));
+        mergeable.then_some(succ)
+    }
+
+    /// Merges linear chains of basic blocks into single blocks, reducing the number of nodes later
+    /// structuring stages have to consider. A block is appended to its predecessor if it's the only
+    /// successor of that predecessor, and that predecessor is its only predecessor. Basic blocks are
+    /// maximal, and returns fall through to the next block, so this only happens when a block is
+    /// the target of a single `GOTO` and follows another `GOTO` (e.g. the statement after a
+    /// `while (true)` loop exited with `break`). Merged `GOTO`s are visited as `nop`s.
+    ///
+    /// This should be called after [`ControlFlowGraph::insert_placeholder_nodes`]. Placeholders are
+    /// always followed by, or preceded by, nodes with multiple predecessors, so are never merged
+    /// into neighbouring blocks, preserving each loop's unique back edge.
+    pub fn coalesce_sequences(&mut self) {
+        // Visit in reverse post-order, so the first node in each chain absorbs the rest of the
+        // chain before any of them are visited
+        let mut merged = NodeSet::with_capacity_for(self);
+        for id in self.depth_first(Order::ReversePostOrder).traversal {
+            if merged.contains(id) {
+                continue;
+            }
+            while let Some(succ) = self.sequence_successor(id) {
+                debug!("Coalescing {} into {}", succ, id);
+                // Append the successor's instructions to this block...
+                let succ_instructions = match take(&mut self[succ].value) {
+                    Structure::Block(instructions) => instructions,
+                    _ => unreachable!("Only blocks are sequences"),
+                };
+                match &mut self[id].value {
+                    Structure::Block(instructions) => instructions.extend(succ_instructions),
+                    _ => unreachable!("Only blocks are sequences"),
+                }
+                // ...then replace it, branching wherever it branched, preserving edge order for
+                // conditional branches (clone() as remove_node() requires a mutable borrow)
+                let successors = self[succ].successors.clone();
+                self.remove_node(succ);
+                for succ_succ in successors {
+                    self.add_edge(id, succ_succ);
+                }
+                merged.insert(succ);
+            }
+        }
+    }
 }
 
 #[allow(non_snake_case)]
 #[cfg(test)]
 mod tests {
     use crate::function::structure::Structure;
+    use crate::graph::DotOptions;
     use crate::tests::load_basic_blocks;
     use classfile_parser::code_attribute::Instruction as JVMInstruction;
 
@@ -323,11 +541,32 @@ mod tests {
         let entry = g.entry.unwrap();
         assert_eq!(
             g[entry].value,
-            Structure::Block(vec![JVMInstruction::Iconst1, JVMInstruction::Ireturn])
+            Structure::Block(vec![
+                (0, JVMInstruction::Iconst1),
+                (1, JVMInstruction::Ireturn),
+            ])
         );
         Ok(())
     }
 
+    #[test]
+    fn basic_blocks_dot_labels() -> anyhow::Result<()> {
+        let g = load_basic_blocks("return 1;")?;
+
+        // Check labels omitted by default...
+        let dot = g.as_dot(&DotOptions::default());
+        assert!(dot.contains("[label=\"0*\\nIconst1\\nIreturn\""));
+
+        // ...but included if enabled
+        let dot = g.as_dot(&DotOptions {
+            label_instructions: true,
+            ..DotOptions::default()
+        });
+        assert!(dot.contains("[label=\"0*\\n0: Iconst1\\n1: Ireturn\""));
+
+        Ok(())
+    }
+
     #[test]
     fn basic_blocks_if() -> anyhow::Result<()> {
         let g = load_basic_blocks(
@@ -342,9 +581,9 @@ mod tests {
         assert_eq!(
             g[entry].value,
             Structure::Block(vec![
-                JVMInstruction::Iload0,
-                JVMInstruction::Iconst1,
-                JVMInstruction::IfIcmple(8),
+                (0, JVMInstruction::Iload0),
+                (1, JVMInstruction::Iconst1),
+                (2, JVMInstruction::IfIcmple(8)),
             ])
         );
         assert_eq!(g[entry].successors.len(), 2);
@@ -355,14 +594,17 @@ mod tests {
         assert_eq!(
             g[false_node].value,
             Structure::Block(vec![
-                JVMInstruction::Iconst1,
-                JVMInstruction::Istore1,
-                JVMInstruction::Goto(5),
+                (5, JVMInstruction::Iconst1),
+                (6, JVMInstruction::Istore1),
+                (7, JVMInstruction::Goto(5)),
             ])
         );
         assert_eq!(
             g[true_node].value,
-            Structure::Block(vec![JVMInstruction::Iconst2, JVMInstruction::Istore1])
+            Structure::Block(vec![
+                (10, JVMInstruction::Iconst2),
+                (11, JVMInstruction::Istore1),
+            ])
         );
         assert_eq!(g[false_node].successors.len(), 1);
         assert_eq!(g[true_node].successors.len(), 1);
@@ -373,9 +615,48 @@ mod tests {
         assert_eq!(false_follow, true_follow);
         assert_eq!(
             g[false_follow].value,
-            Structure::Block(vec![JVMInstruction::Iload1, JVMInstruction::Ireturn])
+            Structure::Block(vec![
+                (12, JVMInstruction::Iload1),
+                (13, JVMInstruction::Ireturn),
+            ])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn conditional_branches() -> anyhow::Result<()> {
+        let g = load_basic_blocks(
+            "int a;
+            if (n > 1) { a = 1; } else { a = 2; };
+            return a;",
+        )?;
+
+        // Check accessors match raw successor order for the conditional entrypoint...
+        let entry = &g[g.entry.unwrap()];
+        assert!(entry.is_conditional());
+        assert_eq!(entry.false_branch(), Some(entry.successors[0]));
+        assert_eq!(entry.true_branch(), Some(entry.successors[1]));
+        // ...with the false branch falling through to the next instruction
+        let false_node = &g[entry.false_branch().unwrap()];
+        assert_eq!(
+            false_node.value,
+            Structure::Block(vec![
+                (5, JVMInstruction::Iconst1),
+                (6, JVMInstruction::Istore1),
+                (7, JVMInstruction::Goto(5)),
+            ])
         );
 
+        // Check accessors return None for unconditional and returning nodes
+        assert!(!false_node.is_conditional());
+        assert_eq!(false_node.false_branch(), None);
+        assert_eq!(false_node.true_branch(), None);
+        let follow = &g[false_node.successors[0]];
+        assert_eq!(follow.out_degree(), 0);
+        assert!(!follow.is_conditional());
+        assert_eq!(follow.true_branch(), None);
+
         Ok(())
     }
 
@@ -389,9 +670,9 @@ mod tests {
         assert_eq!(
             g[entry].value,
             Structure::Block(vec![
-                JVMInstruction::Iload0,
-                JVMInstruction::Iconst1,
-                JVMInstruction::IfIcmple(9)
+                (0, JVMInstruction::Iload0),
+                (1, JVMInstruction::Iconst1),
+                (2, JVMInstruction::IfIcmple(9)),
             ])
         );
         assert_eq!(g[entry].successors.len(), 2);
@@ -401,11 +682,14 @@ mod tests {
         assert_eq!(
             g[latching].value,
             Structure::Block(vec![
-                JVMInstruction::Iinc {
-                    index: 0,
-                    value: -1
-                },
-                JVMInstruction::Goto(-8),
+                (
+                    5,
+                    JVMInstruction::Iinc {
+                        index: 0,
+                        value: -1
+                    }
+                ),
+                (8, JVMInstruction::Goto(-8)),
             ])
         );
         assert_eq!(g[latching].successors, [entry]);
@@ -414,7 +698,10 @@ mod tests {
         let follow = g[entry].successors[1];
         assert_eq!(
             g[follow].value,
-            Structure::Block(vec![JVMInstruction::Iload0, JVMInstruction::Ireturn])
+            Structure::Block(vec![
+                (11, JVMInstruction::Iload0),
+                (12, JVMInstruction::Ireturn),
+            ])
         );
 
         Ok(())
@@ -430,13 +717,16 @@ mod tests {
         assert_eq!(
             g[entry].value,
             Structure::Block(vec![
-                JVMInstruction::Iinc {
-                    index: 0,
-                    value: -1
-                },
-                JVMInstruction::Iload0,
-                JVMInstruction::Iconst1,
-                JVMInstruction::IfIcmpgt(-5),
+                (
+                    0,
+                    JVMInstruction::Iinc {
+                        index: 0,
+                        value: -1
+                    }
+                ),
+                (3, JVMInstruction::Iload0),
+                (4, JVMInstruction::Iconst1),
+                (5, JVMInstruction::IfIcmpgt(-5)),
             ])
         );
         assert_eq!(g[entry].successors.len(), 2);
@@ -446,7 +736,10 @@ mod tests {
         let follow = g[entry].successors[0];
         assert_eq!(
             g[follow].value,
-            Structure::Block(vec![JVMInstruction::Iload0, JVMInstruction::Ireturn])
+            Structure::Block(vec![
+                (8, JVMInstruction::Iload0),
+                (9, JVMInstruction::Ireturn),
+            ])
         );
 
         Ok(())
@@ -467,9 +760,9 @@ mod tests {
         assert_eq!(
             g[entry].value,
             Structure::Block(vec![
-                JVMInstruction::Iload0,
-                JVMInstruction::Iconst2,
-                JVMInstruction::IfIcmple(9),
+                (0, JVMInstruction::Iload0),
+                (1, JVMInstruction::Iconst2),
+                (2, JVMInstruction::IfIcmple(9)),
             ])
         );
         assert_eq!(g[entry].successors.len(), 2);
@@ -479,11 +772,14 @@ mod tests {
         assert_eq!(
             g[inner_latching].value,
             Structure::Block(vec![
-                JVMInstruction::Iinc {
-                    index: 0,
-                    value: -1
-                },
-                JVMInstruction::Goto(-8),
+                (
+                    5,
+                    JVMInstruction::Iinc {
+                        index: 0,
+                        value: -1
+                    }
+                ),
+                (8, JVMInstruction::Goto(-8)),
             ])
         );
         assert_eq!(g[inner_latching].successors, [entry]);
@@ -493,9 +789,9 @@ mod tests {
         assert_eq!(
             g[outer_latching].value,
             Structure::Block(vec![
-                JVMInstruction::Iload0,
-                JVMInstruction::Iconst1,
-                JVMInstruction::IfIcmpgt(-13)
+                (11, JVMInstruction::Iload0),
+                (12, JVMInstruction::Iconst1),
+                (13, JVMInstruction::IfIcmpgt(-13)),
             ])
         );
         assert_eq!(g[outer_latching].successors.len(), 2);
@@ -505,7 +801,10 @@ mod tests {
         let outer_follow = g[outer_latching].successors[0];
         assert_eq!(
             g[outer_follow].value,
-            Structure::Block(vec![JVMInstruction::Iload0, JVMInstruction::Ireturn])
+            Structure::Block(vec![
+                (16, JVMInstruction::Iload0),
+                (17, JVMInstruction::Ireturn),
+            ])
         );
 
         Ok(())
@@ -572,9 +871,9 @@ mod tests {
         assert_eq!(
             g[entry].value,
             Structure::Block(vec![
-                JVMInstruction::Iload0,
-                JVMInstruction::Iconst1,
-                JVMInstruction::IfIcmple(20),
+                (0, JVMInstruction::Iload0),
+                (1, JVMInstruction::Iconst1),
+                (2, JVMInstruction::IfIcmple(20)),
             ])
         );
         assert_eq!(g[entry].successors.len(), 2);
@@ -584,9 +883,9 @@ mod tests {
         assert_eq!(
             g[conditional_header].value,
             Structure::Block(vec![
-                JVMInstruction::Iload0,
-                JVMInstruction::Iconst2,
-                JVMInstruction::IfIcmple(9),
+                (5, JVMInstruction::Iload0),
+                (6, JVMInstruction::Iconst2),
+                (7, JVMInstruction::IfIcmple(9)),
             ])
         );
         assert_eq!(g[conditional_header].successors.len(), 2);
@@ -597,21 +896,29 @@ mod tests {
         assert_eq!(
             g[false_node].value,
             Structure::Block(vec![
-                JVMInstruction::Iinc {
-                    index: 0,
-                    value: -2
-                },
-                JVMInstruction::Goto(-13), // Note two back edges to same node
+                (
+                    10,
+                    JVMInstruction::Iinc {
+                        index: 0,
+                        value: -2
+                    }
+                ),
+                // Note two back edges to same node
+                (13, JVMInstruction::Goto(-13)),
             ])
         );
         assert_eq!(
             g[true_node].value,
             Structure::Block(vec![
-                JVMInstruction::Iinc {
-                    index: 0,
-                    value: -1
-                },
-                JVMInstruction::Goto(-19) // Note two back edges to same node
+                (
+                    16,
+                    JVMInstruction::Iinc {
+                        index: 0,
+                        value: -1
+                    }
+                ),
+                // Note two back edges to same node
+                (19, JVMInstruction::Goto(-19)),
             ])
         );
         assert_eq!(g[false_node].successors.len(), 1);
@@ -628,7 +935,10 @@ mod tests {
         let loop_follow = g[entry].successors[1];
         assert_eq!(
             g[loop_follow].value,
-            Structure::Block(vec![JVMInstruction::Iload0, JVMInstruction::Ireturn])
+            Structure::Block(vec![
+                (22, JVMInstruction::Iload0),
+                (23, JVMInstruction::Ireturn),
+            ])
         );
 
         Ok(())
@@ -719,4 +1029,42 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn coalesce_sequences() -> anyhow::Result<()> {
+        // Each loop's `break` is a GOTO to the statement after the loop, which is only reachable
+        // from that GOTO, as the loop body ends with a GOTO back to the loop's condition
+        let mut g = load_basic_blocks(
+            "while (true) { if (n > 10) break; n += 3; }
+            n *= 2;
+            while (true) { if (n > 50) break; n += 5; }
+            n *= 2;
+            while (true) { if (n > 200) break; n += 7; }
+            return n;",
+        )?;
+        g.insert_placeholder_nodes();
+        assert_eq!(g.len(), 12);
+        g.coalesce_sequences();
+        assert_eq!(g.len(), 9);
+
+        // Check the first loop's exit absorbed the following statement, and still branches to the
+        // next loop's condition
+        let entry = g.entry.unwrap();
+        let exit = g[entry].successors[0];
+        assert_eq!(
+            g[exit].value,
+            Structure::Block(vec![
+                (6, JVMInstruction::Goto(9)),
+                (15, JVMInstruction::Iload0),
+                (16, JVMInstruction::Iconst2),
+                (17, JVMInstruction::Imul),
+                (18, JVMInstruction::Istore0),
+            ])
+        );
+        assert_eq!(g[exit].successors.len(), 1);
+        let next_header = g[exit].successors[0];
+        assert_eq!(g[next_header].successors.len(), 2);
+
+        Ok(())
+    }
 }
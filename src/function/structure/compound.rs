@@ -28,7 +28,7 @@ fn is_conditional_branch(node: &Node<Structure>) -> bool {
             // Check loads or computes something, then jumps
             instructions.len() >= 2
                 && matches!(
-                    instructions.last().unwrap(),
+                    instructions.last().unwrap().1,
                     JVMInstruction::IfAcmpeq(_)
                         | JVMInstruction::IfAcmpne(_)
                         | JVMInstruction::IfIcmpeq(_)
@@ -72,18 +72,29 @@ impl ControlFlowGraph {
         false_index: NodeId,
         true_index: NodeId,
     ) {
+        debug!(
+            "Rewriting {}{} {} {} to compound conditional (false {}, true {})",
+            if left_negated { "!" } else { "" },
+            left_index,
+            kind,
+            right_index,
+            false_index,
+            true_index
+        );
+
         // Extract left and right values to avoid cloning (we'll be replacing left_index and
         // removing right_index, so this is safe)
         let left_value = take(&mut self[left_index].value);
         let right_value = take(&mut self[right_index].value);
 
         // Replace left node with new compound node in graph
-        self[left_index].value = Structure::CompoundConditional {
+        let compound = Structure::CompoundConditional {
             kind,
             left_negated,
             left: Box::new(left_value),
             right: Box::new(right_value),
         };
+        self.replace_value(left_index, compound);
 
         // Remove right node from graph
         self.remove_node(right_index);
@@ -117,10 +128,7 @@ impl ControlFlowGraph {
             for n in self.depth_first(Order::PostOrder).traversal {
                 let n_node = &self[n];
 
-                if n_node.out_degree() == 2 {
-                    let t = n_node.successors[0]; // false branch
-                    let e = n_node.successors[1]; // true branch
-
+                if let (Some(t), Some(e)) = (n_node.false_branch(), n_node.true_branch()) {
                     let t_node = &self[t];
                     let e_node = &self[e];
 
@@ -201,22 +209,22 @@ mod tests {
     fn compound_conditional_fixture() -> (ControlFlowGraph, (NodeId, NodeId, NodeId, NodeId)) {
         let mut g = ControlFlowGraph::new();
         let x = g.add_node(Structure::Block(vec![
-            JVMInstruction::Iload0,
-            JVMInstruction::Iconst1,
-            JVMInstruction::IfIcmple(0),
+            (0, JVMInstruction::Iload0),
+            (1, JVMInstruction::Iconst1),
+            (2, JVMInstruction::IfIcmple(0)),
         ]));
         let y = g.add_node(Structure::Block(vec![
-            JVMInstruction::Iload1,
-            JVMInstruction::Iconst1,
-            JVMInstruction::IfIcmple(0),
+            (3, JVMInstruction::Iload1),
+            (4, JVMInstruction::Iconst1),
+            (5, JVMInstruction::IfIcmple(0)),
         ]));
         let f = g.add_node(Structure::Block(vec![
-            JVMInstruction::Iconst0,
-            JVMInstruction::Ireturn,
+            (6, JVMInstruction::Iconst0),
+            (7, JVMInstruction::Ireturn),
         ]));
         let t = g.add_node(Structure::Block(vec![
-            JVMInstruction::Iconst1,
-            JVMInstruction::Ireturn,
+            (8, JVMInstruction::Iconst1),
+            (9, JVMInstruction::Ireturn),
         ]));
         (g, (x, y, f, t))
     }
@@ -343,16 +351,16 @@ mod tests {
                 left_negated: expected_left_negated,
                 kind: expected_kind,
                 left: Box::new(Structure::Block(vec![
-                    JVMInstruction::Iconst0,
-                    JVMInstruction::Istore1,
-                    JVMInstruction::Iconst0,
-                    JVMInstruction::Istore2,
-                    JVMInstruction::Iload1,
-                    expected_left_conditional_instruction,
+                    (0, JVMInstruction::Iconst0),
+                    (1, JVMInstruction::Istore1),
+                    (2, JVMInstruction::Iconst0),
+                    (3, JVMInstruction::Istore2),
+                    (4, JVMInstruction::Iload1),
+                    (5, expected_left_conditional_instruction),
                 ])),
                 right: Box::new(Structure::Block(vec![
-                    JVMInstruction::Iload2,
-                    expected_right_conditional_instruction,
+                    (8, JVMInstruction::Iload2),
+                    (9, expected_right_conditional_instruction),
                 ]))
             }
         );
@@ -360,14 +368,17 @@ mod tests {
         assert_eq!(
             g[g[entry].successors[0]].value,
             Structure::Block(vec![
-                JVMInstruction::Iconst1,
-                JVMInstruction::Istore0,
-                JVMInstruction::Goto(5),
+                (12, JVMInstruction::Iconst1),
+                (13, JVMInstruction::Istore0),
+                (14, JVMInstruction::Goto(5)),
             ])
         );
         assert_eq!(
             g[g[entry].successors[1]].value,
-            Structure::Block(vec![JVMInstruction::Iconst0, JVMInstruction::Istore0])
+            Structure::Block(vec![
+                (17, JVMInstruction::Iconst0),
+                (18, JVMInstruction::Istore0),
+            ])
         );
 
         Ok(())
@@ -479,37 +490,37 @@ mod tests {
                         left_negated: true,
                         kind: ConditionalKind::Conjunction,
                         left: Box::new(Structure::Block(vec![
-                            JVMInstruction::Iconst0,
-                            JVMInstruction::Istore1,
-                            JVMInstruction::Iconst0,
-                            JVMInstruction::Istore2,
-                            JVMInstruction::Iconst0,
-                            JVMInstruction::Istore3,
-                            JVMInstruction::Iconst0,
-                            JVMInstruction::Istore(4),
-                            JVMInstruction::Iload1,
-                            JVMInstruction::Ifne(7),
+                            (0, JVMInstruction::Iconst0),
+                            (1, JVMInstruction::Istore1),
+                            (2, JVMInstruction::Iconst0),
+                            (3, JVMInstruction::Istore2),
+                            (4, JVMInstruction::Iconst0),
+                            (5, JVMInstruction::Istore3),
+                            (6, JVMInstruction::Iconst0),
+                            (7, JVMInstruction::Istore(4)),
+                            (9, JVMInstruction::Iload1),
+                            (10, JVMInstruction::Ifne(7)),
                         ])),
                         right: Box::new(Structure::Block(vec![
-                            JVMInstruction::Iload2,
-                            JVMInstruction::Ifeq(7),
+                            (13, JVMInstruction::Iload2),
+                            (14, JVMInstruction::Ifeq(7)),
                         ]))
                     }),
                     right: Box::new(Structure::Block(vec![
-                        JVMInstruction::Iload3,
-                        JVMInstruction::Ifne(12),
+                        (17, JVMInstruction::Iload3),
+                        (18, JVMInstruction::Ifne(12)),
                     ]))
                 }),
                 right: Box::new(Structure::CompoundConditional {
                     left_negated: false,
                     kind: ConditionalKind::Disjunction,
                     left: Box::new(Structure::Block(vec![
-                        JVMInstruction::Iload1,
-                        JVMInstruction::Ifeq(13),
+                        (21, JVMInstruction::Iload1),
+                        (22, JVMInstruction::Ifeq(13)),
                     ])),
                     right: Box::new(Structure::Block(vec![
-                        JVMInstruction::Iload(4),
-                        JVMInstruction::Ifeq(8),
+                        (25, JVMInstruction::Iload(4)),
+                        (27, JVMInstruction::Ifeq(8)),
                     ]))
                 }),
             }
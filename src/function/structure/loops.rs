@@ -13,7 +13,7 @@ pub enum LoopKind {
 }
 
 /// Identified pre-/post-tested loop in a [`ControlFlowGraph`].
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub struct Loop {
     /// Whether condition is evaluated before or after body.
     pub kind: LoopKind,
@@ -24,6 +24,8 @@ pub struct Loop {
     pub latching: NodeId,
     /// Node immediately after exiting the loop.
     pub follow: NodeId,
+    /// All nodes in the loop, including `header` and `latching`, and the nodes of nested loops.
+    pub body: NodeSet,
 }
 
 impl fmt::Display for Loop {
@@ -53,9 +55,9 @@ fn is_reducible(G: &[Graph<Vec<NodeId>>]) -> bool {
 }
 
 impl ControlFlowGraph {
-    /// Identifies all pre- and post-tested looks in the control flow graph, returning
-    /// loop kinds and header/latching/follow nodes, using the algorithm described in Figure 6.25
-    /// of "Cristina Cifuentes. Reverse Compilation Techniques. PhD thesis, Queensland University of
+    /// Identifies all pre- and post-tested looks in the control flow graph, returning loop kinds,
+    /// header/latching/follow nodes and bodies, using the algorithm described in Figure 6.25 of
+    /// "Cristina Cifuentes. Reverse Compilation Techniques. PhD thesis, Queensland University of
     /// Technology, 1994".
     ///
     /// See [`Loop`]'s fields' documentation for more details on the types of identified nodes.
@@ -83,6 +85,7 @@ impl ControlFlowGraph {
         // Make sure the graph is reducible
         let reducible = is_reducible(&G);
         ensure!(reducible, "Irreducible flow graphs are not yet supported");
+        debug!("Derived sequence of intervals has {} graph(s)", G.len());
 
         // For each graph in the derived sequence...
         for (i, G_i) in G.into_iter().enumerate() {
@@ -144,7 +147,9 @@ impl ControlFlowGraph {
                         header: h_j,
                         latching: x,
                         follow,
+                        body,
                     };
+                    debug!("Found loop {} in derived graph {}", l, i);
                     loops.insert(h_j, l);
                 }
             }
@@ -238,7 +243,7 @@ mod tests {
         assert_eq!(g[entry].successors.len(), 2);
         let latching = g[entry].successors[0];
         let follow = g[entry].successors[1];
-        let loop_info = loops[entry];
+        let loop_info = &loops[entry];
         assert_eq!(loop_info.kind, LoopKind::PreTested);
         assert_eq!(loop_info.header, entry);
         assert_eq!(loop_info.latching, latching);
@@ -263,7 +268,7 @@ mod tests {
         let entry = g.entry.unwrap();
         assert_eq!(g[entry].successors.len(), 2);
         let follow = g[entry].successors[1];
-        let loop_info = loops[entry];
+        let loop_info = &loops[entry];
         assert_eq!(loop_info.kind, LoopKind::PreTested);
         assert_eq!(loop_info.header, entry);
         assert_eq!(loop_info.follow, follow);
@@ -287,7 +292,7 @@ mod tests {
 
         let entry = g.entry.unwrap();
         assert_eq!(g[entry].successors.len(), 2); // 2-way conditional header node
-        let loop_info = loops[entry];
+        let loop_info = &loops[entry];
         assert_eq!(loop_info.kind, LoopKind::PostTested);
         assert_eq!(loop_info.header, entry);
         assert_eq!(g[loop_info.latching].successors.len(), 2);
@@ -309,7 +314,7 @@ mod tests {
 
         let entry = g.entry.unwrap();
         assert_eq!(g[entry].successors.len(), 2); // post-tested latching node
-        let loop_info = loops[entry];
+        let loop_info = &loops[entry];
         // This is detected as a single node pre-tested loop, but handled as a post-tested loop
         assert_eq!(loop_info.kind, LoopKind::PreTested);
         assert_eq!(loop_info.header, entry);
@@ -343,12 +348,12 @@ mod tests {
         assert_eq!(loops.iter().count(), 2);
 
         let entry = g.entry.unwrap();
-        let post_tested_loop = loops[entry];
+        let post_tested_loop = &loops[entry];
         assert_eq!(post_tested_loop.kind, LoopKind::PostTested);
         assert_eq!(post_tested_loop.header, entry);
         assert_eq!(g[entry].successors.len(), 1);
         let pre_tested_loop_header = g[entry].successors[0];
-        let pre_tested_loop = loops[pre_tested_loop_header];
+        let pre_tested_loop = &loops[pre_tested_loop_header];
         assert_eq!(pre_tested_loop.kind, LoopKind::PreTested);
         assert_eq!(pre_tested_loop.header, pre_tested_loop_header);
         assert_eq!(g[pre_tested_loop_header].successors.len(), 2);
@@ -379,12 +384,12 @@ mod tests {
         let post_tested_loop_header_latching = g[entry].successors[0];
         let pre_tested_follow = g[entry].successors[1];
 
-        let pre_tested_loop = loops[entry];
+        let pre_tested_loop = &loops[entry];
         assert_eq!(pre_tested_loop.kind, LoopKind::PreTested);
         assert_eq!(pre_tested_loop.header, entry);
         assert_eq!(pre_tested_loop.follow, pre_tested_follow);
 
-        let post_tested_loop = loops[post_tested_loop_header_latching];
+        let post_tested_loop = &loops[post_tested_loop_header_latching];
         // This is detected as a single node pre-tested loop, but handled as a post-tested loop
         assert_eq!(post_tested_loop.kind, LoopKind::PreTested);
         assert_eq!(post_tested_loop.header, post_tested_loop_header_latching);
@@ -427,12 +432,12 @@ mod tests {
         let post_tested_loop_follow = g[post_tested_loop_latching].successors[0]; // return n;
 
         // Check loops
-        let post_tested_loop = loops[entry];
+        let post_tested_loop = &loops[entry];
         assert_eq!(post_tested_loop.kind, LoopKind::PostTested);
         assert_eq!(post_tested_loop.header, entry);
         assert_eq!(post_tested_loop.latching, post_tested_loop_latching);
         assert_eq!(post_tested_loop.follow, post_tested_loop_follow);
-        let pre_tested_loop = loops[true_node];
+        let pre_tested_loop = &loops[true_node];
         assert_eq!(pre_tested_loop.kind, LoopKind::PreTested);
         assert_eq!(pre_tested_loop.header, true_node);
         assert_eq!(pre_tested_loop.latching, pre_tested_loop_latching);
@@ -441,6 +446,58 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn loop_nested_bodies() -> anyhow::Result<()> {
+        let mut g = load_basic_blocks(
+            "do {
+                if (n == 2) {
+                    n = 1;
+                } else {
+                    while (n > 2) {
+                        n -= 2;
+                    }
+                }
+            } while (n > 1);
+            return n;",
+        )?;
+        g.insert_placeholder_nodes();
+        g.structure_compound_conditionals();
+        let loops = g.find_loops()?;
+
+        // Extract key nodes (see loop_nested_pre_tested_inside_if_inside_post_tested)
+        let entry = g.entry.unwrap(); // if (n == 2) {
+        let false_node = g[entry].successors[0]; // n = 1;
+        let true_node = g[entry].successors[1]; // while (n > 2) {
+        let pre_tested_loop_latching = g[true_node].successors[0]; // n -= 2;
+        let post_tested_loop_latching = g[false_node].successors[0]; // } while (n > 1)
+        let post_tested_loop_follow = g[post_tested_loop_latching].successors[0]; // return n;
+
+        // Check inner loop body only contains its header and latching...
+        let pre_tested_loop = &loops[true_node];
+        let expected_body = [true_node, pre_tested_loop_latching];
+        assert_eq!(
+            pre_tested_loop.body,
+            expected_body.iter().copied().collect()
+        );
+        // ...and outer loop body contains everything except the follow, including the inner loop
+        let post_tested_loop = &loops[entry];
+        let expected_body = [
+            entry,
+            false_node,
+            true_node,
+            pre_tested_loop_latching,
+            post_tested_loop_latching,
+        ];
+        assert_eq!(
+            post_tested_loop.body,
+            expected_body.iter().copied().collect()
+        );
+        assert!(!post_tested_loop.body.contains(post_tested_loop_follow));
+        assert_eq!(g.len(), 6);
+
+        Ok(())
+    }
+
     #[test]
     fn loop_compound_conditional() -> anyhow::Result<()> {
         let mut g = load_basic_blocks(
@@ -462,7 +519,7 @@ mod tests {
         let follow = g[header].successors[1]; // return n;
 
         // Check loop
-        let loop_info = loops[header];
+        let loop_info = &loops[header];
         assert_eq!(loop_info.kind, LoopKind::PreTested);
         assert_eq!(loop_info.header, header);
         assert_eq!(loop_info.latching, latching);
@@ -3,17 +3,23 @@ mod compound;
 mod loops;
 mod two_way;
 
-use crate::graph::{run_graphviz, DotOptions, NodeId, NodeMap};
+use crate::function::ExceptionHandler;
+use crate::graph::{run_graphviz, DotOptions, GraphsFormat, NodeId, NodeMap};
 use anyhow::Context;
 use classfile_parser::code_attribute::Instruction as JVMInstruction;
 use itertools::Itertools;
-use std::path::PathBuf;
+use std::fs;
+use std::path::{Path, PathBuf};
 
 pub use self::basic::*;
 pub use self::compound::*;
 pub use self::loops::*;
 pub use self::two_way::*;
 
+/// Log target prefix for structuring decisions, logged at [`log::Level::Debug`]. Logging for just
+/// this module can be enabled with `--verbose-structuring`.
+pub const LOG_TARGET: &str = module_path!();
+
 /// Output of [`structure_code`], containing a structured control flow graph with extracted control
 /// flow constructs.
 pub struct StructuredCode {
@@ -23,6 +29,9 @@ pub struct StructuredCode {
     pub loops: NodeMap<Loop>,
     /// Maps identified 2-way conditional headers in `g` to their follow nodes.
     pub conditionals: NodeMap<NodeId>,
+    /// Exception handlers protecting regions of the original code, as `(try_range, handler,
+    /// catch_type)` entries of bytecode offsets. These aren't included in `g` yet.
+    pub exception_handlers: Vec<ExceptionHandler>,
 }
 
 /// Structures JVM bytecode, identifying control flow constructs using the algorithms described in
@@ -32,53 +41,75 @@ pub struct StructuredCode {
 /// See the following functions for more details on each stage of the process:
 ///
 /// 1. [`ControlFlowGraph::insert_basic_blocks`]: finds basic blocks in bytecode
-/// 2. [`ControlFlowGraph::insert_placeholder_nodes`]: inserts placeholders for loop structuring
+/// 2. [`ControlFlowGraph::insert_placeholder_nodes`]: inserts placeholders for loop structuring,
+///    then if `coalesce_sequences` is set, [`ControlFlowGraph::coalesce_sequences`] merges linear
+///    chains of blocks
 /// 3. [`ControlFlowGraph::structure_compound_conditionals`]: rewrite irreducible short-circuit
 ///    patterns to single nodes
 /// 4. [`ControlFlowGraph::find_loops`]: identify pre/post-tested loops
 /// 5. [`ControlFlowGraph::find_2_way_conditionals`]: identify 2-way conditionals (if-statements)
 ///
-/// If `graphs_dir` is provided, the following graphs will be rendered using Graphviz. Note this
-/// significantly slows down compilation:
+/// `exception_handlers` aren't structured yet, but are included in the output so they're available
+/// alongside the control flow graph.
+///
+/// If `graphs_dir` is provided, the following graphs will be written in `graphs_format`, either
+/// rendered using Graphviz or as GraphML. Note this significantly slows down compilation:
 ///
 /// - `<graphs_dir>/basic.png`: after stage 1, basic blocks only
 /// - `<graphs_dir>/placeholder.png`: after stage 2, basic blocks with inserted placeholder nodes
+///   (and coalesced sequences)
 /// - `<graphs_dir>/compound.png`: after stage 3, basic blocks with rewritten short-circuit nodes
-/// - `<graphs_dir>/derived.png`: after stage 3, derived sequence of intervals of control flow graph
+/// - `<graphs_dir>/derived.png`: after stage 3, derived sequence of intervals of control flow graph.
+///   As GraphML, each graph in the sequence is written separately to `derived_<i>.graphml`.
 pub fn structure_code(
     code: Vec<(usize, JVMInstruction)>,
+    exception_handlers: Vec<ExceptionHandler>,
     graphs_dir: Option<&PathBuf>,
+    graphs_format: GraphsFormat,
+    coalesce_sequences: bool,
 ) -> anyhow::Result<StructuredCode> {
     // Create new control flow graph and build basic blocks from function's code
     let mut g = ControlFlowGraph::new();
     g.insert_basic_blocks(code);
+    debug_validate_edges(&g, "basic blocks");
+    // Code consisting only of an endless loop has no exit node, which placeholder insertion's
+    // post-dominator analysis requires, so reject it here like `find_loops` would
+    ensure!(
+        g.iter().any(|node| node.out_degree() == 0),
+        "Endless loops are not yet supported"
+    );
 
     // Write intermediate graph if enabled
-    let dot_opts = DotOptions::default();
     if let Some(graphs_dir) = graphs_dir {
-        run_graphviz(&g.as_dot(&dot_opts), graphs_dir.join("basic.png"))
+        write_graph(&g, graphs_dir, "basic", graphs_format)
             .context("Unable to render basic graph")?;
     }
 
     // Insert dummy nodes where nodes have 2 or more back edges to ensure each loop has a single
     // unique back edge
     g.insert_placeholder_nodes();
+    // Merge linear chains of blocks if enabled, so later stages have fewer nodes to consider
+    if coalesce_sequences {
+        g.coalesce_sequences();
+    }
+    debug_validate_edges(&g, "placeholder insertion");
     if let Some(graphs_dir) = graphs_dir {
-        run_graphviz(&g.as_dot(&dot_opts), graphs_dir.join("placeholder.png"))
+        write_graph(&g, graphs_dir, "placeholder", graphs_format)
             .context("Unable to render placeholder graph")?;
     }
 
     // Combine short-circuit conditionals in single nodes
     g.structure_compound_conditionals();
+    debug_validate_edges(&g, "compound conditional structuring");
     // Write intermediate graph if enabled
     if let Some(graphs_dir) = graphs_dir {
-        run_graphviz(&g.as_dot(&dot_opts), graphs_dir.join("compound.png"))
+        write_graph(&g, graphs_dir, "compound", graphs_format)
             .context("Unable to render compound graph")?;
     }
 
     // Write derived sequence of graphs if enabled
     if let Some(graphs_dir) = graphs_dir {
-        run_graphviz(&derived_sequence_as_dot(&g), graphs_dir.join("derived.png"))
+        write_derived_sequence(&g, graphs_dir, graphs_format)
             .context("Unable to render derived sequence graph")?;
     }
 
@@ -89,24 +120,172 @@ pub fn structure_code(
     // conditionals from earlier
     let ignored_headers = ignored_loop_headers(&loops);
     let conditionals = g.find_2_way_conditionals(&ignored_headers);
+    debug_validate_edges(&g, "loop and conditional structuring");
 
     let structured = StructuredCode {
         g,
         loops,
         conditionals,
+        exception_handlers,
     };
     Ok(structured)
 }
 
-fn derived_sequence_as_dot(g: &ControlFlowGraph) -> String {
+/// Panics if the edges of `g` are inconsistent after the structuring `pass` (see
+/// [`Graph::validate_edge_consistency`]). This is only checked when `debug_assertions` are enabled.
+///
+/// [`Graph::validate_edge_consistency`]: crate::graph::Graph::validate_edge_consistency
+fn debug_validate_edges(g: &ControlFlowGraph, pass: &str) {
+    if cfg!(debug_assertions) {
+        if let Err(e) = g.validate_edge_consistency() {
+            panic!("Inconsistent edges after {}: {}", pass, e);
+        }
+    }
+}
+
+/// Writes an intermediate control flow graph to `<graphs_dir>/<name>.<ext>` in `format`. Rendered
+/// graphs include JVM labels for correlating with `javap -c`.
+fn write_graph(
+    g: &ControlFlowGraph,
+    graphs_dir: &Path,
+    name: &str,
+    format: GraphsFormat,
+) -> anyhow::Result<()> {
+    let path = graphs_dir.join(format!("{}.{}", name, format.extension()));
+    match format {
+        GraphsFormat::Png => {
+            let dot_opts = DotOptions {
+                label_instructions: true,
+                ..Default::default()
+            };
+            run_graphviz(&g.as_dot(&dot_opts), path)?;
+        }
+        GraphsFormat::Graphml => fs::write(path, g.as_graphml())?,
+    }
+    Ok(())
+}
+
+/// Writes the derived sequence of intervals of a control flow graph to `graphs_dir` in `format`.
+/// Rendered sequences are written as a single graph with a subgraph for each interval graph, but
+/// GraphML sequences are written as a separate graph for each.
+fn write_derived_sequence(
+    g: &ControlFlowGraph,
+    graphs_dir: &Path,
+    format: GraphsFormat,
+) -> anyhow::Result<()> {
     #[allow(non_snake_case)]
     let (G, _) = g.intervals_derived_sequence();
-    let dots = G.iter().enumerate().map(|(i, g)| {
-        g.as_dot(&DotOptions {
-            subgraph: Some(i),
-            ..Default::default()
-        })
-    });
-    let dot = format!("digraph {{\n{}\n}}\n", dots.format("\n"));
-    dot
+    match format {
+        GraphsFormat::Png => {
+            let dots = G.iter().enumerate().map(|(i, g)| {
+                g.as_dot(&DotOptions {
+                    subgraph: Some(i),
+                    ..Default::default()
+                })
+            });
+            let dot = format!("digraph {{\n{}\n}}\n", dots.format("\n"));
+            run_graphviz(&dot, graphs_dir.join("derived.png"))?;
+        }
+        GraphsFormat::Graphml => {
+            for (i, g) in G.iter().enumerate() {
+                fs::write(
+                    graphs_dir.join(format!("derived_{}.graphml", i)),
+                    g.as_graphml(),
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::function::structure::{structure_code, StructuredCode};
+    use crate::function::ExceptionHandler;
+    use crate::graph::GraphsFormat;
+    use crate::tests::{capture_logs, load_code};
+    use std::sync::Arc;
+
+    impl StructuredCode {
+        /// Returns a stable textual summary of identified control flow constructs. Each loop is
+        /// summarised as `loop <header> -> <latching> => <follow> (<kind>)`, followed by each 2-way
+        /// conditional as `if <header> => <follow>`, both sorted by header.
+        fn summary(&self) -> String {
+            // NodeMap iterators are always sorted by NodeId
+            let loops = self.loops.values().map(|l| format!("loop {}\n", l));
+            let conditionals = (self.conditionals.iter())
+                .map(|(header, follow)| format!("if {} => {}\n", header, follow));
+            loops.chain(conditionals).collect()
+        }
+    }
+
+    #[test]
+    fn structured_code_summary() -> anyhow::Result<()> {
+        let class = load_code(
+            "static int test(int n) {
+                int total = 0;
+                for (int i = 0; i < n; i++) {
+                    int j = 0;
+                    do {
+                        if (j % 2 == 0) { total += j; } else { total -= i; }
+                        j++;
+                    } while (j < i);
+                }
+                return total;
+            }",
+        )?;
+        let code = class.methods[1].code.lock().unwrap().take().unwrap();
+        let structured = structure_code(code, vec![], None, GraphsFormat::Png, false)?;
+        assert_eq!(
+            structured.summary(),
+            "loop 7 -> 5 => 6 (PostTested)\n\
+            loop 8 -> 6 => 2 (PreTested)\n\
+            if 7 => 5\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn structured_code_exception_handlers() -> anyhow::Result<()> {
+        let class = load_code(
+            "static int test(int n) {
+                try { return n / 2; } catch (ArithmeticException e) { return -1; }
+            }",
+        )?;
+        let method = &class.methods[1];
+        let code = method.code.lock().unwrap().take().unwrap();
+        let handlers = method.exception_handlers.clone();
+        let structured = structure_code(code, handlers, None, GraphsFormat::Png, false)?;
+        // Check handlers match `javap -c`'s exception table:
+        //   from    to  target type
+        //      0     3     4   Class java/lang/ArithmeticException
+        let expected = vec![ExceptionHandler {
+            try_range: 0..3,
+            handler: 4,
+            catch_type: Some(Arc::new(String::from("java/lang/ArithmeticException"))),
+        }];
+        assert_eq!(structured.exception_handlers, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn structure_logs_decisions() -> anyhow::Result<()> {
+        let class = load_code(
+            "static int test(int n) {
+                if (n > 1) { n = 1; } else { n = 0; }
+                return n;
+            }",
+        )?;
+        let code = class.methods[1].code.lock().unwrap().take().unwrap();
+        let (structured, logs) =
+            capture_logs(|| structure_code(code, vec![], None, GraphsFormat::Png, false));
+        assert_eq!(structured?.summary(), "if 0 => 3\n");
+        assert!(
+            logs.iter()
+                .any(|line| line == "Found 2-way conditional 0 => 3"),
+            "{:#?}",
+            logs
+        );
+        Ok(())
+    }
 }
@@ -48,7 +48,9 @@ impl ControlFlowGraph {
 
         let post_order = self.depth_first(Order::PostOrder);
         for &m in &post_order.traversal {
-            if self[m].out_degree() == 2 && !ignored_headers.contains(m) {
+            if self[m].out_degree() == 2 && ignored_headers.contains(m) {
+                debug!("Ignoring loop node {} as 2-way conditional header", m);
+            } else if self[m].out_degree() == 2 {
                 let n = self
                     .iter_id()
                     .filter(|&i| idom[i] == m && self[i].in_degree() >= 2)
@@ -56,13 +58,16 @@ impl ControlFlowGraph {
                     .max_by(|&a, &b| post_order.cmp(a, b).reverse());
                 match n {
                     Some(n) => {
+                        debug!("Found 2-way conditional {} => {}", m, n);
                         follow.insert(m, n);
                         for x in unresolved.iter() {
+                            debug!("Resolved 2-way conditional {} => {}", x, n);
                             follow.insert(x, n);
                         }
                         unresolved.clear();
                     }
                     None => {
+                        debug!("Deferring 2-way conditional {} until a follow is found", m);
                         unresolved.insert(m);
                     }
                 }
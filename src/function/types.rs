@@ -1,9 +1,33 @@
-use crate::class::{ConstantPool, FieldId, MethodDescriptor, MethodId};
+use crate::class::{ConstantPool, FieldId, FunctionType, MethodDescriptor, MethodId};
 use crate::function::locals::LocalInterpretation;
+use crate::graph::GraphsFormat;
 use classfile_parser::code_attribute::Instruction as JVMInstruction;
 use classfile_parser::method_info::MethodAccessFlags;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::mem::discriminant;
+use std::ops::Range;
 use std::sync::{Arc, Mutex};
-use wasm_encoder::Instruction as WASMInstruction;
+use wasm_encoder::{BlockType, Instruction as WASMInstruction, MemArg, ValType};
+
+/// WebAssembly types of values on the JVM operand stack at branch targets, keyed by bytecode
+/// offset, parsed from a method's `StackMapTable` attribute. The top of the stack is last.
+pub type StackMap = HashMap<usize, Vec<ValType>>;
+
+/// Entry in a method's exception table, parsed from its `Code` attribute. Exceptions thrown by
+/// instructions in `try_range` of type `catch_type` jump to `handler`, with the exception on the
+/// stack. All ranges and labels are bytecode offsets.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ExceptionHandler {
+    /// Range of bytecode offsets protected by this handler (end exclusive).
+    pub try_range: Range<usize>,
+    /// Bytecode offset of the handler's first instruction.
+    pub handler: usize,
+    /// Name of the exception class caught by this handler, or [`None`] if it catches all
+    /// exceptions (e.g. `finally` blocks).
+    pub catch_type: Option<Arc<String>>,
+}
 
 /// Parsed function in a Java `.class` file containing JVM bytecode.
 /// Input to [`crate::function::CompileFunctionJob`].
@@ -17,10 +41,19 @@ pub struct Function {
     pub descriptor: Arc<MethodDescriptor>,
     /// Reference to constant pool of containing class.
     pub const_pool: Arc<ConstantPool>,
+    /// Name of the source file the containing class was compiled from, if it was recorded. See
+    /// [`Class::source_file`](crate::class::Class::source_file).
+    pub source_file: Option<Arc<String>>,
     /// JVM bytecode for this function. Only included if this function isn't `native` or `abstract`.
     /// `Mutex` provides interior mutability, we want to take ownership and move this code when
     /// structuring.
     pub code: Mutex<Option<Vec<(usize, JVMInstruction)>>>,
+    /// Operand stack types at branch targets in `code`. Empty if this function doesn't have any
+    /// code, or was compiled for a Java version without stack map frames (before Java 6).
+    pub stack_map: Arc<StackMap>,
+    /// Exception handlers protecting regions of `code`, in the order they're searched. Empty if
+    /// this function doesn't have any code.
+    pub exception_handlers: Vec<ExceptionHandler>,
 }
 
 /// Intermediate instructions generated by function visiting phase for module rendering phase.
@@ -28,14 +61,17 @@ pub struct Function {
 /// Includes all simple WebAssembly instructions with additional pseudo-instructions requiring
 /// virtual method tables or built-in functions. These are lowered to simple instructions when
 /// rendering the final WebAssembly module.
+///
+/// Implements [`Eq`] and [`Hash`] so functions with identical bodies can be deduplicated when
+/// rendering. See [`simple_eq`] for how simple WebAssembly instructions are compared.
 #[derive(Debug)]
 pub enum Instruction<'a> {
     /// Simple WebAssembly instruction.
     I(WASMInstruction<'a>),
 
-    /// Duplicates the value at the top of the stack:
+    /// Duplicates the value of the specified type at the top of the stack:
     /// `[value: t] -> [value: t, value: t]`
-    Dup,
+    Dup(ValType),
 
     /// Creates a new instance of the specified class on the heap returning a reference:
     /// `[] -> [ptr: i32]`
@@ -60,6 +96,16 @@ pub enum Instruction<'a> {
     /// result:
     /// `[this: i32, ...] -> [return: t]`
     CallVirtual(MethodId),
+    /// Calls the specified static method like [`Instruction::CallStatic`] in tail position,
+    /// returning its result from the current function whilst reusing its stack frame. Only produced
+    /// with [`CompileOptions::tail_calls`]:
+    /// `[...] -> []`
+    ReturnCallStatic(MethodId),
+    /// Calls the specified instance method like [`Instruction::CallVirtual`] in tail position,
+    /// returning its result from the current function whilst reusing its stack frame. Only produced
+    /// with [`CompileOptions::tail_calls`]:
+    /// `[this: i32, ...] -> []`
+    ReturnCallVirtual(MethodId),
 
     /// Pops two `long` values `a` and `b` off the top of the stack, returning -1 if `a < b`, 0 if
     /// `a = b` and 1 if `a > b`:
@@ -82,11 +128,329 @@ pub enum Instruction<'a> {
     /// Pops two `double` values `a` and `b` off the top of the stack, returning `a % b`.
     /// `[a: f64, b: f64] -> [c: f64]`
     DoubleRem,
+
+    /// Throws the exception reference on the top of the stack. Exceptions are not yet supported,
+    /// so this traps, reporting [`TrapKind::Throw`] to the host first with `--rich-traps`:
+    /// `[ref: i32] -> []`
+    ///
+    /// [`TrapKind::Throw`]: crate::output::TrapKind::Throw
+    Throw,
+}
+
+/// Immediate arguments of a simple WebAssembly instruction, returned by [`immediates`].
+///
+/// [`WASMInstruction`]s don't implement [`Eq`] or [`Hash`] (some contain floating point
+/// immediates), so their immediates are converted to this instead: floating point constants to
+/// their bits (meaning `NaN` constants with the same bit pattern are equal), and [`MemArg`]s and
+/// [`BlockType`]s to their fields.
+#[derive(Eq, PartialEq, Hash)]
+enum Immediates<'a> {
+    None,
+    Index(u32),
+    Indices(u32, u32),
+    Labels(&'a [u32], u32),
+    BlockType(Option<ValType>, Option<u32>),
+    ValType(ValType),
+    I32(i32),
+    I64(i64),
+    F32(u32),
+    F64(u64),
+    V128(i128),
+    MemArg(u64, u32, u32),
+    MemArgLane(u64, u32, u32, u8),
+    Lane(u8),
+    Lanes([u8; 16]),
+}
+
+/// Returns the immediate arguments of a simple WebAssembly instruction, or [`Immediates::None`]
+/// if it doesn't have any.
+fn immediates<'a>(instruction: &'a WASMInstruction) -> Immediates<'a> {
+    let mem_arg = |arg: &MemArg| Immediates::MemArg(arg.offset, arg.align, arg.memory_index);
+    match instruction {
+        WASMInstruction::Block(block_type)
+        | WASMInstruction::Loop(block_type)
+        | WASMInstruction::If(block_type)
+        | WASMInstruction::Try(block_type) => match *block_type {
+            BlockType::Empty => Immediates::BlockType(None, None),
+            BlockType::Result(t) => Immediates::BlockType(Some(t), None),
+            BlockType::FunctionType(index) => Immediates::BlockType(None, Some(index)),
+        },
+        WASMInstruction::Delegate(index)
+        | WASMInstruction::Catch(index)
+        | WASMInstruction::Br(index)
+        | WASMInstruction::BrIf(index)
+        | WASMInstruction::Call(index)
+        | WASMInstruction::Throw(index)
+        | WASMInstruction::Rethrow(index)
+        | WASMInstruction::LocalGet(index)
+        | WASMInstruction::LocalSet(index)
+        | WASMInstruction::LocalTee(index)
+        | WASMInstruction::GlobalGet(index)
+        | WASMInstruction::GlobalSet(index)
+        | WASMInstruction::MemorySize(index)
+        | WASMInstruction::MemoryGrow(index)
+        | WASMInstruction::DataDrop(index)
+        | WASMInstruction::MemoryFill(index)
+        | WASMInstruction::RefFunc(index)
+        | WASMInstruction::TableFill { table: index }
+        | WASMInstruction::TableSet { table: index }
+        | WASMInstruction::TableGet { table: index }
+        | WASMInstruction::TableGrow { table: index }
+        | WASMInstruction::TableSize { table: index }
+        | WASMInstruction::ElemDrop { segment: index } => Immediates::Index(*index),
+        WASMInstruction::CallIndirect { ty: a, table: b }
+        | WASMInstruction::MemoryInit { mem: a, data: b }
+        | WASMInstruction::MemoryCopy { src: a, dst: b }
+        | WASMInstruction::TableInit {
+            segment: a,
+            table: b,
+        }
+        | WASMInstruction::TableCopy { src: a, dst: b } => Immediates::Indices(*a, *b),
+        WASMInstruction::BrTable(labels, default) => Immediates::Labels(labels, *default),
+        WASMInstruction::TypedSelect(t) | WASMInstruction::RefNull(t) => Immediates::ValType(*t),
+        WASMInstruction::I32Const(value) => Immediates::I32(*value),
+        WASMInstruction::I64Const(value) => Immediates::I64(*value),
+        WASMInstruction::F32Const(value) => Immediates::F32(value.to_bits()),
+        WASMInstruction::F64Const(value) => Immediates::F64(value.to_bits()),
+        WASMInstruction::V128Const(value) => Immediates::V128(*value),
+        WASMInstruction::I32Load(arg)
+        | WASMInstruction::I64Load(arg)
+        | WASMInstruction::F32Load(arg)
+        | WASMInstruction::F64Load(arg)
+        | WASMInstruction::I32Load8_S(arg)
+        | WASMInstruction::I32Load8_U(arg)
+        | WASMInstruction::I32Load16_S(arg)
+        | WASMInstruction::I32Load16_U(arg)
+        | WASMInstruction::I64Load8_S(arg)
+        | WASMInstruction::I64Load8_U(arg)
+        | WASMInstruction::I64Load16_S(arg)
+        | WASMInstruction::I64Load16_U(arg)
+        | WASMInstruction::I64Load32_S(arg)
+        | WASMInstruction::I64Load32_U(arg)
+        | WASMInstruction::I32Store(arg)
+        | WASMInstruction::I64Store(arg)
+        | WASMInstruction::F32Store(arg)
+        | WASMInstruction::F64Store(arg)
+        | WASMInstruction::I32Store8(arg)
+        | WASMInstruction::I32Store16(arg)
+        | WASMInstruction::I64Store8(arg)
+        | WASMInstruction::I64Store16(arg)
+        | WASMInstruction::I64Store32(arg)
+        | WASMInstruction::V128Load { memarg: arg }
+        | WASMInstruction::V128Load8x8S { memarg: arg }
+        | WASMInstruction::V128Load8x8U { memarg: arg }
+        | WASMInstruction::V128Load16x4S { memarg: arg }
+        | WASMInstruction::V128Load16x4U { memarg: arg }
+        | WASMInstruction::V128Load32x2S { memarg: arg }
+        | WASMInstruction::V128Load32x2U { memarg: arg }
+        | WASMInstruction::V128Load8Splat { memarg: arg }
+        | WASMInstruction::V128Load16Splat { memarg: arg }
+        | WASMInstruction::V128Load32Splat { memarg: arg }
+        | WASMInstruction::V128Load64Splat { memarg: arg }
+        | WASMInstruction::V128Load32Zero { memarg: arg }
+        | WASMInstruction::V128Load64Zero { memarg: arg }
+        | WASMInstruction::V128Store { memarg: arg } => mem_arg(arg),
+        WASMInstruction::V128Load8Lane { memarg: arg, lane }
+        | WASMInstruction::V128Load16Lane { memarg: arg, lane }
+        | WASMInstruction::V128Load32Lane { memarg: arg, lane }
+        | WASMInstruction::V128Load64Lane { memarg: arg, lane }
+        | WASMInstruction::V128Store8Lane { memarg: arg, lane }
+        | WASMInstruction::V128Store16Lane { memarg: arg, lane }
+        | WASMInstruction::V128Store32Lane { memarg: arg, lane }
+        | WASMInstruction::V128Store64Lane { memarg: arg, lane } => {
+            Immediates::MemArgLane(arg.offset, arg.align, arg.memory_index, *lane)
+        }
+        WASMInstruction::I8x16ExtractLaneS { lane }
+        | WASMInstruction::I8x16ExtractLaneU { lane }
+        | WASMInstruction::I8x16ReplaceLane { lane }
+        | WASMInstruction::I16x8ExtractLaneS { lane }
+        | WASMInstruction::I16x8ExtractLaneU { lane }
+        | WASMInstruction::I16x8ReplaceLane { lane }
+        | WASMInstruction::I32x4ExtractLane { lane }
+        | WASMInstruction::I32x4ReplaceLane { lane }
+        | WASMInstruction::I64x2ExtractLane { lane }
+        | WASMInstruction::I64x2ReplaceLane { lane }
+        | WASMInstruction::F32x4ExtractLane { lane }
+        | WASMInstruction::F32x4ReplaceLane { lane }
+        | WASMInstruction::F64x2ExtractLane { lane }
+        | WASMInstruction::F64x2ReplaceLane { lane } => Immediates::Lane(*lane),
+        WASMInstruction::I8x16Shuffle { lanes } => Immediates::Lanes(*lanes),
+        // All other instructions are identified by their opcode alone
+        _ => Immediates::None,
+    }
+}
+
+/// Returns whether two simple WebAssembly instructions are the same instruction with the same
+/// immediates (see [`Immediates`]).
+fn simple_eq(a: &WASMInstruction, b: &WASMInstruction) -> bool {
+    discriminant(a) == discriminant(b) && immediates(a) == immediates(b)
+}
+
+/// Hashes a simple WebAssembly instruction consistently with [`simple_eq`].
+fn hash_simple<H: Hasher>(instruction: &WASMInstruction, state: &mut H) {
+    discriminant(instruction).hash(state);
+    immediates(instruction).hash(state);
+}
+
+impl PartialEq for Instruction<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Instruction::I(a), Instruction::I(b)) => simple_eq(a, b),
+            (Instruction::Dup(a), Instruction::Dup(b)) => a == b,
+            (Instruction::New(a), Instruction::New(b)) => a == b,
+            (Instruction::InstanceOf(a), Instruction::InstanceOf(b)) => a == b,
+            (Instruction::GetField(a), Instruction::GetField(b)) => a == b,
+            (Instruction::PutField(a), Instruction::PutField(b)) => a == b,
+            (Instruction::CallStatic(a), Instruction::CallStatic(b)) => a == b,
+            (Instruction::CallVirtual(a), Instruction::CallVirtual(b)) => a == b,
+            (Instruction::ReturnCallStatic(a), Instruction::ReturnCallStatic(b)) => a == b,
+            (Instruction::ReturnCallVirtual(a), Instruction::ReturnCallVirtual(b)) => a == b,
+            (Instruction::FloatCmp(a), Instruction::FloatCmp(b)) => a == b,
+            (Instruction::DoubleCmp(a), Instruction::DoubleCmp(b)) => a == b,
+            // All variants with data have been handled above, so remaining variants are equal if
+            // and only if they're the same variant
+            _ => discriminant(self) == discriminant(other),
+        }
+    }
+}
+
+impl Eq for Instruction<'_> {}
+
+impl Hash for Instruction<'_> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        discriminant(self).hash(state);
+        match self {
+            Instruction::I(instruction) => hash_simple(instruction, state),
+            Instruction::Dup(t) => t.hash(state),
+            Instruction::New(class_name) | Instruction::InstanceOf(class_name) => {
+                class_name.hash(state)
+            }
+            Instruction::GetField(id) | Instruction::PutField(id) => id.hash(state),
+            Instruction::CallStatic(id)
+            | Instruction::CallVirtual(id)
+            | Instruction::ReturnCallStatic(id)
+            | Instruction::ReturnCallVirtual(id) => id.hash(state),
+            Instruction::FloatCmp(nan_behaviour) | Instruction::DoubleCmp(nan_behaviour) => {
+                nan_behaviour.hash(state)
+            }
+            Instruction::LongCmp
+            | Instruction::FloatRem
+            | Instruction::DoubleRem
+            | Instruction::Throw => {}
+        }
+    }
+}
+
+/// Controls whether Java `assert` statements are checked, by determining the value of the
+/// compiler-generated `$assertionsDisabled` static field, set with `--assume-assertions`.
+#[derive(clap::ArgEnum, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Assertions {
+    /// Assertions are checked, trapping if they fail (`$assertionsDisabled` is `false`)
+    Enabled,
+    /// Assertions are skipped (`$assertionsDisabled` is `true`), letting Binaryen remove them
+    Disabled,
+}
+
+/// Controls how JVM instructions the visitor doesn't support are compiled, set with
+/// `--on-unsupported`.
+#[derive(clap::ArgEnum, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum UnsupportedBehaviour {
+    /// Fail to compile the function containing the instruction
+    Error,
+    /// Replace the instruction with a placeholder, dropping its operands and pushing zero if it
+    /// produces a value. Falls back to `Trap` if the instruction's stack effect isn't fixed.
+    Stub,
+    /// Replace the instruction with `unreachable`, trapping if it's executed
+    Trap,
+}
+
+/// JVM feature that can't be compiled yet (e.g. an array instruction), and the feature blocking it.
+/// Returned as the cause of function compilation errors, or recorded in
+/// [`CompiledFunction::unsupported`] if replaced instead, so unsupported features can be reported
+/// with `--report-unsupported-json`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Unsupported {
+    /// What can't be compiled, e.g. `Newarray instruction`.
+    pub description: &'static str,
+    /// Blocking feature, e.g. `Array`.
+    pub category: &'static str,
+}
+
+impl fmt::Display for Unsupported {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} unimplemented ({})", self.description, self.category)
+    }
+}
+
+impl std::error::Error for Unsupported {}
+
+/// Context added to errors from [`CompileFunctionJob`]s, identifying the function that failed to
+/// compile. This can be recovered with [`anyhow::Error::downcast_ref`].
+#[derive(Debug)]
+pub struct CompileFunctionError {
+    /// Identifier of the function that failed to compile.
+    pub id: MethodId,
+}
+
+impl fmt::Display for CompileFunctionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Unable to compile {}", self.id)
+    }
+}
+
+/// Options controlling how functions are compiled, shared by all [`CompileFunctionJob`]s.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct CompileOptions {
+    /// Cache receivers of repeated field accesses in scratch locals instead of reloading them.
+    /// See [`cache_receivers`](crate::function::optimize::cache_receivers) for details.
+    pub cache_receivers: bool,
+    /// Whether Java `assert` statements should be checked.
+    pub assertions: Assertions,
+    /// How to compile instructions that aren't supported yet.
+    pub on_unsupported: UnsupportedBehaviour,
+    /// Rotate pre-tested loops so their condition is tested at the bottom, requiring one branch per
+    /// iteration instead of two.
+    pub loop_rotate: bool,
+    /// Merge linear chains of basic blocks into single blocks before structuring, reducing the
+    /// size of control flow graphs.
+    pub coalesce_sequences: bool,
+    /// Compile functions that fail to compile to stubs that trap when called, instead of failing.
+    pub keep_going: bool,
+    /// Translate 2-way conditionals whose branches just push a value (e.g. `c ? a : b`) into
+    /// `select`s instead of `if`/`else` blocks.
+    pub prefer_select: bool,
+    /// Share WebAssembly locals between local variables of the same type that are never live at
+    /// the same time, reducing the number of locals.
+    /// See [`coalesce`](crate::function::locals::LocalInterpretation::coalesce) for details.
+    pub coalesce_locals: bool,
+    /// Replace calls immediately followed by a return with tail calls, so tail-recursive methods
+    /// don't grow the stack. Requires the WebAssembly tail call proposal.
+    /// See [`mark_tail_calls`](crate::function::peephole::mark_tail_calls) for details.
+    pub tail_calls: bool,
+    /// Format to write intermediate control flow graphs in, if they're written at all.
+    pub graphs_format: GraphsFormat,
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        CompileOptions {
+            cache_receivers: false,
+            assertions: Assertions::Enabled,
+            on_unsupported: UnsupportedBehaviour::Error,
+            loop_rotate: false,
+            coalesce_sequences: false,
+            keep_going: false,
+            prefer_select: false,
+            coalesce_locals: false,
+            tail_calls: false,
+            graphs_format: GraphsFormat::Png,
+        }
+    }
 }
 
 /// Controls what `Instruction::FloatCmp` or `Instruction::DoubleCmp` should return if either of
 /// their arguments are NaN.
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub enum NaNBehaviour {
     /// If either `a` or `b` is NaN, return 1 (`a > b`)
     Greater,
@@ -107,7 +471,7 @@ impl NaNBehaviour {
 
 /// Compiled function containing WebAssembly with pseudo-[`Instruction`]s.
 /// Output of [`crate::function::CompileFunctionJob`].
-#[derive(Debug)]
+#[derive(Debug, Eq, PartialEq, Hash)]
 pub struct CompiledFunction {
     /// Unique universal identifier for this method in the program.
     pub id: MethodId,
@@ -115,6 +479,9 @@ pub struct CompiledFunction {
     pub flags: MethodAccessFlags,
     /// Method parameter and return types.
     pub descriptor: Arc<MethodDescriptor>,
+    /// Name of the source file this method was compiled from, if it was recorded. Written to the
+    /// output module's [`SOURCE_FILES_SECTION`](crate::output::SOURCE_FILES_SECTION) for debugging.
+    pub source_file: Option<Arc<String>>,
     // TODO (someday): maybe split this out into separate struct, then only one Option<...>,
     //  then assert is_none() in visit_import() to remove expect()s in visit_function()
     /// Local interpretation mapping JVM stack index and WebAssembly type pairs to their WebAssembly
@@ -124,9 +491,38 @@ pub struct CompiledFunction {
     /// Output of visiting phase. Only set if this function has code (i.e. it doesn't have `native`
     /// or `abstract` flags).
     pub code: Option<Vec<Instruction<'static>>>,
+    /// Unsupported features replaced with placeholders or `unreachable` when compiling this
+    /// function (see [`UnsupportedBehaviour`]), or the feature that caused it to be compiled to a
+    /// stub with [`CompileOptions::keep_going`]. Used for `--report-unsupported-json`.
+    pub unsupported: Vec<Unsupported>,
+}
+
+/// Parts of a [`CompiledFunction`] that determine its rendered WebAssembly function body and type.
+/// Functions with equal bodies can share a single function in the output module, even if they have
+/// different identifiers. See [`CompiledFunction::body`].
+#[derive(Eq, PartialEq, Hash)]
+pub struct FunctionBody<'a> {
+    pub is_static: bool,
+    pub function_type: &'a FunctionType,
+    pub locals: &'a LocalInterpretation,
+    pub code: &'a [Instruction<'static>],
 }
 
 impl CompiledFunction {
+    /// Returns this function's [`FunctionBody`], if it has code (i.e. it doesn't have `native` or
+    /// `abstract` flags).
+    pub fn body(&self) -> Option<FunctionBody<'_>> {
+        match (&self.locals, &self.code) {
+            (Some(locals), Some(code)) => Some(FunctionBody {
+                is_static: self.is_static(),
+                function_type: &self.descriptor.function_type,
+                locals,
+                code,
+            }),
+            _ => None,
+        }
+    }
+
     /// Returns `true` if and only if this function should be a WebAssembly import (it has it's
     /// implementation defined by the host language). This is the case for `native` methods.
     pub fn is_import(&self) -> bool {
@@ -144,10 +540,114 @@ impl CompiledFunction {
         self.flags.contains(MethodAccessFlags::STATIC)
     }
 
+    /// Returns `true` if and only if this function's code contains tail calls, i.e. it was compiled
+    /// with [`CompileOptions::tail_calls`] and calls a method in tail position.
+    pub fn has_tail_calls(&self) -> bool {
+        self.code.iter().flatten().any(|instruction| {
+            matches!(
+                instruction,
+                Instruction::ReturnCallStatic(_) | Instruction::ReturnCallVirtual(_)
+            )
+        })
+    }
+
     /// Returns `true` if and only if this function should be a WebAssembly export (callable from
-    /// the host language). This is the case for `public static` methods.
+    /// the host language). This is the case for `public static` methods, unless they're `synthetic`
+    /// or `bridge` methods generated by the compiler.
     pub fn is_export(&self) -> bool {
         self.flags
             .contains(MethodAccessFlags::PUBLIC | MethodAccessFlags::STATIC)
+            && !self
+                .flags
+                .intersects(MethodAccessFlags::SYNTHETIC | MethodAccessFlags::BRIDGE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::function::Instruction::{self, I};
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+    use wasm_encoder::{BlockType, Instruction as WASMInstruction, MemArg, ValType};
+
+    fn hash(instruction: &Instruction) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        instruction.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn simple_eq_hash() {
+        let mem_arg = |offset| MemArg {
+            offset,
+            align: 2,
+            memory_index: 0,
+        };
+        let equal = [
+            (WASMInstruction::I32Add, WASMInstruction::I32Add),
+            (WASMInstruction::I32Const(1), WASMInstruction::I32Const(1)),
+            (
+                WASMInstruction::F32Const(f32::NAN),
+                WASMInstruction::F32Const(f32::NAN),
+            ),
+            (
+                WASMInstruction::I32Load(mem_arg(4)),
+                WASMInstruction::I32Load(mem_arg(4)),
+            ),
+            (
+                WASMInstruction::If(BlockType::Result(ValType::I32)),
+                WASMInstruction::If(BlockType::Result(ValType::I32)),
+            ),
+            (
+                WASMInstruction::BrTable(vec![0, 1].into(), 2),
+                WASMInstruction::BrTable(vec![0, 1].into(), 2),
+            ),
+        ];
+        for (a, b) in equal {
+            let (a, b) = (I(a), I(b));
+            assert_eq!(a, b);
+            assert_eq!(hash(&a), hash(&b));
+        }
+
+        let different = [
+            (WASMInstruction::I32Add, WASMInstruction::I32Sub),
+            (WASMInstruction::I32Const(1), WASMInstruction::I32Const(2)),
+            (WASMInstruction::I32Const(1), WASMInstruction::I64Const(1)),
+            (
+                WASMInstruction::F64Const(0.0),
+                WASMInstruction::F64Const(-0.0),
+            ),
+            (
+                WASMInstruction::F32Const(f32::NAN),
+                WASMInstruction::F32Const(f32::from_bits(f32::NAN.to_bits() + 1)),
+            ),
+            (
+                WASMInstruction::I32Load(mem_arg(4)),
+                WASMInstruction::I32Load(mem_arg(8)),
+            ),
+            (
+                WASMInstruction::I32Load(mem_arg(4)),
+                WASMInstruction::I32Load(MemArg {
+                    align: 0,
+                    ..mem_arg(4)
+                }),
+            ),
+            (
+                WASMInstruction::If(BlockType::Result(ValType::I32)),
+                WASMInstruction::If(BlockType::Result(ValType::I64)),
+            ),
+            (
+                WASMInstruction::Block(BlockType::Empty),
+                WASMInstruction::Block(BlockType::FunctionType(0)),
+            ),
+            (WASMInstruction::LocalGet(0), WASMInstruction::LocalSet(0)),
+            (
+                WASMInstruction::BrTable(vec![0, 1].into(), 2),
+                WASMInstruction::BrTable(vec![1, 0].into(), 2),
+            ),
+        ];
+        for (a, b) in different {
+            assert_ne!(I(a), I(b));
+        }
     }
 }
@@ -0,0 +1,120 @@
+use crate::class::{Class, FieldId, JAVA_LANG_OBJECT};
+use crate::function::{CompiledFunction, Instruction};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Returns the name of the class declaring the field `id`, if it's been loaded.
+///
+/// Normally, a field's class name is the accessing class, not the superclass the field was defined
+/// in, so we need to search up the inheritance tree.
+fn declaring_class<'a>(
+    classes: &'a HashMap<Arc<String>, Class>,
+    id: &FieldId,
+) -> Option<&'a Class> {
+    let mut class_name = &id.class_name;
+    while class_name.as_str() != JAVA_LANG_OBJECT {
+        let class = classes.get(class_name)?;
+        if class.declares_field(&id.name) {
+            return Some(class);
+        }
+        class_name = &class.super_class_name;
+    }
+    None
+}
+
+/// Checks `final` fields are only written to in the declaring class's constructors, returning a
+/// diagnostic message for each illegal write. Each message is also logged as a warning.
+///
+/// Montera doesn't enforce JVM access rules, so this is purely a diagnostic, catching bytecode that
+/// wouldn't pass verification on a real JVM (see the `putfield` instruction in section [6.5] of the
+/// Java Virtual Machine Specification).
+///
+/// [6.5]: https://docs.oracle.com/javase/specs/jvms/se7/html/jvms-6.html#jvms-6.5.putfield
+pub fn verify_final_writes(
+    classes: &HashMap<Arc<String>, Class>,
+    functions: &[CompiledFunction],
+) -> Vec<String> {
+    let mut diagnostics = vec![];
+    for func in functions {
+        let code = match &func.code {
+            Some(code) => code,
+            None => continue,
+        };
+        let is_initializer = matches!(func.id.name.as_str(), "<init>" | "<clinit>");
+        for instruction in code {
+            let id = match instruction {
+                Instruction::PutField(id) => id,
+                _ => continue,
+            };
+            // Ignore fields in classes we haven't loaded, we can't know if these are final
+            let class = match declaring_class(classes, id) {
+                Some(class) => class,
+                None => continue,
+            };
+            if !class.final_fields.contains(&id.name) {
+                continue;
+            }
+            // Final fields may only be written to by the declaring class's initializers
+            if !is_initializer || func.id.class_name != class.class_name {
+                let message = format!(
+                    "{} writes to final field {}.{} outside of its constructor",
+                    func.id, class.class_name, id.name
+                );
+                warn!("{}", message);
+                diagnostics.push(message);
+            }
+        }
+    }
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::class::{FieldDescriptor, FieldId};
+    use crate::function::{verify_final_writes, Instruction};
+    use crate::tests::{compile_code_functions, str_arc};
+    use std::sync::Arc;
+
+    #[test]
+    fn verify_final_writes_in_constructor() -> anyhow::Result<()> {
+        let (classes, functions) = compile_code_functions(
+            "final int a;
+            int b;
+            Test(int a) { this.a = a; }
+            void set(int b) { this.b = b; }",
+            1,
+        )?;
+        assert!(verify_final_writes(&classes, &functions).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn verify_final_writes_outside_constructor() -> anyhow::Result<()> {
+        let (classes, mut functions) = compile_code_functions(
+            "final int a = 1;
+            void set(int a) {}",
+            1,
+        )?;
+
+        // `javac` won't let us write to a final field outside a constructor, so add the write
+        // ourselves: `this.a = a;`
+        let set = functions
+            .iter_mut()
+            .find(|func| func.id.name.as_str() == "set")
+            .unwrap();
+        let field_id = FieldId {
+            class_name: str_arc("Test"),
+            name: str_arc("a"),
+            descriptor: Arc::new(FieldDescriptor::Int),
+        };
+        let code = set.code.as_mut().unwrap();
+        code.insert(0, Instruction::PutField(field_id));
+
+        let diagnostics = verify_final_writes(&classes, &functions);
+        assert_eq!(
+            diagnostics,
+            ["Test.set(I)V writes to final field Test.a outside of its constructor"]
+        );
+        Ok(())
+    }
+}
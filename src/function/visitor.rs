@@ -1,14 +1,28 @@
-use crate::class::{ConstantPool, FieldDescriptor, NumericConstant, JAVA_LANG_OBJECT};
+use crate::class::{Constant, ConstantPool, FieldDescriptor, NumericConstant, JAVA_LANG_OBJECT};
 use crate::function::locals::LocalInterpretation;
+use crate::function::stack::{fixed_stack_effect, pushed_types, StackTypes};
 use crate::function::structure::{ConditionalKind, Loop, LoopKind, Structure, StructuredCode};
 use crate::function::Instruction::{self, I};
-use crate::function::NaNBehaviour;
+use crate::function::{Assertions, NaNBehaviour, StackMap, Unsupported, UnsupportedBehaviour};
 use crate::graph::{Node, NodeId};
+use crate::output::zero_const;
 use classfile_parser::code_attribute::Instruction as JVMInstruction;
+use std::cell::RefCell;
 use std::sync::Arc;
 use wasm_encoder::ValType;
 use wasm_encoder::{BlockType, Instruction as WASMInstruction};
 
+/// Returns an [`Unsupported`] error from the enclosing function, for a JVM feature that can't be
+/// compiled yet, blocked by the `category` feature (e.g. `"Array"`).
+macro_rules! unsupported {
+    ($description:expr, $category:expr) => {
+        bail!(Unsupported {
+            description: $description,
+            category: $category,
+        })
+    };
+}
+
 /// WebAssembly generation visiting phase operating on individual functions.
 /// Performed in parallel by [`crate::function::CompileFunctionJob`].
 ///
@@ -21,6 +35,111 @@ pub struct Visitor {
     pub const_pool: Arc<ConstantPool>,
     pub locals: Arc<LocalInterpretation>,
     pub code: StructuredCode,
+    pub stack_map: Arc<StackMap>,
+    pub assertions: Assertions,
+    pub on_unsupported: UnsupportedBehaviour,
+    pub loop_rotate: bool,
+    /// Scratch local for stashing 2-way conditionals' conditions when translating them into
+    /// `select`s. If `None`, 2-way conditionals are always translated into `if`/`else` blocks. See
+    /// [`select_branches`] for details.
+    pub select_local: Option<u32>,
+    /// Unsupported features replaced with placeholders or `unreachable` instead of failing the
+    /// function, in the order they were visited. See [`Visitor::visit_or_replace`].
+    pub replaced: RefCell<Vec<Unsupported>>,
+}
+
+/// Returns whether `instruction` pushes a single value without side effects or trapping, so it can
+/// be evaluated unconditionally as an operand of `select`.
+fn is_pure_push(instruction: &JVMInstruction) -> bool {
+    matches!(
+        instruction,
+        JVMInstruction::Aconstnull
+            | JVMInstruction::Iconstm1
+            | JVMInstruction::Iconst0
+            | JVMInstruction::Iconst1
+            | JVMInstruction::Iconst2
+            | JVMInstruction::Iconst3
+            | JVMInstruction::Iconst4
+            | JVMInstruction::Iconst5
+            | JVMInstruction::Lconst0
+            | JVMInstruction::Lconst1
+            | JVMInstruction::Fconst0
+            | JVMInstruction::Fconst1
+            | JVMInstruction::Fconst2
+            | JVMInstruction::Dconst0
+            | JVMInstruction::Dconst1
+            | JVMInstruction::Bipush(_)
+            | JVMInstruction::Sipush(_)
+            // Always long or double constants, unlike Ldc which may load a String or Class
+            | JVMInstruction::Ldc2W(_)
+            | JVMInstruction::Aload(_)
+            | JVMInstruction::AloadWide(_)
+            | JVMInstruction::Aload0
+            | JVMInstruction::Aload1
+            | JVMInstruction::Aload2
+            | JVMInstruction::Aload3
+            | JVMInstruction::Dload(_)
+            | JVMInstruction::DloadWide(_)
+            | JVMInstruction::Dload0
+            | JVMInstruction::Dload1
+            | JVMInstruction::Dload2
+            | JVMInstruction::Dload3
+            | JVMInstruction::Fload(_)
+            | JVMInstruction::FloadWide(_)
+            | JVMInstruction::Fload0
+            | JVMInstruction::Fload1
+            | JVMInstruction::Fload2
+            | JVMInstruction::Fload3
+            | JVMInstruction::Iload(_)
+            | JVMInstruction::IloadWide(_)
+            | JVMInstruction::Iload0
+            | JVMInstruction::Iload1
+            | JVMInstruction::Iload2
+            | JVMInstruction::Iload3
+            | JVMInstruction::Lload(_)
+            | JVMInstruction::LloadWide(_)
+            | JVMInstruction::Lload0
+            | JVMInstruction::Lload1
+            | JVMInstruction::Lload2
+            | JVMInstruction::Lload3
+    )
+}
+
+/// Returns whether `n` is a branch of a 2-way conditional that just pushes a single value with
+/// [`is_pure_push`] (optionally followed by a `GOTO`), before converging on `follow`.
+fn is_select_branch(code: &StructuredCode, n: NodeId, follow: NodeId) -> bool {
+    let node = &code.g[n];
+    if n == follow || node.in_degree() != 1 || node.successors != [follow] {
+        return false;
+    }
+    match &node.value {
+        Structure::Block(instructions) => match instructions.as_slice() {
+            [(_, push)] => is_pure_push(push),
+            [(_, push), (_, JVMInstruction::Goto(_) | JVMInstruction::GotoW(_))] => {
+                is_pure_push(push)
+            }
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Returns the true and false branches of the 2-way conditional at `header` if they both just push
+/// a single value before converging on `follow` (e.g. `c ? a : b`). These values can be computed
+/// unconditionally, so the conditional can be translated into a `select` instead of an
+/// `if`/`else` block.
+pub fn select_branches(
+    code: &StructuredCode,
+    header: NodeId,
+    follow: NodeId,
+) -> Option<(NodeId, NodeId)> {
+    let node = &code.g[header];
+    let (true_node, false_node) = (node.true_branch()?, node.false_branch()?);
+    if is_select_branch(code, true_node, follow) && is_select_branch(code, false_node, follow) {
+        Some((true_node, false_node))
+    } else {
+        None
+    }
 }
 
 impl Visitor {
@@ -29,9 +148,13 @@ impl Visitor {
     /// This is arguably the most important function in the project. An exhaustive `match` statement
     /// ensures all parsed JVM instructions are handled or explicitly marked as unimplemented.
     /// If instructions are added in the future, a compile time error will be produced.
+    ///
+    /// `stack` contains the types of values on the operand stack before this instruction, for
+    /// instructions whose semantics depend on them.
     fn visit(
         &self,
         out: &mut Vec<Instruction<'_>>,
+        stack: &StackTypes,
         instruction: &JVMInstruction,
     ) -> anyhow::Result<()> {
         let const_pool = &*self.const_pool;
@@ -39,8 +162,8 @@ impl Visitor {
         // Instructions defined here: https://docs.oracle.com/javase/specs/jvms/se7/html/jvms-6.html
         // Unimplemented instructions have the blocking feature in brackets.
         match instruction {
-            JVMInstruction::Aaload => bail!("Aaload instruction unimplemented (Array)"),
-            JVMInstruction::Aastore => bail!("Aastore instruction unimplemented (Array)"),
+            JVMInstruction::Aaload => unsupported!("Aaload instruction", "Array"),
+            JVMInstruction::Aastore => unsupported!("Aastore instruction", "Array"),
             JVMInstruction::Aconstnull => out.push(I(WASMInstruction::I32Const(0))),
             JVMInstruction::Aload(n) => locals.get(out, ValType::I32, *n as u32),
             JVMInstruction::AloadWide(n) => locals.get(out, ValType::I32, *n as u32),
@@ -48,9 +171,9 @@ impl Visitor {
             JVMInstruction::Aload1 => locals.get(out, ValType::I32, 1),
             JVMInstruction::Aload2 => locals.get(out, ValType::I32, 2),
             JVMInstruction::Aload3 => locals.get(out, ValType::I32, 3),
-            JVMInstruction::Anewarray(_) => bail!("Anewarray instruction unimplemented (Array)"),
+            JVMInstruction::Anewarray(_) => unsupported!("Anewarray instruction", "Array"),
             JVMInstruction::Areturn => out.push(I(WASMInstruction::Return)),
-            JVMInstruction::Arraylength => bail!("Arraylength instruction unimplemented (Array)"),
+            JVMInstruction::Arraylength => unsupported!("Arraylength instruction", "Array"),
             JVMInstruction::Astore(n) => locals.set(out, ValType::I32, *n as u32),
             JVMInstruction::AstoreWide(n) => locals.set(out, ValType::I32, *n as u32),
             JVMInstruction::Astore0 => locals.set(out, ValType::I32, 0),
@@ -59,23 +182,23 @@ impl Visitor {
             JVMInstruction::Astore3 => locals.set(out, ValType::I32, 3),
             JVMInstruction::Athrow => {
                 // Exceptions are not yet supported, but are required for assertions.
-                // In this case, emit an unreachable instruction to cause a trap.
-                out.push(I(WASMInstruction::Unreachable))
+                // In this case, emit a throw pseudo-instruction that's rendered as a trap.
+                out.push(Instruction::Throw)
             }
-            JVMInstruction::Baload => bail!("Baload instruction unimplemented (Array)"),
-            JVMInstruction::Bastore => bail!("Bastore instruction unimplemented (Array)"),
+            JVMInstruction::Baload => unsupported!("Baload instruction", "Array"),
+            JVMInstruction::Bastore => unsupported!("Bastore instruction", "Array"),
             JVMInstruction::Bipush(n) => out.push(I(WASMInstruction::I32Const(*n as i32))),
-            JVMInstruction::Caload => bail!("Caload instruction unimplemented (Array)"),
-            JVMInstruction::Castore => bail!("Castore instruction unimplemented (Array)"),
+            JVMInstruction::Caload => unsupported!("Caload instruction", "Array"),
+            JVMInstruction::Castore => unsupported!("Castore instruction", "Array"),
             JVMInstruction::Checkcast(_) => {
-                bail!("Checkcast instruction unimplemented (Exception)")
+                unsupported!("Checkcast instruction", "Exception")
             }
             JVMInstruction::D2f => out.push(I(WASMInstruction::F32DemoteF64)),
             JVMInstruction::D2i => out.push(I(WASMInstruction::I32TruncF64S)),
             JVMInstruction::D2l => out.push(I(WASMInstruction::I64TruncF64S)),
             JVMInstruction::Dadd => out.push(I(WASMInstruction::F64Add)),
-            JVMInstruction::Daload => bail!("Daload instruction unimplemented (Array)"),
-            JVMInstruction::Dastore => bail!("Dastore instruction unimplemented (Array)"),
+            JVMInstruction::Daload => unsupported!("Daload instruction", "Array"),
+            JVMInstruction::Dastore => unsupported!("Dastore instruction", "Array"),
             JVMInstruction::Dcmpg => out.push(Instruction::DoubleCmp(NaNBehaviour::Greater)),
             JVMInstruction::Dcmpl => out.push(Instruction::DoubleCmp(NaNBehaviour::Lesser)),
             JVMInstruction::Dconst0 => out.push(I(WASMInstruction::F64Const(0.0))),
@@ -100,28 +223,44 @@ impl Visitor {
             JVMInstruction::Dsub => out.push(I(WASMInstruction::F64Sub)),
             // The semantics of Dup* instructions depends on the type of the stack at runtime.
             // Some of these instructions also insert copies 2/3 values down the stack.
-            // To implement these properly, we'd need to perform type inference on the emitted
-            // instructions, then insert the appropriate scratch locals and instructions.
+            // To implement these properly, we'd need to use the tracked stack types to insert the
+            // appropriate scratch locals and instructions for each value involved.
             //
-            // The Dup instruction is used after a New to run the constructor and store a reference
-            // in a local. Technically, it can be used with any category 1 computational type
-            // (https://docs.oracle.com/javase/specs/jvms/se7/html/jvms-2.html#jvms-2.11.1),
-            // but I've only observed it being used with int (i32) computational types.
+            // The Dup instruction is most commonly used after a New to run the constructor and
+            // store a reference in a local, but can be used with any category 1 computational type
+            // (https://docs.oracle.com/javase/specs/jvms/se7/html/jvms-2.html#jvms-2.11.1), so we
+            // use the tracked stack type. At merge points, this comes from the StackMapTable.
             //
-            // Therefore, if this instruction is produced, we add an additional i32 scratch local
-            // to the function, and use local_tee/get instructions to duplicate the value.
-            JVMInstruction::Dup => out.push(Instruction::Dup),
-            JVMInstruction::Dupx1 => bail!("Dupx1 instruction unimplemented (Stack Type)"),
-            JVMInstruction::Dupx2 => bail!("Dupx2 instruction unimplemented (Stack Type)"),
-            JVMInstruction::Dup2 => bail!("Dup2 instruction unimplemented (Stack Type)"),
-            JVMInstruction::Dup2x1 => bail!("Dup2x1 instruction unimplemented (Stack Type)"),
-            JVMInstruction::Dup2x2 => bail!("Dup2x2 instruction unimplemented (Stack Type)"),
+            // If this instruction is produced, we add an additional scratch local of this type to
+            // the function, and use local_tee/get instructions to duplicate the value.
+            // The type is only unknown if this block wasn't a branch target and the value was
+            // pushed before it. Guessing the type would produce invalid code if we guessed wrong.
+            JVMInstruction::Dup => match stack.top() {
+                Some(t) => out.push(Instruction::Dup(t)),
+                None => unsupported!("Dup instruction for unknown types", "Stack Type"),
+            },
+            // Dup2 duplicates a single category 2 value (long/double), or two category 1 values.
+            // The former is a single WebAssembly value, so can be duplicated like Dup. The latter
+            // is only generated by javac for array element compound assignments (e.g. `a[i]++`),
+            // which aren't supported yet. Guessing the type here would produce invalid code if we
+            // guessed wrong, so require it to be known.
+            JVMInstruction::Dup2 => match stack.top() {
+                Some(t @ (ValType::I64 | ValType::F64)) => out.push(Instruction::Dup(t)),
+                Some(_) => {
+                    unsupported!("Dup2 instruction for category 1 values", "Stack Type")
+                }
+                None => unsupported!("Dup2 instruction for unknown types", "Stack Type"),
+            },
+            JVMInstruction::Dupx1 => unsupported!("Dupx1 instruction", "Stack Type"),
+            JVMInstruction::Dupx2 => unsupported!("Dupx2 instruction", "Stack Type"),
+            JVMInstruction::Dup2x1 => unsupported!("Dup2x1 instruction", "Stack Type"),
+            JVMInstruction::Dup2x2 => unsupported!("Dup2x2 instruction", "Stack Type"),
             JVMInstruction::F2d => out.push(I(WASMInstruction::F64PromoteF32)),
             JVMInstruction::F2i => out.push(I(WASMInstruction::I32TruncF32S)),
             JVMInstruction::F2l => out.push(I(WASMInstruction::I64TruncF32S)),
             JVMInstruction::Fadd => out.push(I(WASMInstruction::F32Add)),
-            JVMInstruction::Faload => bail!("Faload instruction unimplemented (Array)"),
-            JVMInstruction::Fastore => bail!("Fastore instruction unimplemented (Array)"),
+            JVMInstruction::Faload => unsupported!("Faload instruction", "Array"),
+            JVMInstruction::Fastore => unsupported!("Fastore instruction", "Array"),
             JVMInstruction::Fcmpg => out.push(Instruction::FloatCmp(NaNBehaviour::Greater)),
             JVMInstruction::Fcmpl => out.push(Instruction::FloatCmp(NaNBehaviour::Lesser)),
             JVMInstruction::Fconst0 => out.push(I(WASMInstruction::F32Const(0.0))),
@@ -153,24 +292,29 @@ impl Visitor {
                 // Static fields are not yet supported, but are required for assertions
                 let id = const_pool.field(*n);
                 if *id.name == "$assertionsDisabled" && *id.descriptor == FieldDescriptor::Boolean {
-                    // Always enable assertions
-                    out.push(I(WASMInstruction::I32Const(0)));
+                    // Assertions are checked unless disabled with `--assume-assertions disabled`
+                    let disabled = self.assertions == Assertions::Disabled;
+                    out.push(I(WASMInstruction::I32Const(disabled as i32)));
                 } else {
-                    bail!("Getstatic instruction unimplemented (Static Field)")
+                    unsupported!("Getstatic instruction", "Static Field")
                 }
             }
             JVMInstruction::Goto(_) => out.push(I(WASMInstruction::Nop)),
             JVMInstruction::GotoW(_) => out.push(I(WASMInstruction::Nop)),
-            JVMInstruction::I2b => out.push(I(WASMInstruction::Nop)),
-            JVMInstruction::I2c => out.push(I(WASMInstruction::Nop)),
+            JVMInstruction::I2b => out.push(I(WASMInstruction::I32Extend8S)),
+            JVMInstruction::I2c => {
+                // chars are unsigned, so zero-extend rather than sign-extend
+                out.push(I(WASMInstruction::I32Const(0xffff)));
+                out.push(I(WASMInstruction::I32And));
+            }
             JVMInstruction::I2d => out.push(I(WASMInstruction::F64ConvertI32S)),
             JVMInstruction::I2f => out.push(I(WASMInstruction::F32ConvertI32S)),
             JVMInstruction::I2l => out.push(I(WASMInstruction::I64ExtendI32S)),
-            JVMInstruction::I2s => out.push(I(WASMInstruction::Nop)),
+            JVMInstruction::I2s => out.push(I(WASMInstruction::I32Extend16S)),
             JVMInstruction::Iadd => out.push(I(WASMInstruction::I32Add)),
-            JVMInstruction::Iaload => bail!("Iaload instruction unimplemented (Array)"),
+            JVMInstruction::Iaload => unsupported!("Iaload instruction", "Array"),
             JVMInstruction::Iand => out.push(I(WASMInstruction::I32And)),
-            JVMInstruction::Iastore => bail!("Iastore instruction unimplemented (Array)"),
+            JVMInstruction::Iastore => unsupported!("Iastore instruction", "Array"),
             JVMInstruction::Iconstm1 => out.push(I(WASMInstruction::I32Const(-1))),
             JVMInstruction::Iconst0 => out.push(I(WASMInstruction::I32Const(0))),
             JVMInstruction::Iconst1 => out.push(I(WASMInstruction::I32Const(1))),
@@ -240,10 +384,10 @@ impl Visitor {
                 out.push(Instruction::InstanceOf(class_name));
             }
             JVMInstruction::Invokedynamic(_) => {
-                bail!("Invokedynamic instruction unimplemented (Dynamic Type)")
+                unsupported!("Invokedynamic instruction", "Dynamic Type")
             }
             JVMInstruction::Invokeinterface { .. } => {
-                bail!("Invokeinterface instruction unimplemented (Interface)")
+                unsupported!("Invokeinterface instruction", "Interface")
             }
             JVMInstruction::Invokespecial(n) => {
                 let id = const_pool.method(*n);
@@ -251,6 +395,8 @@ impl Visitor {
                     // Implicit Object super(), no-op, but need to consume this reference
                     out.push(I(WASMInstruction::Drop))
                 } else {
+                    // Constructors, private and superclass methods (e.g. `super.m()`) are called
+                    // without dynamic dispatch, the renderer resolves inherited implementations
                     out.push(Instruction::CallStatic(id));
                 }
             }
@@ -276,34 +422,42 @@ impl Visitor {
             JVMInstruction::Isub => out.push(I(WASMInstruction::I32Sub)),
             JVMInstruction::Iushr => out.push(I(WASMInstruction::I32ShrU)),
             JVMInstruction::Ixor => out.push(I(WASMInstruction::I32Xor)),
-            JVMInstruction::Jsr(_) => bail!("Jsr instruction unimplemented (Irreducible)"),
-            JVMInstruction::JsrW(_) => bail!("JsrW instruction unimplemented (Irreducible)"),
+            JVMInstruction::Jsr(_) => unsupported!("Jsr instruction", "Irreducible"),
+            JVMInstruction::JsrW(_) => unsupported!("JsrW instruction", "Irreducible"),
             JVMInstruction::L2d => out.push(I(WASMInstruction::F64ConvertI64S)),
             JVMInstruction::L2f => out.push(I(WASMInstruction::F32ConvertI64S)),
             JVMInstruction::L2i => out.push(I(WASMInstruction::I32WrapI64)),
             JVMInstruction::Ladd => out.push(I(WASMInstruction::I64Add)),
-            JVMInstruction::Laload => bail!("Laload instruction unimplemented (Array)"),
+            JVMInstruction::Laload => unsupported!("Laload instruction", "Array"),
             JVMInstruction::Land => out.push(I(WASMInstruction::I64And)),
-            JVMInstruction::Lastore => bail!("Lastore instruction unimplemented (Array)"),
+            JVMInstruction::Lastore => unsupported!("Lastore instruction", "Array"),
             JVMInstruction::Lcmp => out.push(Instruction::LongCmp),
             JVMInstruction::Lconst0 => out.push(I(WASMInstruction::I64Const(0))),
             JVMInstruction::Lconst1 => out.push(I(WASMInstruction::I64Const(1))),
             JVMInstruction::Ldc(n) => {
-                let num = const_pool.num(*n as u16);
-                out.push(match num {
-                    NumericConstant::Integer(num) => I(WASMInstruction::I32Const(num)),
-                    NumericConstant::Float(num) => I(WASMInstruction::F32Const(num)),
+                let constant = const_pool.get(*n as u16);
+                out.push(match constant {
+                    Constant::Number(NumericConstant::Integer(num)) => {
+                        I(WASMInstruction::I32Const(num))
+                    }
+                    Constant::Number(NumericConstant::Float(num)) => {
+                        I(WASMInstruction::F32Const(num))
+                    }
                     // TODO (someday): Ldc can be reference to String, Class or Method
-                    _ => bail!("Ldc constants other than int/float unimplemented"),
+                    _ => unsupported!("Ldc constants other than int/float", "String/Class"),
                 })
             }
             JVMInstruction::LdcW(n) => {
-                let num = const_pool.num(*n);
-                out.push(match num {
-                    NumericConstant::Integer(num) => I(WASMInstruction::I32Const(num)),
-                    NumericConstant::Float(num) => I(WASMInstruction::F32Const(num)),
+                let constant = const_pool.get(*n);
+                out.push(match constant {
+                    Constant::Number(NumericConstant::Integer(num)) => {
+                        I(WASMInstruction::I32Const(num))
+                    }
+                    Constant::Number(NumericConstant::Float(num)) => {
+                        I(WASMInstruction::F32Const(num))
+                    }
                     // TODO (someday): LdcW can be reference to String, Class or Method
-                    _ => bail!("LdcW constants other than int/float unimplemented"),
+                    _ => unsupported!("LdcW constants other than int/float", "String/Class"),
                 })
             }
             JVMInstruction::Ldc2W(n) => {
@@ -327,7 +481,7 @@ impl Visitor {
                 out.push(I(WASMInstruction::I64Mul));
             }
             JVMInstruction::Lookupswitch { .. } => {
-                bail!("Lookupswitch instruction unimplemented (n-Way Branch)")
+                unsupported!("Lookupswitch instruction", "n-Way Branch")
             }
             JVMInstruction::Lor => out.push(I(WASMInstruction::I64Or)),
             JVMInstruction::Lrem => out.push(I(WASMInstruction::I64RemS)),
@@ -356,17 +510,17 @@ impl Visitor {
             }
             JVMInstruction::Lxor => out.push(I(WASMInstruction::I64Xor)),
             JVMInstruction::Monitorenter => {
-                bail!("Monitorenter instruction unimplemented (Monitor)")
+                unsupported!("Monitorenter instruction", "Monitor")
             }
-            JVMInstruction::Monitorexit => bail!("Monitorexit instruction unimplemented (Monitor)"),
+            JVMInstruction::Monitorexit => unsupported!("Monitorexit instruction", "Monitor"),
             JVMInstruction::Multianewarray { .. } => {
-                bail!("Multianewarray instruction unimplemented (Array)")
+                unsupported!("Multianewarray instruction", "Array")
             }
             JVMInstruction::New(n) => {
                 let class_name = const_pool.class_name(*n);
                 out.push(Instruction::New(class_name));
             }
-            JVMInstruction::Newarray(_) => bail!("Newarray instruction unimplemented (Array)"),
+            JVMInstruction::Newarray(_) => unsupported!("Newarray instruction", "Array"),
             JVMInstruction::Nop => out.push(I(WASMInstruction::Nop)),
             JVMInstruction::Pop => out.push(I(WASMInstruction::Drop)),
             JVMInstruction::Pop2 => out.push(I(WASMInstruction::Drop)),
@@ -375,22 +529,64 @@ impl Visitor {
                 out.push(Instruction::PutField(id));
             }
             JVMInstruction::Putstatic(_) => {
-                bail!("Putstatic instruction unimplemented (Static Field)")
+                unsupported!("Putstatic instruction", "Static Field")
             }
-            JVMInstruction::Ret(_) => bail!("Ret instruction unimplemented (Irreducible)"),
-            JVMInstruction::RetWide(_) => bail!("RetWide instruction unimplemented (Irreducible)"),
+            JVMInstruction::Ret(_) => unsupported!("Ret instruction", "Irreducible"),
+            JVMInstruction::RetWide(_) => unsupported!("RetWide instruction", "Irreducible"),
             JVMInstruction::Return => out.push(I(WASMInstruction::Return)),
-            JVMInstruction::Saload => bail!("Saload instruction unimplemented (Array)"),
-            JVMInstruction::Sastore => bail!("Sastore instruction unimplemented (Array)"),
+            JVMInstruction::Saload => unsupported!("Saload instruction", "Array"),
+            JVMInstruction::Sastore => unsupported!("Sastore instruction", "Array"),
             JVMInstruction::Sipush(n) => out.push(I(WASMInstruction::I32Const(*n as i32))),
-            JVMInstruction::Swap => bail!("Swap instruction unimplemented (Stack Type)"),
+            JVMInstruction::Swap => unsupported!("Swap instruction", "Stack Type"),
             JVMInstruction::Tableswitch { .. } => {
-                bail!("Tableswitch instruction unimplemented (n-Way Branch)")
+                unsupported!("Tableswitch instruction", "n-Way Branch")
             }
         };
         Ok(())
     }
 
+    /// Translates a single JVM instruction like [`Visitor::visit`], but if the instruction is
+    /// unsupported, replaces it with a placeholder or `unreachable` depending on `on_unsupported`,
+    /// instead of failing the whole function. See [`UnsupportedBehaviour`] for more details.
+    fn visit_or_replace(
+        &self,
+        out: &mut Vec<Instruction<'_>>,
+        stack: &StackTypes,
+        instruction: &JVMInstruction,
+    ) -> anyhow::Result<()> {
+        let e = match self.visit(out, stack, instruction) {
+            Ok(()) => return Ok(()),
+            Err(e) => e,
+        };
+        let effect = match self.on_unsupported {
+            UnsupportedBehaviour::Error => return Err(e),
+            UnsupportedBehaviour::Stub => fixed_stack_effect(&self.const_pool, instruction),
+            UnsupportedBehaviour::Trap => None,
+        };
+        // Record what was replaced for `--report-unsupported-json`
+        if let Some(unsupported) = e.downcast_ref::<Unsupported>() {
+            self.replaced.borrow_mut().push(*unsupported);
+        }
+        match effect {
+            Some((pops, push)) => {
+                warn!("Stubbing unsupported instruction: {}", e);
+                for _ in 0..pops {
+                    out.push(I(WASMInstruction::Drop));
+                }
+                if let Some(t) = push {
+                    out.push(I(zero_const(t)));
+                }
+            }
+            None => {
+                // unreachable makes the stack polymorphic, so the rest of the block still
+                // validates whatever the instruction's stack effect
+                warn!("Trapping on unsupported instruction: {}", e);
+                out.push(I(WASMInstruction::Unreachable));
+            }
+        }
+        Ok(())
+    }
+
     /// Translates a [`Structure`] (either a basic block or compound short-circuit conditional) into
     /// one or more WebAssembly (pseudo-)instructions.
     fn visit_struct(
@@ -400,9 +596,13 @@ impl Visitor {
     ) -> anyhow::Result<()> {
         match structure {
             Structure::Block(instructions) => {
-                // Basic block, visit all instructions in sequence
-                for instruction in instructions {
-                    self.visit(out, instruction)?;
+                // Basic block, visit all instructions in sequence, tracking the types of values on
+                // the stack from the block's stack map frame (if any)
+                let offset = instructions.first().map_or(0, |(offset, _)| *offset);
+                let mut stack = StackTypes::at_block(&self.stack_map, offset);
+                for (_, instruction) in instructions {
+                    self.visit_or_replace(out, &stack, instruction)?;
+                    stack.update(&self.const_pool, instruction);
                 }
             }
             Structure::CompoundConditional {
@@ -470,24 +670,29 @@ impl Visitor {
 
     /// Translates a structured [`Loop`] (with identified type, header, latching and follow node)
     /// into multiple WebAssembly (pseudo-)instructions.
-    fn visit_loop(&self, out: &mut Vec<Instruction<'_>>, loop_info: Loop) -> anyhow::Result<()> {
+    fn visit_loop(&self, out: &mut Vec<Instruction<'_>>, loop_info: &Loop) -> anyhow::Result<()> {
         // Allow easily breaking out of the loop...
         out.push(I(WASMInstruction::Block(BlockType::Empty)));
-        // ...and continuing to the next iteration
-        out.push(I(WASMInstruction::Loop(BlockType::Empty)));
-        // (this will almost certainly get optimised by wasm-opt to just "loop")
 
         match loop_info.kind {
             LoopKind::PreTested => {
                 let header = &self.code.g[loop_info.header];
                 assert_eq!(header.out_degree(), 2); // Header should be 2-way conditional
 
+                let single_node = loop_info.header == loop_info.latching
+                    && header.true_branch() == Some(loop_info.header);
+                if self.loop_rotate && !single_node {
+                    return self.visit_rotated_loop(out, loop_info);
+                }
+
+                // ...and continuing to the next iteration
+                out.push(I(WASMInstruction::Loop(BlockType::Empty)));
+                // (this will almost certainly get optimised by wasm-opt to just "loop")
+
                 // If this is a pre-tested loop, the condition is in the header, so evaluate it
                 self.visit_node(out, header)?;
 
-                if loop_info.header == loop_info.latching
-                    && header.successors[1 /* true */] == loop_info.header
-                {
+                if single_node {
                     // Special case: single node post-tested loop where latching node is the header,
                     // and the true branch is the header again. In this case, branch back to the
                     // start of the loop if the condition is true, and break out otherwise.
@@ -510,6 +715,9 @@ impl Visitor {
                 }
             }
             LoopKind::PostTested => {
+                // ...and continuing to the next iteration
+                out.push(I(WASMInstruction::Loop(BlockType::Empty)));
+
                 let latching = &self.code.g[loop_info.latching];
                 assert_eq!(latching.out_degree(), 2); // Latching should be 2-way conditional
 
@@ -537,6 +745,89 @@ impl Visitor {
         Ok(())
     }
 
+    /// Translates a structured pre-tested [`Loop`] into a rotated form, where the condition is
+    /// tested once before entering the loop, then again at the bottom of each iteration. This
+    /// duplicates the header's code, but only requires one branch per iteration instead of two.
+    /// Note the outer `block` must already have been pushed. Enabled with `--loop-rotate`.
+    ///
+    /// ```text
+    /// block
+    ///   <header>
+    ///   br_if 0     ;; break out if the condition is true
+    ///   loop
+    ///     <body>
+    ///     <header>
+    ///     i32.eqz
+    ///     br_if 0   ;; continue if the condition is false
+    ///   end
+    /// end
+    /// ```
+    fn visit_rotated_loop(
+        &self,
+        out: &mut Vec<Instruction<'_>>,
+        loop_info: &Loop,
+    ) -> anyhow::Result<()> {
+        let header = &self.code.g[loop_info.header];
+        // Follow should be true branch of header conditional, so the body should be the false one
+        assert_eq!(header.successors[1], loop_info.follow);
+        let body = header.successors[0];
+
+        // Test the condition once before entering the loop, skipping it entirely if true...
+        self.visit_node(out, header)?;
+        out.push(I(WASMInstruction::BrIf(0)));
+
+        // ...then run the body, retesting the condition at the end of each iteration
+        out.push(I(WASMInstruction::Loop(BlockType::Empty)));
+        self.visit_until(out, body, Some(loop_info.header), false)?;
+        self.visit_node(out, header)?;
+        out.push(I(WASMInstruction::I32Eqz));
+        out.push(I(WASMInstruction::BrIf(0)));
+
+        out.push(I(WASMInstruction::End));
+        out.push(I(WASMInstruction::End));
+
+        Ok(())
+    }
+
+    /// Returns the type of the `if` block for a 2-way conditional with `true_node` and `false_node`
+    /// branches converging on `follow`. If both branches are single blocks leaving one value of the
+    /// same type on the stack, and `follow`'s stack map frame expects that value (e.g.
+    /// `return c ? f(a) : b;`), this is a value-producing block. Otherwise, the block is empty.
+    fn conditional_block_type(
+        &self,
+        true_node: NodeId,
+        false_node: NodeId,
+        follow: NodeId,
+    ) -> BlockType {
+        let branch_type = |n: NodeId| {
+            let node = &self.code.g[n];
+            if n == follow || node.successors != [follow] {
+                return None;
+            }
+            match &node.value {
+                Structure::Block(instructions) => {
+                    let instructions = instructions.iter().map(|(_, instruction)| instruction);
+                    match pushed_types(&self.const_pool, instructions)?.as_slice() {
+                        &[t] => Some(t),
+                        _ => None,
+                    }
+                }
+                _ => None,
+            }
+        };
+        let follow_offset = match &self.code.g[follow].value {
+            Structure::Block(instructions) => instructions.first().map(|(offset, _)| *offset),
+            _ => None,
+        };
+        let follow_type = follow_offset
+            .and_then(|offset| self.stack_map.get(&offset))
+            .and_then(|frame| frame.last().copied());
+        match (branch_type(true_node), branch_type(false_node)) {
+            (Some(t), Some(f)) if t == f && follow_type == Some(t) => BlockType::Result(t),
+            _ => BlockType::Empty,
+        }
+    }
+
     /// Translates a structured 2-way conditional (with identified header and follow node) into
     /// multiple WebAssembly (pseudo-)instructions.
     fn visit_conditional(
@@ -551,7 +842,22 @@ impl Visitor {
         let false_node = node.successors[0];
 
         self.visit_node(out, node)?;
-        out.push(I(WASMInstruction::If(BlockType::Empty)));
+
+        // If both branches just push a value, evaluate both and select the right one, stashing the
+        // condition as it must be on top of the stack
+        if let Some(select_local) = self.select_local {
+            if select_branches(&self.code, header, follow).is_some() {
+                out.push(I(WASMInstruction::LocalSet(select_local)));
+                self.visit_node(out, &self.code.g[true_node])?;
+                self.visit_node(out, &self.code.g[false_node])?;
+                out.push(I(WASMInstruction::LocalGet(select_local)));
+                out.push(I(WASMInstruction::Select));
+                return Ok(());
+            }
+        }
+
+        let block_type = self.conditional_block_type(true_node, false_node, follow);
+        out.push(I(WASMInstruction::If(block_type)));
         {
             self.visit_until(out, true_node, Some(follow), false)?;
         }
@@ -587,7 +893,7 @@ impl Visitor {
             if !ignore_first_loop {
                 if let Some(loop_info) = self.code.loops.get(n) {
                     // If n is a loop header node...
-                    self.visit_loop(out, *loop_info)?;
+                    self.visit_loop(out, loop_info)?;
                     n = loop_info.follow;
                     continue;
                 }
@@ -221,8 +221,66 @@ impl<T> From<HashMap<NodeId, T>> for NodeMap<T> {
 
 #[cfg(test)]
 mod tests {
-    use crate::graph::{Graph, NodeMap, NodeSet};
+    use crate::graph::{Graph, NodeId, NodeMap, NodeSet};
     use itertools::Itertools;
+    use std::collections::HashMap;
+
+    impl<T> NodeMap<T> {
+        /// Returns the [`Entry`] for the node, for in-place insertion or update of its value (see
+        /// [`HashMap::entry`]).
+        fn entry(&mut self, key: NodeId) -> Entry<'_, T> {
+            if key.0 >= self.inner.len() {
+                self.inner.resize_with(key.0 + 1, || None)
+            }
+            let slot = &mut self.inner[key.0];
+            Entry { key, slot }
+        }
+    }
+
+    /// View into a single node's (possibly vacant) slot in a [`NodeMap`], returned by
+    /// [`NodeMap::entry`].
+    ///
+    /// Unlike [`std::collections::hash_map::Entry`], this isn't split into occupied and vacant
+    /// variants, as the slot for the node always exists once the entry has been created.
+    #[derive(Debug)]
+    struct Entry<'a, T> {
+        key: NodeId,
+        slot: &'a mut Option<T>,
+    }
+
+    impl<'a, T> Entry<'a, T> {
+        /// Returns the node this entry is for.
+        fn key(&self) -> NodeId {
+            self.key
+        }
+
+        /// Inserts `default` if the entry is vacant, returning a mutable reference to the value.
+        fn or_insert(self, default: T) -> &'a mut T {
+            self.slot.get_or_insert(default)
+        }
+
+        /// Inserts the result of `default` if the entry is vacant, returning a mutable reference to
+        /// the value. `default` is only called if the entry is vacant.
+        fn or_insert_with<F: FnOnce() -> T>(self, default: F) -> &'a mut T {
+            self.slot.get_or_insert_with(default)
+        }
+
+        /// Calls `f` with the value if the entry is occupied, returning the entry for chaining.
+        fn and_modify<F: FnOnce(&mut T)>(self, f: F) -> Self {
+            if let Some(value) = self.slot.as_mut() {
+                f(value);
+            }
+            self
+        }
+    }
+
+    impl<'a, T: Default> Entry<'a, T> {
+        /// Inserts the default value if the entry is vacant, returning a mutable reference to the
+        /// value.
+        fn or_default(self) -> &'a mut T {
+            self.slot.get_or_insert_with(T::default)
+        }
+    }
 
     #[test]
     fn set() {
@@ -360,4 +418,46 @@ mod tests {
         assert_eq!(map.get(n2).copied(), None);
         assert_eq!(map.get(n3).copied(), Some(35));
     }
+
+    #[test]
+    fn map_entry() {
+        let mut g = Graph::new();
+        let n1 = g.add_node(1);
+        let n2 = g.add_node(2);
+
+        let mut map = NodeMap::with_capacity_for(&g);
+        assert_eq!(map.entry(n1).key(), n1);
+
+        // Check or_insert only inserts into vacant entries, returning the current value
+        assert_eq!(*map.entry(n1).or_insert(10), 10);
+        assert_eq!(*map.entry(n1).or_insert(20), 10);
+        *map.entry(n1).or_insert(20) += 1;
+        assert_eq!(map.get(n1).copied(), Some(11));
+
+        // Check or_insert_with only calls its function for vacant entries
+        assert_eq!(*map.entry(n1).or_insert_with(|| unreachable!()), 11);
+        assert_eq!(*map.entry(n2).or_insert_with(|| 20), 20);
+
+        // Check and_modify only modifies occupied entries, with the same semantics as HashMap
+        let mut hash_map = HashMap::new();
+        hash_map.insert(n1, 11);
+        hash_map.insert(n2, 20);
+        let n3 = g.add_node(3);
+        for &n in &[n1, n2, n3] {
+            let value = *map.entry(n).and_modify(|v| *v *= 2).or_insert(30);
+            let expected = *hash_map.entry(n).and_modify(|v| *v *= 2).or_insert(30);
+            assert_eq!(value, expected);
+        }
+        assert_eq!(map.get(n1).copied(), Some(22));
+        assert_eq!(map.get(n2).copied(), Some(40));
+        assert_eq!(map.get(n3).copied(), Some(30));
+        assert_eq!(map.inner.len(), 3);
+
+        // Check or_default inserts the default value
+        let mut counts = NodeMap::new();
+        *counts.entry(n2).or_default() += 1;
+        *counts.entry(n2).or_default() += 1;
+        assert_eq!(counts.get(n1), None);
+        assert_eq!(counts.get(n2).copied(), Some(2));
+    }
 }
@@ -12,6 +12,9 @@ pub struct DotOptions {
     pub omit_node_ids: bool,
     /// Hide branch indices from the output
     pub omit_branch_ids: bool,
+    /// Format node values using alternate `{:#?}` formatting. For control flow graphs, this
+    /// prefixes each instruction with its JVM bytecode label.
+    pub label_instructions: bool,
     /// Prefix nodes with optional subgraph identifier & return a `subgraph` instead of a `digraph`
     pub subgraph: Option<usize>,
 }
@@ -31,16 +34,22 @@ impl<T: Debug> Graph<T> {
         let prefix = &opts.subgraph.map_or(String::new(), |i| format!("s{}_", i));
         // Build iterator for output separated by newlines
         let lines = self.iter().flat_map(|node| {
+            // Format node value, optionally using alternate formatting
+            let value = if opts.label_instructions {
+                format!("{:#?}", node.value)
+            } else {
+                format!("{:?}", node.value)
+            };
             // Build label for this node, optionally containing the node ID
             let label = if opts.omit_node_ids {
-                format!("{value:?}", value=node.value)
+                value
             } else {
                 // If we're including node IDs and this is the entrypoint, mark it with an "*"
                 let entry = match self.entry {
                     Some(id) if id == node.id => "*",
                     _ => "",
                 };
-                format!("{id}{entry}\\n{value:?}", id = node.id, value = node.value)
+                format!("{id}{entry}\\n{value}", id = node.id)
             };
             // Build full, styled DOT string for this node
             let node_string = format!(
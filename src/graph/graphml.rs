@@ -0,0 +1,182 @@
+use crate::graph::Graph;
+use itertools::Itertools;
+use std::fmt::Debug;
+
+/// Format intermediate graphs are written in, set with `--graphs-format`.
+#[derive(clap::ArgEnum, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum GraphsFormat {
+    /// Render graphs to PNG images using Graphviz (see [`Graph::as_dot`])
+    Png,
+    /// Write graphs as GraphML for external analysis tools such as yEd or Gephi (see
+    /// [`Graph::as_graphml`])
+    Graphml,
+}
+
+impl GraphsFormat {
+    /// Returns the file extension for graphs written in this format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            GraphsFormat::Png => "png",
+            GraphsFormat::Graphml => "graphml",
+        }
+    }
+}
+
+/// Escapes characters with special meaning in XML text and attribute values.
+fn xml_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+impl<T: Debug> Graph<T> {
+    /// Converts graph to [GraphML] for analysis with external tools, such as yEd or Gephi, which
+    /// cope with larger graphs than Graphviz.
+    ///
+    /// Nodes are labelled with their value using alternate `{:#?}` formatting (so control flow
+    /// graph instructions are prefixed with their JVM bytecode labels), and the entrypoint is
+    /// marked with `entry`. Edges are labelled with their `branch` index. Edges leaving nodes with
+    /// exactly 2 successors are also labelled with the `condition` they're followed on, assuming
+    /// the false branch comes first as in control flow graphs.
+    ///
+    /// [GraphML]: http://graphml.graphdrawing.org/
+    pub fn as_graphml(&self) -> String {
+        let lines = self.iter().flat_map(|node| {
+            // Debug formatting escapes newlines for DOT labels, but GraphML supports them directly
+            let label = format!("{:#?}", node.value).replace("\\n", "\n");
+            let entry = self.entry == Some(node.id);
+            let node_string = format!(
+                "    <node id=\"n{id}\">\n      <data key=\"label\">{label}</data>\n      <data key=\"entry\">{entry}</data>\n    </node>",
+                id = node.id,
+                label = xml_escape(&label),
+            );
+
+            let conditional = node.out_degree() == 2;
+            let edge_strings = (node.successors.iter().enumerate()).map(move |(branch, target)| {
+                let condition = if conditional {
+                    format!("\n      <data key=\"condition\">{}</data>", branch == 1)
+                } else {
+                    String::new()
+                };
+                format!(
+                    "    <edge source=\"n{id}\" target=\"n{target}\">\n      <data key=\"branch\">{branch}</data>{condition}\n    </edge>",
+                    id = node.id
+                )
+            });
+
+            std::iter::once(node_string).chain(edge_strings)
+        });
+        let lines = lines.format("\n");
+
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<graphml xmlns="http://graphml.graphdrawing.org/xmlns">
+  <key id="label" for="node" attr.name="label" attr.type="string"/>
+  <key id="entry" for="node" attr.name="entry" attr.type="boolean"/>
+  <key id="branch" for="edge" attr.name="branch" attr.type="int"/>
+  <key id="condition" for="edge" attr.name="condition" attr.type="boolean"/>
+  <graph id="G" edgedefault="directed">
+{lines}
+  </graph>
+</graphml>
+"#
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::graph::tests::fixture_cyclic;
+    use crate::tests::load_basic_blocks;
+
+    /// Asserts `xml` is well-formed, with every start tag closed by a matching end tag in order.
+    fn assert_well_formed(xml: &str) {
+        let mut open = vec![];
+        for tag in xml.split('<').skip(1) {
+            let tag = &tag[..tag.find('>').expect("Unterminated tag")];
+            if tag.starts_with('?') || tag.ends_with('/') {
+                continue;
+            }
+            match tag.strip_prefix('/') {
+                Some(name) => assert_eq!(open.pop(), Some(name)),
+                None => open.push(tag.split(' ').next().unwrap()),
+            }
+        }
+        assert!(open.is_empty(), "Unclosed tags: {:?}", open);
+    }
+
+    #[test]
+    fn as_graphml() {
+        let (g, _) = fixture_cyclic();
+        let graphml = g.as_graphml();
+        assert_well_formed(&graphml);
+        assert_eq!(
+            graphml,
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<graphml xmlns="http://graphml.graphdrawing.org/xmlns">
+  <key id="label" for="node" attr.name="label" attr.type="string"/>
+  <key id="entry" for="node" attr.name="entry" attr.type="boolean"/>
+  <key id="branch" for="edge" attr.name="branch" attr.type="int"/>
+  <key id="condition" for="edge" attr.name="condition" attr.type="boolean"/>
+  <graph id="G" edgedefault="directed">
+    <node id="n0">
+      <data key="label">1</data>
+      <data key="entry">true</data>
+    </node>
+    <edge source="n0" target="n0">
+      <data key="branch">0</data>
+      <data key="condition">false</data>
+    </edge>
+    <edge source="n0" target="n1">
+      <data key="branch">1</data>
+      <data key="condition">true</data>
+    </edge>
+    <node id="n1">
+      <data key="label">2</data>
+      <data key="entry">false</data>
+    </node>
+    <edge source="n1" target="n0">
+      <data key="branch">0</data>
+    </edge>
+  </graph>
+</graphml>
+"#
+        );
+    }
+
+    #[test]
+    fn as_graphml_if() -> anyhow::Result<()> {
+        let g = load_basic_blocks("if (n > 0) { n = 1; } return n;")?;
+        let graphml = g.as_graphml();
+        assert_well_formed(&graphml);
+
+        // Check condition, then and follow blocks are included, with both branches of the
+        // condition and the fallthrough from the then block
+        assert_eq!(graphml.matches("<node ").count(), 3);
+        assert_eq!(graphml.matches("<edge ").count(), 3);
+        assert_eq!(
+            graphml
+                .matches(r#"<data key="condition">true</data>"#)
+                .count(),
+            1
+        );
+        assert_eq!(
+            graphml
+                .matches(r#"<data key="condition">false</data>"#)
+                .count(),
+            1
+        );
+        // Check nodes are labelled with their instructions
+        assert!(graphml.contains("Ifle"));
+        Ok(())
+    }
+}
@@ -1,6 +1,7 @@
 mod collections;
 mod dominators;
 mod dot;
+mod graphml;
 mod intervals;
 mod traversal;
 mod types;
@@ -8,6 +9,7 @@ mod types;
 pub use self::collections::*;
 pub use self::dominators::*;
 pub use self::dot::*;
+pub use self::graphml::*;
 pub use self::intervals::*;
 pub use self::traversal::*;
 pub use self::types::*;
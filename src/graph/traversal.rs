@@ -129,8 +129,155 @@ impl<T> Graph<T> {
 
 #[cfg(test)]
 mod tests {
-    use crate::graph::tests::{fixture_1, fixture_cyclic};
-    use crate::graph::Order;
+    use crate::graph::tests::{fixture_1, fixture_2, fixture_3, fixture_cyclic};
+    use crate::graph::{Graph, NodeId, NodeMap, NodeSet, Order};
+    use std::collections::VecDeque;
+
+    /// Events emitted by [`Graph::dfs_visit`] traversals.
+    #[derive(Debug, Copy, Clone, Eq, PartialEq)]
+    enum DfsEvent {
+        /// The node has been visited for the first time, before any of its successors.
+        Discover(NodeId),
+        /// All the node's successors have been visited.
+        Finish(NodeId),
+        /// The edge `from -> to` led to `to` being discovered.
+        TreeEdge(NodeId, NodeId),
+        /// The edge `from -> to` leads to a node that has been discovered but not finished, so is
+        /// an ancestor of (or the same node as) `from`.
+        BackEdge(NodeId, NodeId),
+        /// The edge `from -> to` leads to a node that has already been finished, either a
+        /// descendant of `from` (forward edge) or in another subtree (cross edge).
+        CrossEdge(NodeId, NodeId),
+    }
+
+    /// Errors returned by [`Graph`] algorithms that don't apply to all graphs.
+    #[derive(Debug, Copy, Clone, Eq, PartialEq)]
+    enum GraphError {
+        /// The graph contains a cycle, but the algorithm requires a directed acyclic graph.
+        Cycle,
+    }
+
+    impl<T> Graph<T> {
+        /// Recursive helper function for [`Graph::dfs_visit`].
+        fn dfs_visit_inner(
+            &self,
+            visitor: &mut impl FnMut(DfsEvent),
+            discovered: &mut NodeSet,
+            finished: &mut NodeSet,
+            node: NodeId,
+        ) {
+            discovered.insert(node);
+            visitor(DfsEvent::Discover(node));
+            for &succ in &self[node].successors {
+                if !discovered.contains(succ) {
+                    visitor(DfsEvent::TreeEdge(node, succ));
+                    self.dfs_visit_inner(visitor, discovered, finished, succ);
+                } else if !finished.contains(succ) {
+                    visitor(DfsEvent::BackEdge(node, succ));
+                } else {
+                    visitor(DfsEvent::CrossEdge(node, succ));
+                }
+            }
+            finished.insert(node);
+            visitor(DfsEvent::Finish(node));
+        }
+
+        /// Performs a depth-first traversal on this graph from `start`, calling `visitor` with a
+        /// [`DfsEvent`] as each node is discovered and finished, and as each edge is explored.
+        /// Successors are visited in order. Unlike [`Graph::depth_first`], this allows multiple
+        /// analyses to share a single traversal.
+        fn dfs_visit(&self, start: NodeId, visitor: &mut impl FnMut(DfsEvent)) {
+            let mut discovered = NodeSet::with_capacity_for(self);
+            let mut finished = NodeSet::with_capacity_for(self);
+            self.dfs_visit_inner(visitor, &mut discovered, &mut finished, start);
+        }
+
+        /// Returns a topological ordering of all nodes in this graph, where each node appears
+        /// before all its successors, using [Kahn's algorithm]. Unlike [`Graph::depth_first`], this
+        /// doesn't require an entrypoint, and includes nodes unreachable from it.
+        ///
+        /// Returns [`GraphError::Cycle`] if the graph isn't a directed acyclic graph, as no such
+        /// ordering exists.
+        ///
+        /// [Kahn's algorithm]: https://en.wikipedia.org/wiki/Topological_sorting#Kahn's_algorithm
+        fn topological_order(&self) -> Result<Vec<NodeId>, GraphError> {
+            let mut order = Vec::with_capacity(self.len());
+            // Count remaining incoming edges for each node, queueing those without any
+            let mut in_degrees = NodeMap::with_capacity_for(self);
+            let mut queue = VecDeque::new();
+            for node in self.iter() {
+                in_degrees.insert(node.id, node.in_degree());
+                if node.in_degree() == 0 {
+                    queue.push_back(node.id);
+                }
+            }
+            // Visit nodes without incoming edges, removing their outgoing edges
+            while let Some(node) = queue.pop_front() {
+                order.push(node);
+                for &succ in &self[node].successors {
+                    let in_degree = in_degrees[succ] - 1;
+                    in_degrees.insert(succ, in_degree);
+                    if in_degree == 0 {
+                        queue.push_back(succ);
+                    }
+                }
+            }
+            // If any nodes still have incoming edges, they must be part of (or reachable from) a
+            // cycle
+            if order.len() == self.len() {
+                Ok(order)
+            } else {
+                Err(GraphError::Cycle)
+            }
+        }
+
+        /// Recursive helper function for [`Graph::simple_paths`].
+        fn simple_paths_inner(
+            &self,
+            to: NodeId,
+            max_len: usize,
+            paths: &mut Vec<Vec<NodeId>>,
+            path: &mut Vec<NodeId>,
+            on_path: &mut NodeSet,
+        ) {
+            let node = *path.last().unwrap();
+            if node == to {
+                paths.push(path.clone());
+                return;
+            }
+            // Stop extending this path if it's already as long as allowed
+            if path.len() == max_len {
+                return;
+            }
+            for &succ in &self[node].successors {
+                // Only visit nodes not already on this path, so the path remains acyclic
+                if on_path.insert(succ) {
+                    path.push(succ);
+                    self.simple_paths_inner(to, max_len, paths, path, on_path);
+                    path.pop();
+                    on_path.remove(succ);
+                }
+            }
+        }
+
+        /// Returns all simple (acyclic) paths from `from` to `to` containing at most `max_len`
+        /// nodes, in depth-first order. Useful for diagnosing structuring decisions, e.g. why a
+        /// particular follow node was chosen.
+        ///
+        /// The number of simple paths can grow exponentially with the size of the graph, hence the
+        /// `max_len` bound.
+        fn simple_paths(&self, from: NodeId, to: NodeId, max_len: usize) -> Vec<Vec<NodeId>> {
+            let mut paths = vec![];
+            if max_len == 0 {
+                return paths;
+            }
+            let mut path = vec![from];
+            let mut on_path = NodeSet::with_capacity_for(self);
+            on_path.insert(from);
+            self.simple_paths_inner(to, max_len, &mut paths, &mut path, &mut on_path);
+            paths
+        }
+    }
 
     #[test]
     fn depth_first_pre_order_1() {
@@ -166,4 +313,82 @@ mod tests {
         let traversal = g.depth_first(Order::PostOrder).traversal;
         assert_eq!(traversal, vec![n2, n1]);
     }
+
+    #[test]
+    fn dfs_visit_3() {
+        let (g, (n1, n2, n3)) = fixture_3();
+        let mut events = vec![];
+        g.dfs_visit(n1, &mut |event| events.push(event));
+        assert_eq!(
+            events,
+            vec![
+                DfsEvent::Discover(n1),
+                DfsEvent::TreeEdge(n1, n2),
+                DfsEvent::Discover(n2),
+                DfsEvent::TreeEdge(n2, n3),
+                DfsEvent::Discover(n3),
+                DfsEvent::Finish(n3),
+                DfsEvent::Finish(n2),
+                DfsEvent::Finish(n1),
+            ]
+        );
+    }
+
+    #[test]
+    fn dfs_visit_cyclic() {
+        let (g, (n1, n2)) = fixture_cyclic();
+        let mut events = vec![];
+        g.dfs_visit(n1, &mut |event| events.push(event));
+        assert_eq!(
+            events,
+            vec![
+                DfsEvent::Discover(n1),
+                DfsEvent::BackEdge(n1, n1),
+                DfsEvent::TreeEdge(n1, n2),
+                DfsEvent::Discover(n2),
+                DfsEvent::BackEdge(n2, n1),
+                DfsEvent::Finish(n2),
+                DfsEvent::Finish(n1),
+            ]
+        );
+    }
+
+    #[test]
+    fn simple_paths_2() {
+        let (g, (n1, n2, n3, n4, n5, n6, n7, n8)) = fixture_2();
+        let paths = g.simple_paths(n1, n8, usize::MAX);
+        assert_eq!(
+            paths,
+            vec![
+                vec![n1, n2, n3, n4, n6, n7, n8],
+                vec![n1, n2, n3, n5, n6, n7, n8],
+                vec![n1, n2, n7, n8],
+            ]
+        );
+
+        // Check longer paths excluded when bounded
+        let paths = g.simple_paths(n1, n8, 6);
+        assert_eq!(paths, vec![vec![n1, n2, n7, n8]]);
+        assert!(g.simple_paths(n1, n8, 3).is_empty());
+    }
+
+    #[test]
+    fn simple_paths_cyclic() {
+        let (g, (n1, n2)) = fixture_cyclic();
+        // Check cycles aren't followed, and paths to self only contain self
+        assert_eq!(g.simple_paths(n1, n2, usize::MAX), vec![vec![n1, n2]]);
+        assert_eq!(g.simple_paths(n1, n1, usize::MAX), vec![vec![n1]]);
+    }
+
+    #[test]
+    fn topological_order_3() {
+        let (g, (n1, n2, n3)) = fixture_3();
+        assert_eq!(g.topological_order(), Ok(vec![n1, n2, n3]));
+    }
+
+    #[test]
+    fn topological_order_cyclic() {
+        let (g, _) = fixture_cyclic();
+        assert_eq!(g.topological_order(), Err(GraphError::Cycle));
+    }
 }
@@ -1,4 +1,4 @@
-use std::mem::take;
+use std::mem::{replace, take};
 use std::{fmt, ops};
 
 /// Removes the first instance of `value` in `vec`.
@@ -197,6 +197,16 @@ impl<T> Graph<T> {
         self[to_target].predecessors.push(source);
     }
 
+    /// Replaces the value of the node `id` with `value`, returning its previous value. Edges are
+    /// unchanged.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `id` node does not exist in the graph.
+    pub fn replace_value(&mut self, id: NodeId, value: T) -> T {
+        replace(&mut self[id].value, value)
+    }
+
     /// Removes all outgoing edges from `source`.
     ///
     /// # Panics
@@ -241,6 +251,52 @@ impl<T> Graph<T> {
         self.nodes.len()
     }
 
+    /// Checks the `successors` and `predecessors` of every node agree, returning a description of
+    /// the first inconsistency found if they don't. Each edge `a -> b` should appear as many times
+    /// in `a`'s `successors` as `a` appears in `b`'s `predecessors`, and both nodes should exist.
+    ///
+    /// Edges are stored twice, so a bug in a mutation could update one list but not the other.
+    /// This is used to catch these after structuring passes when `debug_assertions` are enabled.
+    pub fn validate_edge_consistency(&self) -> Result<(), String> {
+        let exists = |id: NodeId| matches!(self.nodes.get(id.0), Some(Some(_)));
+        let count = |ids: &[NodeId], id: NodeId| ids.iter().filter(|&&x| x == id).count();
+        for node in self.iter() {
+            for &succ in &node.successors {
+                if !exists(succ) {
+                    return Err(format!(
+                        "Edge {} -> {} targets a removed node",
+                        node.id, succ
+                    ));
+                }
+                let succ_count = count(&node.successors, succ);
+                let pred_count = count(&self[succ].predecessors, node.id);
+                if succ_count != pred_count {
+                    return Err(format!(
+                        "Edge {} -> {} appears {} time(s) in successors, {} in predecessors",
+                        node.id, succ, succ_count, pred_count
+                    ));
+                }
+            }
+            for &pred in &node.predecessors {
+                if !exists(pred) {
+                    return Err(format!(
+                        "Edge {} -> {} sources a removed node",
+                        pred, node.id
+                    ));
+                }
+                let succ_count = count(&self[pred].successors, node.id);
+                let pred_count = count(&node.predecessors, pred);
+                if succ_count != pred_count {
+                    return Err(format!(
+                        "Edge {} -> {} appears {} time(s) in successors, {} in predecessors",
+                        pred, node.id, succ_count, pred_count
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
     /// Creates a new graph with the same topology, applying a closure to each [`Node`]'s `value`.
     ///
     /// Note node IDs, edges and their order will be the same in the new graph.
@@ -336,8 +392,57 @@ impl<T> IntoIterator for Graph<T> {
 #[cfg(test)]
 pub mod tests {
     use super::*;
-    use crate::graph::Order;
+    use crate::graph::{NodeSet, Order};
     use itertools::Itertools;
+    use std::collections::BTreeMap;
+
+    impl<T> Graph<T> {
+        /// Removes all nodes for which `keep` returns `false`, and all their edges, from the graph.
+        ///
+        /// `keep` is called once for each node in insertion order, before any nodes are removed.
+        /// This is more efficient than calling [`Graph::remove_node`] for each node, as the edges
+        /// of each remaining node are only filtered once. If the `entry`point is removed, it is
+        /// reset to [`Option::None`].
+        fn retain_nodes<F: Fn(NodeId, &T) -> bool>(&mut self, keep: F) {
+            // Replace removed nodes with `None` tombstones, recording their IDs
+            let mut removed = NodeSet::with_capacity_for(self);
+            for maybe_node in &mut self.nodes {
+                if let Some(node) = maybe_node {
+                    if !keep(node.id, &node.value) {
+                        removed.insert(node.id);
+                        *maybe_node = None;
+                    }
+                }
+            }
+            // Remove edges to removed nodes from all remaining nodes
+            for node in self.nodes.iter_mut().flatten() {
+                node.predecessors.retain(|&pred| !removed.contains(pred));
+                node.successors.retain(|&succ| !removed.contains(succ));
+            }
+            // Reset entrypoint if removed
+            if matches!(self.entry, Some(entry) if removed.contains(entry)) {
+                self.entry = None;
+            }
+        }
+
+        /// Returns the number of directed edges in the graph, counting duplicate edges between the
+        /// same nodes separately.
+        fn edge_count(&self) -> usize {
+            self.iter().map(Node::out_degree).sum()
+        }
+
+        /// Returns a map from each degree (in-degree plus out-degree) to the number of nodes in the
+        /// graph with that degree. Self-loops count towards both degrees.
+        fn degree_histogram(&self) -> BTreeMap<usize, usize> {
+            let mut histogram = BTreeMap::new();
+            for node in self.iter() {
+                *histogram
+                    .entry(node.in_degree() + node.out_degree())
+                    .or_insert(0) += 1;
+            }
+            histogram
+        }
+    }
 
     /// Constructs a graph based on Figure 6.9 (Page 133) from "Cristina Cifuentes. Reverse
     /// Compilation Techniques. PhD thesis, Queensland University of Technology, 1994".
@@ -592,6 +697,87 @@ pub mod tests {
         assert_eq!(g.iter().map(|x| x.value).collect_vec(), [1, 3, 4, 5]);
     }
 
+    #[test]
+    fn edge_count() {
+        let (g, _) = fixture_1();
+        assert_eq!(g.edge_count(), 7);
+        let (g, _) = fixture_cyclic();
+        assert_eq!(g.edge_count(), 3);
+        assert_eq!(Graph::<()>::new().edge_count(), 0);
+    }
+
+    #[test]
+    fn degree_histogram() {
+        let (g, _) = fixture_1();
+        let histogram = g.degree_histogram();
+        assert_eq!(histogram, BTreeMap::from([(1, 1), (2, 3), (3, 1), (4, 1)]));
+        assert_eq!(histogram.values().sum::<usize>(), g.len());
+        // Each edge contributes to the degree of both its endpoints
+        let degree_sum: usize = histogram.iter().map(|(degree, count)| degree * count).sum();
+        assert_eq!(degree_sum, 2 * g.edge_count());
+    }
+
+    #[test]
+    fn validate_edge_consistency() {
+        // Check consistent graphs are accepted, including after mutations
+        let (mut g, (_n1, n2, n3, _n4, n5, n6)) = fixture_1();
+        assert_eq!(g.validate_edge_consistency(), Ok(()));
+        g.swap_edge(n2, n3, n6);
+        g.remove_node(n5);
+        assert_eq!(g.validate_edge_consistency(), Ok(()));
+        let (g, _) = fixture_cyclic();
+        assert_eq!(g.validate_edge_consistency(), Ok(()));
+
+        // Check successors without matching predecessors are detected
+        let (mut g, (n1, n2, ..)) = fixture_1();
+        g[n1].successors.push(n2);
+        assert_eq!(
+            g.validate_edge_consistency(),
+            Err(String::from(
+                "Edge 0 -> 1 appears 2 time(s) in successors, 1 in predecessors"
+            ))
+        );
+
+        // Check predecessors without matching successors are detected
+        let (mut g, (n1, _n2, n3, ..)) = fixture_1();
+        g[n1].predecessors.push(n3);
+        assert_eq!(
+            g.validate_edge_consistency(),
+            Err(String::from(
+                "Edge 2 -> 0 appears 0 time(s) in successors, 1 in predecessors"
+            ))
+        );
+
+        // Check edges to removed nodes are detected
+        let (mut g, (n1, ..)) = fixture_cyclic();
+        g.nodes[n1.0] = None;
+        assert_eq!(
+            g.validate_edge_consistency(),
+            Err(String::from("Edge 1 -> 0 targets a removed node"))
+        );
+    }
+
+    #[test]
+    fn replace_value() {
+        let (mut g, (n1, n2, n3, n4, n5, _)) = fixture_1();
+        // Check previous value returned and new value stored, without changing edges
+        assert_eq!(g.replace_value(n2, 20), 2);
+        assert_eq!(g[n2].value, 20);
+        assert_eq!(g[n1].value, 1);
+        assert_eq!(g[n2].predecessors, [n1, n4]);
+        assert_eq!(g[n2].successors, [n3, n5]);
+        assert_eq!(g.replace_value(n2, 200), 20);
+        assert_eq!(g[n2].value, 200);
+    }
+
+    #[test]
+    #[should_panic = "Not found"]
+    fn invalid_replace_value() {
+        let (mut g, (_, n2, ..)) = fixture_1();
+        g.remove_node(n2);
+        g.replace_value(n2, 20);
+    }
+
     #[test]
     fn remove_node_cyclic() {
         let (mut g, (n1, n2)) = fixture_cyclic();
@@ -621,6 +807,30 @@ pub mod tests {
         g.remove_node(NodeId(0));
     }
 
+    #[test]
+    fn retain_nodes() {
+        let (mut g, (n1, n2, _n3, n4, n5, n6, _n7, n8)) = fixture_2();
+        g.retain_nodes(|_, &value| value != 3 && value != 7);
+        assert_eq!(g.len(), 6);
+        assert_eq!(g.edge_count(), 3);
+        assert_eq!(g.entry, Some(n1));
+        // Check edges to removed nodes are gone, preserving the order of remaining edges
+        assert_eq!(g[n1].successors, [n2]);
+        assert_eq!(g[n2].predecessors, [n1]);
+        assert_eq!(g[n2].successors, []);
+        assert_eq!(g[n4].predecessors, []);
+        assert_eq!(g[n4].successors, [n6]);
+        assert_eq!(g[n5].predecessors, []);
+        assert_eq!(g[n6].predecessors, [n4, n5]);
+        assert_eq!(g[n8].predecessors, []);
+        assert_eq!(g.iter().map(|x| x.value).collect_vec(), [1, 2, 4, 5, 6, 8]);
+
+        // Check removing entrypoint resets entrypoint
+        g.retain_nodes(|id, _| id != n1);
+        assert_eq!(g.entry, None);
+        assert_eq!(g[n2].predecessors, []);
+    }
+
     #[test]
     fn remove_edge() {
         let (mut g, (n1, n2, n3, n4, n5, _n6)) = fixture_1();
@@ -0,0 +1,48 @@
+use crate::class::Class;
+use crate::function::{inspect_function, unsupported_cause};
+use itertools::Itertools;
+use std::fmt::Write;
+
+/// Returns a human-readable description of each method in `class` for `montera inspect`, for
+/// exploring which methods can be compiled without producing any output. Each method is listed with
+/// its access flags, whether it has code, and the unsupported features it contains (found by
+/// compiling it with [`inspect_function`]), e.g.
+///
+/// ```text
+/// Test.length()I
+///   flags: STATIC
+///   code: yes
+///   unsupported: Newarray instruction (Array), Arraylength instruction (Array)
+/// ```
+///
+/// Methods that fail to compile for other reasons (e.g. endless loops) are listed with their error.
+/// Note this takes each method's code, so `class` can't be compiled afterwards.
+pub fn inspect_class(class: &Class) -> String {
+    let mut out = String::new();
+    for function in &class.methods {
+        writeln!(out, "{}", function.id).unwrap();
+        writeln!(out, "  flags: {:?}", function.flags).unwrap();
+        let has_code = function.code.lock().unwrap().is_some();
+        writeln!(out, "  code: {}", if has_code { "yes" } else { "no" }).unwrap();
+
+        let unsupported = match inspect_function(function) {
+            Ok(unsupported) => unsupported,
+            Err(e) => match unsupported_cause(&e) {
+                Some(unsupported) => vec![unsupported],
+                None => {
+                    writeln!(out, "  error: {:#}", e).unwrap();
+                    continue;
+                }
+            },
+        };
+        if unsupported.is_empty() {
+            writeln!(out, "  unsupported: none").unwrap();
+        } else {
+            let unsupported = (unsupported.iter()).map(|unsupported| {
+                format!("{} ({})", unsupported.description, unsupported.category)
+            });
+            writeln!(out, "  unsupported: {}", unsupported.format(", ")).unwrap();
+        }
+    }
+    out
+}
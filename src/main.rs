@@ -1,11 +1,13 @@
 mod class;
 mod function;
 mod graph;
+mod inspect;
 mod options;
 mod output;
 mod scheduler;
 #[cfg(test)]
 mod tests;
+mod timings;
 mod virtuals;
 
 #[macro_use]
@@ -17,35 +19,53 @@ extern crate anyhow;
 #[macro_use]
 extern crate log;
 
-use crate::class::{Class, LoadClassJob};
-use crate::function::{CompileFunctionJob, CompiledFunction, Function};
+use crate::class::{load_class, Class, LoadClassJob};
+use crate::function::structure::LOG_TARGET as STRUCTURE_LOG_TARGET;
+use crate::function::{
+    verify_final_writes, CompileFunctionJob, CompileOptions, CompiledFunction, Function,
+};
 use crate::graph::run_graphviz;
-use crate::options::Options;
-use crate::output::{Module, Renderer};
-use crate::scheduler::Scheduler;
+use crate::inspect::inspect_class;
+use crate::options::{Command, Options};
+use crate::output::{
+    diff_modules, report_unsupported, size_report, summarise_module, Module, RenderOptions,
+    Renderer, SOURCE_FILES_SECTION,
+};
+use crate::scheduler::{Scheduler, SchedulerKind, SerialScheduler};
+use crate::timings::{SharedDuration, Timings};
 use crate::virtuals::VirtualTable;
 use anyhow::Context;
 use clap::Parser;
+use itertools::Itertools;
+use log::LevelFilter;
 use std::collections::HashMap;
 use std::path::PathBuf;
 use std::process::exit;
-use std::rc::Rc;
 use std::sync::mpsc::{channel, Receiver};
 use std::sync::Arc;
 use std::time::Instant;
 use std::{fs, panic};
+use wasmparser::BinaryReader;
 
 /// Queues jobs to load and parse all classes at `input_paths`, returning a channel to receive
-/// parsed [`Class`]es on. See [`LoadClassJob`] for more details.
+/// parsed [`Class`]es on. Time spent loading each class is added to `load_duration`. See
+/// [`LoadClassJob`] for more details.
 pub fn load_classes(
     schd: &impl Scheduler,
     input_paths: Vec<PathBuf>,
+    compat: bool,
+    load_duration: &SharedDuration,
 ) -> Receiver<anyhow::Result<Class>> {
     let (class_tx, class_rx) = channel();
     for path in input_paths {
         info!("Loading {}...", path.display());
         let result_tx = class_tx.clone();
-        let job = LoadClassJob { path, result_tx };
+        let job = LoadClassJob {
+            path,
+            compat,
+            result_tx,
+            load_duration: load_duration.clone(),
+        };
         schd.schedule(Box::new(job));
     }
     // Implicitly drop our copy of the sender, so the channel closes when all classes finish parsing
@@ -69,11 +89,14 @@ pub fn create_graphs_dir(
 
 /// Queues jobs to compile all functions of [`Class`]es, returning all parsed classes, the total
 /// number of functions, and a channel to receive [`CompiledFunction`]s on. If `graphs_root_dir`
-/// is specified, intermediate structuring graphs will be rendered. See [`CompileFunctionJob`] for
-/// more details.
+/// is specified, intermediate structuring graphs will be rendered. `options` controls how each
+/// function is compiled, and time spent compiling each function is added to `compile_duration`.
+/// See [`CompileFunctionJob`] for more details.
 pub fn compile_functions<'a>(
     schd: &impl Scheduler,
     graphs_root_dir: Option<&PathBuf>,
+    options: CompileOptions,
+    compile_duration: &SharedDuration,
     class_count: usize,
     class_rx: Receiver<anyhow::Result<Class>>,
 ) -> anyhow::Result<(
@@ -90,6 +113,18 @@ pub fn compile_functions<'a>(
     for class in class_rx {
         let class = class.context("Unable to load class")?;
 
+        // Skip module and package descriptors, which don't contain any code
+        if class.is_descriptor() {
+            info!("Skipping {} descriptor...", class.class_name);
+            continue;
+        }
+
+        // Skip annotation types, which don't contain any code worth compiling
+        if class.is_annotation() {
+            info!("Skipping {} annotation...", class.class_name);
+            continue;
+        }
+
         // Log class if debugging
         class.dump();
 
@@ -104,7 +139,9 @@ pub fn compile_functions<'a>(
             let job = CompileFunctionJob {
                 function: Arc::clone(function),
                 graphs_dir,
+                options,
                 result_tx,
+                compile_duration: compile_duration.clone(),
             };
             schd.schedule(Box::new(job));
         }
@@ -122,8 +159,8 @@ pub fn compile_functions<'a>(
 pub fn construct_virtual_table(
     graphs_root_dir: Option<&PathBuf>,
     classes: &Arc<HashMap<Arc<String>, Class>>,
-) -> anyhow::Result<Rc<VirtualTable>> {
-    let virtual_table = Rc::new(VirtualTable::from_classes(classes));
+) -> anyhow::Result<Arc<VirtualTable>> {
+    let virtual_table = Arc::new(VirtualTable::from_classes(classes));
     if let Some(graphs_dir) = graphs_root_dir {
         let dot = virtual_table.as_dot();
         run_graphviz(&dot, graphs_dir.join("virtual.png"))
@@ -133,52 +170,140 @@ pub fn construct_virtual_table(
     Ok(virtual_table)
 }
 
-/// Waits for the results of all function compilations, storing them in a single `Vec`.
+/// Waits for the results of all function compilations, returning successfully compiled functions
+/// and errors separately.
+///
+/// If any functions fail to compile, up to `max_errors` failures are accumulated, so the full scope
+/// of unsupported features can be seen in one run. A `max_errors` of `0` means there is no limit.
+/// See [`check_errors`] for reporting them together.
 pub fn collect_functions(
     function_count: usize,
     function_rx: Receiver<anyhow::Result<CompiledFunction>>,
-) -> anyhow::Result<Vec<CompiledFunction>> {
+    max_errors: usize,
+) -> (Vec<CompiledFunction>, Vec<anyhow::Error>) {
     let mut functions = Vec::with_capacity(function_count);
+    let mut errors = vec![];
     for function in function_rx {
-        let function = function.context("Unable to compile function")?;
-        functions.push(function);
+        match function {
+            Ok(function) => functions.push(function),
+            Err(e) => {
+                errors.push(e);
+                // Stop waiting for results if we've reached the error limit
+                if errors.len() == max_errors {
+                    break;
+                }
+            }
+        }
+    }
+    (functions, errors)
+}
+
+/// Returns an error reporting all function compilation `errors` together, if there are any.
+pub fn check_errors(mut errors: Vec<anyhow::Error>) -> anyhow::Result<()> {
+    match errors.len() {
+        0 => Ok(()),
+        1 => Err(errors.remove(0)),
+        n => {
+            // Include the full chain of causes for each error
+            let messages = errors.iter().map(|e| format!("- {:#}", e)).join("\n");
+            bail!("Unable to compile {} functions:\n{}", n, messages)
+        }
     }
-    Ok(functions)
 }
 
 /// Performs the rendering phase of WebAssembly generation, lowering all pseudo-instructions to real
-/// WebAssembly instructions using program wide information. See [`Renderer`] for more details.
+/// WebAssembly instructions using program wide information. `options` controls how the module is
+/// rendered. Fails if functions instantiate, test against, or access fields of classes that
+/// weren't loaded. See [`Renderer`] for more details.
 pub fn render_module(
     classes: Arc<HashMap<Arc<String>, Class>>,
-    virtual_table: Rc<VirtualTable>,
+    virtual_table: Arc<VirtualTable>,
     functions: Vec<CompiledFunction>,
-) -> Module {
+    options: RenderOptions,
+) -> anyhow::Result<Module> {
     info!("Rendering WebAssembly module...");
     let mut module = Module::new();
+    module.null_checks = options.null_checks;
+    module.tail_calls = functions.iter().any(CompiledFunction::has_tail_calls);
+    module.emit_producers = options.emit_producers;
+    module.emit_debug_globals = options.emit_debug_globals;
 
     // Render all functions to WebAssembly module
-    let renderer = Renderer::new(classes, Rc::clone(&virtual_table), functions);
-    let function_indices = renderer.render_all(&mut module);
+    let mut renderer = Renderer::new(classes, Arc::clone(&virtual_table), functions);
+    renderer.demo_main = options.demo_main;
+    renderer.uniform_headers = options.uniform_headers;
+    renderer.abstract_behaviour = options.abstract_behaviour;
+    renderer.assert_mode = options.assert_mode;
+    renderer.rich_traps = options.rich_traps;
+    renderer.render_threads = options.render_threads;
+    renderer.threads = options.threads;
+    let function_indices = renderer.render_all(&mut module)?;
 
     // Render virtual method table to WebAssembly module
     virtual_table.render(&mut module, &function_indices);
 
-    module
+    Ok(module)
 }
 
 /// Writes a WebAssembly module's bytes to disk, in both the binary `.wasm` and text `.wat` formats.
+/// If `roundtrip` is set, the text format is checked with [`check_roundtrip_wat`] first.
 pub fn write_module(
     output_path: &PathBuf,
     wasm: &[u8],
     wasm_ext: &str,
     wat_ext: &str,
+    roundtrip: bool,
 ) -> anyhow::Result<()> {
     let wat = wasmprinter::print_bytes(&wasm).context("Unable to render module to text")?;
+    if roundtrip {
+        check_roundtrip_wat(wasm, &wat)?;
+    }
     fs::write(output_path.with_extension(wat_ext), wat).context("Unable to write text")?;
     fs::write(output_path.with_extension(wasm_ext), &wasm).context("Unable to write binary")?;
     Ok(())
 }
 
+/// Checks re-parsing `wat`, the text format of the binary `wasm` module, produces the same bytes.
+/// This catches disagreements between `wasm-encoder` and `wasmprinter`, which would make the
+/// written `.wat` files misleading when debugging. The `producers` and [`SOURCE_FILES_SECTION`]
+/// custom sections can't be expressed in the text format, so are ignored.
+pub fn check_roundtrip_wat(wasm: &[u8], wat: &str) -> anyhow::Result<()> {
+    let wasm = &strip_custom_sections(wasm).context("Unable to parse binary")?;
+    let roundtrip = wat::parse_str(wat).context("Unable to parse rendered text")?;
+    if let Some(offset) = (wasm.iter().zip(&roundtrip)).position(|(a, b)| a != b) {
+        bail!(
+            "Rendered text differs from binary at byte offset {}",
+            offset
+        );
+    }
+    ensure!(
+        wasm.len() == roundtrip.len(),
+        "Rendered text is {} byte(s), but binary is {} byte(s)",
+        roundtrip.len(),
+        wasm.len()
+    );
+    Ok(())
+}
+
+/// Returns the binary `wasm` module without its `producers` and [`SOURCE_FILES_SECTION`] custom
+/// sections, if any.
+fn strip_custom_sections(wasm: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let stripped_names = ["producers", SOURCE_FILES_SECTION];
+    let mut reader = BinaryReader::new(wasm);
+    let mut stripped = reader.read_bytes(8)?.to_vec(); // Magic and version
+    while !reader.eof() {
+        let start = reader.current_position();
+        let id = reader.read_u8()?;
+        let size = reader.read_var_u32()?;
+        let content = reader.read_bytes(size as usize)?;
+        // Custom sections have ID 0 and start with their name
+        if id != 0 || !stripped_names.contains(&BinaryReader::new(content).read_string()?) {
+            stripped.extend_from_slice(&wasm[start..reader.current_position()]);
+        }
+    }
+    Ok(stripped)
+}
+
 /// Optimises a binary WebAssembly module using [Binaryen](https://github.com/WebAssembly/binaryen).
 pub fn optimise_module(wasm: &[u8]) -> anyhow::Result<Vec<u8>> {
     info!("Optimising WebAssembly module...");
@@ -194,6 +319,41 @@ pub fn optimise_module(wasm: &[u8]) -> anyhow::Result<Vec<u8>> {
     Ok(binaryen_module.write())
 }
 
+/// Returns the job scheduler for `kind` and its number of workers. `kind` must have already been
+/// resolved (see [`SchedulerKind::resolve`]).
+fn scheduler(kind: SchedulerKind) -> (Box<dyn Scheduler>, usize) {
+    match kind {
+        SchedulerKind::Parallel => parallel_scheduler(),
+        _ => {
+            info!("Using 1 worker...");
+            (Box::new(SerialScheduler {}), 1)
+        }
+    }
+}
+
+/// Returns the parallel job scheduler selected by the enabled features and its number of workers,
+/// falling back to serial scheduling if none are enabled. See [`SchedulerKind::Parallel`].
+#[cfg(feature = "rayon_scheduler")]
+fn parallel_scheduler() -> (Box<dyn Scheduler>, usize) {
+    let workers = rayon::current_num_threads();
+    info!("Using {} rayon worker(s)...", workers);
+    (Box::new(crate::scheduler::RayonScheduler {}), workers)
+}
+#[cfg(all(feature = "parallel_scheduler", not(feature = "rayon_scheduler")))]
+fn parallel_scheduler() -> (Box<dyn Scheduler>, usize) {
+    let workers = num_cpus::get_physical();
+    info!("Using {} worker(s)...", workers);
+    (
+        Box::new(crate::scheduler::WorkerScheduler::new(workers)),
+        workers,
+    )
+}
+#[cfg(not(any(feature = "parallel_scheduler", feature = "rayon_scheduler")))]
+fn parallel_scheduler() -> (Box<dyn Scheduler>, usize) {
+    info!("Using 1 worker...");
+    (Box::new(SerialScheduler {}), 1)
+}
+
 /// Main entrypoint for the command line interface. Compiles `.class` files to WebAssembly.
 fn main() -> anyhow::Result<()> {
     // Get the current time for logging the total execution time at the end
@@ -209,60 +369,196 @@ fn main() -> anyhow::Result<()> {
     }));
 
     // Setup logger and parse command line options
-    env_logger::builder().format_timestamp(None).init();
-
-    // Initialise appropriate job scheduler
-    #[cfg(feature = "parallel_scheduler")]
-    let schd = {
-        let workers = num_cpus::get_physical();
-        info!("Using {} worker(s)...", workers);
-        crate::scheduler::WorkerScheduler::new(workers)
-    };
-    #[cfg(not(feature = "parallel_scheduler"))]
-    let schd = {
-        info!("Using 1 worker...");
-        crate::scheduler::SerialScheduler {}
-    };
+    let mut logger = env_logger::builder();
+    logger.format_timestamp(None);
+    if opts.verbose_structuring {
+        logger.filter_module(STRUCTURE_LOG_TARGET, LevelFilter::Debug);
+    }
+    logger.init();
+
+    // Inspect classes instead of compiling them if requested
+    if let Some(Command::Inspect {
+        compat_mode,
+        input_paths,
+    }) = &opts.command
+    {
+        for path in input_paths {
+            let class = load_class(path, *compat_mode)?;
+            print!("{}", inspect_class(&class));
+        }
+        return Ok(());
+    }
+
+    let print_timings = opts.print_timings;
+    let timings = run(opts)?;
+    info!("Finished in {}ms!", start.elapsed().as_millis());
+    if print_timings {
+        println!("{}", timings);
+    }
+    Ok(())
+}
+
+/// Compiles the `.class` files at `opts.input_paths` to WebAssembly, writing all requested outputs
+/// and returning the time spent in each phase of the pipeline.
+pub fn run(opts: Options) -> anyhow::Result<Timings> {
+    // Resolve output paths, the module path is only optional when running a subcommand. If an
+    // output directory is set, it's created up front as outputs may be written before the module.
+    let module_path = opts.module_path().expect("Output path should be required");
+    let graphs_root_dir = (opts.graphs_root_dir.as_ref()).map(|dir| opts.artifact_path(dir));
+    let report_path = (opts.report_unsupported_json.as_ref()).map(|path| opts.artifact_path(path));
+    let summary_path = (opts.dump_json_module.as_ref()).map(|path| opts.artifact_path(path));
+    if let Some(output_dir) = &opts.output_dir {
+        fs::create_dir_all(output_dir).with_context(|| {
+            format!(
+                "Unable to create output directory: {}",
+                output_dir.display()
+            )
+        })?;
+    }
 
-    // Queue jobs for loading input classes
+    let mut timings = Timings::default();
+    let load_duration = SharedDuration::default();
+    let compile_duration = SharedDuration::default();
+
+    // Initialise appropriate job scheduler, rendering with as many threads as it has workers, and
+    // queue jobs for loading input classes. The number of functions `auto` chooses a scheduler with
+    // isn't known until all classes have loaded, so in this case, classes are loaded serially first.
     let class_count = opts.input_paths.len();
-    let class_rx = load_classes(&schd, opts.input_paths);
+    let (schd, render_threads, class_rx) = if opts.scheduler == SchedulerKind::Auto {
+        let schd = SerialScheduler {};
+        let class_rx = load_classes(&schd, opts.input_paths, opts.compat_mode, &load_duration);
+        let classes: Vec<_> = class_rx.into_iter().collect();
+        let function_count = classes.iter().flatten().map(|c| c.methods.len()).sum();
+        let (schd, render_threads) = scheduler(opts.scheduler.resolve(function_count));
+        // Move loaded classes back to a channel for queueing compilation jobs
+        let (class_tx, class_rx) = channel();
+        for class in classes {
+            class_tx.send(class)?;
+        }
+        (schd, render_threads, class_rx)
+    } else {
+        let (schd, render_threads) = scheduler(opts.scheduler);
+        let class_rx = load_classes(&schd, opts.input_paths, opts.compat_mode, &load_duration);
+        (schd, render_threads, class_rx)
+    };
 
     // Queue jobs for function compilation as classes are loaded
-    let graphs_root_dir = opts.graphs_root_dir.as_ref();
-    let (classes, function_count, function_rx) =
-        compile_functions(&schd, graphs_root_dir, class_count, class_rx)?;
+    let graphs_root_dir = graphs_root_dir.as_ref();
+    let (classes, function_count, function_rx) = compile_functions(
+        &schd,
+        graphs_root_dir,
+        CompileOptions {
+            cache_receivers: opts.cache_receivers,
+            assertions: opts.assume_assertions,
+            on_unsupported: opts.on_unsupported,
+            loop_rotate: opts.loop_rotate,
+            coalesce_sequences: opts.coalesce_sequences,
+            keep_going: opts.keep_going,
+            prefer_select: opts.prefer_select,
+            coalesce_locals: opts.coalesce_locals,
+            tail_calls: opts.tail_calls,
+            graphs_format: opts.graphs_format,
+        },
+        &compile_duration,
+        class_count,
+        class_rx,
+    )?;
+
+    // All classes have been received, so have finished loading
+    timings.load = load_duration.get();
 
     // Construct virtual method table containing virtual class and method IDs
     let classes = Arc::new(classes);
-    let virtual_table = construct_virtual_table(graphs_root_dir, &classes)?;
+    let virtual_table = Timings::time(&mut timings.virtual_table, || {
+        construct_virtual_table(graphs_root_dir, &classes)
+    })?;
 
     // Collect function compilation results
-    let functions = collect_functions(function_count, function_rx)?;
+    let (functions, errors) = collect_functions(function_count, function_rx, opts.max_errors);
+
+    // Write report of unsupported features if enabled, before failing on any errors
+    if let Some(report_path) = &report_path {
+        let report = report_unsupported(&functions, &errors);
+        fs::write(report_path, report).context("Unable to write unsupported report")?;
+    }
+    check_errors(errors)?;
+    timings.compile = compile_duration.get();
+
+    // Check final fields are only written to in constructors if enabled
+    if opts.verify_final_writes {
+        verify_final_writes(&classes, &functions);
+    }
+
+    // Check the demo main method exists and has the right signature if enabled
+    if let Some(demo_main) = &opts.demo_main {
+        demo_main.check(&functions)?;
+    }
 
     // Render functions and virtual table to WebAssembly module
-    let module = render_module(classes, virtual_table, functions);
+    let options = RenderOptions {
+        null_checks: opts.null_checks,
+        demo_main: opts.demo_main,
+        uniform_headers: opts.uniform_headers,
+        abstract_behaviour: opts.abstract_behaviour,
+        assert_mode: opts.assert_mode,
+        rich_traps: opts.rich_traps,
+        render_threads,
+        threads: opts.threads,
+        emit_producers: !opts.no_producers,
+        emit_debug_globals: opts.emit_debug_globals,
+    };
+    let wasm_bytes = Timings::time(&mut timings.render, || {
+        render_module(classes, virtual_table, functions, options).map(|module| module.finish())
+    })?;
 
     // Make sure output directory exists
-    if let Some(parent) = opts.output_path.parent() {
+    if let Some(parent) = module_path.parent() {
         fs::create_dir_all(parent)
             .with_context(|| format!("Unable to create output directory: {}", parent.display()))?;
     }
 
     // Write unoptimized WebAssembly module to disk in both binary and text forms
-    let wasm_bytes = module.finish();
     info!("Writing unoptimised WebAssembly module...");
-    write_module(&opts.output_path, &wasm_bytes, "wasm", "wat")
-        .context("Unable to write unoptimised module")?;
+    let roundtrip_wat = opts.roundtrip_wat;
+    Timings::time(&mut timings.write, || {
+        write_module(&module_path, &wasm_bytes, "wasm", "wat", roundtrip_wat)
+    })
+    .context("Unable to write unoptimised module")?;
+
+    // Write summary of unoptimised module to disk if enabled
+    if let Some(summary_path) = &summary_path {
+        let summary = summarise_module(&wasm_bytes).context("Unable to summarise module")?;
+        fs::write(summary_path, summary).context("Unable to write module summary")?;
+    }
+
+    // Compare unoptimised module with a baseline module if enabled
+    if let Some(baseline_path) = &opts.diff {
+        let baseline = fs::read(baseline_path).context("Unable to read baseline module")?;
+        let diff = diff_modules(&wasm_bytes, &baseline).context("Unable to diff modules")?;
+        println!("{}", diff);
+    }
+
+    // Print sizes of unoptimised module's functions if enabled
+    if opts.size_report {
+        let report = size_report(&wasm_bytes).context("Unable to measure module size")?;
+        println!("{}", report);
+    }
 
     if opts.optimise {
         // Optimise module and write to disk in both binary and text forms
-        let opt_wasm_bytes = optimise_module(&wasm_bytes)?;
+        let opt_wasm_bytes = Timings::time(&mut timings.optimise, || optimise_module(&wasm_bytes))?;
         info!("Writing optimised WebAssembly module...");
-        write_module(&opts.output_path, &opt_wasm_bytes, "opt.wasm", "opt.wat")
-            .context("Unable to write optimised module")?;
+        Timings::time(&mut timings.write, || {
+            write_module(
+                &module_path,
+                &opt_wasm_bytes,
+                "opt.wasm",
+                "opt.wat",
+                roundtrip_wat,
+            )
+        })
+        .context("Unable to write optimised module")?;
     }
 
-    info!("Finished in {}ms!", start.elapsed().as_millis());
-    Ok(())
+    Ok(timings)
 }
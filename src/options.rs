@@ -1,12 +1,41 @@
+use crate::function::{Assertions, UnsupportedBehaviour};
+use crate::graph::GraphsFormat;
+use crate::output::{AbstractBehaviour, AssertMode, DemoMain};
+use crate::scheduler::SchedulerKind;
 use clap::Parser;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 #[derive(Parser, Debug)]
-#[clap(version, about)]
+#[clap(
+    version,
+    about,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 pub struct Options {
-    /// Path to output file (.wasm or .wat)
-    #[clap(short = 'o', long = "output", value_name = "PATH", parse(from_os_str))]
-    pub output_path: PathBuf,
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+
+    /// Path to output file (.wasm or .wat). Only optional when running a subcommand or writing to
+    /// an output directory
+    #[clap(
+        short = 'o',
+        long = "output",
+        value_name = "PATH",
+        required_unless_present = "output-dir",
+        parse(from_os_str)
+    )]
+    pub output_path: Option<PathBuf>,
+
+    /// Directory to write all outputs to instead, naming the module `module.wasm`. Relative paths
+    /// for other outputs (e.g. graphs) are resolved against this directory
+    #[clap(
+        long,
+        value_name = "DIR",
+        conflicts_with = "output-path",
+        parse(from_os_str)
+    )]
+    pub output_dir: Option<PathBuf>,
 
     /// Optimise WebAssembly using Binaryen
     #[clap(short = 'O', long)]
@@ -16,7 +45,194 @@ pub struct Options {
     #[clap(short = 'g', long = "graphs", value_name = "DIR", parse(from_os_str))]
     pub graphs_root_dir: Option<PathBuf>,
 
+    /// Format to write intermediate control flow graphs in: PNG images rendered with Graphviz, or
+    /// GraphML for external analysis tools
+    #[clap(long, arg_enum, value_name = "FORMAT", default_value = "png")]
+    pub graphs_format: GraphsFormat,
+
+    /// Accept slightly malformed class files (e.g. from obfuscators), compiling concrete methods
+    /// without code to stubs that trap when called
+    #[clap(long)]
+    pub compat_mode: bool,
+
+    /// Warn when final fields are written to outside their declaring class's constructors
+    #[clap(long)]
+    pub verify_final_writes: bool,
+
+    /// Cache repeated field access receivers (e.g. `a` in `a.x + a.y`) instead of reloading them
+    #[clap(long)]
+    pub cache_receivers: bool,
+
+    /// Whether to check assert statements, trapping if they fail
+    #[clap(long, arg_enum, value_name = "MODE", default_value = "enabled")]
+    pub assume_assertions: Assertions,
+
+    /// How to compile unsupported instructions: fail, replace with placeholders, or trap
+    #[clap(long, arg_enum, value_name = "MODE", default_value = "error")]
+    pub on_unsupported: UnsupportedBehaviour,
+
+    /// Rotate pre-tested loops to test their condition at the bottom, removing a branch per
+    /// iteration
+    #[clap(long)]
+    pub loop_rotate: bool,
+
+    /// Merge linear chains of basic blocks before structuring control flow, reducing graph size
+    #[clap(long)]
+    pub coalesce_sequences: bool,
+
+    /// Translate conditionals whose branches just push a value (e.g. `c ? a : b`) into `select`s
+    #[clap(long)]
+    pub prefer_select: bool,
+
+    /// Share WebAssembly locals between local variables of the same type that are never live at the
+    /// same time
+    #[clap(long)]
+    pub coalesce_locals: bool,
+
+    /// Compile calls immediately followed by a return to tail calls, so tail-recursive methods
+    /// don't grow the stack. Requires a runtime supporting the WebAssembly tail call proposal
+    #[clap(long)]
+    pub tail_calls: bool,
+
+    /// Log each decision made when structuring control flow, regardless of the `RUST_LOG` level
+    #[clap(long)]
+    pub verbose_structuring: bool,
+
+    /// Trap when accessing fields or calling virtual methods through null references
+    #[clap(long)]
+    pub null_checks: bool,
+
+    /// Start all instances with an 8 byte header containing their virtual class ID and space for an
+    /// array length, instead of a 4 byte header containing just the virtual class ID
+    #[clap(long)]
+    pub uniform_headers: bool,
+
+    /// How to compile abstract methods if they're ever called: trap, or return a zero value
+    #[clap(
+        long = "abstract-behavior",
+        arg_enum,
+        value_name = "MODE",
+        default_value = "trap"
+    )]
+    pub abstract_behaviour: AbstractBehaviour,
+
+    /// How to report failed assertions: trap, or call an imported `onAssertionFailure` function
+    /// with the detail message first
+    #[clap(long, arg_enum, value_name = "MODE", default_value = "trap")]
+    pub assert_mode: AssertMode,
+
+    /// Call an imported `trap` function with a code identifying the kind of error (1 for a thrown
+    /// exception or failed assertion, 2 for a null reference, 3 for an abstract method call) before
+    /// trapping
+    #[clap(long)]
+    pub rich_traps: bool,
+
+    /// Include a WASI `_start` calling a static `(I)I` method (e.g. `Test.f(I)I:42`) with a
+    /// constant argument, and printing the result
+    #[clap(long, value_name = "METHOD:ARG")]
+    pub demo_main: Option<DemoMain>,
+
+    /// Don't include a producers section identifying montera as the compiler
+    #[clap(long)]
+    pub no_producers: bool,
+
+    /// Write a JSON summary of the module's functions, imports, exports, built-ins and virtual
+    /// table, for diffing output across compiler versions
+    #[clap(long, value_name = "PATH", parse(from_os_str))]
+    pub dump_json_module: Option<PathBuf>,
+
+    /// Compare the unoptimised module's functions with a baseline `.wasm` module by name, printing
+    /// added, removed and changed functions, for triaging regressions
+    #[clap(long, value_name = "PATH", parse(from_os_str))]
+    pub diff: Option<PathBuf>,
+
+    /// Print the size of the unoptimised module's code section, and each function's encoded body
+    /// size, largest first, for tracking output size on size-constrained targets
+    #[clap(long)]
+    pub size_report: bool,
+
+    /// Write a JSON report listing each method's unsupported instructions and their categories,
+    /// for gating builds or tracking progress. This is written even if functions fail to compile
+    #[clap(long, value_name = "PATH", parse(from_os_str))]
+    pub report_unsupported_json: Option<PathBuf>,
+
+    /// Export the heap allocator's base and next pointer as `heap_base` and `heap_next` globals, so
+    /// hosts can inspect how much memory has been allocated
+    #[clap(long)]
+    pub emit_debug_globals: bool,
+
+    /// Check the written text format re-parses to the same bytes as the binary format
+    #[clap(long)]
+    pub roundtrip_wat: bool,
+
+    /// Maximum number of function compilation errors to report before stopping (0 for no limit)
+    #[clap(long, value_name = "N", default_value = "1")]
+    pub max_errors: usize,
+
+    /// Still write the module if functions fail to compile, replacing them with stubs that trap
+    /// when called
+    #[clap(long)]
+    pub keep_going: bool,
+
+    /// How to schedule loading and compiling jobs: in parallel, serially, or automatically
+    /// choosing serial scheduling for inputs with few functions to avoid the cost of spinning up
+    /// worker threads. Classes are always loaded serially when choosing automatically. Output is
+    /// byte-identical whichever is chosen
+    #[clap(long, arg_enum, value_name = "MODE", default_value = "parallel")]
+    pub scheduler: SchedulerKind,
+
+    /// Print a breakdown of time spent in each phase of compilation
+    #[clap(long)]
+    pub print_timings: bool,
+
+    /// Access volatile fields with atomic loads and stores, giving them acquire/release semantics.
+    /// Requires a runtime supporting the WebAssembly threads proposal
+    #[clap(long)]
+    pub threads: bool,
+
     /// Input class files (.class)
     #[clap(required = true, value_name = "CLASS", parse(from_os_str))]
     pub input_paths: Vec<PathBuf>,
 }
+
+/// File stem of the module written to `--output-dir`, e.g. `module.wasm` or `module.opt.wat`.
+pub const OUTPUT_DIR_MODULE_STEM: &str = "module";
+
+impl Options {
+    /// Returns the path to write the module to, whose extension will be replaced for each format
+    /// (see [`write_module`]). This is [`OUTPUT_DIR_MODULE_STEM`] in the output directory if set,
+    /// or the output path otherwise. Returns `None` when running a subcommand.
+    ///
+    /// [`write_module`]: crate::write_module
+    pub fn module_path(&self) -> Option<PathBuf> {
+        match &self.output_dir {
+            Some(output_dir) => Some(output_dir.join(OUTPUT_DIR_MODULE_STEM)),
+            None => self.output_path.clone(),
+        }
+    }
+
+    /// Returns the path to write another output to, resolving relative `path`s against the output
+    /// directory if set.
+    pub fn artifact_path(&self, path: &Path) -> PathBuf {
+        match &self.output_dir {
+            Some(output_dir) => output_dir.join(path),
+            None => path.to_path_buf(),
+        }
+    }
+}
+
+/// Subcommands run instead of compiling input classes.
+#[derive(clap::Subcommand, Debug)]
+pub enum Command {
+    /// Print each method's access flags, whether it has code, and which unsupported instructions
+    /// it contains, without writing any output
+    Inspect {
+        /// Accept slightly malformed class files (e.g. from obfuscators)
+        #[clap(long)]
+        compat_mode: bool,
+
+        /// Class files to inspect (.class)
+        #[clap(required = true, value_name = "CLASS", parse(from_os_str))]
+        input_paths: Vec<PathBuf>,
+    },
+}
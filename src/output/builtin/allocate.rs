@@ -1,17 +1,46 @@
 use crate::class::FunctionType;
+use crate::output::memory::align_to;
 use crate::virtuals::VIRTUAL_CLASS_ID_MEM_ARG;
 use wasm_encoder::{Function as WASMFunction, Instruction as WASMInstruction, ValType};
 
+/// Alignment of the address of the first block allocated on the heap, so 8 byte fields in it are
+/// aligned.
+pub const HEAP_ALIGN: u32 = 8;
+
+/// Returns the address of the first block allocated on the heap of a module with
+/// `static_data_size` bytes of static data at the start of memory. The heap starts immediately
+/// after any static data, rounded up to [`HEAP_ALIGN`] bytes, and never at 0, so we can use 0 as
+/// the null reference.
+///
+/// Blocks are allocated contiguously after this (see [`construct_allocate`]), so every block's
+/// address is fully determined by the static data size and the sizes of the blocks allocated
+/// before it.
+pub const fn heap_base(static_data_size: u32) -> i32 {
+    let end = if static_data_size == 0 {
+        1
+    } else {
+        static_data_size
+    };
+    align_to(end, HEAP_ALIGN) as i32
+}
+
+/// Address of the first block allocated on the heap. Modules don't contain any static data yet, so
+/// this is 8, the first aligned address after the null reference.
+pub const HEAP_BASE: i32 = heap_base(0);
+
 /// Constructs a function (type and body) for allocating empty memory blocks for object instances
 /// on the heap. The function has the signature `[size: i32, virtual_class_id: i32] -> [ptr: i32]`.
 ///
 /// This uses a bump allocator. The `mut i32` global variable at `heap_next_global_index` points
 /// to the next free address in hte heap. On allocation, the current value of this variable is
-/// returned (start of block) and incremented by the desired size of the block. This allocator
-/// is very fast, but no garbage collection is performed.
+/// returned (start of block) and incremented by the desired size of the block. Blocks aren't
+/// padded, so each starts exactly the previous block's size after it. Instance sizes are multiples
+/// of [`HEAP_ALIGN`] though, so every instance stays aligned. This allocator is very fast, but no
+/// garbage collection is performed.
 ///
 /// This function will also store the 4 byte `virtual_class_id` at the start of the block to
-/// identify the instance type.
+/// identify the instance type. Memory is never reused, so any remaining header bytes (e.g. the
+/// array length with `--uniform-headers`) will already be zero.
 pub fn construct_allocate(heap_next_global_index: u32) -> (FunctionType, WASMFunction) {
     let func_type = FunctionType {
         params: vec![ValType::I32, ValType::I32], // [size: i32, virtual_class_id: i32]
@@ -36,11 +65,23 @@ pub fn construct_allocate(heap_next_global_index: u32) -> (FunctionType, WASMFun
 
 #[cfg(test)]
 mod tests {
-    use crate::output::builtin::BuiltinFunction;
+    use crate::output::builtin::{heap_base, BuiltinFunction, HEAP_BASE};
     use crate::tests::{construct_builtin_module, WASM_ENGINE};
     use std::convert::TryInto;
     use wasmtime::{Linker, Module, Store};
 
+    #[test]
+    fn heap_base_after_static_data() {
+        // Check 0 is reserved for the null reference
+        assert_eq!(heap_base(0), 8);
+        assert_eq!(HEAP_BASE, heap_base(0));
+        // Check heap starts at the next aligned address after static data
+        assert_eq!(heap_base(1), 8);
+        assert_eq!(heap_base(8), 8);
+        assert_eq!(heap_base(9), 16);
+        assert_eq!(heap_base(100), 104);
+    }
+
     #[test]
     fn allocate() -> anyhow::Result<()> {
         // Instantiate WebAssembly module
@@ -67,6 +108,35 @@ mod tests {
         assert_eq!(vid1, 42);
         assert_eq!(vid2, 5000);
 
+        // Check debug globals not exported by default
+        assert!(instance.get_global(&mut store, "heap_next").is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn allocate_debug_globals() -> anyhow::Result<()> {
+        // Instantiate WebAssembly module
+        let mut module = construct_builtin_module(&[BuiltinFunction::Allocate]);
+        module.emit_debug_globals = true;
+        let module = Module::new(&WASM_ENGINE, module.finish())?;
+        let linker = Linker::new(&WASM_ENGINE);
+        let mut store = Store::new(&WASM_ENGINE, 0);
+        let instance = linker.instantiate(&mut store, &module)?;
+
+        // Get references to exports
+        let allocate = instance.get_typed_func::<(i32, i32), i32, _>(&mut store, "!Allocate")?;
+        let heap_base = instance.get_global(&mut store, "heap_base").unwrap();
+        let heap_next = instance.get_global(&mut store, "heap_next").unwrap();
+        let heap_base = heap_base.get(&mut store).unwrap_i32();
+        assert_eq!(heap_base, HEAP_BASE);
+        assert_eq!(heap_next.get(&mut store).unwrap_i32(), heap_base);
+
+        // Check next pointer reflects total allocated size
+        allocate.call(&mut store, (/* size */ 16, /* virtual class ID */ 42))?;
+        allocate.call(&mut store, (/* size */ 10, /* virtual class ID */ 5000))?;
+        assert_eq!(heap_next.get(&mut store).unwrap_i32() - heap_base, 16 + 10);
+
         Ok(())
     }
 }
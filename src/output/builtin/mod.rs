@@ -1,12 +1,14 @@
 mod allocate;
 mod compare;
 mod instanceof;
+mod null;
 mod number;
 mod rem;
 
 pub use self::allocate::*;
 pub use self::compare::*;
 pub use self::instanceof::*;
+pub use self::null::*;
 pub use self::rem::*;
 
 /// Possible built-in functions for high-level JVM instructions that are not supported by
@@ -19,6 +21,9 @@ pub enum BuiltinFunction {
     /// See [`instanceof::construct_instanceof`] for more details.
     /// `[ptr: i32, target_virtual_class_id: i32] -> [is: i32]`
     InstanceOf,
+    /// See [`null::construct_null_check`] for more details.
+    /// `[ptr: i32] -> [ptr: i32]`
+    NullCheck,
 
     /// See [`compare::construct_compare`] for more details.
     /// `[a: i64, b: i64] -> [ord: i32]`
@@ -44,6 +49,7 @@ impl BuiltinFunction {
         match self {
             BuiltinFunction::Allocate => "!Allocate",
             BuiltinFunction::InstanceOf => "!InstanceOf",
+            BuiltinFunction::NullCheck => "!NullCheck",
             BuiltinFunction::LongCmp => "!LongCmp",
             BuiltinFunction::FloatCmp => "!FloatCmp",
             BuiltinFunction::DoubleCmp => "!DoubleCmp",
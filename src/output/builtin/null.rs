@@ -0,0 +1,58 @@
+use crate::class::FunctionType;
+use crate::output::trap::trap;
+use crate::output::TrapKind;
+use wasm_encoder::{BlockType, Function as WASMFunction, Instruction as WASMInstruction, ValType};
+
+/// Constructs a function (type and body) for checking an object reference isn't `null`. This
+/// function has the signature: `[ptr: i32] -> [ptr: i32]`, trapping if `ptr` is `0` and returning
+/// `ptr` unchanged otherwise.
+///
+/// Exceptions are not yet supported, so this traps instead of throwing a `NullPointerException`.
+/// Without this check, accesses through `null` would silently read from or write to memory near
+/// address `0`. If `trap_index` is set (with `--rich-traps`), the imported trap function at that
+/// index is called with [`TrapKind::NullPointer`]'s code first.
+pub fn construct_null_check(trap_index: Option<u32>) -> (FunctionType, WASMFunction) {
+    let func_type = FunctionType {
+        params: vec![ValType::I32],  // [ptr: i32]
+        results: vec![ValType::I32], // [ptr: i32]
+    };
+    let mut f = WASMFunction::new(vec![]);
+
+    // 1. If the reference is null, trap
+    f.instruction(&WASMInstruction::LocalGet(/* ptr */ 0))
+        .instruction(&WASMInstruction::I32Eqz /* null */)
+        .instruction(&WASMInstruction::If(BlockType::Empty));
+    trap(&mut f, trap_index, TrapKind::NullPointer).instruction(&WASMInstruction::End);
+
+    // 2. Otherwise, return the reference
+    f.instruction(&WASMInstruction::LocalGet(/* ptr */ 0))
+        .instruction(&WASMInstruction::End);
+
+    (func_type, f)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::output::builtin::BuiltinFunction;
+    use crate::tests::{construct_builtin_module, WASM_ENGINE};
+    use wasmtime::{Linker, Module, Store, TrapCode};
+
+    #[test]
+    fn null_check() -> anyhow::Result<()> {
+        let module = construct_builtin_module(&[BuiltinFunction::NullCheck]);
+        let module = Module::new(&WASM_ENGINE, module.finish())?;
+        let linker = Linker::new(&WASM_ENGINE);
+        let mut store = Store::new(&WASM_ENGINE, 0);
+        let instance = linker.instantiate(&mut store, &module)?;
+
+        let null_check = instance.get_typed_func::<i32, i32, _>(&mut store, "!NullCheck")?;
+
+        // Check non-null references returned unchanged
+        assert_eq!(null_check.call(&mut store, 8)?, 8);
+        // Check null references trap
+        let res = null_check.call(&mut store, 0).unwrap_err();
+        assert_eq!(res.trap_code(), Some(TrapCode::UnreachableCodeReached));
+
+        Ok(())
+    }
+}
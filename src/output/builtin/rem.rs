@@ -116,7 +116,13 @@ mod tests {
             construct_builtin_module(&[BuiltinFunction::FloatRem, BuiltinFunction::DoubleRem]);
 
         let bytes = module.finish();
-        write_module(&PathBuf::from_str("test.wasm")?, &bytes, "wasm", "wat")?;
+        write_module(
+            &PathBuf::from_str("test.wasm")?,
+            &bytes,
+            "wasm",
+            "wat",
+            false,
+        )?;
 
         let module = Module::new(&WASM_ENGINE, bytes)?;
         let linker = Linker::new(&WASM_ENGINE);
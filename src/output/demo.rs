@@ -0,0 +1,252 @@
+use crate::class::FunctionType;
+use crate::function::CompiledFunction;
+use crate::output::builtin::BuiltinFunction;
+use crate::output::types::EnsuredFunction;
+use crate::output::Module;
+use std::str::FromStr;
+use std::sync::Arc;
+use wasm_encoder::{
+    BlockType, EntityType, Export, Function as WASMFunction, Instruction as WASMInstruction,
+    MemArg, ValType,
+};
+
+/// Name of the WASI module `fd_write` is imported from.
+pub const WASI_MODULE: &str = "wasi_snapshot_preview1";
+
+/// File descriptor for standard output.
+const STDOUT: i32 = 1;
+
+/// Size of the heap block used for printing, large enough for a `fd_write` I/O vector, the number
+/// of bytes written, and the longest formatted `int` (`-2147483648\n`), after the virtual class ID.
+///
+/// ```text
+/// [vid: i32][iov_base: i32][iov_len: i32][nwritten: i32][chars: 16 x u8]
+/// 0         4              8             12             16              32
+/// ```
+const PRINT_BLOCK_SIZE: i32 = 32;
+
+/// Method to call from a generated WASI `_start` function with a constant argument, printing the
+/// result to standard output. Parsed from `--demo-main` options of the form
+/// `Class.method(I)I:<arg>`, using the same method names as exports.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct DemoMain {
+    pub method: String,
+    pub arg: i32,
+}
+
+impl FromStr for DemoMain {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (method, arg) = s
+            .rsplit_once(':')
+            .ok_or_else(|| anyhow!("Expected <METHOD>:<ARG>, got \"{}\"", s))?;
+        let arg = arg
+            .parse()
+            .map_err(|_| anyhow!("Expected int argument, got \"{}\"", arg))?;
+        Ok(DemoMain {
+            method: method.to_string(),
+            arg,
+        })
+    }
+}
+
+impl DemoMain {
+    /// Checks the method to call exists in `functions`, and is `static` with signature `(I)I`.
+    pub fn check(&self, functions: &[CompiledFunction]) -> anyhow::Result<()> {
+        let function = functions
+            .iter()
+            .find(|function| function.id.to_string() == self.method)
+            .ok_or_else(|| anyhow!("Unable to find demo main method {}", self.method))?;
+        ensure!(
+            function.is_static(),
+            "Demo main method {} must be static",
+            self.method
+        );
+        ensure!(
+            function.descriptor.to_string() == "(I)I",
+            "Demo main method {} must accept and return an int",
+            self.method
+        );
+        Ok(())
+    }
+}
+
+impl Module {
+    /// Adds the WASI `fd_write` import to this module, returning its function index. This has the
+    /// signature `[fd: i32, iovs: i32, iovs_len: i32, nwritten: i32] -> [errno: i32]`.
+    ///
+    /// As imports must come before all other functions, this must be called before any other
+    /// functions are indexed.
+    pub fn import_wasi_fd_write(&mut self) -> u32 {
+        let func_type = Arc::new(FunctionType {
+            params: vec![ValType::I32; 4], // [fd: i32, iovs: i32, iovs_len: i32, nwritten: i32]
+            results: vec![ValType::I32],   // [errno: i32]
+        });
+        let type_index = self.ensure_type(&func_type);
+        self.imports.import(
+            WASI_MODULE,
+            Some("fd_write"),
+            EntityType::Function(type_index),
+        );
+
+        let index = self.next_function_index;
+        self.next_function_index += 1;
+        self.function_names.append(index, "!fd_write");
+        index
+    }
+
+    /// Adds an exported WASI `_start` function to this module, calling the `(I)I` function at
+    /// `target_index` with `arg` and printing the result in decimal followed by a newline using
+    /// `fd_write` at `fd_write_index`. Note that [`Module::render_ensured_functions_queue`] must
+    /// be called to actually render the function to the module.
+    pub fn add_demo_start(&mut self, fd_write_index: u32, target_index: u32, arg: i32) {
+        // Ensure allocate before getting our index, so ensured functions are rendered in order
+        let allocate_index = self.ensure_builtin_function(BuiltinFunction::Allocate);
+        let index = self.next_function_index;
+        self.next_function_index += 1;
+
+        let func_type = Arc::new(FunctionType {
+            params: vec![],
+            results: vec![],
+        });
+        let type_index = self.ensure_type(&func_type);
+
+        const BLOCK: u32 = 0; // Start of heap block
+        const POS: u32 = 1; // Start of formatted characters, written backwards from the end
+        const NEG: u32 = 2; // Whether the result is negative
+        const VALUE: u32 = 3; // Remaining absolute value of the result to format
+        let byte = |offset| MemArg {
+            offset,
+            align: 0,
+            memory_index: 0,
+        };
+        let word = |offset| MemArg {
+            offset,
+            align: 2, // log2(4) = 2
+            memory_index: 0,
+        };
+        let mut f = WASMFunction::new(vec![(3, ValType::I32), (1, ValType::I64)]);
+
+        // 1. Allocate a block on the heap for printing, using `java/lang/Object`'s virtual class ID
+        f.instruction(&WASMInstruction::I32Const(PRINT_BLOCK_SIZE))
+            .instruction(&WASMInstruction::I32Const(0))
+            .instruction(&WASMInstruction::Call(allocate_index))
+            .instruction(&WASMInstruction::LocalTee(BLOCK))
+            .instruction(&WASMInstruction::I32Const(PRINT_BLOCK_SIZE))
+            .instruction(&WASMInstruction::I32Add)
+            .instruction(&WASMInstruction::LocalSet(POS));
+
+        // 2. Write the trailing newline
+        f.instruction(&WASMInstruction::LocalGet(POS))
+            .instruction(&WASMInstruction::I32Const(1))
+            .instruction(&WASMInstruction::I32Sub)
+            .instruction(&WASMInstruction::LocalTee(POS))
+            .instruction(&WASMInstruction::I32Const(b'\n' as i32))
+            .instruction(&WASMInstruction::I32Store8(byte(0)));
+
+        // 3. Call the target, taking the absolute value of the result as an i64 so `MIN_VALUE`
+        //    doesn't overflow
+        f.instruction(&WASMInstruction::I32Const(arg))
+            .instruction(&WASMInstruction::Call(target_index))
+            .instruction(&WASMInstruction::I64ExtendI32S)
+            .instruction(&WASMInstruction::LocalTee(VALUE))
+            .instruction(&WASMInstruction::I64Const(0))
+            .instruction(&WASMInstruction::I64LtS)
+            .instruction(&WASMInstruction::LocalTee(NEG))
+            .instruction(&WASMInstruction::If(BlockType::Empty))
+            .instruction(&WASMInstruction::I64Const(0))
+            .instruction(&WASMInstruction::LocalGet(VALUE))
+            .instruction(&WASMInstruction::I64Sub)
+            .instruction(&WASMInstruction::LocalSet(VALUE))
+            .instruction(&WASMInstruction::End);
+
+        // 4. Write digits backwards, least significant first, until the value is 0 (writing at
+        //    least one digit)
+        f.instruction(&WASMInstruction::Loop(BlockType::Empty))
+            .instruction(&WASMInstruction::LocalGet(POS))
+            .instruction(&WASMInstruction::I32Const(1))
+            .instruction(&WASMInstruction::I32Sub)
+            .instruction(&WASMInstruction::LocalTee(POS))
+            .instruction(&WASMInstruction::LocalGet(VALUE))
+            .instruction(&WASMInstruction::I64Const(10))
+            .instruction(&WASMInstruction::I64RemU)
+            .instruction(&WASMInstruction::I32WrapI64)
+            .instruction(&WASMInstruction::I32Const(b'0' as i32))
+            .instruction(&WASMInstruction::I32Add)
+            .instruction(&WASMInstruction::I32Store8(byte(0)))
+            .instruction(&WASMInstruction::LocalGet(VALUE))
+            .instruction(&WASMInstruction::I64Const(10))
+            .instruction(&WASMInstruction::I64DivU)
+            .instruction(&WASMInstruction::LocalTee(VALUE))
+            .instruction(&WASMInstruction::I64Const(0))
+            .instruction(&WASMInstruction::I64Neq)
+            .instruction(&WASMInstruction::BrIf(0))
+            .instruction(&WASMInstruction::End);
+
+        // 5. Write the sign if negative
+        f.instruction(&WASMInstruction::LocalGet(NEG))
+            .instruction(&WASMInstruction::If(BlockType::Empty))
+            .instruction(&WASMInstruction::LocalGet(POS))
+            .instruction(&WASMInstruction::I32Const(1))
+            .instruction(&WASMInstruction::I32Sub)
+            .instruction(&WASMInstruction::LocalTee(POS))
+            .instruction(&WASMInstruction::I32Const(b'-' as i32))
+            .instruction(&WASMInstruction::I32Store8(byte(0)))
+            .instruction(&WASMInstruction::End);
+
+        // 6. Fill in the I/O vector with the start and length of the formatted characters
+        f.instruction(&WASMInstruction::LocalGet(BLOCK))
+            .instruction(&WASMInstruction::LocalGet(POS))
+            .instruction(&WASMInstruction::I32Store(word(4)))
+            .instruction(&WASMInstruction::LocalGet(BLOCK))
+            .instruction(&WASMInstruction::LocalGet(BLOCK))
+            .instruction(&WASMInstruction::I32Const(PRINT_BLOCK_SIZE))
+            .instruction(&WASMInstruction::I32Add)
+            .instruction(&WASMInstruction::LocalGet(POS))
+            .instruction(&WASMInstruction::I32Sub)
+            .instruction(&WASMInstruction::I32Store(word(8)));
+
+        // 7. Write to standard output, ignoring any errors
+        f.instruction(&WASMInstruction::I32Const(STDOUT))
+            .instruction(&WASMInstruction::LocalGet(BLOCK))
+            .instruction(&WASMInstruction::I32Const(4))
+            .instruction(&WASMInstruction::I32Add)
+            .instruction(&WASMInstruction::I32Const(1))
+            .instruction(&WASMInstruction::LocalGet(BLOCK))
+            .instruction(&WASMInstruction::I32Const(12))
+            .instruction(&WASMInstruction::I32Add)
+            .instruction(&WASMInstruction::Call(fd_write_index))
+            .instruction(&WASMInstruction::Drop)
+            .instruction(&WASMInstruction::End);
+
+        // Queue writing function to sections
+        self.ensured_functions.push(EnsuredFunction {
+            type_index,
+            function_index: index,
+            function: f,
+            name: String::from("!DemoStart"),
+        });
+        self.exports.export("_start", Export::Function(index));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::output::DemoMain;
+
+    #[test]
+    fn parse_demo_main() -> anyhow::Result<()> {
+        let demo_main: DemoMain = "Test.f(I)I:-42".parse()?;
+        assert_eq!(
+            demo_main,
+            DemoMain {
+                method: String::from("Test.f(I)I"),
+                arg: -42
+            }
+        );
+        assert!("Test.f(I)I".parse::<DemoMain>().is_err());
+        assert!("Test.f(I)I:x".parse::<DemoMain>().is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,153 @@
+use crate::class::FunctionType;
+use crate::output::merge::ParsedModule;
+use itertools::Itertools;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::Formatter;
+use std::sync::Arc;
+use wasmparser::{Operator, Type};
+
+/// Part of a function body compared when diffing. Instructions referencing functions or types are
+/// compared by name or signature instead of index, as indices shift whenever functions are added or
+/// removed. All other instructions are compared by their encoded bytes.
+#[derive(Debug, Eq, PartialEq)]
+enum BodyPart<'a> {
+    Locals(Vec<(u32, Type)>),
+    Call(String),
+    CallIndirect(Arc<FunctionType>),
+    ReturnCall(String),
+    ReturnCallIndirect(Arc<FunctionType>),
+    Bytes(&'a [u8]),
+}
+
+/// Comparable contents of a function: its type and, if it isn't imported, its body.
+#[derive(Debug, Eq, PartialEq)]
+struct FunctionContents<'a> {
+    func_type: Arc<FunctionType>,
+    body: Option<Vec<BodyPart<'a>>>,
+}
+
+/// Returns the name of the function at `index`, falling back to its index if it has no name.
+pub(super) fn function_name(module: &ParsedModule<'_>, index: u32) -> String {
+    match module.names.get(&index) {
+        Some(name) => name.to_string(),
+        None => format!("#{}", index),
+    }
+}
+
+/// Returns the comparable contents of every function in a finished module's bytes, by name.
+fn function_contents<'a>(
+    wasm: &'a [u8],
+    module: &ParsedModule<'a>,
+) -> anyhow::Result<BTreeMap<String, FunctionContents<'a>>> {
+    let mut functions = BTreeMap::new();
+    for index in 0..module.function_count() as u32 {
+        let func_type = Arc::clone(&module.types[module.function_type_index(index) as usize]);
+        let body = match (index as usize).checked_sub(module.imports.len()) {
+            Some(defined_index) => {
+                let body = &module.bodies[defined_index];
+                let mut parts = vec![];
+
+                let mut locals_reader = body.get_locals_reader()?;
+                let mut locals = vec![];
+                for _ in 0..locals_reader.get_count() {
+                    locals.push(locals_reader.read()?);
+                }
+                parts.push(BodyPart::Locals(locals));
+
+                let mut reader = body.get_operators_reader()?;
+                let mut operators = vec![];
+                while !reader.eof() {
+                    operators.push(reader.read_with_offset()?);
+                }
+                let end = body.range().end;
+                for (i, (operator, start)) in operators.iter().enumerate() {
+                    parts.push(match operator {
+                        Operator::Call { function_index } => {
+                            BodyPart::Call(function_name(module, *function_index))
+                        }
+                        Operator::CallIndirect { index, .. } => {
+                            BodyPart::CallIndirect(Arc::clone(&module.types[*index as usize]))
+                        }
+                        Operator::ReturnCall { function_index } => {
+                            BodyPart::ReturnCall(function_name(module, *function_index))
+                        }
+                        Operator::ReturnCallIndirect { index, .. } => {
+                            BodyPart::ReturnCallIndirect(Arc::clone(&module.types[*index as usize]))
+                        }
+                        _ => {
+                            let end = operators.get(i + 1).map_or(end, |(_, next)| *next);
+                            BodyPart::Bytes(&wasm[*start..end])
+                        }
+                    });
+                }
+                Some(parts)
+            }
+            None => None,
+        };
+        let contents = FunctionContents { func_type, body };
+        functions.insert(function_name(module, index), contents);
+    }
+    Ok(functions)
+}
+
+/// Function-level differences between two modules, identifying functions by name (see
+/// [`diff_modules`]). Each list is sorted by name.
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct ModuleDiff {
+    /// Functions only in the new module.
+    pub added: Vec<String>,
+    /// Functions only in the baseline module.
+    pub removed: Vec<String>,
+    /// Functions in both modules with different types, bodies, or that are only imported in one.
+    pub changed: Vec<String>,
+}
+
+impl ModuleDiff {
+    /// Returns `true` if and only if both modules contained the same functions.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl fmt::Display for ModuleDiff {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "No function differences");
+        }
+        let lines = (self.added.iter().map(|name| ('+', name)))
+            .chain(self.removed.iter().map(|name| ('-', name)))
+            .chain(self.changed.iter().map(|name| ('~', name)))
+            .map(|(prefix, name)| format!("{} {}", prefix, name));
+        write!(f, "{}", lines.format("\n"))
+    }
+}
+
+/// Compares the functions of two finished modules' bytes (see [`Module::finish`]), for triaging
+/// regressions against a `baseline` with `--diff`. Functions are matched by name, and calls are
+/// compared by callee name, so adding or removing a function doesn't change its callers.
+///
+/// [`Module::finish`]: crate::output::Module::finish
+pub fn diff_modules(new: &[u8], baseline: &[u8]) -> anyhow::Result<ModuleDiff> {
+    let new_module = ParsedModule::parse(new)?;
+    let baseline_module = ParsedModule::parse(baseline)?;
+    let new_functions = function_contents(new, &new_module)?;
+    let baseline_functions = function_contents(baseline, &baseline_module)?;
+
+    let mut diff = ModuleDiff::default();
+    for (name, contents) in &new_functions {
+        match baseline_functions.get(name) {
+            Some(baseline_contents) if baseline_contents != contents => {
+                diff.changed.push(name.clone())
+            }
+            Some(_) => {}
+            None => diff.added.push(name.clone()),
+        }
+    }
+    for name in baseline_functions.keys() {
+        if !new_functions.contains_key(name) {
+            diff.removed.push(name.clone());
+        }
+    }
+    Ok(diff)
+}
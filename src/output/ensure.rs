@@ -1,16 +1,21 @@
 use crate::class::FunctionType;
 use crate::output::builtin::{
-    construct_allocate, construct_compare, construct_instanceof, construct_rem, BuiltinFunction,
+    construct_allocate, construct_compare, construct_instanceof, construct_null_check,
+    construct_rem, BuiltinFunction, HEAP_BASE,
 };
-use crate::output::types::EnsuredFunction;
-use crate::output::Module;
+use crate::output::trap::trap;
+use crate::output::types::{return_call_indirect, EnsuredFunction};
+use crate::output::{Module, TrapKind};
 use crate::virtuals::VIRTUAL_CLASS_ID_MEM_ARG;
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
+use std::ops::Range;
 use std::sync::Arc;
 use wasm_encoder::{
-    Function as WASMFunction, GlobalType, Instruction as WASMInstruction, TypeSection, ValType,
+    encoders, BlockType, CodeSection, Function as WASMFunction, GlobalType,
+    Instruction as WASMInstruction, Section, TypeSection, ValType,
 };
+use wasmparser::{BinaryReader, CodeSectionReader, Operator};
 
 /// Possible types or functions other functions want to *ensure* exist once in the output module.
 /// These represent functions' dependencies.
@@ -27,6 +32,135 @@ pub enum Ensurable {
     Builtin(BuiltinFunction),
 }
 
+/// Source of function indices for the built-in and virtual dispatcher functions a function body
+/// calls. Rendering directly to a [`Module`] ensures dependencies as they're encountered, whereas
+/// parallel rendering records them with [`RecordedDependencies`], and patches their indices in
+/// later with [`RecordedBody::patch`].
+pub(super) trait Dependencies {
+    /// Returns the function index of `builtin`. See [`Module::ensure_builtin_function`].
+    fn builtin(&mut self, builtin: BuiltinFunction) -> u32;
+    /// Returns the function index of the dispatcher for `func_type`. See
+    /// [`Module::ensure_dispatcher_function`].
+    fn dispatcher(&mut self, func_type: &Arc<FunctionType>) -> u32;
+}
+
+impl Dependencies for Module {
+    fn builtin(&mut self, builtin: BuiltinFunction) -> u32 {
+        self.ensure_builtin_function(builtin)
+    }
+
+    fn dispatcher(&mut self, func_type: &Arc<FunctionType>) -> u32 {
+        self.ensure_dispatcher_function(func_type)
+    }
+}
+
+/// Function indices at least this large are placeholders returned by [`RecordedDependencies`],
+/// offset by the index of the dependency they call. Modules never have this many functions.
+const PLACEHOLDER_INDEX_BASE: u32 = 0xF000_0000;
+
+/// Dependencies of a function body in the order they were required, returning unique placeholder
+/// indices. These can be ensured later with [`Module::ensure`] in the same order rendering directly
+/// to the module would, so indices are identical. See [`RecordedBody`].
+#[derive(Default)]
+pub(super) struct RecordedDependencies(Vec<Ensurable>);
+
+impl RecordedDependencies {
+    /// Records a dependency on `ensurable`, returning its placeholder index.
+    fn record(&mut self, ensurable: Ensurable) -> u32 {
+        let index = PLACEHOLDER_INDEX_BASE + self.0.len() as u32;
+        self.0.push(ensurable);
+        index
+    }
+}
+
+impl Dependencies for RecordedDependencies {
+    fn builtin(&mut self, builtin: BuiltinFunction) -> u32 {
+        self.record(Ensurable::Builtin(builtin))
+    }
+
+    fn dispatcher(&mut self, func_type: &Arc<FunctionType>) -> u32 {
+        self.record(Ensurable::Dispatcher(Arc::clone(func_type)))
+    }
+}
+
+/// Encoded function body rendered with [`RecordedDependencies`], recording where placeholder
+/// indices are called, so real indices can be patched in once dependencies have been ensured,
+/// without rendering the body again.
+pub(super) struct RecordedBody {
+    /// Encoded locals and instructions, excluding the body's size prefix.
+    bytes: Vec<u8>,
+    /// Dependencies of the body in the order they were required.
+    dependencies: Vec<Ensurable>,
+    /// Byte ranges in `bytes` of each placeholder index immediate, and the index in `dependencies`
+    /// of the dependency called.
+    placeholders: Vec<(Range<usize>, usize)>,
+}
+
+impl RecordedBody {
+    /// Encodes `f`, finding the `call` and `return_call` instructions calling placeholder indices
+    /// returned by `deps`.
+    pub fn new(f: &WASMFunction, deps: RecordedDependencies) -> Self {
+        // wasm_encoder doesn't expose function bodies' bytes, so encode it in a code section, then
+        // find the body after the section's size prefix
+        let mut code = CodeSection::new();
+        code.function(f);
+        let mut section = vec![];
+        code.encode(&mut section);
+        let mut reader = BinaryReader::new(&section);
+        reader.read_var_u32().unwrap();
+        let start = reader.original_position();
+        let mut reader = CodeSectionReader::new(&section[start..], start)
+            .expect("Rendered code section should be valid");
+        let body = reader.read().expect("Rendered body should be valid");
+        // Offsets are relative to the start of the section
+        let range = body.range();
+
+        let mut placeholders = vec![];
+        let mut operators = body.get_operators_reader().unwrap();
+        while !operators.eof() {
+            let (operator, start) = operators
+                .read_with_offset()
+                .expect("Rendered instructions should be valid");
+            match operator {
+                Operator::Call { function_index } | Operator::ReturnCall { function_index }
+                    if function_index >= PLACEHOLDER_INDEX_BASE =>
+                {
+                    // Both opcodes are a single byte, followed by just the index
+                    let immediate =
+                        (start + 1 - range.start)..(operators.original_position() - range.start);
+                    let dependency = (function_index - PLACEHOLDER_INDEX_BASE) as usize;
+                    placeholders.push((immediate, dependency));
+                }
+                _ => {}
+            }
+        }
+
+        RecordedBody {
+            bytes: section[range.start..range.end].to_vec(),
+            dependencies: deps.0,
+            placeholders,
+        }
+    }
+
+    /// Ensures this body's dependencies in `out` in the order they were required, returning the
+    /// encoded body with their real indices patched in. Indices may be encoded in fewer bytes than
+    /// placeholders, so the body may shrink.
+    pub fn patch(&self, out: &mut Module) -> Vec<u8> {
+        let indices: Vec<_> = (self.dependencies.iter())
+            .map(|ensurable| out.ensure(ensurable))
+            .collect();
+        let mut bytes = Vec::with_capacity(self.bytes.len());
+        let mut copied = 0;
+        for (immediate, dependency) in &self.placeholders {
+            bytes.extend_from_slice(&self.bytes[copied..immediate.start]);
+            bytes.extend(encoders::u32(indices[*dependency]));
+            copied = immediate.end;
+        }
+        bytes.extend_from_slice(&self.bytes[copied..]);
+        bytes
+    }
+}
+
 /// Ensures a function type is included in a WebAssembly module, adding it if it isn't, and
 /// returning the new or existing type index either way.
 fn ensure_type(
@@ -74,8 +208,21 @@ impl Module {
         }
     }
 
+    /// Ensures a type or function is included in a WebAssembly module, adding it if it isn't, and
+    /// returning the new or existing index either way. See [`Ensurable`] for what index is returned.
+    pub(super) fn ensure(&mut self, ensurable: &Ensurable) -> u32 {
+        match ensurable {
+            Ensurable::Type(func_type) => self.ensure_type(func_type),
+            Ensurable::Dispatcher(func_type) => self.ensure_dispatcher_function(func_type),
+            Ensurable::Builtin(builtin) => self.ensure_builtin_function(*builtin),
+        }
+    }
+
     /// Ensures a function type is included in a WebAssembly module, adding it if it isn't, and
     /// returning the new or existing type index either way.
+    ///
+    /// This is called for every rendered function and dispatcher, so existing types are looked up
+    /// in the `ensured` hash map, giving amortised constant time deduplication.
     pub fn ensure_type(&mut self, func_type: &Arc<FunctionType>) -> u32 {
         let Module {
             ensured,
@@ -90,6 +237,13 @@ impl Module {
     /// isn't, and returning the new or existing function index either way. Note that
     /// [`Module::render_ensured_functions_queue`] must be called to actually render the function to
     /// the module. See [`crate::virtuals::VirtualTable`] for details on dispatchers.
+    ///
+    /// If [`Module::null_checks`] is set, dispatchers will trap if their implicit `this` parameter
+    /// is `null`, instead of dispatching using a bogus virtual class ID. Checking here means the
+    /// reference doesn't need to be extracted from underneath the arguments at each call site.
+    ///
+    /// If [`Module::tail_calls`] is set, dispatchers will tail call the implementation, so a
+    /// virtual call in tail position doesn't leave the dispatcher's frame on the stack.
     pub fn ensure_dispatcher_function(&mut self, func_type: &Arc<FunctionType>) -> u32 {
         let Module {
             ensured,
@@ -97,6 +251,9 @@ impl Module {
             next_function_index,
             ensured_functions,
             types,
+            null_checks,
+            tail_calls,
+            trap_index,
             ..
         } = self;
         // Return existing dispatcher function index or create a new one
@@ -131,6 +288,14 @@ impl Module {
 
                 // Construct dispatcher function code
                 let mut f = WASMFunction::new(vec![]);
+                // 0. If enabled, trap if the implicit this parameter is null
+                if *null_checks {
+                    f.instruction(&WASMInstruction::LocalGet(0))
+                        .instruction(&WASMInstruction::I32Eqz)
+                        .instruction(&WASMInstruction::If(BlockType::Empty));
+                    trap(&mut f, *trap_index, TrapKind::NullPointer)
+                        .instruction(&WASMInstruction::End);
+                }
                 // 1. Get all parameters for function indirect call
                 for i in 0..call_params_len {
                     f.instruction(&WASMInstruction::LocalGet(i));
@@ -142,11 +307,16 @@ impl Module {
                 // 3. Add virtual method offset
                 f.instruction(&WASMInstruction::LocalGet(call_params_len));
                 f.instruction(&WASMInstruction::I32Add);
-                // 4. Call correct function, using parameters from start of this call (3a)
-                f.instruction(&WASMInstruction::CallIndirect {
-                    ty: original_type_index,
-                    table: 0,
-                });
+                // 4. Call correct function, using parameters from start of this call (3a), reusing
+                //    this function's stack frame if tail calls are enabled
+                if *tail_calls {
+                    return_call_indirect(&mut f, original_type_index, 0);
+                } else {
+                    f.instruction(&WASMInstruction::CallIndirect {
+                        ty: original_type_index,
+                        table: 0,
+                    });
+                }
                 f.instruction(&WASMInstruction::End);
 
                 // Queue writing function to sections
@@ -173,9 +343,11 @@ impl Module {
             next_type_index,
             next_function_index,
             next_global_index,
+            heap_next_global_index,
             ensured_functions,
             types,
             globals,
+            trap_index,
             ..
         } = self;
         // Return existing builtin function index or create a new one
@@ -195,18 +367,19 @@ impl Module {
                 // Construct builtin function
                 let (func_type, f) = match builtin {
                     BuiltinFunction::Allocate => {
-                        // Get global for bump allocator's heap next pointer
-                        let heap_next_global_index = *next_global_index;
+                        // Get global for bump allocator's heap next pointer, recording it so it
+                        // can be exported with `--emit-debug-globals`
+                        let global_index = *next_global_index;
                         *next_global_index += 1;
+                        *heap_next_global_index = Some(global_index);
                         globals.global(
                             GlobalType {
                                 val_type: ValType::I32,
                                 mutable: true,
                             },
-                            // Start at 8, so we can use 0 as null reference, whilst still being 8-byte aligned
-                            &WASMInstruction::I32Const(8),
+                            &WASMInstruction::I32Const(HEAP_BASE),
                         );
-                        construct_allocate(heap_next_global_index)
+                        construct_allocate(global_index)
                     }
                     BuiltinFunction::InstanceOf => {
                         // Get type of super ID functions: [] -> [super_vid: i32]
@@ -218,6 +391,7 @@ impl Module {
                             ensure_type(ensured, next_type_index, types, &super_id_func_type);
                         construct_instanceof(super_id_type_index)
                     }
+                    BuiltinFunction::NullCheck => construct_null_check(*trap_index),
                     BuiltinFunction::LongCmp => construct_compare(ValType::I64),
                     BuiltinFunction::FloatCmp => construct_compare(ValType::F32),
                     BuiltinFunction::DoubleCmp => construct_compare(ValType::F64),
@@ -240,3 +414,137 @@ impl Module {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::class::FunctionType;
+    use crate::output::merge::ParsedModule;
+    use crate::output::Module;
+    use crate::tests::{construct_code_module, validate_functions};
+    use std::sync::Arc;
+    use wasm_encoder::ValType;
+
+    #[test]
+    fn ensure_type_deduplicates() {
+        let mut module = Module::new();
+        let a = Arc::new(FunctionType {
+            params: vec![ValType::I32],
+            results: vec![ValType::I64],
+        });
+        let b = Arc::new(FunctionType {
+            params: vec![ValType::F32],
+            results: vec![],
+        });
+
+        // Check new types are assigned new indices...
+        assert_eq!(module.ensure_type(&a), 0);
+        assert_eq!(module.ensure_type(&b), 1);
+        // ...and existing types reuse indices, even if they're not the same Arc
+        assert_eq!(module.ensure_type(&a), 0);
+        assert_eq!(module.ensure_type(&Arc::new((*b).clone())), 1);
+        assert_eq!(module.types.len(), 2);
+    }
+
+    #[test]
+    fn ensure_type_many_identical_signatures() -> anyhow::Result<()> {
+        // Use different bodies so functions aren't deduplicated
+        let code = (0..32)
+            .map(|i| format!("static int f{i}(int x) {{ return x + {i}; }}\n"))
+            .collect::<String>();
+        let module = construct_code_module(&code)?;
+        // Implicit constructor's type (`[this: i32] -> []`), `[x: i32] -> [i32]`, and the virtual
+        // table's super ID function type (`[] -> [super_vid: i32]`)
+        assert_eq!(module.types.len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn ensure_dispatcher_deduplicates() -> anyhow::Result<()> {
+        // `f` and `g` have identical descriptors, and `h`'s differs but has the same WebAssembly
+        // function type, as references are `i32`s
+        let module = construct_code_module(
+            "int f(int x) { return x + 1; }
+            int g(int x) { return x + 2; }
+            int h(Test x) { return 3; }
+            long l(int x) { return 4; }
+
+            public static int call(int x) {
+                Test t = new Test();
+                return t.f(x) + t.g(x) + t.h(t) + (int) t.l(x);
+            }",
+        )?;
+        let wat = wasmprinter::print_bytes(module.finish())?;
+        let dispatchers = wat
+            .lines()
+            .filter_map(|line| line.trim_start().strip_prefix("(func $!Dispatcher_"))
+            .map(|line| line.split_whitespace().next().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(dispatchers, ["i32_i32", "i32_i64"]);
+        Ok(())
+    }
+
+    #[test]
+    fn ensure_dispatcher_results() -> anyhow::Result<()> {
+        // `set` returns `void` so its dispatcher has no results, whereas `get`'s has one
+        let module = construct_code_module(
+            "int x;
+            void set(int x) { this.x = x; }
+            int get() { return this.x; }
+
+            public static int call(int x) {
+                Test t = new Test();
+                t.set(x);
+                return t.get();
+            }",
+        )?;
+        let wasm = module.finish();
+        let wat = wasmprinter::print_bytes(&wasm)?;
+        let dispatchers = wat
+            .lines()
+            .filter_map(|line| line.trim_start().strip_prefix("(func $!Dispatcher_"))
+            .map(|line| line.split_whitespace().next().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(dispatchers, ["i32_", "_i32"]);
+        // Check both dispatchers' bodies match their signatures
+        validate_functions(&wasm)?;
+        Ok(())
+    }
+
+    #[test]
+    fn ensure_dispatcher_stable_names() -> anyhow::Result<()> {
+        // Dispatcher names only depend on their function type, so separately compiled programs
+        // needing the same dispatcher name it identically, even if it has a different index.
+        // This allows them to be deduplicated by name when merging modules.
+        let first = construct_code_module(
+            "int f(int x) { return x + 1; }
+            public static int call(int x) { return new Test().f(x); }",
+        )?;
+        let second = construct_code_module(
+            "long l(long x) { return x; }
+            int g(int x) { return x + 2; }
+            public static int call(int x) { Test t = new Test(); return t.g((int) t.l(x)); }",
+        )?;
+        let first_wasm = first.finish();
+        let second_wasm = second.finish();
+        let first_module = ParsedModule::parse(&first_wasm)?;
+        let second_module = ParsedModule::parse(&second_wasm)?;
+        let dispatcher_index = |module: &ParsedModule<'_>| {
+            (module.names.iter())
+                .find(|(_, &name)| name == "!Dispatcher_i32_i32")
+                .map(|(&index, _)| index)
+        };
+        let first_index = dispatcher_index(&first_module).expect("Dispatcher not found");
+        let second_index = dispatcher_index(&second_module).expect("Dispatcher not found");
+        assert_ne!(first_index, second_index);
+
+        // Check compiling the same program again names all functions identically
+        let again = construct_code_module(
+            "int f(int x) { return x + 1; }
+            public static int call(int x) { return new Test().f(x); }",
+        )?;
+        let again_wasm = again.finish();
+        let again_module = ParsedModule::parse(&again_wasm)?;
+        assert_eq!(again_module.names, first_module.names);
+        Ok(())
+    }
+}
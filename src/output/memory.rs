@@ -0,0 +1,278 @@
+use crate::class::FieldDescriptor;
+use std::iter;
+use wasm_encoder::{encoders, Function, Instruction as WASMInstruction, MemArg, ValType};
+
+/// Number of bits a value occupies in memory. This may be narrower than the value's WebAssembly
+/// type, e.g. Java `byte`s, `short`s and `char`s are operated on as `i32`s, but only need 8 or 16
+/// bits of storage. Narrow values are extended when loaded, and wrapped when stored.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Width {
+    W8,
+    W16,
+    W32,
+    W64,
+}
+
+impl Width {
+    /// Returns the width of a full value of WebAssembly type `t`.
+    pub fn of(t: ValType) -> Self {
+        match t {
+            ValType::I32 | ValType::F32 => Width::W32,
+            ValType::I64 | ValType::F64 => Width::W64,
+            _ => unimplemented!("{:?}", t),
+        }
+    }
+
+    /// Returns the width a Java field of type `descriptor` is stored with, and whether it should be
+    /// sign extended when loaded. `boolean`s and `byte`s are stored in 8 bits, `char`s and `short`s
+    /// in 16 bits, and all other types at their WebAssembly type's full width (see
+    /// [`FieldDescriptor::size`]). Only `byte`s and `short`s are signed.
+    pub fn of_field(descriptor: &FieldDescriptor) -> (Self, bool) {
+        match descriptor {
+            FieldDescriptor::Boolean => (Width::W8, false),
+            FieldDescriptor::Byte => (Width::W8, true),
+            FieldDescriptor::Char => (Width::W16, false),
+            FieldDescriptor::Short => (Width::W16, true),
+            _ => (Width::of(descriptor.as_type()), false),
+        }
+    }
+
+    /// Returns the natural alignment of values of this width, as the base-2 logarithm of the
+    /// number of bytes expected by [`MemArg`] immediates.
+    pub fn align(self) -> u32 {
+        // https://webassembly.github.io/spec/core/text/instructions.html#memory-instructions
+        match self {
+            Width::W8 => 0,  // log2(1) = 0
+            Width::W16 => 1, // log2(2) = 1
+            Width::W32 => 2, // log2(4) = 2
+            Width::W64 => 3, // log2(8) = 3
+        }
+    }
+}
+
+/// Rounds `value` up to the next multiple of `align`, e.g. to find the next address a value can be
+/// naturally aligned at.
+pub const fn align_to(value: u32, align: u32) -> u32 {
+    (value + align - 1) / align * align
+}
+
+/// Returns the instruction pushing the zero value of WebAssembly type `t`, i.e. the default value
+/// of a Java field or array element of that type.
+pub fn zero_const(t: ValType) -> WASMInstruction<'static> {
+    match t {
+        ValType::I32 => WASMInstruction::I32Const(0),
+        ValType::I64 => WASMInstruction::I64Const(0),
+        ValType::F32 => WASMInstruction::F32Const(0.0),
+        ValType::F64 => WASMInstruction::F64Const(0.0),
+        _ => unimplemented!("{:?}", t),
+    }
+}
+
+/// Returns the memory argument immediate for a naturally aligned access of a `width` value at
+/// `offset` bytes from an address.
+pub fn mem_arg(offset: u32, width: Width) -> MemArg {
+    MemArg {
+        offset: offset as u64,
+        align: width.align(),
+        memory_index: 0,
+    }
+}
+
+/// Returns the instruction loading a `width` value from memory as WebAssembly type `t`, sign
+/// extending it if `signed` is set and `width` is narrower than `t`, and zero extending it
+/// otherwise. Panics if floating point values are narrower than their type, or `width` is wider
+/// than `t`.
+pub fn load_instr(t: ValType, width: Width, signed: bool, arg: MemArg) -> WASMInstruction<'static> {
+    match (t, width, signed) {
+        (ValType::I32, Width::W8, true) => WASMInstruction::I32Load8_S(arg),
+        (ValType::I32, Width::W8, false) => WASMInstruction::I32Load8_U(arg),
+        (ValType::I32, Width::W16, true) => WASMInstruction::I32Load16_S(arg),
+        (ValType::I32, Width::W16, false) => WASMInstruction::I32Load16_U(arg),
+        (ValType::I32, Width::W32, _) => WASMInstruction::I32Load(arg),
+        (ValType::I64, Width::W8, true) => WASMInstruction::I64Load8_S(arg),
+        (ValType::I64, Width::W8, false) => WASMInstruction::I64Load8_U(arg),
+        (ValType::I64, Width::W16, true) => WASMInstruction::I64Load16_S(arg),
+        (ValType::I64, Width::W16, false) => WASMInstruction::I64Load16_U(arg),
+        (ValType::I64, Width::W32, true) => WASMInstruction::I64Load32_S(arg),
+        (ValType::I64, Width::W32, false) => WASMInstruction::I64Load32_U(arg),
+        (ValType::I64, Width::W64, _) => WASMInstruction::I64Load(arg),
+        (ValType::F32, Width::W32, _) => WASMInstruction::F32Load(arg),
+        (ValType::F64, Width::W64, _) => WASMInstruction::F64Load(arg),
+        _ => unimplemented!("{:?} load of {:?}", width, t),
+    }
+}
+
+/// Returns the instruction storing a value of WebAssembly type `t` to memory as a `width` value,
+/// wrapping it if `width` is narrower than `t`. Panics in the same cases as [`load_instr`].
+pub fn store_instr(t: ValType, width: Width, arg: MemArg) -> WASMInstruction<'static> {
+    match (t, width) {
+        (ValType::I32, Width::W8) => WASMInstruction::I32Store8(arg),
+        (ValType::I32, Width::W16) => WASMInstruction::I32Store16(arg),
+        (ValType::I32, Width::W32) => WASMInstruction::I32Store(arg),
+        (ValType::I64, Width::W8) => WASMInstruction::I64Store8(arg),
+        (ValType::I64, Width::W16) => WASMInstruction::I64Store16(arg),
+        (ValType::I64, Width::W32) => WASMInstruction::I64Store32(arg),
+        (ValType::I64, Width::W64) => WASMInstruction::I64Store(arg),
+        (ValType::F32, Width::W32) => WASMInstruction::F32Store(arg),
+        (ValType::F64, Width::W64) => WASMInstruction::F64Store(arg),
+        _ => unimplemented!("{:?} store of {:?}", width, t),
+    }
+}
+
+/// Writes a sequentially consistent atomic load of a `width` value of WebAssembly type `t` from the
+/// [threads proposal] to `f`. Narrow atomic loads always zero extend, so `i32`s are sign extended
+/// afterwards if `signed` is set. There are no atomic floating point loads, so these are loaded as
+/// integers and reinterpreted. `wasm_encoder` doesn't support this proposal yet, so the instruction
+/// is encoded manually. Panics in the same cases as [`load_instr`], or for narrow `i64` loads.
+///
+/// [threads proposal]: https://github.com/WebAssembly/threads/blob/main/proposals/threads/Overview.md
+pub fn atomic_load(
+    f: &mut Function,
+    t: ValType,
+    width: Width,
+    signed: bool,
+    arg: MemArg,
+) -> &mut Function {
+    match (t, width, signed) {
+        (ValType::I32, Width::W8, false) => atomic_mem_instr(f, 0x12, arg),
+        (ValType::I32, Width::W8, true) => {
+            atomic_mem_instr(f, 0x12, arg).instruction(&WASMInstruction::I32Extend8S)
+        }
+        (ValType::I32, Width::W16, false) => atomic_mem_instr(f, 0x13, arg),
+        (ValType::I32, Width::W16, true) => {
+            atomic_mem_instr(f, 0x13, arg).instruction(&WASMInstruction::I32Extend16S)
+        }
+        (ValType::I32, Width::W32, _) => atomic_mem_instr(f, 0x10, arg),
+        (ValType::I64, Width::W64, _) => atomic_mem_instr(f, 0x11, arg),
+        (ValType::F32, Width::W32, _) => {
+            atomic_mem_instr(f, 0x10, arg).instruction(&WASMInstruction::F32ReinterpretI32)
+        }
+        (ValType::F64, Width::W64, _) => {
+            atomic_mem_instr(f, 0x11, arg).instruction(&WASMInstruction::F64ReinterpretI64)
+        }
+        _ => unimplemented!("atomic {:?} load of {:?}", width, t),
+    }
+}
+
+/// Writes a sequentially consistent atomic store of a value of WebAssembly type `t` to `f` as a
+/// `width` value, wrapping it if `width` is narrower than `t`, and reinterpreting floating point
+/// values as integers first. See [`atomic_load`].
+pub fn atomic_store(f: &mut Function, t: ValType, width: Width, arg: MemArg) -> &mut Function {
+    match (t, width) {
+        (ValType::I32, Width::W8) => atomic_mem_instr(f, 0x19, arg),
+        (ValType::I32, Width::W16) => atomic_mem_instr(f, 0x1A, arg),
+        (ValType::I32, Width::W32) => atomic_mem_instr(f, 0x17, arg),
+        (ValType::I64, Width::W64) => atomic_mem_instr(f, 0x18, arg),
+        (ValType::F32, Width::W32) => {
+            f.instruction(&WASMInstruction::I32ReinterpretF32);
+            atomic_mem_instr(f, 0x17, arg)
+        }
+        (ValType::F64, Width::W64) => {
+            f.instruction(&WASMInstruction::I64ReinterpretF64);
+            atomic_mem_instr(f, 0x18, arg)
+        }
+        _ => unimplemented!("atomic {:?} store of {:?}", width, t),
+    }
+}
+
+/// Writes the atomic memory instruction with the `0xFE` prefixed `opcode` and memory argument
+/// immediate `arg` to `f`. Atomic accesses must be naturally aligned, so `arg` must be too.
+fn atomic_mem_instr(f: &mut Function, opcode: u32, arg: MemArg) -> &mut Function {
+    debug_assert_eq!(arg.memory_index, 0);
+    let immediates = encoders::u32(arg.align).chain(encoders::u64(arg.offset));
+    f.raw(
+        iter::once(0xFE)
+            .chain(encoders::u32(opcode))
+            .chain(immediates),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::class::FieldDescriptor;
+    use crate::output::memory::{load_instr, mem_arg, store_instr, Width};
+    use wasm_encoder::{Instruction as WASMInstruction, MemArg, ValType};
+
+    #[test]
+    fn width_of_type() {
+        assert_eq!(Width::of(ValType::I32), Width::W32);
+        assert_eq!(Width::of(ValType::F32), Width::W32);
+        assert_eq!(Width::of(ValType::I64), Width::W64);
+        assert_eq!(Width::of(ValType::F64), Width::W64);
+    }
+
+    #[test]
+    fn width_of_field() {
+        assert_eq!(
+            Width::of_field(&FieldDescriptor::Boolean),
+            (Width::W8, false)
+        );
+        assert_eq!(Width::of_field(&FieldDescriptor::Byte), (Width::W8, true));
+        assert_eq!(Width::of_field(&FieldDescriptor::Char), (Width::W16, false));
+        assert_eq!(Width::of_field(&FieldDescriptor::Short), (Width::W16, true));
+        assert_eq!(Width::of_field(&FieldDescriptor::Int), (Width::W32, false));
+        assert_eq!(
+            Width::of_field(&FieldDescriptor::Float),
+            (Width::W32, false)
+        );
+        assert_eq!(Width::of_field(&FieldDescriptor::Long), (Width::W64, false));
+        assert_eq!(
+            Width::of_field(&FieldDescriptor::Double),
+            (Width::W64, false)
+        );
+        let object = FieldDescriptor::Object(String::from("Test"));
+        assert_eq!(Width::of_field(&object), (Width::W32, false));
+    }
+
+    #[test]
+    fn load_all_widths() {
+        use WASMInstruction::*;
+        // Check alignment is natural for the width, not the type
+        let load = |t, width, signed| load_instr(t, width, signed, mem_arg(4, width));
+        let arg = |align| move |arg: MemArg| arg.offset == 4 && arg.align == align;
+
+        assert!(matches!(load(ValType::I32, Width::W8, true), I32Load8_S(a) if arg(0)(a)));
+        assert!(matches!(load(ValType::I32, Width::W8, false), I32Load8_U(a) if arg(0)(a)));
+        assert!(matches!(load(ValType::I32, Width::W16, true), I32Load16_S(a) if arg(1)(a)));
+        assert!(matches!(load(ValType::I32, Width::W16, false), I32Load16_U(a) if arg(1)(a)));
+        assert!(matches!(load(ValType::I32, Width::W32, true), I32Load(a) if arg(2)(a)));
+        assert!(matches!(load(ValType::I64, Width::W8, true), I64Load8_S(a) if arg(0)(a)));
+        assert!(matches!(load(ValType::I64, Width::W8, false), I64Load8_U(a) if arg(0)(a)));
+        assert!(matches!(load(ValType::I64, Width::W16, true), I64Load16_S(a) if arg(1)(a)));
+        assert!(matches!(load(ValType::I64, Width::W16, false), I64Load16_U(a) if arg(1)(a)));
+        assert!(matches!(load(ValType::I64, Width::W32, true), I64Load32_S(a) if arg(2)(a)));
+        assert!(matches!(load(ValType::I64, Width::W32, false), I64Load32_U(a) if arg(2)(a)));
+        assert!(matches!(load(ValType::I64, Width::W64, false), I64Load(a) if arg(3)(a)));
+        assert!(matches!(load(ValType::F32, Width::W32, true), F32Load(a) if arg(2)(a)));
+        assert!(matches!(load(ValType::F64, Width::W64, true), F64Load(a) if arg(3)(a)));
+    }
+
+    #[test]
+    fn store_all_widths() {
+        use WASMInstruction::*;
+        let store = |t, width| store_instr(t, width, mem_arg(8, width));
+        let arg = |align| move |arg: MemArg| arg.offset == 8 && arg.align == align;
+
+        assert!(matches!(store(ValType::I32, Width::W8), I32Store8(a) if arg(0)(a)));
+        assert!(matches!(store(ValType::I32, Width::W16), I32Store16(a) if arg(1)(a)));
+        assert!(matches!(store(ValType::I32, Width::W32), I32Store(a) if arg(2)(a)));
+        assert!(matches!(store(ValType::I64, Width::W8), I64Store8(a) if arg(0)(a)));
+        assert!(matches!(store(ValType::I64, Width::W16), I64Store16(a) if arg(1)(a)));
+        assert!(matches!(store(ValType::I64, Width::W32), I64Store32(a) if arg(2)(a)));
+        assert!(matches!(store(ValType::I64, Width::W64), I64Store(a) if arg(3)(a)));
+        assert!(matches!(store(ValType::F32, Width::W32), F32Store(a) if arg(2)(a)));
+        assert!(matches!(store(ValType::F64, Width::W64), F64Store(a) if arg(3)(a)));
+    }
+
+    #[test]
+    #[should_panic = "W16 load of F32"]
+    fn load_narrow_float() {
+        load_instr(ValType::F32, Width::W16, false, mem_arg(0, Width::W16));
+    }
+
+    #[test]
+    #[should_panic = "W64 store of I32"]
+    fn store_wide_int() {
+        store_instr(ValType::I32, Width::W64, mem_arg(0, Width::W64));
+    }
+}
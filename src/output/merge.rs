@@ -0,0 +1,496 @@
+use crate::class::FunctionType;
+use crate::output::types::{return_call, return_call_indirect};
+use crate::output::{Module, EXTERNAL_MODULE};
+use std::collections::{BTreeMap, HashMap};
+use std::mem::take;
+use std::sync::Arc;
+use wasm_encoder::{
+    Elements, EntityType, Export, Function as WASMFunction, GlobalType,
+    Instruction as WASMInstruction, TableType, ValType,
+};
+use wasmparser::{
+    ElementItem, ElementKind, ExternalKind, FunctionBody, ImportSectionEntryType, InitExpr, Name,
+    NameSectionReader, Operator, Parser, Payload, Type, TypeDef, TypeOrFuncType,
+};
+
+/// Converts a parsed WebAssembly value type to its encoder equivalent.
+fn val_type(t: Type) -> anyhow::Result<ValType> {
+    Ok(match t {
+        Type::I32 => ValType::I32,
+        Type::I64 => ValType::I64,
+        Type::F32 => ValType::F32,
+        Type::F64 => ValType::F64,
+        Type::FuncRef => ValType::FuncRef,
+        _ => bail!("Unable to merge modules using {:?} values", t),
+    })
+}
+
+/// Returns the value of a constant expression consisting of a single `i32.const`. This is the only
+/// kind of constant expression used for global initialisers and element offsets.
+fn const_i32(init_expr: &InitExpr<'_>) -> anyhow::Result<i32> {
+    match init_expr.get_operators_reader().read()? {
+        Operator::I32Const { value } => Ok(value),
+        op => bail!("Unable to merge modules with {:?} constant expressions", op),
+    }
+}
+
+/// Contents of a finished WebAssembly module, parsed for merging or summarising. Only the subset of
+/// WebAssembly produced by this compiler is supported. All indices are relative to this module.
+#[derive(Default)]
+pub(super) struct ParsedModule<'a> {
+    pub(super) types: Vec<Arc<FunctionType>>,
+    /// Module name, field name and type index of each imported function.
+    pub(super) imports: Vec<(&'a str, &'a str, u32)>,
+    /// Type index of each defined function, after all imported functions.
+    pub(super) functions: Vec<u32>,
+    tables: Vec<TableType>,
+    /// Type and `i32.const` initial value of each global.
+    globals: Vec<(GlobalType, i32)>,
+    /// Name and function index of each exported function.
+    pub(super) exports: Vec<(&'a str, u32)>,
+    /// Table index, offset and function indices of each active element segment.
+    pub(super) elements: Vec<(u32, i32, Vec<u32>)>,
+    /// Body of each defined function.
+    pub(super) bodies: Vec<FunctionBody<'a>>,
+    /// Size in bytes of the code section's contents, or `0` if there isn't one.
+    pub(super) code_size: usize,
+    /// Debug names of functions from the name section.
+    pub(super) names: HashMap<u32, &'a str>,
+}
+
+impl<'a> ParsedModule<'a> {
+    /// Parses a finished module's bytes (see [`Module::finish`]).
+    pub(super) fn parse(bytes: &'a [u8]) -> anyhow::Result<Self> {
+        let mut module = ParsedModule::default();
+        for payload in Parser::new(0).parse_all(bytes) {
+            match payload? {
+                // Merged modules get their own heap memory
+                Payload::Version { .. } | Payload::MemorySection(_) | Payload::End => {}
+                Payload::TypeSection(reader) => {
+                    for ty in reader {
+                        let ty = match ty? {
+                            TypeDef::Func(ty) => ty,
+                            _ => bail!("Unable to merge modules with non-function types"),
+                        };
+                        let params = ty.params.iter().map(|&t| val_type(t));
+                        let results = ty.returns.iter().map(|&t| val_type(t));
+                        module.types.push(Arc::new(FunctionType {
+                            params: params.collect::<anyhow::Result<_>>()?,
+                            results: results.collect::<anyhow::Result<_>>()?,
+                        }));
+                    }
+                }
+                Payload::ImportSection(reader) => {
+                    for import in reader {
+                        let import = import?;
+                        match (import.field, import.ty) {
+                            (Some(field), ImportSectionEntryType::Function(type_index)) => {
+                                module.imports.push((import.module, field, type_index))
+                            }
+                            _ => bail!("Unable to merge modules with non-function imports"),
+                        }
+                    }
+                }
+                Payload::FunctionSection(reader) => {
+                    for type_index in reader {
+                        module.functions.push(type_index?);
+                    }
+                }
+                Payload::TableSection(reader) => {
+                    for table in reader {
+                        let table = table?;
+                        module.tables.push(TableType {
+                            element_type: val_type(table.element_type)?,
+                            minimum: table.initial,
+                            maximum: table.maximum,
+                        });
+                    }
+                }
+                Payload::GlobalSection(reader) => {
+                    for global in reader {
+                        let global = global?;
+                        let global_type = GlobalType {
+                            val_type: val_type(global.ty.content_type)?,
+                            mutable: global.ty.mutable,
+                        };
+                        module
+                            .globals
+                            .push((global_type, const_i32(&global.init_expr)?));
+                    }
+                }
+                Payload::ExportSection(reader) => {
+                    for export in reader {
+                        let export = export?;
+                        match export.kind {
+                            ExternalKind::Function => {
+                                module.exports.push((export.field, export.index))
+                            }
+                            // Merged modules export their own heap memory and debug globals
+                            ExternalKind::Memory | ExternalKind::Global => {}
+                            _ => bail!("Unable to merge modules with non-function exports"),
+                        }
+                    }
+                }
+                Payload::ElementSection(reader) => {
+                    for element in reader {
+                        let element = element?;
+                        let (table_index, offset) = match element.kind {
+                            ElementKind::Active {
+                                table_index,
+                                init_expr,
+                            } => (table_index, const_i32(&init_expr)?),
+                            _ => bail!("Unable to merge modules with non-active elements"),
+                        };
+                        let mut function_indices = vec![];
+                        let mut items = element.items.get_items_reader()?;
+                        for _ in 0..items.get_count() {
+                            match items.read()? {
+                                ElementItem::Func(function_index) => {
+                                    function_indices.push(function_index)
+                                }
+                                ElementItem::Expr(_) => {
+                                    bail!("Unable to merge modules with expression elements")
+                                }
+                            }
+                        }
+                        module
+                            .elements
+                            .push((table_index, offset, function_indices));
+                    }
+                }
+                Payload::CodeSectionStart { range, .. } => {
+                    module.code_size = range.end - range.start
+                }
+                Payload::CodeSectionEntry(body) => module.bodies.push(body),
+                Payload::CustomSection {
+                    name: "name",
+                    data,
+                    data_offset,
+                    ..
+                } => {
+                    let mut reader = NameSectionReader::new(data, data_offset)?;
+                    while !reader.eof() {
+                        if let Name::Function(names) = reader.read()? {
+                            let mut names = names.get_map()?;
+                            for _ in 0..names.get_count() {
+                                let naming = names.read()?;
+                                module.names.insert(naming.index, naming.name);
+                            }
+                        }
+                    }
+                }
+                // Merged modules get their own producers section if enabled
+                Payload::CustomSection { .. } => {}
+                _ => bail!("Unable to merge modules with unsupported sections"),
+            }
+        }
+        Ok(module)
+    }
+
+    /// Returns the total number of functions (imported and defined) in this module.
+    pub(super) fn function_count(&self) -> usize {
+        self.imports.len() + self.functions.len()
+    }
+
+    /// Returns the index of this module's type for the function at `function_index`.
+    pub(super) fn function_type_index(&self, function_index: u32) -> u32 {
+        let function_index = function_index as usize;
+        match function_index.checked_sub(self.imports.len()) {
+            Some(defined_index) => self.functions[defined_index],
+            None => self.imports[function_index].2,
+        }
+    }
+}
+
+/// Mappings from a parsed module's indices to indices in the merged module.
+struct Remap {
+    types: Vec<u32>,
+    functions: Vec<u32>,
+    globals: Vec<u32>,
+    tables: Vec<u32>,
+}
+
+impl Remap {
+    /// Re-encodes a function body from `bytes`, updating all function, type, global and table
+    /// indices. All other instructions are copied verbatim.
+    fn function(&self, bytes: &[u8], body: &FunctionBody<'_>) -> anyhow::Result<WASMFunction> {
+        let mut locals_reader = body.get_locals_reader()?;
+        let mut locals = vec![];
+        for _ in 0..locals_reader.get_count() {
+            let (count, t) = locals_reader.read()?;
+            locals.push((count, val_type(t)?));
+        }
+        let mut f = WASMFunction::new(locals);
+
+        // Find the byte range of each instruction, so unchanged instructions can just be copied
+        let mut reader = body.get_operators_reader()?;
+        let mut operators = vec![];
+        while !reader.eof() {
+            operators.push(reader.read_with_offset()?);
+        }
+        let end = body.range().end;
+        for (i, (operator, start)) in operators.iter().enumerate() {
+            match operator {
+                Operator::Call { function_index } => {
+                    let function_index = self.functions[*function_index as usize];
+                    f.instruction(&WASMInstruction::Call(function_index))
+                }
+                Operator::CallIndirect { index, table_index } => {
+                    f.instruction(&WASMInstruction::CallIndirect {
+                        ty: self.types[*index as usize],
+                        table: self.tables[*table_index as usize],
+                    })
+                }
+                Operator::ReturnCall { function_index } => {
+                    return_call(&mut f, self.functions[*function_index as usize])
+                }
+                Operator::ReturnCallIndirect { index, table_index } => return_call_indirect(
+                    &mut f,
+                    self.types[*index as usize],
+                    self.tables[*table_index as usize],
+                ),
+                Operator::GlobalGet { global_index } => {
+                    let global_index = self.globals[*global_index as usize];
+                    f.instruction(&WASMInstruction::GlobalGet(global_index))
+                }
+                Operator::GlobalSet { global_index } => {
+                    let global_index = self.globals[*global_index as usize];
+                    f.instruction(&WASMInstruction::GlobalSet(global_index))
+                }
+                Operator::Block {
+                    ty: TypeOrFuncType::FuncType(_),
+                }
+                | Operator::Loop {
+                    ty: TypeOrFuncType::FuncType(_),
+                }
+                | Operator::If {
+                    ty: TypeOrFuncType::FuncType(_),
+                }
+                | Operator::RefFunc { .. } => {
+                    bail!("Unable to merge modules with {:?} instructions", operator)
+                }
+                _ => {
+                    let end = operators.get(i + 1).map_or(end, |(_, next)| *next);
+                    f.raw(bytes[*start..end].iter().copied())
+                }
+            };
+        }
+        Ok(f)
+    }
+}
+
+impl Module {
+    /// Merges two independently rendered modules into a single module, re-indexing types,
+    /// functions, globals and tables, and updating all references to them.
+    ///
+    /// Methods imported from the [`EXTERNAL_MODULE`] (see
+    /// [`Renderer::import_missing`](crate::output::Renderer::import_missing)) are resolved to
+    /// functions in the other module with the same identifier. Unresolved imports are kept, so they
+    /// can be resolved by later merges. Built-in, dispatcher and super ID functions are
+    /// deduplicated by name, so both modules share a heap allocator.
+    ///
+    /// Virtual class IDs are program-wide, so both modules must have been rendered with the same
+    /// [`VirtualTable`](crate::VirtualTable). Their tables will then be identical, and only one is
+    /// kept. The merged module shouldn't be rendered to any further, other than by merging.
+    pub fn merge(mut self, mut other: Module) -> anyhow::Result<Module> {
+        let symbols = [take(&mut self.symbols), take(&mut other.symbols)];
+        let mut out = Module::new();
+        out.null_checks = self.null_checks;
+        out.tail_calls = self.tail_calls || other.tail_calls;
+        out.emit_producers = self.emit_producers;
+        // Only export debug globals from the merged module, using the shared heap allocator's
+        out.emit_debug_globals = self.emit_debug_globals;
+        self.emit_debug_globals = false;
+        other.emit_debug_globals = false;
+        let heap_next_global_indices = [self.heap_next_global_index, other.heap_next_global_index];
+        let bytes = [self.finish(), other.finish()];
+        let modules = [
+            ParsedModule::parse(&bytes[0])?,
+            ParsedModule::parse(&bytes[1])?,
+        ];
+
+        // Add all types, deduplicating those used by both modules
+        let types = modules
+            .iter()
+            .map(|module| module.types.iter().map(|t| out.ensure_type(t)).collect())
+            .collect::<Vec<Vec<_>>>();
+
+        // Keep imports that can't be resolved to functions in the other module, these must come
+        // before all other functions
+        let mut functions = [
+            vec![None; modules[0].function_count()],
+            vec![None; modules[1].function_count()],
+        ];
+        let mut names = BTreeMap::new();
+        let mut imports = HashMap::new();
+        for (i, module) in modules.iter().enumerate() {
+            for (j, &(import_module, field, type_index)) in module.imports.iter().enumerate() {
+                if import_module == EXTERNAL_MODULE && symbols[1 - i].contains_key(field) {
+                    continue;
+                }
+                functions[i][j] =
+                    Some(*imports.entry((import_module, field)).or_insert_with(|| {
+                        let import_type = EntityType::Function(types[i][type_index as usize]);
+                        out.imports.import(import_module, Some(field), import_type);
+                        let index = out.next_function_index;
+                        out.next_function_index += 1;
+                        if let Some(&name) = module.names.get(&(j as u32)) {
+                            names.insert(index, name);
+                        }
+                        index
+                    }));
+            }
+        }
+
+        // Add defined functions, sharing system-defined functions (those named starting with `!`)
+        let mut system_functions = HashMap::new();
+        let mut bodies = vec![];
+        for (i, module) in modules.iter().enumerate() {
+            for (k, &type_index) in module.functions.iter().enumerate() {
+                let j = module.imports.len() + k;
+                let name = module.names.get(&(j as u32)).copied();
+                let type_index = types[i][type_index as usize];
+                if let Some(name) = name.filter(|name| name.starts_with('!')) {
+                    if let Some(&(index, other_type_index)) = system_functions.get(name) {
+                        ensure!(
+                            type_index == other_type_index,
+                            "Unable to merge {} functions with different types",
+                            name
+                        );
+                        functions[i][j] = Some(index);
+                        continue;
+                    }
+                    system_functions.insert(name, (out.next_function_index, type_index));
+                }
+                functions[i][j] = Some(out.next_function_index);
+                if let Some(name) = name {
+                    names.insert(out.next_function_index, name);
+                }
+                out.next_function_index += 1;
+                out.functions.function(type_index);
+                bodies.push((i, k));
+            }
+        }
+
+        // Resolve imports of methods defined in the other module
+        for (i, module) in modules.iter().enumerate() {
+            for (j, &(_, field, type_index)) in module.imports.iter().enumerate() {
+                if functions[i][j].is_some() {
+                    continue;
+                }
+                let other_index = symbols[1 - i][field];
+                let other_type_index = modules[1 - i].function_type_index(other_index);
+                ensure!(
+                    types[i][type_index as usize] == types[1 - i][other_type_index as usize],
+                    "Unable to resolve import of {} with a different type",
+                    field
+                );
+                functions[i][j] = functions[1 - i][other_index as usize];
+            }
+        }
+        let functions = functions
+            .iter()
+            .map(|functions| functions.iter().map(|index| index.unwrap()).collect())
+            .collect::<Vec<Vec<_>>>();
+
+        // Add all globals, those of deduplicated functions will be unused
+        let mut globals = [vec![], vec![]];
+        for (i, module) in modules.iter().enumerate() {
+            for &(global_type, value) in &module.globals {
+                globals[i].push(out.next_global_index);
+                out.next_global_index += 1;
+                out.globals
+                    .global(global_type, &WASMInstruction::I32Const(value));
+            }
+        }
+
+        // System-defined functions are shared with the first module defining them, so use its heap
+        // allocator's next pointer
+        out.heap_next_global_index = (0..2)
+            .find_map(|i| heap_next_global_indices[i].map(|index| globals[i][index as usize]));
+
+        // Keep a single virtual table, checking the other is identical if present
+        let remap_elements = |i: usize| -> Vec<_> {
+            let elements = modules[i].elements.iter();
+            let functions = &functions[i];
+            elements
+                .map(|(table_index, offset, function_indices)| {
+                    let function_indices = function_indices
+                        .iter()
+                        .map(|&function_index| functions[function_index as usize])
+                        .collect::<Vec<_>>();
+                    (*table_index, *offset, function_indices)
+                })
+                .collect()
+        };
+        let elements = remap_elements(0);
+        let table_module = if modules[0].tables.is_empty() { 1 } else { 0 };
+        if table_module == 0 && !modules[1].tables.is_empty() {
+            ensure!(
+                modules[0].tables.len() == modules[1].tables.len() && elements == remap_elements(1),
+                "Unable to merge modules rendered with different virtual tables"
+            );
+        }
+        for &table in &modules[table_module].tables {
+            out.tables.table(table);
+        }
+        let elements = if table_module == 0 {
+            elements
+        } else {
+            remap_elements(1)
+        };
+        for (table_index, offset, function_indices) in elements {
+            out.elements.active(
+                Some(table_index),
+                &WASMInstruction::I32Const(offset),
+                ValType::FuncRef,
+                Elements::Functions(&function_indices),
+            );
+        }
+        let tables = (0..modules[table_module].tables.len() as u32).collect::<Vec<_>>();
+
+        // Re-encode function bodies with updated indices
+        let remaps = (0..2)
+            .map(|i| Remap {
+                types: types[i].clone(),
+                functions: functions[i].clone(),
+                globals: globals[i].clone(),
+                tables: tables.clone(),
+            })
+            .collect::<Vec<_>>();
+        for (i, k) in bodies {
+            let f = remaps[i].function(&bytes[i], &modules[i].bodies[k])?;
+            out.codes.function(&f);
+        }
+
+        // Export functions from both modules
+        let mut exports = HashMap::new();
+        for (i, module) in modules.iter().enumerate() {
+            for &(name, function_index) in &module.exports {
+                let function_index = functions[i][function_index as usize];
+                if let Some(existing) = exports.insert(name, function_index) {
+                    ensure!(
+                        existing == function_index,
+                        "Unable to merge modules both exporting {}",
+                        name
+                    );
+                    continue;
+                }
+                out.exports.export(name, Export::Function(function_index));
+            }
+        }
+
+        // Record debug names and symbols for future merges
+        for (index, name) in names {
+            out.function_names.append(index, name);
+        }
+        for (i, symbols) in symbols.iter().enumerate() {
+            for (name, &function_index) in symbols {
+                let function_index = functions[i][function_index as usize];
+                out.symbols.insert(name.clone(), function_index);
+            }
+        }
+
+        Ok(out)
+    }
+}
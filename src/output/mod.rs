@@ -1,8 +1,23 @@
 mod builtin;
+mod demo;
+mod diff;
 mod ensure;
+mod memory;
+mod merge;
 mod render;
+mod report;
+mod size;
+mod summary;
+mod trap;
 mod types;
 
 pub use builtin::BuiltinFunction;
+pub use demo::DemoMain;
+pub use diff::{diff_modules, ModuleDiff};
+pub use memory::zero_const;
 pub use render::*;
+pub use report::report_unsupported;
+pub use size::{size_report, SizeReport};
+pub use summary::summarise_module;
+pub use trap::{TrapKind, TRAP_IMPORT};
 pub use types::*;
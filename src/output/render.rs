@@ -1,18 +1,229 @@
-use crate::class::{FieldId, MethodId, JAVA_LANG_OBJECT};
+use crate::class::{FieldDescriptor, FieldId, FunctionType, MethodId, JAVA_LANG_OBJECT};
 use crate::function::{CompiledFunction, Instruction};
-use crate::output::builtin::BuiltinFunction;
-use crate::virtuals::VIRTUAL_CLASS_ID_SIZE;
+use crate::output::builtin::{BuiltinFunction, HEAP_ALIGN};
+use crate::output::ensure::{Dependencies, RecordedBody, RecordedDependencies};
+use crate::output::memory::{
+    align_to, atomic_load, atomic_store, load_instr, mem_arg, store_instr, zero_const, Width,
+};
+use crate::output::trap::trap;
+use crate::output::types::return_call;
+use crate::output::{DemoMain, TrapKind, TRAP_IMPORT};
+use crate::virtuals::{UNIFORM_HEADER_SIZE, VIRTUAL_CLASS_ID_MEM_ARG, VIRTUAL_CLASS_ID_SIZE};
 use crate::{Class, Module, VirtualTable};
+use anyhow::Context;
+use classfile_parser::method_info::MethodAccessFlags;
 use std::cmp::Ordering;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::convert::TryFrom;
 use std::mem::take;
-use std::rc::Rc;
 use std::sync::Arc;
+use std::thread;
 use wasm_encoder::{
     EntityType, Export, Function as WASMFunction, Instruction as WASMInstruction, MemArg, ValType,
 };
 
+/// Name of the function imported from the `imports` module to report failed assertions, if
+/// [`Renderer::assert_mode`] is [`AssertMode::Host`].
+pub const ASSERTION_FAILURE_IMPORT: &str = "onAssertionFailure";
+
+/// Name of the module methods not rendered in the current module are imported from, if
+/// [`Renderer::import_missing`] is set.
+pub const EXTERNAL_MODULE: &str = "external";
+
+/// Methods of `java/lang/Object` lowered directly to WebAssembly instructions when called on a class
+/// that doesn't override them. The Java standard library is not supported, so there are no
+/// functions to dispatch to for these.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum ObjectIntrinsic {
+    /// `boolean equals(Object)`, compares references for identity
+    Equals,
+    /// `int hashCode()`, returns the reference's address, which is stable as objects never move
+    HashCode,
+    /// `Class getClass()`, returns the instance's virtual class ID in place of a `Class` object
+    GetClass,
+}
+
+impl ObjectIntrinsic {
+    /// Returns the intrinsic with the same name and descriptor as `id`, ignoring its class.
+    fn from_method(id: &MethodId) -> Option<Self> {
+        match (id.name.as_str(), id.descriptor.to_string().as_str()) {
+            ("equals", "(Ljava/lang/Object;)Z") => Some(ObjectIntrinsic::Equals),
+            ("hashCode", "()I") => Some(ObjectIntrinsic::HashCode),
+            ("getClass", "()Ljava/lang/Class;") => Some(ObjectIntrinsic::GetClass),
+            _ => None,
+        }
+    }
+
+    /// Returns the type of value this intrinsic leaves on the stack in place of the call's result.
+    /// This must match the method's return type, so results ignored with `pop` are still dropped.
+    fn result_type(self) -> ValType {
+        // All return a `boolean`, `int` or reference, which are all `i32`s
+        ValType::I32
+    }
+}
+
+/// Static methods of `java/lang/Float` and `java/lang/Double` reinterpreting floating point values
+/// as integer bits and vice versa, lowered directly to WebAssembly's `reinterpret` instructions, and
+/// floating point methods of `java/lang/Math` with equivalent WebAssembly instructions.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum StaticIntrinsic {
+    /// `int Float.floatToRawIntBits(float)`
+    FloatToRawIntBits,
+    /// `int Float.floatToIntBits(float)`, like `floatToRawIntBits` but collapsing all NaNs to the
+    /// canonical NaN
+    FloatToIntBits,
+    /// `float Float.intBitsToFloat(int)`
+    IntBitsToFloat,
+    /// `long Double.doubleToRawLongBits(double)`
+    DoubleToRawLongBits,
+    /// `long Double.doubleToLongBits(double)`, like `doubleToRawLongBits` but collapsing all NaNs
+    /// to the canonical NaN
+    DoubleToLongBits,
+    /// `double Double.longBitsToDouble(long)`
+    LongBitsToDouble,
+    /// `float Math.min(float, float)`. Like `f32.min`, this returns NaN if either value is NaN,
+    /// and treats `-0.0` as less than `0.0`. Only the NaN's payload bits may differ from Java's.
+    FloatMin,
+    /// `float Math.max(float, float)`, see [`StaticIntrinsic::FloatMin`]
+    FloatMax,
+    /// `double Math.min(double, double)`, see [`StaticIntrinsic::FloatMin`]
+    DoubleMin,
+    /// `double Math.max(double, double)`, see [`StaticIntrinsic::FloatMin`]
+    DoubleMax,
+}
+
+impl StaticIntrinsic {
+    /// Returns the intrinsic with the same class, name and descriptor as `id`.
+    fn from_method(id: &MethodId) -> Option<Self> {
+        let descriptor = id.descriptor.to_string();
+        match (
+            id.class_name.as_str(),
+            id.name.as_str(),
+            descriptor.as_str(),
+        ) {
+            ("java/lang/Float", "floatToRawIntBits", "(F)I") => {
+                Some(StaticIntrinsic::FloatToRawIntBits)
+            }
+            ("java/lang/Float", "floatToIntBits", "(F)I") => Some(StaticIntrinsic::FloatToIntBits),
+            ("java/lang/Float", "intBitsToFloat", "(I)F") => Some(StaticIntrinsic::IntBitsToFloat),
+            ("java/lang/Double", "doubleToRawLongBits", "(D)J") => {
+                Some(StaticIntrinsic::DoubleToRawLongBits)
+            }
+            ("java/lang/Double", "doubleToLongBits", "(D)J") => {
+                Some(StaticIntrinsic::DoubleToLongBits)
+            }
+            ("java/lang/Double", "longBitsToDouble", "(J)D") => {
+                Some(StaticIntrinsic::LongBitsToDouble)
+            }
+            ("java/lang/Math", "min", "(FF)F") => Some(StaticIntrinsic::FloatMin),
+            ("java/lang/Math", "max", "(FF)F") => Some(StaticIntrinsic::FloatMax),
+            ("java/lang/Math", "min", "(DD)D") => Some(StaticIntrinsic::DoubleMin),
+            ("java/lang/Math", "max", "(DD)D") => Some(StaticIntrinsic::DoubleMax),
+            _ => None,
+        }
+    }
+
+    /// Returns the type of value this intrinsic leaves on the stack in place of the call's result.
+    /// See [`ObjectIntrinsic::result_type`].
+    fn result_type(self) -> ValType {
+        match self {
+            StaticIntrinsic::FloatToRawIntBits | StaticIntrinsic::FloatToIntBits => ValType::I32,
+            StaticIntrinsic::IntBitsToFloat
+            | StaticIntrinsic::FloatMin
+            | StaticIntrinsic::FloatMax => ValType::F32,
+            StaticIntrinsic::DoubleToRawLongBits | StaticIntrinsic::DoubleToLongBits => {
+                ValType::I64
+            }
+            StaticIntrinsic::LongBitsToDouble
+            | StaticIntrinsic::DoubleMin
+            | StaticIntrinsic::DoubleMax => ValType::F64,
+        }
+    }
+
+    /// Returns the type of scratch local required to lower this intrinsic, if any. NaN checks
+    /// need the argument twice, in addition to reinterpreting it.
+    fn scratch_type(self) -> Option<ValType> {
+        match self {
+            StaticIntrinsic::FloatToIntBits => Some(ValType::F32),
+            StaticIntrinsic::DoubleToLongBits => Some(ValType::F64),
+            _ => None,
+        }
+    }
+}
+
+/// Returns whether `id` is `AssertionError`'s constructor, which is only called when an assertion
+/// fails, so isn't called at all (see [`Instruction::CallStatic`]'s rendering).
+fn is_assertion_error_constructor(id: &MethodId) -> bool {
+    *id.class_name == "java/lang/AssertionError" && id.name.as_str() == "<init>"
+}
+
+/// Controls how the bodies of `abstract` methods are rendered, set with `--abstract-behavior`.
+/// These are only called if there's a bug, as dispatch always selects an implementation.
+#[derive(clap::ArgEnum, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum AbstractBehaviour {
+    /// Trap with `unreachable` if the method is called
+    Trap,
+    /// Return the zero value of the method's return type (if any), degrading gracefully
+    Default,
+}
+
+/// Controls how failed `assert` statements are reported, set with `--assert-mode`. Either way, the
+/// `AssertionError` is never constructed and the following `athrow` traps with `unreachable`.
+#[derive(clap::ArgEnum, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum AssertMode {
+    /// Trap with `unreachable` without notifying the host
+    Trap,
+    /// Call the imported [`ASSERTION_FAILURE_IMPORT`] function with a reference to the detail
+    /// message (or `0` if there isn't one, or it's a primitive) before trapping. The host may
+    /// throw its own error from this function to report the failure.
+    Host,
+}
+
+/// Options controlling how a module is rendered by [`render_module`](crate::render_module).
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RenderOptions {
+    /// Trap on field accesses and virtual method calls through `null` references.
+    /// See [`Module::null_checks`].
+    pub null_checks: bool,
+    /// Method for a WASI `_start` function to call and print the result of, if any.
+    /// See [`Renderer::demo_main`].
+    pub demo_main: Option<DemoMain>,
+    /// Start all instances with an 8 byte header. See [`Renderer::uniform_headers`].
+    pub uniform_headers: bool,
+    /// How to render the bodies of `abstract` methods.
+    pub abstract_behaviour: AbstractBehaviour,
+    /// How to report failed assertions.
+    pub assert_mode: AssertMode,
+    /// Call the imported [`TRAP_IMPORT`] function with a [`TrapKind`]'s code before trapping.
+    /// See [`Renderer::rich_traps`].
+    pub rich_traps: bool,
+    /// Number of threads to render function bodies with.
+    pub render_threads: usize,
+    /// Access `volatile` fields atomically. See [`Renderer::threads`].
+    pub threads: bool,
+    /// Include a `producers` section. See [`Module::emit_producers`].
+    pub emit_producers: bool,
+    /// Export the bump allocator's globals. See [`Module::emit_debug_globals`].
+    pub emit_debug_globals: bool,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions {
+            null_checks: false,
+            demo_main: None,
+            uniform_headers: false,
+            abstract_behaviour: AbstractBehaviour::Trap,
+            assert_mode: AssertMode::Trap,
+            rich_traps: false,
+            render_threads: 1,
+            threads: false,
+            emit_producers: true,
+            emit_debug_globals: false,
+        }
+    }
+}
+
 /// WebAssembly generation rendering phase operating on the whole program.
 /// Performed on the main thread once all functions have been compiled by
 /// [`crate::function::CompileFunctionJob`].
@@ -23,11 +234,66 @@ use wasm_encoder::{
 /// and virtual dispatcher functions will be included in the final module.
 pub struct Renderer {
     classes: Arc<HashMap<Arc<String>, Class>>,
-    virtual_table: Rc<VirtualTable>,
+    virtual_table: Arc<VirtualTable>,
     functions: Vec<CompiledFunction>,
     /// Maps user-defined methods to their function index in the final module. Populated by
     /// [`Renderer::index_functions`].
     function_indices: HashMap<MethodId, u32>,
+    /// If set, `volatile` fields will be accessed with atomic loads and stores from the threads
+    /// proposal. These are sequentially consistent, so also give Java's acquire/release semantics
+    /// without separate fences. Enabled with `--threads`.
+    pub threads: bool,
+    /// If set, a WASI `_start` function calling this method and printing its result will be
+    /// included in the module. Enabled with `--demo-main`.
+    pub demo_main: Option<DemoMain>,
+    /// If set, methods of `classes` not in `functions` will be imported from the
+    /// [`EXTERNAL_MODULE`], so they can be called and included in the virtual table. This allows
+    /// classes to be rendered separately, then combined with [`Module::merge`].
+    pub import_missing: bool,
+    /// If set, all instances will start with a [`UNIFORM_HEADER_SIZE`] byte header, instead of just
+    /// their virtual class ID, reserving space for an array length. Enabled with
+    /// `--uniform-headers`.
+    pub uniform_headers: bool,
+    /// How to render the bodies of `abstract` methods. Defaults to [`AbstractBehaviour::Trap`].
+    pub abstract_behaviour: AbstractBehaviour,
+    /// How to report failed assertions. Defaults to [`AssertMode::Trap`].
+    pub assert_mode: AssertMode,
+    /// Function index of the imported [`ASSERTION_FAILURE_IMPORT`] function, if
+    /// [`Renderer::assert_mode`] is [`AssertMode::Host`]. Populated by [`Renderer::render_all`].
+    assertion_failure_index: Option<u32>,
+    /// If set, traps will call the imported [`TRAP_IMPORT`] function with their [`TrapKind`]'s
+    /// code first, so the host can tell different kinds of error apart. Enabled with
+    /// `--rich-traps`.
+    pub rich_traps: bool,
+    /// Function index of the imported [`TRAP_IMPORT`] function, if [`Renderer::rich_traps`] is
+    /// set. Populated by [`Renderer::render_all`].
+    trap_index: Option<u32>,
+    /// Number of threads to render function bodies with. If greater than `1`, see
+    /// [`Renderer::render_functions_parallel`]. Defaults to `1`.
+    pub render_threads: usize,
+}
+
+/// Lays out the fields of `classes`, a class followed by its superclasses up to (but excluding)
+/// `java/lang/Object`, after a `header_size` byte instance header. Returns the end of the last
+/// field, calling `f` with the index in `classes` of each field's class, its name and offset.
+///
+/// Superclasses' fields come first, in declaration order. Each field is padded to its natural
+/// alignment (e.g. a `long` following an `int` at offset 4 is placed at offset 8, not 8 + 4 = 12),
+/// so as instances start [`HEAP_ALIGN`] byte aligned, all field addresses are aligned too.
+fn layout_fields(
+    header_size: u32,
+    classes: &[&Class],
+    mut f: impl FnMut(usize, &Arc<String>, u32),
+) -> u32 {
+    let mut end = header_size;
+    for (i, class) in classes.iter().enumerate().rev() {
+        for (name, size) in &class.fields {
+            let offset = align_to(end, *size);
+            f(i, name, offset);
+            end = offset + size;
+        }
+    }
+    end
 }
 
 impl Renderer {
@@ -35,7 +301,7 @@ impl Renderer {
     /// function indices in the final module.
     pub fn new(
         classes: Arc<HashMap<Arc<String>, Class>>,
-        virtual_table: Rc<VirtualTable>,
+        virtual_table: Arc<VirtualTable>,
         functions: Vec<CompiledFunction>,
     ) -> Self {
         Self {
@@ -43,11 +309,27 @@ impl Renderer {
             virtual_table,
             functions,
             function_indices: HashMap::new(),
+            threads: false,
+            demo_main: None,
+            import_missing: false,
+            uniform_headers: false,
+            abstract_behaviour: AbstractBehaviour::Trap,
+            assert_mode: AssertMode::Trap,
+            assertion_failure_index: None,
+            rich_traps: false,
+            trap_index: None,
+            render_threads: 1,
         }
     }
 
     /// Assign an index to each user-defined function, placing imports first as required by WASM.
     ///
+    /// Functions with identical bodies (see [`CompiledFunction::body`]) are deduplicated, sharing
+    /// the index of the first such function. Duplicates are removed from `self.functions`, so they
+    /// won't be rendered, but will still be exported if required. As each index has a single debug
+    /// name and source file, duplicates don't get their own [`Module::function_names`] or source
+    /// files entries, and appear as the first function in debug info.
+    ///
     /// This must be called before [`Renderer::render`], as the index of a function must be known
     /// to `call` it.
     fn index_functions(&mut self, out: &mut Module) {
@@ -63,23 +345,130 @@ impl Renderer {
                 Ordering::Greater
             }
         });
+        // Import methods rendered in other modules, these must come before all other functions
+        if self.import_missing {
+            self.index_external_functions(out);
+        }
         // Assign functions an index, this will be the order they're rendered in the module
         debug!("Function Identifiers:");
-        for (i, func) in self.functions.iter().enumerate() {
-            assert_eq!(i, out.next_function_index as usize);
-            debug!("{:>4}: {}", i, func.id);
+        let mut bodies = HashMap::new();
+        let mut keep = Vec::with_capacity(self.functions.len());
+        for func in &self.functions {
+            // If we've already seen a function with this body, reuse its index
+            let body = func.body();
+            if let Some(&index) = body.as_ref().and_then(|body| bodies.get(body)) {
+                debug!("{:>4}: {} (duplicate)", index, func.id);
+                self.function_indices.insert(func.id.clone(), index);
+                out.symbols.insert(func.id.to_string(), index);
+                if func.is_export() {
+                    let name = format!("{}", func.id);
+                    out.exports.export(&name, Export::Function(index));
+                }
+                keep.push(false);
+                continue;
+            }
+            if let Some(body) = body {
+                bodies.insert(body, out.next_function_index);
+            }
+
+            debug!("{:>4}: {}", out.next_function_index, func.id);
             // MethodId are a collection of Arc's so clone() is cheap
             self.function_indices
                 .insert(func.id.clone(), out.next_function_index);
-            // Record method name for debug info
+            // Record method name for debug info and merging
+            out.function_names
+                .append(out.next_function_index, &func.id.name());
+            if let Some(source_file) = &func.source_file {
+                (out.source_files).push((out.next_function_index, Arc::clone(source_file)));
+            }
+            out.symbols
+                .insert(func.id.to_string(), out.next_function_index);
+            out.next_function_index += 1;
+            keep.push(true);
+        }
+        // Remove duplicates so they aren't rendered
+        let mut keep = keep.into_iter();
+        self.functions.retain(|_| keep.next().unwrap());
+    }
+
+    /// Imports all methods of `classes` that aren't being rendered from the [`EXTERNAL_MODULE`],
+    /// assigning them indices. Imports are named by method identifier, matching the keys of
+    /// [`Module::merge`]'s symbols, and sorted so separately rendered modules are deterministic.
+    fn index_external_functions(&mut self, out: &mut Module) {
+        let rendered: HashSet<_> = self.functions.iter().map(|func| &func.id).collect();
+        let mut external: Vec<_> = self
+            .classes
+            .values()
+            .flat_map(|class| &class.methods)
+            .filter(|func| !rendered.contains(&func.id))
+            .collect();
+        external.sort_by(|a, b| a.id.cmp(&b.id));
+
+        for func in external {
+            // Native methods are imported without an implicit this, see `render_import`
+            let flags = MethodAccessFlags::STATIC | MethodAccessFlags::NATIVE;
+            let func_type = if func.flags.intersects(flags) {
+                Arc::clone(&func.descriptor.function_type)
+            } else {
+                Arc::new(func.descriptor.function_type.with_implicit_this())
+            };
+            let type_index = out.ensure_type(&func_type);
+            let name = format!("{}", func.id);
+            out.imports.import(
+                EXTERNAL_MODULE,
+                Some(&name),
+                EntityType::Function(type_index),
+            );
+
+            debug!("{:>4}: {} (external)", out.next_function_index, func.id);
+            self.function_indices
+                .insert(func.id.clone(), out.next_function_index);
             out.function_names
                 .append(out.next_function_index, &func.id.name());
             out.next_function_index += 1;
         }
     }
 
+    /// Imports the host's [`ASSERTION_FAILURE_IMPORT`] function, taking a reference to the failed
+    /// assertion's detail message, returning its function index. This must be called before
+    /// [`Renderer::index_functions`], as imports must come before all other functions.
+    fn import_assertion_failure(&self, out: &mut Module) -> u32 {
+        let func_type = Arc::new(FunctionType {
+            params: vec![ValType::I32], // [message: i32]
+            results: vec![],
+        });
+        let type_index = out.ensure_type(&func_type);
+        let import_type = EntityType::Function(type_index);
+        out.imports
+            .import("imports", Some(ASSERTION_FAILURE_IMPORT), import_type);
+
+        let index = out.next_function_index;
+        out.next_function_index += 1;
+        out.function_names.append(index, "!onAssertionFailure");
+        index
+    }
+
+    /// Imports the host's [`TRAP_IMPORT`] function, taking a [`TrapKind`]'s code, returning its
+    /// function index. Like [`Renderer::import_assertion_failure`], this must be called before
+    /// [`Renderer::index_functions`].
+    fn import_trap(&self, out: &mut Module) -> u32 {
+        let func_type = Arc::new(FunctionType {
+            params: vec![ValType::I32], // [code: i32]
+            results: vec![],
+        });
+        let type_index = out.ensure_type(&func_type);
+        let import_type = EntityType::Function(type_index);
+        out.imports
+            .import("imports", Some(TRAP_IMPORT), import_type);
+
+        let index = out.next_function_index;
+        out.next_function_index += 1;
+        out.function_names.append(index, "!trap");
+        index
+    }
+
     /// Renders a WebAssembly import (external method) to the module.
-    fn render_import(&self, out: &mut Module, func: CompiledFunction) {
+    fn render_import(&self, out: &mut Module, func: &CompiledFunction) {
         let name = format!("{}", func.id);
         // Get the index corresponding to this import's function type
         let type_index = out.ensure_type(&func.descriptor.function_type);
@@ -88,115 +477,183 @@ impl Renderer {
         out.imports.import("imports", Some(&name), import_type);
     }
 
-    /// Renders an abstract function (without an implementation) to WebAssembly as an `unreachable`.
-    fn render_abstract(&self, out: &mut Module, func: CompiledFunction) {
+    /// Renders the body of an abstract function (without an implementation) to WebAssembly as an
+    /// `unreachable`, or returning the zero value of its return type, depending on
+    /// [`Renderer::abstract_behaviour`].
+    fn render_abstract_body(&self, func: &CompiledFunction) -> WASMFunction {
         // Static functions cannot be abstract
         assert!(!func.is_static());
 
-        // Create unreachable function with no locals
+        // Create function with no locals
         let mut f = WASMFunction::new(vec![]);
-        f.instruction(&WASMInstruction::Unreachable)
-            .instruction(&WASMInstruction::End);
+        match self.abstract_behaviour {
+            AbstractBehaviour::Trap => {
+                trap(&mut f, self.trap_index, TrapKind::AbstractMethod);
+            }
+            AbstractBehaviour::Default => {
+                for &t in &func.descriptor.function_type.results {
+                    f.instruction(&zero_const(t));
+                }
+            }
+        }
+        f.instruction(&WASMInstruction::End);
+        f
+    }
 
-        // Render function to module
-        let func_type = Arc::new(func.descriptor.function_type.with_implicit_this());
-        let type_index = out.ensure_type(&func_type);
-        out.functions.function(type_index);
-        out.codes.function(&f);
+    /// Returns the number of bytes before the first field of an instance.
+    fn get_header_size(&self) -> u32 {
+        if self.uniform_headers {
+            UNIFORM_HEADER_SIZE
+        } else {
+            VIRTUAL_CLASS_ID_SIZE
+        }
     }
 
-    /// Computes the total size of the named class's fields, including subclasses' and the virtual
-    /// class ID.
+    /// Computes the total size of the named class's fields, including subclasses', the instance
+    /// header and padding (see [`layout_fields`]). This is rounded up to [`HEAP_ALIGN`] bytes, so
+    /// the next instance allocated after this one is aligned too.
     fn get_class_size<'a>(&'a self, mut class_name: &'a Arc<String>) -> i32 {
-        let mut size = VIRTUAL_CLASS_ID_SIZE; // First 4 bytes for virtual class ID
+        let mut classes = vec![];
         while class_name.as_str() != JAVA_LANG_OBJECT {
             let class = &self.classes[class_name];
-            size += class.size;
+            classes.push(class);
             class_name = &class.super_class_name;
         }
+        let end = layout_fields(self.get_header_size(), &classes, |_, _, _| {});
+        let size = align_to(end, HEAP_ALIGN);
         i32::try_from(size).expect("Class size exceeded i32 bounds")
     }
 
-    /// Returns the WebAssembly type, memory offset and alignment immediates for a class field.
-    fn get_field_offset(&self, id: &FieldId) -> (ValType, MemArg) {
+    /// Returns the loaded class named `class_name` while resolving the field `id`, or a
+    /// descriptive error if it wasn't loaded.
+    fn find_field_class(&self, class_name: &Arc<String>, id: &FieldId) -> anyhow::Result<&Class> {
+        self.classes.get(class_name).ok_or_else(|| {
+            anyhow!(
+                "Unknown class {} while resolving field {}; did you forget to include it?",
+                class_name,
+                id
+            )
+        })
+    }
+
+    /// Returns the WebAssembly type, memory offset and alignment immediates for a class field, and
+    /// whether it's `volatile`, or an error if the class declaring it, or any of that class's
+    /// superclasses, weren't loaded.
+    fn find_field_offset(&self, id: &FieldId) -> anyhow::Result<(ValType, MemArg, bool)> {
         // Find field in inheritance tree, starting with ID's class_name. Normally, the class_name
         // is the calling class, not the superclass the field was defined in. However, if a field has
         // the same name as a field in a superclass, the superclass will be used as the class name
         // if accessing the "hidden" field: https://docs.oracle.com/javase/tutorial/java/IandI/hidevariables.html
         let mut class_name = &id.class_name;
-        let mut offset = None;
-        while offset.is_none() {
-            let class = &self.classes[class_name];
-            offset = class.field_offsets.get(&id.name);
-            class_name = &class.super_class_name;
-        }
-        let mut offset = *offset.unwrap() as u32;
-
-        // Add size of all remaining superclasses' sizes to offset
+        let mut classes = vec![];
+        let mut field_class = None;
         while class_name.as_str() != JAVA_LANG_OBJECT {
-            let class = &self.classes[class_name];
-            offset += class.size;
+            let class = self.find_field_class(class_name, id)?;
+            if field_class.is_none() && class.declares_field(&id.name) {
+                field_class = Some(classes.len());
+            }
+            classes.push(class);
             class_name = &class.super_class_name;
         }
+        let field_class = field_class.ok_or_else(|| anyhow!("Unknown field {}", id))?;
 
-        // Add virtual class ID size to offset
-        offset += VIRTUAL_CLASS_ID_SIZE;
+        // Lay out the field's class and all its superclasses to find the field's offset
+        let mut offset = 0;
+        layout_fields(self.get_header_size(), &classes, |i, name, field_offset| {
+            if i == field_class && **name == *id.name {
+                offset = field_offset;
+            }
+        });
 
-        // Calculate field alignment in memory based on type
+        // Construct memory argument immediate containing offset, aligned based on stored width
         let field_type = id.descriptor.as_type();
-        // https://webassembly.github.io/spec/core/text/instructions.html#memory-instructions
-        let align = match field_type {
-            ValType::I32 | ValType::F32 => 2, // log2(4) = 2
-            ValType::I64 | ValType::F64 => 3, // log2(8) = 3
-            _ => unimplemented!("{:?}", field_type),
-        };
+        let arg = mem_arg(offset, Width::of_field(&id.descriptor).0);
+        let volatile = classes[field_class].volatile_fields.contains(&id.name);
 
-        // Construct memory argument immediate containing offset
-        let arg = MemArg {
-            offset: offset as u64,
-            align,
-            memory_index: 0, // Index of memory we're addressing, not index into memory
-        };
+        Ok((field_type, arg, volatile))
+    }
 
-        (field_type, arg)
+    /// Returns the WebAssembly type, memory offset and alignment immediates for a class field, and
+    /// whether it's `volatile`.
+    fn get_field_offset(&self, id: &FieldId) -> (ValType, MemArg, bool) {
+        self.find_field_offset(id)
+            .expect("Field references should've been checked")
+    }
+
+    /// Returns the `java/lang/Object` intrinsic to lower a virtual call to `id` to, if `id` isn't
+    /// defined by its class or any of its superclasses.
+    ///
+    /// TODO (someday): subclasses of `id`'s class overriding the method won't be dispatched to,
+    ///  this requires adding `java/lang/Object`'s methods to the virtual table
+    fn get_object_intrinsic(&self, id: &MethodId) -> Option<ObjectIntrinsic> {
+        match self.virtual_table.find_method_virtual_offset(id) {
+            Some(_) => None,
+            None => ObjectIntrinsic::from_method(id),
+        }
+    }
+
+    /// Returns the function index to call for a call to `id` without dynamic dispatch.
+    ///
+    /// `invokespecial` calls to superclass methods name the direct superclass, and `invokestatic`
+    /// calls name the static type (e.g. `B.f()` for `A.f()` inherited by `B`), both of which may
+    /// inherit the method, so walk up the inheritance chain to find the class providing the
+    /// implementation. Static methods hidden by a subclass's are resolved by static type as each
+    /// class's own is indexed.
+    fn static_function_index(&self, id: &MethodId) -> u32 {
+        match self.function_indices.get(id) {
+            Some(&index) => index,
+            None => {
+                let implementation = self
+                    .virtual_table
+                    .find_method_implementation(id)
+                    .expect("Method not included in virtual table");
+                self.function_indices[implementation]
+            }
+        }
     }
 
     /// Renders a (pseudo-)instruction to a WebAssembly function body.
     ///
-    /// Pseudo-instructions will likely require built-in or virtual dispatcher functions. The
-    /// `Dup` instruction requires a temporary "scratch" local to duplicate from. This must be
-    /// defined if `Dup` is used. See [`Instruction`] for more details on pseudo-instructions.
+    /// Pseudo-instructions will likely require built-in or virtual dispatcher functions. Some
+    /// instructions require temporary "scratch" locals of a specific type: `Dup` requires one of
+    /// the duplicated value's type to duplicate from, null-checked `PutField`s require one of the
+    /// field's type to stash the value whilst checking the reference underneath it, and
+    /// null-checked `Object#equals` intrinsics require an `i32` one for the same reason, and NaN
+    /// canonicalising bit intrinsics require one of the argument's type (see
+    /// [`StaticIntrinsic::scratch_type`]). These must be defined in `scratch_locals` if used. See [`Instruction`] for more details on pseudo-instructions.
     ///
     /// Note [`Renderer::index_functions`] must be called before this function.
-    fn render(
+    fn render<D: Dependencies>(
         &self,
-        out: &mut Module,
+        deps: &mut D,
+        null_checks: bool,
         f: &mut WASMFunction,
-        instruction: Instruction,
-        scratch_local: Option<u32>,
+        instruction: &Instruction,
+        scratch_locals: &HashMap<ValType, u32>,
     ) {
         match instruction {
             // Simple WebAssembly instruction, add to function directly
-            Instruction::I(instruction) => f.instruction(&instruction),
+            Instruction::I(instruction) => f.instruction(instruction),
             // Duplicates the value at the top of the stack
-            Instruction::Dup => {
-                let scratch_local = scratch_local.unwrap();
+            Instruction::Dup(t) => {
+                let scratch_local = scratch_locals[t];
                 // LocalTee is equivalent to LocalSet followed by LocalGet
                 f.instruction(&WASMInstruction::LocalTee(scratch_local))
                     .instruction(&WASMInstruction::LocalGet(scratch_local))
             }
             //  Creates a new instance of the specified class on the heap returning a reference
             Instruction::New(class_name) => {
-                if *class_name == "java/lang/AssertionError" {
+                if **class_name == "java/lang/AssertionError" {
                     // The Java standard library is not supported, but basic support is required
                     // for assertions. If we're creating an AssertionError, we've failed an
                     // assertion so the instruction following this will be a throw (which we
                     // currently translate to unreachable). Therefore, just emit null here.
                     f.instruction(&WASMInstruction::I32Const(0))
                 } else {
-                    let size = self.get_class_size(&class_name);
-                    let virtual_class_id = self.virtual_table.get_virtual_class_id(&class_name);
-                    let allocate_index = out.ensure_builtin_function(BuiltinFunction::Allocate);
+                    let size = self.get_class_size(class_name);
+                    let virtual_class_id = (self.virtual_table.get_virtual_class_id(class_name))
+                        .expect("Class references should've been checked");
+                    let allocate_index = deps.builtin(BuiltinFunction::Allocate);
                     f.instruction(&WASMInstruction::I32Const(size))
                         .instruction(&WASMInstruction::I32Const(virtual_class_id))
                         .instruction(&WASMInstruction::Call(allocate_index))
@@ -204,68 +661,205 @@ impl Renderer {
             }
             // Checks if the reference is an `instanceof` the specified class
             Instruction::InstanceOf(class_name) => {
-                let virtual_class_id = self.virtual_table.get_virtual_class_id(&class_name);
-                let instanceof_index = out.ensure_builtin_function(BuiltinFunction::InstanceOf);
+                let virtual_class_id = (self.virtual_table.get_virtual_class_id(class_name))
+                    .expect("Class references should've been checked");
+                let instanceof_index = deps.builtin(BuiltinFunction::InstanceOf);
                 f.instruction(&WASMInstruction::I32Const(virtual_class_id))
                     .instruction(&WASMInstruction::Call(instanceof_index))
             }
             // Gets the value of the specified field of the object reference on the top of the stack
             Instruction::GetField(id) => {
-                let (field_type, arg) = self.get_field_offset(&id);
-                f.instruction(&match field_type {
-                    ValType::I32 => WASMInstruction::I32Load(arg),
-                    ValType::I64 => WASMInstruction::I64Load(arg),
-                    ValType::F32 => WASMInstruction::F32Load(arg),
-                    ValType::F64 => WASMInstruction::F64Load(arg),
-                    _ => unimplemented!("{:?}", field_type),
-                })
+                let (field_type, arg, volatile) = self.get_field_offset(id);
+                if null_checks {
+                    let null_check_index = deps.builtin(BuiltinFunction::NullCheck);
+                    f.instruction(&WASMInstruction::Call(null_check_index));
+                }
+                // Narrow fields are extended back to their type's full width
+                let (width, signed) = Width::of_field(&id.descriptor);
+                if self.threads && volatile {
+                    atomic_load(f, field_type, width, signed, arg)
+                } else {
+                    f.instruction(&load_instr(field_type, width, signed, arg))
+                }
             }
             // Puts the value into the specified field of the object reference on the top of the
             // stack
             Instruction::PutField(id) => {
-                let (field_type, arg) = self.get_field_offset(&id);
-                f.instruction(&match field_type {
-                    ValType::I32 => WASMInstruction::I32Store(arg),
-                    ValType::I64 => WASMInstruction::I64Store(arg),
-                    ValType::F32 => WASMInstruction::F32Store(arg),
-                    ValType::F64 => WASMInstruction::F64Store(arg),
-                    _ => unimplemented!("{:?}", field_type),
-                })
+                let (field_type, arg, volatile) = self.get_field_offset(id);
+                if null_checks {
+                    // Reference is underneath the value, so stash the value whilst checking it
+                    let scratch_local = scratch_locals[&field_type];
+                    let null_check_index = deps.builtin(BuiltinFunction::NullCheck);
+                    f.instruction(&WASMInstruction::LocalSet(scratch_local))
+                        .instruction(&WASMInstruction::Call(null_check_index))
+                        .instruction(&WASMInstruction::LocalGet(scratch_local));
+                }
+                let (width, _) = Width::of_field(&id.descriptor);
+                if self.threads && volatile {
+                    atomic_store(f, field_type, width, arg)
+                } else {
+                    f.instruction(&store_instr(field_type, width, arg))
+                }
             }
             // Calls the specified static method (no dynamic dispatch), popping the required number
             // of parameters off the stack and pushing back the result
             Instruction::CallStatic(id) => {
-                if *id.class_name == "java/lang/AssertionError" && id.name.as_str() == "<init>" {
+                if is_assertion_error_constructor(id) {
                     // The Java standard library is not supported, but basic support is required
                     // for assertions. If we're constructing an AssertionError, we've failed an
                     // assertion so the instruction following this will be a throw (which we
-                    // currently translate to unreachable). Therefore, just nop here.
-                    f.instruction(&WASMInstruction::Nop)
+                    // currently translate to unreachable). Therefore, just consume the reference
+                    // and any arguments like the void constructor would, optionally reporting the
+                    // failure to the host first.
+                    match self.assertion_failure_index {
+                        // AssertionError(Object) is used for all reference detail messages, which
+                        // will be on top of the stack, so pass it straight to the host
+                        Some(index)
+                            if matches!(id.descriptor.params[..], [FieldDescriptor::Object(_)]) =>
+                        {
+                            f.instruction(&WASMInstruction::Call(index))
+                                .instruction(&WASMInstruction::Drop)
+                        }
+                        Some(index) => {
+                            for _ in 0..id.descriptor.params.len() {
+                                f.instruction(&WASMInstruction::Drop);
+                            }
+                            f.instruction(&WASMInstruction::I32Const(0))
+                                .instruction(&WASMInstruction::Call(index))
+                                .instruction(&WASMInstruction::Drop)
+                        }
+                        None => {
+                            for _ in 0..=id.descriptor.params.len() {
+                                f.instruction(&WASMInstruction::Drop);
+                            }
+                            f
+                        }
+                    }
+                } else if let Some(intrinsic) = StaticIntrinsic::from_method(id) {
+                    debug_assert_eq!(
+                        id.descriptor.function_type.results,
+                        [intrinsic.result_type()]
+                    );
+                    match intrinsic {
+                        StaticIntrinsic::FloatToRawIntBits => {
+                            f.instruction(&WASMInstruction::I32ReinterpretF32)
+                        }
+                        StaticIntrinsic::IntBitsToFloat => {
+                            f.instruction(&WASMInstruction::F32ReinterpretI32)
+                        }
+                        StaticIntrinsic::DoubleToRawLongBits => {
+                            f.instruction(&WASMInstruction::I64ReinterpretF64)
+                        }
+                        StaticIntrinsic::LongBitsToDouble => {
+                            f.instruction(&WASMInstruction::F64ReinterpretI64)
+                        }
+                        StaticIntrinsic::FloatMin => f.instruction(&WASMInstruction::F32Min),
+                        StaticIntrinsic::FloatMax => f.instruction(&WASMInstruction::F32Max),
+                        StaticIntrinsic::DoubleMin => f.instruction(&WASMInstruction::F64Min),
+                        StaticIntrinsic::DoubleMax => f.instruction(&WASMInstruction::F64Max),
+                        // Select the canonical NaN's bits if the value isn't equal to itself
+                        StaticIntrinsic::FloatToIntBits => {
+                            let scratch_local = scratch_locals[&ValType::F32];
+                            f.instruction(&WASMInstruction::LocalSet(scratch_local))
+                                .instruction(&WASMInstruction::I32Const(0x7fc00000))
+                                .instruction(&WASMInstruction::LocalGet(scratch_local))
+                                .instruction(&WASMInstruction::I32ReinterpretF32)
+                                .instruction(&WASMInstruction::LocalGet(scratch_local))
+                                .instruction(&WASMInstruction::LocalGet(scratch_local))
+                                .instruction(&WASMInstruction::F32Neq)
+                                .instruction(&WASMInstruction::Select)
+                        }
+                        StaticIntrinsic::DoubleToLongBits => {
+                            let scratch_local = scratch_locals[&ValType::F64];
+                            f.instruction(&WASMInstruction::LocalSet(scratch_local))
+                                .instruction(&WASMInstruction::I64Const(0x7ff8000000000000))
+                                .instruction(&WASMInstruction::LocalGet(scratch_local))
+                                .instruction(&WASMInstruction::I64ReinterpretF64)
+                                .instruction(&WASMInstruction::LocalGet(scratch_local))
+                                .instruction(&WASMInstruction::LocalGet(scratch_local))
+                                .instruction(&WASMInstruction::F64Neq)
+                                .instruction(&WASMInstruction::Select)
+                        }
+                    }
                 } else {
-                    let index = self.function_indices[&id];
-                    f.instruction(&WASMInstruction::Call(index))
+                    f.instruction(&WASMInstruction::Call(self.static_function_index(id)))
                 }
             }
             // Calls the specified instance method (using dynamic dispatch), popping the required
             // number of parameters off the stack (including an implicit `this` reference) and
             // pushing back the result
             Instruction::CallVirtual(id) => {
-                let virtual_offset = self.virtual_table.get_method_virtual_offset(&id);
-                let dispatcher_index = out.ensure_dispatcher_function(&id.descriptor.function_type);
-                f.instruction(&WASMInstruction::I32Const(virtual_offset))
-                    .instruction(&WASMInstruction::Call(dispatcher_index))
+                if let Some(intrinsic) = self.get_object_intrinsic(id) {
+                    debug_assert_eq!(
+                        id.descriptor.function_type.results,
+                        [intrinsic.result_type()]
+                    );
+                    if null_checks {
+                        let null_check_index = deps.builtin(BuiltinFunction::NullCheck);
+                        if intrinsic == ObjectIntrinsic::Equals {
+                            // Reference is underneath the argument, so stash it whilst checking
+                            let scratch_local = scratch_locals[&ValType::I32];
+                            f.instruction(&WASMInstruction::LocalSet(scratch_local))
+                                .instruction(&WASMInstruction::Call(null_check_index))
+                                .instruction(&WASMInstruction::LocalGet(scratch_local));
+                        } else {
+                            f.instruction(&WASMInstruction::Call(null_check_index));
+                        }
+                    }
+                    match intrinsic {
+                        ObjectIntrinsic::Equals => f.instruction(&WASMInstruction::I32Eq),
+                        // Reference is already on the stack
+                        ObjectIntrinsic::HashCode => f.instruction(&WASMInstruction::Nop),
+                        ObjectIntrinsic::GetClass => {
+                            f.instruction(&WASMInstruction::I32Load(VIRTUAL_CLASS_ID_MEM_ARG))
+                        }
+                    }
+                } else {
+                    let virtual_offset = self.virtual_table.get_method_virtual_offset(id);
+                    let dispatcher_index = deps.dispatcher(&id.descriptor.function_type);
+                    f.instruction(&WASMInstruction::I32Const(virtual_offset))
+                        .instruction(&WASMInstruction::Call(dispatcher_index))
+                }
+            }
+            // Calls the specified static method in tail position, returning its result whilst
+            // reusing this function's stack frame. Special cases that don't call a function (e.g.
+            // intrinsics) are rendered as regular calls followed by a `return`.
+            Instruction::ReturnCallStatic(id) => {
+                if is_assertion_error_constructor(id) || StaticIntrinsic::from_method(id).is_some()
+                {
+                    let call = Instruction::CallStatic(id.clone());
+                    self.render(deps, null_checks, f, &call, scratch_locals);
+                    f.instruction(&WASMInstruction::Return)
+                } else {
+                    return_call(f, self.static_function_index(id))
+                }
+            }
+            // Calls the specified instance method in tail position, returning its result whilst
+            // reusing this function's stack frame. The dispatcher tail calls the implementation
+            // too (see `Module::tail_calls`), so recursion through it doesn't grow the stack.
+            Instruction::ReturnCallVirtual(id) => {
+                if self.get_object_intrinsic(id).is_some() {
+                    let call = Instruction::CallVirtual(id.clone());
+                    self.render(deps, null_checks, f, &call, scratch_locals);
+                    f.instruction(&WASMInstruction::Return)
+                } else {
+                    let virtual_offset = self.virtual_table.get_method_virtual_offset(id);
+                    let dispatcher_index = deps.dispatcher(&id.descriptor.function_type);
+                    f.instruction(&WASMInstruction::I32Const(virtual_offset));
+                    return_call(f, dispatcher_index)
+                }
             }
             // Pops two `long` values `a` and `b` off the top of the stack, returning -1 if `a < b`,
             // 0 if `a = b` and 1 if `a > b`
             Instruction::LongCmp => {
-                let long_cmp_index = out.ensure_builtin_function(BuiltinFunction::LongCmp);
+                let long_cmp_index = deps.builtin(BuiltinFunction::LongCmp);
                 f.instruction(&WASMInstruction::Call(long_cmp_index))
             }
             // Pops two `float` values `a` and `b` off the top of the stack, returning -1 if `a < b`,
             // 0 if `a = b` and 1 if `a > b`. If either `a` or `b` is NaN, the result is determined
             // by the specified `NaNBehaviour`
             Instruction::FloatCmp(nan_behaviour) => {
-                let float_cmp_index = out.ensure_builtin_function(BuiltinFunction::FloatCmp);
+                let float_cmp_index = deps.builtin(BuiltinFunction::FloatCmp);
                 let nan_greater = nan_behaviour.as_nan_greater_int();
                 f.instruction(&WASMInstruction::I32Const(nan_greater))
                     .instruction(&WASMInstruction::Call(float_cmp_index))
@@ -274,44 +868,75 @@ impl Renderer {
             // 0 if `a = b` and 1 if `a > b`. If either `a` or `b` is NaN, the result is determined
             // by the specified `NaNBehaviour`
             Instruction::DoubleCmp(nan_behaviour) => {
-                let double_cmp_index = out.ensure_builtin_function(BuiltinFunction::DoubleCmp);
+                let double_cmp_index = deps.builtin(BuiltinFunction::DoubleCmp);
                 let nan_greater = nan_behaviour.as_nan_greater_int();
                 f.instruction(&WASMInstruction::I32Const(nan_greater))
                     .instruction(&WASMInstruction::Call(double_cmp_index))
             }
             // Pops two `float` values `a` and `b` off the top of the stack, returning `a % b`.
             Instruction::FloatRem => {
-                let float_rem_index = out.ensure_builtin_function(BuiltinFunction::FloatRem);
+                let float_rem_index = deps.builtin(BuiltinFunction::FloatRem);
                 f.instruction(&WASMInstruction::Call(float_rem_index))
             }
             // Pops two `double` values `a` and `b` off the top of the stack, returning `a % b`.
             Instruction::DoubleRem => {
-                let double_rem_index = out.ensure_builtin_function(BuiltinFunction::DoubleRem);
+                let double_rem_index = deps.builtin(BuiltinFunction::DoubleRem);
                 f.instruction(&WASMInstruction::Call(double_rem_index))
             }
+            // Exceptions are not yet supported, so trap instead of throwing. `unreachable` makes
+            // the stack polymorphic, so the exception reference doesn't need to be dropped.
+            Instruction::Throw => trap(f, self.trap_index, TrapKind::Throw),
         };
     }
 
-    /// Renders a WebAssembly function (with code) to the module. If the function is `public static`,
-    /// it will be exported to the host.
+    /// Renders the body of a WebAssembly function (with code), using `deps` to get the indices of
+    /// any built-in or virtual dispatcher functions it calls.
     ///
     /// Each (pseudo-) instruction will be lowered to a real WebAssembly instruction by
     /// [`Renderer::render`].
     ///
     /// Note [`Renderer::index_functions`] must be called before this function.
-    fn render_function(&self, out: &mut Module, func: CompiledFunction) {
-        let is_static = func.is_static();
-        let is_export = func.is_export();
-
-        let locals = func.locals.expect("Non-imports must have locals");
-        let code = func.code.expect("Non-imports must have code");
-
-        // Check if code needs a scratch local (for Dup)
-        let needs_scratch = code.iter().any(|i| matches!(i, Instruction::Dup));
-        let (scratch_local, append_locals) = match needs_scratch {
-            true => (Some(locals.len() as u32), vec![ValType::I32]),
-            false => (None, vec![]),
-        };
+    fn render_body<D: Dependencies>(
+        &self,
+        deps: &mut D,
+        null_checks: bool,
+        func: &CompiledFunction,
+    ) -> WASMFunction {
+        let locals = func.locals.as_ref().expect("Non-imports must have locals");
+        let code = func.code.as_ref().expect("Non-imports must have code");
+
+        // Check which types of scratch locals code needs (for Dup, null-checked PutField, equals and
+        // NaN canonicalising bit intrinsics), appending one local for each type
+        let mut scratch_types = BTreeSet::new();
+        for instruction in code {
+            match instruction {
+                Instruction::Dup(t) => {
+                    scratch_types.insert(*t);
+                }
+                Instruction::PutField(id) if null_checks => {
+                    scratch_types.insert(id.descriptor.as_type());
+                }
+                Instruction::CallVirtual(id) | Instruction::ReturnCallVirtual(id)
+                    if null_checks
+                        && self.get_object_intrinsic(id) == Some(ObjectIntrinsic::Equals) =>
+                {
+                    scratch_types.insert(ValType::I32);
+                }
+                Instruction::CallStatic(id) | Instruction::ReturnCallStatic(id) => {
+                    let intrinsic = StaticIntrinsic::from_method(id);
+                    if let Some(t) = intrinsic.and_then(StaticIntrinsic::scratch_type) {
+                        scratch_types.insert(t);
+                    }
+                }
+                _ => {}
+            }
+        }
+        let append_locals: Vec<_> = scratch_types.into_iter().collect();
+        let scratch_locals = append_locals
+            .iter()
+            .enumerate()
+            .map(|(i, &t)| (t, (locals.len() + i) as u32))
+            .collect();
 
         // Create new function with required locals
         let locals_rle = locals.run_length_encode(&append_locals);
@@ -319,44 +944,197 @@ impl Renderer {
 
         // Write all instructions to function
         for instruction in code {
-            self.render(out, &mut f, instruction, scratch_local);
+            self.render(deps, null_checks, &mut f, instruction, &scratch_locals);
         }
+        f
+    }
 
-        // Render function to module
-        let func_type = match is_static {
+    /// Renders the body of a non-import function, either abstract or with code.
+    fn render_any_body<D: Dependencies>(
+        &self,
+        deps: &mut D,
+        null_checks: bool,
+        func: &CompiledFunction,
+    ) -> WASMFunction {
+        if func.is_abstract() {
+            self.render_abstract_body(func)
+        } else {
+            self.render_body(deps, null_checks, func)
+        }
+    }
+
+    /// Returns the WebAssembly type of a non-import function, including its implicit `this`
+    /// parameter if it's an instance method.
+    fn function_type(&self, func: &CompiledFunction) -> Arc<FunctionType> {
+        match func.is_static() {
             true => Arc::clone(&func.descriptor.function_type),
             false => Arc::new(func.descriptor.function_type.with_implicit_this()),
-        };
-        let type_index = out.ensure_type(&func_type);
+        }
+    }
+
+    /// Writes a rendered function body to the module. If the function is `public static`, it will
+    /// be exported to the host.
+    fn write_function(&self, out: &mut Module, func: &CompiledFunction, f: &WASMFunction) {
+        // Render function to module
+        let type_index = out.ensure_type(&self.function_type(func));
+        out.functions.function(type_index);
+        out.codes.function(f);
+        self.export_function(out, func);
+    }
+
+    /// Writes an encoded function body to the module, exporting it if required like
+    /// [`Renderer::write_function`].
+    fn write_function_bytes(&self, out: &mut Module, func: &CompiledFunction, bytes: &[u8]) {
+        let type_index = out.ensure_type(&self.function_type(func));
         out.functions.function(type_index);
-        out.codes.function(&f);
+        out.codes.raw(bytes);
+        self.export_function(out, func);
+    }
 
-        // If exported, render export to module
-        if is_export {
+    /// Exports a function to the host if it's `public static`.
+    fn export_function(&self, out: &mut Module, func: &CompiledFunction) {
+        if func.is_export() {
             let name = format!("{}", func.id);
             let function_index = self.function_indices[&func.id];
             out.exports.export(&name, Export::Function(function_index));
         }
     }
 
-    /// Renders all user-defined functions (including native imports) to the WebAssembly functions.
-    pub fn render_all(mut self, out: &mut Module) -> HashMap<MethodId, u32> {
-        // Sort and assign indices to functions
-        self.index_functions(out);
-        // Render each function, move functions out of self so we can mutably borrow again when
-        // calling render_import()/render_function(). We shouldn't need them again anyways.
-        for func in take(&mut self.functions) {
+    /// Renders functions one at a time, ensuring dependencies as they're encountered.
+    fn render_functions_serial(&self, out: &mut Module, functions: &[CompiledFunction]) {
+        for func in functions {
             if func.is_import() {
                 self.render_import(out, func);
-            } else if func.is_abstract() {
-                self.render_abstract(out, func);
             } else {
-                self.render_function(out, func);
+                let null_checks = out.null_checks;
+                let f = self.render_any_body(out, null_checks, func);
+                self.write_function(out, func, &f);
             }
         }
-        // Render any ensured functions (builtins and virtual dispatchers)
+    }
+
+    /// Renders function bodies across [`Renderer::render_threads`] threads, producing identical
+    /// bytes to [`Renderer::render_functions_serial`].
+    ///
+    /// Function indices of dependencies are baked into bodies, and depend on the order they're
+    /// ensured in. Therefore, each body is rendered once in parallel with placeholder indices,
+    /// recording where they're called (see [`RecordedBody`]). Bodies are then merged into the
+    /// module serially in function order, ensuring their dependencies in the order serial
+    /// rendering would, and patching the real indices in.
+    fn render_functions_parallel(&self, out: &mut Module, functions: &[CompiledFunction]) {
+        let null_checks = out.null_checks;
+
+        // Render each body with placeholder dependency indices
+        let bodies = self.map_parallel(functions, |func| {
+            (!func.is_import()).then(|| {
+                let mut deps = RecordedDependencies::default();
+                let f = self.render_any_body(&mut deps, null_checks, func);
+                RecordedBody::new(&f, deps)
+            })
+        });
+
+        // Ensure dependencies, imports and function types in the order serial rendering would,
+        // writing patched bodies to the module
+        for (func, body) in functions.iter().zip(bodies) {
+            match body {
+                Some(body) => {
+                    let bytes = body.patch(out);
+                    self.write_function_bytes(out, func, &bytes);
+                }
+                None => self.render_import(out, func),
+            }
+        }
+    }
+
+    /// Applies `op` to each function across [`Renderer::render_threads`] scoped threads, returning
+    /// results in the same order as `functions`.
+    fn map_parallel<R, F>(&self, functions: &[CompiledFunction], op: F) -> Vec<R>
+    where
+        R: Send,
+        F: Fn(&CompiledFunction) -> R + Sync,
+    {
+        let chunk_size = (functions.len() + self.render_threads - 1) / self.render_threads;
+        let op = &op;
+        thread::scope(|scope| {
+            let handles: Vec<_> = functions
+                .chunks(chunk_size.max(1))
+                .map(|chunk| scope.spawn(move || chunk.iter().map(op).collect::<Vec<_>>()))
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().unwrap())
+                .collect()
+        })
+    }
+
+    /// Checks every class instantiated or tested against by the functions being rendered has a
+    /// virtual class ID, and every field accessed can be found in a loaded class, returning a
+    /// descriptive error if a class wasn't loaded (e.g. it was missing from the input classes).
+    /// Lowering [`Instruction::New`], [`Instruction::InstanceOf`], [`Instruction::GetField`] and
+    /// [`Instruction::PutField`] can't fail once this has succeeded.
+    fn check_references(&self) -> anyhow::Result<()> {
+        for func in &self.functions {
+            for instruction in func.code.iter().flatten() {
+                let result = match instruction {
+                    // AssertionErrors are never constructed, see `Instruction::New`'s rendering
+                    Instruction::New(class_name) if **class_name != "java/lang/AssertionError" => {
+                        self.virtual_table
+                            .get_virtual_class_id(class_name)
+                            .map(drop)
+                    }
+                    Instruction::InstanceOf(class_name) => self
+                        .virtual_table
+                        .get_virtual_class_id(class_name)
+                        .map(drop),
+                    Instruction::GetField(id) | Instruction::PutField(id) => {
+                        self.find_field_offset(id).map(drop)
+                    }
+                    _ => continue,
+                };
+                result.with_context(|| format!("Unable to render {}", func.id))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Renders all user-defined functions (including native imports) to the WebAssembly functions.
+    /// Fails if functions reference classes that weren't loaded, before anything is rendered.
+    pub fn render_all(mut self, out: &mut Module) -> anyhow::Result<HashMap<MethodId, u32>> {
+        // Check referenced classes exist first, so rendering can't fail part way through
+        self.check_references()?;
+        // Import WASI functions if required, before user imports are assigned indices
+        let fd_write_index = self.demo_main.as_ref().map(|_| out.import_wasi_fd_write());
+        if self.assert_mode == AssertMode::Host {
+            self.assertion_failure_index = Some(self.import_assertion_failure(out));
+        }
+        if self.rich_traps {
+            self.trap_index = Some(self.import_trap(out));
+            // Built-in functions and dispatchers are ensured on the module, so need the index too
+            out.trap_index = self.trap_index;
+        }
+        // Sort and assign indices to functions
+        self.index_functions(out);
+        // Render each function, move functions out of self so we can borrow self whilst mutably
+        // borrowing the module. We shouldn't need them again anyways.
+        let functions = take(&mut self.functions);
+        if self.render_threads > 1 {
+            self.render_functions_parallel(out, &functions);
+        } else {
+            self.render_functions_serial(out, &functions);
+        }
+        // Add WASI entrypoint if required, calling the target method
+        if let (Some(demo_main), Some(fd_write_index)) = (&self.demo_main, fd_write_index) {
+            let target_index = self
+                .function_indices
+                .iter()
+                .find(|(id, _)| id.to_string() == demo_main.method)
+                .map(|(_, &index)| index)
+                .expect("Demo main method should've been checked");
+            out.add_demo_start(fd_write_index, target_index, demo_main.arg);
+        }
+        // Render any ensured functions (builtins, virtual dispatchers and the WASI entrypoint)
         out.render_ensured_functions_queue();
         // Return function indices for use in virtual table rendering
-        self.function_indices
+        Ok(self.function_indices)
     }
 }
@@ -0,0 +1,69 @@
+use crate::class::MethodId;
+use crate::function::{unsupported_cause, CompileFunctionError, CompiledFunction, Unsupported};
+use crate::output::summary::{json_str, write_array};
+use itertools::Itertools;
+use std::collections::BTreeMap;
+
+/// Returns a JSON object for an unsupported feature, e.g.
+/// `{"description": "Newarray instruction", "category": "Array"}`.
+fn unsupported_json(unsupported: &Unsupported) -> String {
+    format!(
+        "{{\"description\": {}, \"category\": {}}}",
+        json_str(unsupported.description),
+        json_str(unsupported.category)
+    )
+}
+
+/// Returns a JSON report of the unsupported features in each method, for gating builds or tracking
+/// progress with `--report-unsupported-json`. This includes features replaced when compiling
+/// `functions` (see [`UnsupportedBehaviour`]), and the features that caused compilation `errors`.
+/// Errors that weren't caused by an [`Unsupported`] feature (e.g. endless loops) aren't included.
+///
+/// Methods are sorted by ID, and followed by the number of unsupported features in each category.
+///
+/// [`UnsupportedBehaviour`]: crate::function::UnsupportedBehaviour
+pub fn report_unsupported(functions: &[CompiledFunction], errors: &[anyhow::Error]) -> String {
+    let mut methods: BTreeMap<&MethodId, Vec<Unsupported>> = BTreeMap::new();
+    for function in functions {
+        if !function.unsupported.is_empty() {
+            let unsupported = methods.entry(&function.id).or_default();
+            unsupported.extend(function.unsupported.iter().copied());
+        }
+    }
+    for e in errors {
+        let context = e.downcast_ref::<CompileFunctionError>();
+        if let (Some(context), Some(unsupported)) = (context, unsupported_cause(e)) {
+            methods.entry(&context.id).or_default().push(unsupported);
+        }
+    }
+
+    let mut categories: BTreeMap<&str, usize> = BTreeMap::new();
+    for unsupported in methods.values().flatten() {
+        *categories.entry(unsupported.category).or_default() += 1;
+    }
+
+    let methods = (methods.iter())
+        .map(|(id, unsupported)| {
+            format!(
+                "{{\"method\": {}, \"unsupported\": [{}]}}",
+                json_str(&id.to_string()),
+                unsupported.iter().map(unsupported_json).format(", ")
+            )
+        })
+        .collect::<Vec<_>>();
+    let categories = (categories.iter())
+        .map(|(category, count)| {
+            format!(
+                "{{\"category\": {}, \"count\": {}}}",
+                json_str(category),
+                count
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let mut out = String::from("{\n");
+    write_array(&mut out, "methods", &methods, false);
+    write_array(&mut out, "categories", &categories, true);
+    out.push_str("}\n");
+    out
+}
@@ -0,0 +1,55 @@
+use crate::output::diff::function_name;
+use crate::output::merge::ParsedModule;
+use itertools::Itertools;
+use std::fmt;
+use std::fmt::Formatter;
+
+/// Encoded sizes of a module's code, for tracking output size on size-constrained targets with
+/// `--size-report` (see [`size_report`]).
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct SizeReport {
+    /// Size in bytes of the code section's contents, including each body's length prefix.
+    pub code_size: usize,
+    /// Name and encoded body size in bytes of each defined function, largest first. Functions with
+    /// the same size are sorted by name.
+    pub functions: Vec<(String, usize)>,
+}
+
+impl fmt::Display for SizeReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Code section: {} byte(s)", self.code_size)?;
+        let width = (self.functions.iter())
+            .map(|(_, size)| size.to_string().len())
+            .max()
+            .unwrap_or(0);
+        let lines = (self.functions.iter())
+            .map(|(name, size)| format!("  {:>width$}  {}", size, name, width = width));
+        for line in lines {
+            write!(f, "\n{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the size of a finished module's code section (see [`Module::finish`]), and each of its
+/// defined functions' encoded body sizes (including locals, but excluding length prefixes). This
+/// includes built-ins and dispatchers, but not imported functions, as they have no body.
+///
+/// [`Module::finish`]: crate::output::Module::finish
+pub fn size_report(wasm: &[u8]) -> anyhow::Result<SizeReport> {
+    let module = ParsedModule::parse(wasm)?;
+    let functions = (module.bodies.iter().enumerate())
+        .map(|(defined_index, body)| {
+            let index = (module.imports.len() + defined_index) as u32;
+            let range = body.range();
+            (function_name(&module, index), range.end - range.start)
+        })
+        .sorted_by(|(a_name, a_size), (b_name, b_size)| {
+            b_size.cmp(a_size).then_with(|| a_name.cmp(b_name))
+        })
+        .collect();
+    Ok(SizeReport {
+        code_size: module.code_size,
+        functions,
+    })
+}
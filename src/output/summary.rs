@@ -0,0 +1,128 @@
+use crate::class::{val_type_name, FunctionType};
+use crate::output::merge::ParsedModule;
+use itertools::Itertools;
+use std::fmt::Write;
+
+/// Returns `s` as a quoted JSON string, escaping characters as required.
+pub(super) fn json_str(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Returns a human-readable signature for a function type, e.g. `[i32, i64] -> [i32]`.
+fn type_signature(func_type: &FunctionType) -> String {
+    let params = func_type.params.iter().copied().map(val_type_name);
+    let results = func_type.results.iter().copied().map(val_type_name);
+    format!("[{}] -> [{}]", params.format(", "), results.format(", "))
+}
+
+/// Writes a JSON array field named `key` to `out`, with each of `items` on its own line.
+pub(super) fn write_array(out: &mut String, key: &str, items: &[String], last: bool) {
+    let comma = if last { "" } else { "," };
+    if items.is_empty() {
+        writeln!(out, "  {}: []{}", json_str(key), comma).unwrap();
+        return;
+    }
+    writeln!(out, "  {}: [", json_str(key)).unwrap();
+    for (i, item) in items.iter().enumerate() {
+        let item_comma = if i == items.len() - 1 { "" } else { "," };
+        writeln!(out, "    {}{}", item, item_comma).unwrap();
+    }
+    writeln!(out, "  ]{}", comma).unwrap();
+}
+
+/// Returns a stable JSON summary of a finished module's bytes (see [`Module::finish`]), for
+/// diffing output across compiler versions with `--dump-json-module`. This includes the name and
+/// type signature of each function, imports, function exports, built-in and dispatcher functions
+/// used, and the virtual table's elements.
+///
+/// Everything except virtual table elements is sorted by name, so the summary doesn't depend on
+/// function indices. Unlike the module's bytes, it also doesn't depend on the encoder's version.
+///
+/// [`Module::finish`]: crate::output::Module::finish
+pub fn summarise_module(wasm: &[u8]) -> anyhow::Result<String> {
+    let module = ParsedModule::parse(wasm)?;
+    let function_name = |index: u32| match module.names.get(&index) {
+        Some(name) => json_str(name),
+        None => json_str(&format!("#{}", index)),
+    };
+    let function_type = |index: u32| {
+        let func_type = &module.types[module.function_type_index(index) as usize];
+        json_str(&type_signature(func_type))
+    };
+
+    // Functions may share indices if their bodies were deduplicated, but will only have one name
+    let functions = (0..module.function_count() as u32)
+        .map(|index| (function_name(index), function_type(index)))
+        .sorted()
+        .map(|(name, ty)| format!("{{\"name\": {}, \"type\": {}}}", name, ty))
+        .collect::<Vec<_>>();
+    let imports = (module.imports.iter().enumerate())
+        .map(|(index, &(module_name, field, _))| (module_name, field, index as u32))
+        .sorted()
+        .map(|(module_name, field, index)| {
+            format!(
+                "{{\"module\": {}, \"name\": {}, \"type\": {}}}",
+                json_str(module_name),
+                json_str(field),
+                function_type(index)
+            )
+        })
+        .collect::<Vec<_>>();
+    let exports = (module.exports.iter())
+        .sorted()
+        .map(|&(name, index)| {
+            format!(
+                "{{\"name\": {}, \"function\": {}}}",
+                json_str(name),
+                function_name(index)
+            )
+        })
+        .collect::<Vec<_>>();
+    let builtins = (module.names.values())
+        .filter(|name| name.starts_with('!') && !name.starts_with("!Super_"))
+        .sorted()
+        .map(|name| json_str(name))
+        .collect::<Vec<_>>();
+    let virtual_table = (module.elements.iter())
+        .map(|(table_index, offset, function_indices)| {
+            let function_names = function_indices.iter().map(|&i| function_name(i));
+            format!(
+                "{{\"table\": {}, \"offset\": {}, \"functions\": [{}]}}",
+                table_index,
+                offset,
+                function_names.format(", ")
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let mut out = String::from("{\n");
+    write_array(&mut out, "functions", &functions, false);
+    write_array(&mut out, "imports", &imports, false);
+    write_array(&mut out, "exports", &exports, false);
+    write_array(&mut out, "builtins", &builtins, false);
+    write_array(&mut out, "virtual_table", &virtual_table, true);
+    out.push_str("}\n");
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::output::summary::json_str;
+
+    #[test]
+    fn json_str_escapes() {
+        assert_eq!(json_str("Test.<init>()V"), "\"Test.<init>()V\"");
+        assert_eq!(json_str("a\"b\\c\n"), "\"a\\\"b\\\\c\\u000a\"");
+    }
+}
@@ -0,0 +1,48 @@
+use wasm_encoder::{Function as WASMFunction, Instruction as WASMInstruction};
+
+/// Name of the function imported from the `imports` module to report the kind of error a module is
+/// about to trap with, if [`Renderer::rich_traps`] is set. This has the signature
+/// `[code: i32] -> []`, where `code` is a [`TrapKind`]'s code. The host may throw its own error
+/// from this function instead of returning.
+///
+/// [`Renderer::rich_traps`]: crate::output::Renderer::rich_traps
+pub const TRAP_IMPORT: &str = "trap";
+
+/// Kinds of error reported to the host's [`TRAP_IMPORT`] function with `--rich-traps`. Exceptions
+/// are not yet supported, so all of these trap with `unreachable` instead of throwing.
+///
+/// Casts aren't included as the `checkcast` instruction isn't supported yet. Integer division by
+/// zero isn't included either, as WebAssembly's division instructions already trap with their own
+/// distinct trap code. Unsupported instructions replaced with traps (see
+/// [`UnsupportedBehaviour::Trap`](crate::function::UnsupportedBehaviour::Trap)) are compiled before
+/// rendering, so still trap without calling the host.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TrapKind {
+    /// `athrow` instruction, including the one following a failed assertion
+    Throw = 1,
+    /// Field access or virtual method call through a `null` reference, with `--null-checks`
+    NullPointer = 2,
+    /// Call to an `abstract` method, with `--abstract-behavior trap`
+    AbstractMethod = 3,
+}
+
+impl TrapKind {
+    /// Returns the code passed to the host's [`TRAP_IMPORT`] function for this kind of error.
+    pub fn code(self) -> i32 {
+        self as i32
+    }
+}
+
+/// Writes an `unreachable` instruction to `f`, first calling the imported [`TRAP_IMPORT`] function
+/// at `trap_index` with `kind`'s code if `--rich-traps` is enabled (`trap_index` is `Some`).
+pub(super) fn trap(
+    f: &mut WASMFunction,
+    trap_index: Option<u32>,
+    kind: TrapKind,
+) -> &mut WASMFunction {
+    if let Some(trap_index) = trap_index {
+        f.instruction(&WASMInstruction::I32Const(kind.code()))
+            .instruction(&WASMInstruction::Call(trap_index));
+    }
+    f.instruction(&WASMInstruction::Unreachable)
+}
@@ -1,11 +1,22 @@
+use crate::output::builtin::HEAP_BASE;
 use crate::output::ensure::Ensurable;
 use std::collections::HashMap;
+use std::iter;
+use std::sync::Arc;
 use wasm_encoder::{
-    CodeSection, ElementSection, Export, ExportSection, Function as WASMFunction, FunctionSection,
-    GlobalSection, ImportSection, MemorySection, MemoryType, Module as WASMModule, NameMap,
-    NameSection, TableSection, TypeSection,
+    encoders, CodeSection, CustomSection, ElementSection, Export, ExportSection,
+    Function as WASMFunction, FunctionSection, GlobalSection, GlobalType, ImportSection,
+    Instruction as WASMInstruction, MemorySection, MemoryType, Module as WASMModule, NameMap,
+    NameSection, TableSection, TypeSection, ValType,
 };
 
+/// Name of the custom section mapping user-defined function indices to the source files (e.g.
+/// `Test.java`) they were compiled from, for debugging. This is encoded like the name section's
+/// function names: a vector of function index and file name pairs, sorted by function index.
+/// Functions from classes without a recorded source file are omitted, and [`Module::merge`] doesn't
+/// preserve this section yet.
+pub const SOURCE_FILES_SECTION: &str = "source_files";
+
 /// Function that another function wants to **ensure** exists once in the output module.
 /// This represents a function dependency.
 pub(super) struct EnsuredFunction {
@@ -24,15 +35,18 @@ pub(super) struct EnsuredFunction {
 /// This has the following structure:
 ///
 /// - Function Type Declarations (Type Section)
+/// - WASI Imports (Import Section, optional)
 /// - User Imports (Import Section)
 /// - User Functions (Function Section)
-/// - Built-in/Dispatcher Functions (Function Section)
+/// - Built-in/Dispatcher/WASI Entrypoint Functions (Function Section)
 /// - Super Virtual ID Functions (Function Section)
 /// - Table Declaration (Table Section)
 /// - Memory Declaration (Memory Section)
 /// - Virtual Table Elements (Element Section)
 /// - Function Code (Code Section)
 /// - Debug Function Names (Name Section)
+/// - Debug Function Source Files (Source Files Section, optional)
+/// - Toolchain Information (Producers Section, optional)
 ///
 /// When rendering user functions, we don't know what built-ins/dispatchers future functions will
 /// require. We also need known indices for each user function so future functions can be called.
@@ -48,11 +62,41 @@ pub struct Module {
     pub next_function_index: u32,
     /// Index in the module of the next added global variable.
     pub(super) next_global_index: u32,
+    /// Index of the bump allocator's heap next pointer global, if the
+    /// [`BuiltinFunction::Allocate`](crate::output::BuiltinFunction::Allocate) built-in has been
+    /// ensured.
+    pub(super) heap_next_global_index: Option<u32>,
     /// Instead of directly writing ensured functions to the function/code sections, delay writing
     /// them until all user functions have been written so we can predict their IDs for calls.
     pub(super) ensured_functions: Vec<EnsuredFunction>,
     /// Debug names for each function, used in WebAssembly text output.
     pub function_names: NameMap,
+    /// Source files user-defined functions were compiled from, in ascending function index order.
+    /// Written to the [`SOURCE_FILES_SECTION`].
+    pub(super) source_files: Vec<(u32, Arc<String>)>,
+    /// Function indices of user-defined methods (including imports), keyed by method identifier
+    /// (e.g. `Test.f(I)I`). Used by [`Module::merge`] to resolve imports of methods rendered in
+    /// another module.
+    pub(super) symbols: HashMap<String, u32>,
+    /// Whether field accesses and virtual method calls should trap if their object reference is
+    /// `null`, instead of accessing memory near address `0`. Enabled with `--null-checks`.
+    pub null_checks: bool,
+    /// Function index of the imported [`TRAP_IMPORT`](crate::output::TRAP_IMPORT) function, called
+    /// before null checks trap. Set by [`Renderer::render_all`](crate::output::Renderer::render_all)
+    /// if [`Renderer::rich_traps`](crate::output::Renderer::rich_traps) is set.
+    pub(super) trap_index: Option<u32>,
+    /// Whether virtual dispatchers should call method implementations with `return_call_indirect`,
+    /// so virtual calls in tail position don't grow the stack. Set by
+    /// [`render_module`](crate::render_module) if any function makes tail calls (see
+    /// [`Instruction::ReturnCallVirtual`](crate::function::Instruction::ReturnCallVirtual)).
+    pub tail_calls: bool,
+    /// Whether to include a `producers` section recording the source language and this compiler's
+    /// version. Disabled with `--no-producers`.
+    pub emit_producers: bool,
+    /// Whether to export the bump allocator's heap base and next pointer as `heap_base` and
+    /// `heap_next` globals, so hosts can inspect how much memory has been allocated. Only exported
+    /// if the module allocates. Enabled with `--emit-debug-globals`.
+    pub emit_debug_globals: bool,
 
     // https://webassembly.github.io/spec/core/binary/modules.html#sections
     pub types: TypeSection,         // 1
@@ -74,7 +118,15 @@ impl Module {
             next_type_index: 0,
             next_function_index: 0,
             next_global_index: 0,
+            heap_next_global_index: None,
             ensured_functions: Vec::new(),
+            source_files: Vec::new(),
+            symbols: HashMap::new(),
+            null_checks: false,
+            trap_index: None,
+            tail_calls: false,
+            emit_producers: true,
+            emit_debug_globals: false,
 
             types: TypeSection::new(),
             imports: ImportSection::new(),
@@ -101,26 +153,242 @@ impl Module {
         self.exports.export("memory", Export::Memory(0));
     }
 
+    /// Adds and exports an immutable `heap_base` global, and exports the bump allocator's heap next
+    /// pointer as `heap_next`, if this module allocates. See [`Module::emit_debug_globals`].
+    fn add_debug_globals(&mut self) {
+        if let Some(heap_next_global_index) = self.heap_next_global_index {
+            let heap_base_global_index = self.next_global_index;
+            self.next_global_index += 1;
+            self.globals.global(
+                GlobalType {
+                    val_type: ValType::I32,
+                    mutable: false,
+                },
+                &WASMInstruction::I32Const(HEAP_BASE),
+            );
+            self.exports
+                .export("heap_base", Export::Global(heap_base_global_index));
+            self.exports
+                .export("heap_next", Export::Global(heap_next_global_index));
+        }
+    }
+
     /// Finalises this module and converts it to *unoptimised* executable bytes.
     /// This result can be written directly to a binary `.wasm` file.
-    pub fn finish(self) -> Vec<u8> {
+    pub fn finish(mut self) -> Vec<u8> {
+        // Export debug globals if enabled
+        if self.emit_debug_globals {
+            self.add_debug_globals();
+        }
+
         // Build names section
         let mut names = NameSection::new();
         names.functions(&self.function_names);
 
         let mut module = WASMModule::new();
-        // Attach sections to module
+        // Attach sections to module, omitting optional sections if they're empty as the text format
+        // parser does, so `--roundtrip-wat` produces identical bytes
         module.section(&self.types);
-        module.section(&self.imports);
+        if self.imports.len() > 0 {
+            module.section(&self.imports);
+        }
         module.section(&self.functions);
         module.section(&self.tables);
         module.section(&self.memories);
         module.section(&self.globals);
         module.section(&self.exports);
-        module.section(&self.elements);
+        if self.elements.len() > 0 {
+            module.section(&self.elements);
+        }
         module.section(&self.codes);
         module.section(&names);
+        // Build source files section if any functions were compiled from known source files
+        if !self.source_files.is_empty() {
+            let source_files = encode_source_files(&self.source_files);
+            module.section(&CustomSection {
+                name: SOURCE_FILES_SECTION,
+                data: &source_files,
+            });
+        }
+        // Build producers section if enabled
+        if self.emit_producers {
+            let producers = encode_producers();
+            module.section(&CustomSection {
+                name: "producers",
+                data: &producers,
+            });
+        }
         // Convert to bytes
         module.finish()
     }
 }
+
+/// Writes a `return_call` instruction from the [tail call proposal] to `f`, calling the function
+/// at `function_index` and returning its result. `wasm_encoder` doesn't support this proposal yet,
+/// so the instruction is encoded manually.
+///
+/// [tail call proposal]: https://github.com/WebAssembly/tail-call/blob/main/proposals/tail-call/Overview.md
+pub(super) fn return_call(f: &mut WASMFunction, function_index: u32) -> &mut WASMFunction {
+    f.raw(iter::once(0x12).chain(encoders::u32(function_index)))
+}
+
+/// Writes a `return_call_indirect` instruction from the tail call proposal to `f`, calling the
+/// function of type `ty` at the index on the top of the stack in `table`. See [`return_call`].
+pub(super) fn return_call_indirect(f: &mut WASMFunction, ty: u32, table: u32) -> &mut WASMFunction {
+    let immediates = encoders::u32(ty).chain(encoders::u32(table));
+    f.raw(iter::once(0x13).chain(immediates))
+}
+
+/// Returns the contents of a [`SOURCE_FILES_SECTION`] recording the source file of each function
+/// index in `source_files`.
+fn encode_source_files(source_files: &[(u32, Arc<String>)]) -> Vec<u8> {
+    let mut data = vec![];
+    data.extend(encoders::u32(source_files.len() as u32));
+    for (function_index, source_file) in source_files {
+        data.extend(encoders::u32(*function_index));
+        data.extend(encoders::str(source_file));
+    }
+    data
+}
+
+/// Returns the contents of a `producers` custom section, identifying the toolchain that produced
+/// a module: `language=Java` and `processed-by=montera <version>`. See the [Tool Conventions].
+///
+/// [Tool Conventions]: https://github.com/WebAssembly/tool-conventions/blob/main/ProducersSection.md
+fn encode_producers() -> Vec<u8> {
+    let fields = [
+        ("language", "Java", ""),
+        ("processed-by", "montera", env!("CARGO_PKG_VERSION")),
+    ];
+    let mut data = vec![];
+    data.extend(encoders::u32(fields.len() as u32));
+    for (field_name, name, version) in fields {
+        data.extend(encoders::str(field_name));
+        // Each field has exactly one value
+        data.extend(encoders::u32(1));
+        data.extend(encoders::str(name));
+        data.extend(encoders::str(version));
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::output::types::{return_call, return_call_indirect};
+    use crate::output::Module;
+    use wasm_encoder::{
+        CodeSection, Function as WASMFunction, Instruction as WASMInstruction, Section,
+    };
+    use wasmparser::{CodeSectionReader, Operator};
+
+    /// Reads an unsigned LEB128 integer from the start of `bytes`, advancing past it.
+    fn read_u32(bytes: &mut &[u8]) -> u32 {
+        let mut result = 0;
+        let mut shift = 0;
+        loop {
+            let byte = bytes[0];
+            *bytes = &bytes[1..];
+            result |= ((byte & 0x7f) as u32) << shift;
+            if byte & 0x80 == 0 {
+                return result;
+            }
+            shift += 7;
+        }
+    }
+
+    /// Reads a length-prefixed UTF-8 string from the start of `bytes`, advancing past it.
+    fn read_str<'a>(bytes: &mut &'a [u8]) -> &'a str {
+        let len = read_u32(bytes) as usize;
+        let s = std::str::from_utf8(&bytes[..len]).unwrap();
+        *bytes = &bytes[len..];
+        s
+    }
+
+    /// Returns the contents of the custom section named `name` in the module `bytes`, if any.
+    fn find_custom_section<'a>(mut bytes: &'a [u8], name: &str) -> Option<&'a [u8]> {
+        bytes = &bytes[8..]; // Skip magic and version
+        while !bytes.is_empty() {
+            let id = bytes[0];
+            bytes = &bytes[1..];
+            let size = read_u32(&mut bytes) as usize;
+            let (mut section, rest) = bytes.split_at(size);
+            bytes = rest;
+            if id == 0 && read_str(&mut section) == name {
+                return Some(section);
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn producers_section() {
+        let bytes = Module::new().finish();
+        let mut section = find_custom_section(&bytes, "producers").unwrap();
+
+        // Decode fields
+        let mut fields = vec![];
+        for _ in 0..read_u32(&mut section) {
+            let field_name = read_str(&mut section);
+            for _ in 0..read_u32(&mut section) {
+                let name = read_str(&mut section);
+                let version = read_str(&mut section);
+                fields.push((field_name, name, version));
+            }
+        }
+        assert!(section.is_empty());
+        assert_eq!(
+            fields,
+            [
+                ("language", "Java", ""),
+                ("processed-by", "montera", env!("CARGO_PKG_VERSION"))
+            ]
+        );
+    }
+
+    #[test]
+    fn producers_section_disabled() {
+        let mut module = Module::new();
+        module.emit_producers = false;
+        let bytes = module.finish();
+        assert!(find_custom_section(&bytes, "producers").is_none());
+        // Check other custom sections still included
+        assert!(find_custom_section(&bytes, "name").is_some());
+    }
+
+    #[test]
+    fn return_call_encoding() -> anyhow::Result<()> {
+        // Use indices needing multi-byte LEB128 encodings
+        let mut f = WASMFunction::new(vec![]);
+        return_call(&mut f, 300);
+        return_call_indirect(&mut f, 200, 0);
+        f.instruction(&WASMInstruction::End);
+        let mut codes = CodeSection::new();
+        codes.function(&f);
+        let mut bytes = vec![];
+        codes.encode(&mut bytes);
+
+        // Check the hand-encoded instructions decode to the tail call proposal's operators
+        let mut section = &bytes[..];
+        read_u32(&mut section); // Skip section size
+        let body = CodeSectionReader::new(section, 0)?.read()?;
+        let operators =
+            (body.get_operators_reader()?.into_iter()).collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(operators.len(), 3);
+        assert!(matches!(
+            operators[0],
+            Operator::ReturnCall {
+                function_index: 300
+            }
+        ));
+        assert!(matches!(
+            operators[1],
+            Operator::ReturnCallIndirect {
+                index: 200,
+                table_index: 0
+            }
+        ));
+        assert!(matches!(operators[2], Operator::End));
+
+        Ok(())
+    }
+}
@@ -18,14 +18,20 @@ pub trait Scheduler {
 ///
 /// Uses a MPSC channel to send jobs to workers. Access to the receiving side is mediated using a
 /// mutual-exclusion lock.
-#[cfg(feature = "parallel_scheduler")]
+#[cfg(all(
+    feature = "parallel_scheduler",
+    any(not(feature = "rayon_scheduler"), test)
+))]
 pub struct WorkerScheduler {
     sender: Sender<Box<dyn Job>>,
     receiver: Arc<Mutex<Receiver<Box<dyn Job>>>>,
     handles: Vec<JoinHandle<()>>,
 }
 
-#[cfg(feature = "parallel_scheduler")]
+#[cfg(all(
+    feature = "parallel_scheduler",
+    any(not(feature = "rayon_scheduler"), test)
+))]
 impl WorkerScheduler {
     /// Constructs a new scheduler using `workers` worker threads.
     pub fn new(workers: usize) -> Self {
@@ -75,6 +81,10 @@ impl WorkerScheduler {
     }
 }
 
+#[cfg(all(
+    feature = "parallel_scheduler",
+    any(not(feature = "rayon_scheduler"), test)
+))]
 impl Scheduler for WorkerScheduler {
     fn schedule(&self, job: Box<dyn Job>) {
         // Send the job on the channel to any receiving worker thread
@@ -82,20 +92,76 @@ impl Scheduler for WorkerScheduler {
     }
 }
 
+/// Schedules jobs on rayon's global thread pool, executing jobs in parallel.
+///
+/// Unlike [`WorkerScheduler`], this doesn't spawn any threads of its own, so montera can share a
+/// thread pool with an embedder already using rayon.
+#[cfg(feature = "rayon_scheduler")]
+pub struct RayonScheduler;
+
+#[cfg(feature = "rayon_scheduler")]
+impl Scheduler for RayonScheduler {
+    fn schedule(&self, job: Box<dyn Job>) {
+        // Jobs return results using channels, so we don't need to wait for them to complete
+        rayon::spawn(move || job.process());
+    }
+}
+
+// Permit scheduling with a scheduler selected at runtime (e.g. `Box<dyn Scheduler>`)
+impl<S: Scheduler + ?Sized> Scheduler for Box<S> {
+    fn schedule(&self, job: Box<dyn Job>) {
+        (**self).schedule(job)
+    }
+}
+
 /// Schedules jobs immediately on the current thread, executing jobs in serial.
-#[cfg(any(not(feature = "parallel_scheduler"), test))]
 pub struct SerialScheduler;
 
-#[cfg(any(not(feature = "parallel_scheduler"), test))]
 impl Scheduler for SerialScheduler {
     fn schedule(&self, job: Box<dyn Job>) {
         job.process();
     }
 }
 
+/// Minimum number of functions for [`SchedulerKind::Auto`] to compile them in parallel. For fewer,
+/// the cost of spinning up worker threads outweighs the benefit of compiling in parallel.
+pub const AUTO_PARALLEL_MIN_FUNCTIONS: usize = 16;
+
+/// Controls which [`Scheduler`] jobs are scheduled with, set with `--scheduler`.
+///
+/// Output is byte-identical whichever is used: functions finish compiling in any order, but are
+/// sorted before rendering, and the virtual table orders classes by name. `Serial` can be used to
+/// reproduce a module built with `Parallel` without spinning up any threads.
+#[derive(clap::ArgEnum, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SchedulerKind {
+    /// Use `Serial` for small inputs (see [`AUTO_PARALLEL_MIN_FUNCTIONS`]), `Parallel` otherwise
+    Auto,
+    /// Execute jobs on the current thread ([`SerialScheduler`])
+    Serial,
+    /// Execute jobs on worker threads, using the scheduler selected by the enabled features.
+    /// Falls back to `Serial` if no parallel scheduler features are enabled.
+    Parallel,
+}
+
+impl SchedulerKind {
+    /// Resolves `Auto` to either `Serial` or `Parallel` for compiling `function_count` functions.
+    /// Other kinds are returned unchanged.
+    pub fn resolve(self, function_count: usize) -> Self {
+        match self {
+            SchedulerKind::Auto if function_count < AUTO_PARALLEL_MIN_FUNCTIONS => {
+                SchedulerKind::Serial
+            }
+            SchedulerKind::Auto => SchedulerKind::Parallel,
+            kind => kind,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::scheduler::{Job, SerialScheduler, WorkerScheduler};
+    use crate::scheduler::{
+        Job, SchedulerKind, SerialScheduler, WorkerScheduler, AUTO_PARALLEL_MIN_FUNCTIONS,
+    };
     use crate::Scheduler;
     use std::sync::mpsc::{channel, Sender};
     use std::thread::sleep;
@@ -146,4 +212,37 @@ mod tests {
     fn serial_scheduler() {
         test_scheduler(Box::new(SerialScheduler {}))
     }
+
+    #[test]
+    fn boxed_scheduler() {
+        let schd: Box<dyn Scheduler> = Box::new(SerialScheduler {});
+        test_scheduler(Box::new(schd))
+    }
+
+    #[test]
+    fn auto_scheduler_kind() {
+        // Check a single function is compiled serially...
+        assert_eq!(SchedulerKind::Auto.resolve(1), SchedulerKind::Serial);
+        let max_serial = AUTO_PARALLEL_MIN_FUNCTIONS - 1;
+        assert_eq!(
+            SchedulerKind::Auto.resolve(max_serial),
+            SchedulerKind::Serial
+        );
+        // ...but larger inputs are compiled in parallel
+        let min_parallel = AUTO_PARALLEL_MIN_FUNCTIONS;
+        assert_eq!(
+            SchedulerKind::Auto.resolve(min_parallel),
+            SchedulerKind::Parallel
+        );
+
+        // Check explicit kinds are always respected
+        assert_eq!(SchedulerKind::Serial.resolve(100), SchedulerKind::Serial);
+        assert_eq!(SchedulerKind::Parallel.resolve(1), SchedulerKind::Parallel);
+    }
+
+    #[cfg(feature = "rayon_scheduler")]
+    #[test]
+    fn rayon_scheduler() {
+        test_scheduler(Box::new(crate::scheduler::RayonScheduler {}))
+    }
 }
@@ -1,4 +1,11 @@
-use crate::tests::{construct_code_module, WASM_ENGINE};
+use crate::function::CompileOptions;
+use crate::function::Instruction::I;
+use crate::output::RenderOptions;
+use crate::tests::{
+    compile_code_functions_with_options, construct_code_module, construct_code_module_with_options,
+    WASM_ENGINE,
+};
+use wasm_encoder::{BlockType, Instruction as WASMInstruction, ValType};
 use wasmtime::{Linker, Module, Store};
 
 /// IF_ACMPEQ, IF_ACMPNE, IF_CMPEQ, IF_CMPNE, IF_CMPLT, IF_CMPLE, IF_CMPGT, IF_CMPGE
@@ -175,6 +182,63 @@ fn l_f_d_cmp() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// LCMP followed by IFEQ, IFNE, IFLT, IFLE, IFGT, IFGE, fused into i64 comparisons
+#[test]
+fn lcmp_if_fused() -> anyhow::Result<()> {
+    let module = construct_code_module(
+        "public static boolean lcmp_eq(long a, long b) { if (a == b) { return true; } else { return false; } }
+        public static boolean lcmp_ne(long a, long b) { if (a != b) { return true; } else { return false; } }
+        public static boolean lcmp_lt(long a, long b) { if (a < b) { return true; } else { return false; } }
+        public static boolean lcmp_le(long a, long b) { if (a <= b) { return true; } else { return false; } }
+        public static boolean lcmp_gt(long a, long b) { if (a > b) { return true; } else { return false; } }
+        public static boolean lcmp_ge(long a, long b) { if (a >= b) { return true; } else { return false; } }",
+    )?;
+    let bytes = module.finish();
+    // Check built-in comparison function never called, so wasn't included in the module
+    let wat = wasmprinter::print_bytes(&bytes)?;
+    assert!(!wat.contains("!LongCmp"));
+
+    let module = Module::new(&WASM_ENGINE, bytes)?;
+    let linker = Linker::new(&WASM_ENGINE);
+    let mut store = Store::new(&WASM_ENGINE, 0);
+    let instance = linker.instantiate(&mut store, &module)?;
+
+    let lcmp_eq = instance.get_typed_func::<(i64, i64), i32, _>(&mut store, "Test.lcmp_eq(JJ)Z")?;
+    let lcmp_ne = instance.get_typed_func::<(i64, i64), i32, _>(&mut store, "Test.lcmp_ne(JJ)Z")?;
+    let lcmp_lt = instance.get_typed_func::<(i64, i64), i32, _>(&mut store, "Test.lcmp_lt(JJ)Z")?;
+    let lcmp_le = instance.get_typed_func::<(i64, i64), i32, _>(&mut store, "Test.lcmp_le(JJ)Z")?;
+    let lcmp_gt = instance.get_typed_func::<(i64, i64), i32, _>(&mut store, "Test.lcmp_gt(JJ)Z")?;
+    let lcmp_ge = instance.get_typed_func::<(i64, i64), i32, _>(&mut store, "Test.lcmp_ge(JJ)Z")?;
+
+    // Use values that differ only in their upper 32 bits to check no truncation
+    let big = 1_i64 << 40;
+    let bigger = big + (1 << 32);
+
+    assert_eq!(lcmp_eq.call(&mut store, (big, big))?, 1);
+    assert_eq!(lcmp_eq.call(&mut store, (big, bigger))?, 0);
+
+    assert_eq!(lcmp_ne.call(&mut store, (big, big))?, 0);
+    assert_eq!(lcmp_ne.call(&mut store, (big, bigger))?, 1);
+
+    assert_eq!(lcmp_lt.call(&mut store, (bigger, big))?, 0);
+    assert_eq!(lcmp_lt.call(&mut store, (big, big))?, 0);
+    assert_eq!(lcmp_lt.call(&mut store, (-bigger, big))?, 1);
+
+    assert_eq!(lcmp_le.call(&mut store, (bigger, big))?, 0);
+    assert_eq!(lcmp_le.call(&mut store, (big, big))?, 1);
+    assert_eq!(lcmp_le.call(&mut store, (-bigger, big))?, 1);
+
+    assert_eq!(lcmp_gt.call(&mut store, (bigger, big))?, 1);
+    assert_eq!(lcmp_gt.call(&mut store, (big, big))?, 0);
+    assert_eq!(lcmp_gt.call(&mut store, (-bigger, big))?, 0);
+
+    assert_eq!(lcmp_ge.call(&mut store, (bigger, big))?, 1);
+    assert_eq!(lcmp_ge.call(&mut store, (big, big))?, 1);
+    assert_eq!(lcmp_ge.call(&mut store, (-bigger, big))?, 0);
+
+    Ok(())
+}
+
 #[test]
 fn if_nested() -> anyhow::Result<()> {
     let module = construct_code_module(
@@ -339,6 +403,113 @@ fn pre_tested_loop() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Pre-tested loops rotated with `--loop-rotate`
+#[test]
+fn pre_tested_loop_rotated() -> anyhow::Result<()> {
+    let code = "public static int copy_for(int a) {
+            int b = 0;
+            for (int i = 0; i < a; i++) {
+                b++;
+            }
+            return b;
+        }
+
+        public static int triangle(int a) {
+            int b = 0;
+            while (a > 0) {
+                int i = a;
+                while (i > 0) {
+                    b++;
+                    i--;
+                }
+                a--;
+            }
+            return b;
+        }";
+
+    // Count branches within the loop, executed on every iteration
+    let loop_branches = |loop_rotate| -> anyhow::Result<usize> {
+        let options = CompileOptions {
+            loop_rotate,
+            ..CompileOptions::default()
+        };
+        let (_, functions) = compile_code_functions_with_options(code, 1, options)?;
+        let copy_for = functions
+            .iter()
+            .find(|f| f.id.to_string() == "Test.copy_for(I)I")
+            .unwrap();
+        let code = copy_for.code.as_ref().unwrap();
+        let loop_start = code
+            .iter()
+            .position(|instruction| matches!(instruction, I(WASMInstruction::Loop(_))))
+            .unwrap();
+        let branches = code[loop_start..]
+            .iter()
+            .filter(|instruction| {
+                matches!(
+                    instruction,
+                    I(WASMInstruction::Br(_) | WASMInstruction::BrIf(_))
+                )
+            })
+            .count();
+        Ok(branches)
+    };
+    assert_eq!(loop_branches(false)?, 2);
+    assert_eq!(loop_branches(true)?, 1);
+
+    // Check rotated loops still run correctly, including when nested and never entered
+    let options = CompileOptions {
+        loop_rotate: true,
+        ..CompileOptions::default()
+    };
+    let module = construct_code_module_with_options(code, options, RenderOptions::default())?;
+    let module = Module::new(&WASM_ENGINE, module.finish())?;
+    let linker = Linker::new(&WASM_ENGINE);
+    let mut store = Store::new(&WASM_ENGINE, 0);
+    let instance = linker.instantiate(&mut store, &module)?;
+
+    let copy_for = instance.get_typed_func::<i32, i32, _>(&mut store, "Test.copy_for(I)I")?;
+    let triangle = instance.get_typed_func::<i32, i32, _>(&mut store, "Test.triangle(I)I")?;
+
+    assert_eq!(copy_for.call(&mut store, 0)?, 0);
+    assert_eq!(copy_for.call(&mut store, 5)?, 5);
+
+    assert_eq!(triangle.call(&mut store, 0)?, 0);
+    assert_eq!(triangle.call(&mut store, 4)?, 10);
+
+    Ok(())
+}
+
+/// Linear chains of blocks merged with `--coalesce-sequences`
+#[test]
+fn coalesced_sequences() -> anyhow::Result<()> {
+    let options = CompileOptions {
+        coalesce_sequences: true,
+        ..CompileOptions::default()
+    };
+    let module = construct_code_module_with_options(
+        "public static int sequences(int n) {
+            while (n <= 10) { n += 3; }
+            n *= 2;
+            while (n <= 50) { n += 5; }
+            return n;
+        }",
+        options,
+        RenderOptions::default(),
+    )?;
+    let module = Module::new(&WASM_ENGINE, module.finish())?;
+    let linker = Linker::new(&WASM_ENGINE);
+    let mut store = Store::new(&WASM_ENGINE, 0);
+    let instance = linker.instantiate(&mut store, &module)?;
+
+    let sequences = instance.get_typed_func::<i32, i32, _>(&mut store, "Test.sequences(I)I")?;
+    assert_eq!(sequences.call(&mut store, 0)?, 54); // 12 * 2 = 24, then 24 + 5 * 6
+    assert_eq!(sequences.call(&mut store, 11)?, 52); // 22, then 22 + 5 * 6
+    assert_eq!(sequences.call(&mut store, 30)?, 60);
+
+    Ok(())
+}
+
 #[test]
 fn post_tested_loop() -> anyhow::Result<()> {
     let module = construct_code_module(
@@ -381,3 +552,107 @@ fn post_tested_loop() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+/// Conditionals translated into selects with `--prefer-select`
+#[test]
+fn prefer_select() -> anyhow::Result<()> {
+    let code = "public static int max(int a, int b) { return a > b ? a : b; }
+        public static long pick(boolean c, long a, long b) { return c ? a : b; }
+        public static double sign(double x) { return x < 0 ? -1.0 : 1.0; }
+        public static int flag(boolean c) { return c ? 10 : -1; }";
+
+    // Check selects are only emitted if enabled
+    let selects = |prefer_select| -> anyhow::Result<usize> {
+        let options = CompileOptions {
+            prefer_select,
+            ..CompileOptions::default()
+        };
+        let (_, functions) = compile_code_functions_with_options(code, 1, options)?;
+        let selects = functions
+            .iter()
+            .flat_map(|f| f.code.iter().flatten())
+            .filter(|instruction| matches!(instruction, I(WASMInstruction::Select)))
+            .count();
+        Ok(selects)
+    };
+    assert_eq!(selects(false)?, 0);
+    assert_eq!(selects(true)?, 4);
+
+    // Check selects produce the correct values
+    let options = CompileOptions {
+        prefer_select: true,
+        ..CompileOptions::default()
+    };
+    let module = construct_code_module_with_options(code, options, RenderOptions::default())?;
+    let module = Module::new(&WASM_ENGINE, module.finish())?;
+    let linker = Linker::new(&WASM_ENGINE);
+    let mut store = Store::new(&WASM_ENGINE, 0);
+    let instance = linker.instantiate(&mut store, &module)?;
+
+    let max = instance.get_typed_func::<(i32, i32), i32, _>(&mut store, "Test.max(II)I")?;
+    let pick = instance.get_typed_func::<(i32, i64, i64), i64, _>(&mut store, "Test.pick(ZJJ)J")?;
+    let sign = instance.get_typed_func::<f64, f64, _>(&mut store, "Test.sign(D)D")?;
+    let flag = instance.get_typed_func::<i32, i32, _>(&mut store, "Test.flag(Z)I")?;
+
+    assert_eq!(max.call(&mut store, (3, 7))?, 7);
+    assert_eq!(max.call(&mut store, (7, 3))?, 7);
+    assert_eq!(max.call(&mut store, (-2, -2))?, -2);
+
+    assert_eq!(pick.call(&mut store, (1, 1 << 40, 2))?, 1 << 40);
+    assert_eq!(pick.call(&mut store, (0, 1 << 40, 2))?, 2);
+
+    assert_eq!(sign.call(&mut store, -5.0)?, -1.0);
+    assert_eq!(sign.call(&mut store, 5.0)?, 1.0);
+
+    assert_eq!(flag.call(&mut store, 1)?, 10);
+    assert_eq!(flag.call(&mut store, 0)?, -1);
+
+    Ok(())
+}
+
+/// Conditionals producing values (e.g. `c ? a : b`), translated into `if`s with result types
+#[test]
+fn value_conditionals() -> anyhow::Result<()> {
+    let code = "public static int pick(boolean c) { return c ? 1 : 2; }
+        public static int square(int x) { return x * x; }
+        public static int absSquare(int x) { return x > 0 ? square(x) : -x; }
+        public static long widen(boolean c, int a, long b) { return c ? a : b; }";
+
+    // Check conditionals whose branches push a value produce values without selects
+    let (_, functions) = compile_code_functions_with_options(code, 1, CompileOptions::default())?;
+    let value_ifs = functions
+        .iter()
+        .flat_map(|f| f.code.iter().flatten())
+        .filter(|instruction| {
+            matches!(
+                instruction,
+                I(WASMInstruction::If(BlockType::Result(
+                    ValType::I32 | ValType::I64
+                )))
+            )
+        })
+        .count();
+    assert_eq!(value_ifs, 3);
+
+    let module = construct_code_module(code)?;
+    let module = Module::new(&WASM_ENGINE, module.finish())?;
+    let linker = Linker::new(&WASM_ENGINE);
+    let mut store = Store::new(&WASM_ENGINE, 0);
+    let instance = linker.instantiate(&mut store, &module)?;
+
+    let pick = instance.get_typed_func::<i32, i32, _>(&mut store, "Test.pick(Z)I")?;
+    let abs_square = instance.get_typed_func::<i32, i32, _>(&mut store, "Test.absSquare(I)I")?;
+    let widen =
+        instance.get_typed_func::<(i32, i32, i64), i64, _>(&mut store, "Test.widen(ZIJ)J")?;
+
+    assert_eq!(pick.call(&mut store, 1)?, 1);
+    assert_eq!(pick.call(&mut store, 0)?, 2);
+
+    assert_eq!(abs_square.call(&mut store, 3)?, 9);
+    assert_eq!(abs_square.call(&mut store, -3)?, 3);
+
+    assert_eq!(widen.call(&mut store, (1, -5, 1 << 40))?, -5);
+    assert_eq!(widen.call(&mut store, (0, -5, 1 << 40))?, 1 << 40);
+
+    Ok(())
+}
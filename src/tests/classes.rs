@@ -1,5 +1,16 @@
-use crate::tests::{construct_code_module, WASM_ENGINE};
-use wasmtime::{Linker, Module, Store};
+use crate::function::Instruction::{self, I};
+use crate::function::{CompileOptions, CompiledFunction};
+use crate::output::{AbstractBehaviour, RenderOptions, Renderer};
+use crate::tests::{
+    compile_code_functions, compile_code_functions_with_options, construct_code_module,
+    construct_code_module_with_options, next_allocation_address, str_arc, validate_functions,
+    validate_functions_with_features, TargetFeatures, WASM_ENGINE,
+};
+use crate::{construct_virtual_table, render_module, Module as OutputModule};
+use std::convert::TryInto;
+use std::sync::Arc;
+use wasm_encoder::Instruction as WASMInstruction;
+use wasmtime::{Config, Engine, Linker, Module, Store, TrapCode};
 
 /// NEW <class>, INSTANCEOF <class>
 #[allow(non_snake_case)]
@@ -43,6 +54,109 @@ fn new_instanceof() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// INSTANCEOF <class> walking a 3-level hierarchy with super ID functions
+#[test]
+fn instanceof_ancestors() -> anyhow::Result<()> {
+    let (classes, functions) = compile_code_functions(
+        "static class A { int a() { return 1; } }
+        static class B extends A { int b() { return 2; } }
+        static class C extends B { int c() { return 3; } }
+
+        public static Object make(int kind) {
+            if (kind == 0) { return new A(); }
+            if (kind == 1) { return new B(); }
+            return new C();
+        }
+        // Note if's returning values aren't yet supported, hence the explicit returns.
+        public static boolean is_a(Object p) { if (p instanceof A) { return true; } return false; }
+        public static boolean is_b(Object p) { if (p instanceof B) { return true; } return false; }
+        public static boolean is_c(Object p) { if (p instanceof C) { return true; } return false; }",
+        1,
+    )?;
+    let classes = Arc::new(classes);
+    let virtual_table = construct_virtual_table(None, &classes)?;
+
+    // Check each class's super ID function is its first table element, before its methods
+    for class_name in ["Test$A", "Test$B", "Test$C"] {
+        let class_name = str_arc(class_name);
+        let offset = virtual_table.super_id_function_offset(&class_name)?;
+        assert_eq!(offset, virtual_table.get_virtual_class_id(&class_name)?);
+    }
+    let a_id = virtual_table.get_virtual_class_id(&str_arc("Test$A"))?;
+    let b_id = virtual_table.get_virtual_class_id(&str_arc("Test$B"))?;
+    let c_id = virtual_table.get_virtual_class_id(&str_arc("Test$C"))?;
+    assert!(a_id < b_id && b_id < c_id);
+
+    let module = render_module(classes, virtual_table, functions, RenderOptions::default())?;
+    let module = Module::new(&WASM_ENGINE, module.finish())?;
+    let linker = Linker::new(&WASM_ENGINE);
+    let mut store = Store::new(&WASM_ENGINE, 0);
+    let instance = linker.instantiate(&mut store, &module)?;
+
+    let make =
+        instance.get_typed_func::<i32, i32, _>(&mut store, "Test.make(I)Ljava/lang/Object;")?;
+    let is_a =
+        instance.get_typed_func::<i32, i32, _>(&mut store, "Test.is_a(Ljava/lang/Object;)Z")?;
+    let is_b =
+        instance.get_typed_func::<i32, i32, _>(&mut store, "Test.is_b(Ljava/lang/Object;)Z")?;
+    let is_c =
+        instance.get_typed_func::<i32, i32, _>(&mut store, "Test.is_c(Ljava/lang/Object;)Z")?;
+
+    // Check instances are instances of every ancestor, walking up through each super ID function
+    let a = make.call(&mut store, 0)?;
+    let b = make.call(&mut store, 1)?;
+    let c = make.call(&mut store, 2)?;
+    assert_eq!(is_a.call(&mut store, c)?, 1);
+    assert_eq!(is_b.call(&mut store, c)?, 1);
+    assert_eq!(is_c.call(&mut store, c)?, 1);
+    assert_eq!(is_a.call(&mut store, b)?, 1);
+    assert_eq!(is_b.call(&mut store, b)?, 1);
+    assert_eq!(is_a.call(&mut store, a)?, 1);
+
+    // Check instances aren't instances of their descendants
+    assert_eq!(is_c.call(&mut store, b)?, 0);
+    assert_eq!(is_b.call(&mut store, a)?, 0);
+    assert_eq!(is_c.call(&mut store, a)?, 0);
+
+    Ok(())
+}
+
+/// NEW <class> allocating at deterministic addresses
+#[test]
+fn new_allocation_layout() -> anyhow::Result<()> {
+    let mut module = construct_code_module(
+        "static class Large { long a; long b; } // 4 byte header + 4 padding + 16 bytes of fields
+        static class Small { int a; } // 4 byte header + 4 bytes of fields
+
+        // Object's hashCode() returns the instance's address
+        public static int new_large() { return new Large().hashCode(); }
+        public static int new_small() { return new Small().hashCode(); }",
+    )?;
+    module.emit_debug_globals = true;
+    let module = Module::new(&WASM_ENGINE, module.finish())?;
+    let linker = Linker::new(&WASM_ENGINE);
+    let mut store = Store::new(&WASM_ENGINE, 0);
+    let instance = linker.instantiate(&mut store, &module)?;
+
+    let new_large = instance.get_typed_func::<(), i32, _>(&mut store, "Test.new_large()I")?;
+    let new_small = instance.get_typed_func::<(), i32, _>(&mut store, "Test.new_small()I")?;
+
+    // Check the first object is allocated at the heap's base...
+    let heap_base = next_allocation_address(&mut store, &instance);
+    assert_eq!(heap_base, 8);
+    let large = new_large.call(&mut store, ())?;
+    assert_eq!(large, heap_base);
+    // ...and the next object immediately after it
+    let next = next_allocation_address(&mut store, &instance);
+    let small = new_small.call(&mut store, ())?;
+    assert_eq!(small, next);
+    assert_eq!(small - large, 4 + 4 + 16);
+    let next = next_allocation_address(&mut store, &instance);
+    assert_eq!(next - small, 4 + 4);
+
+    Ok(())
+}
+
 /// GETFIELD <field>, PUTFIELD <field>
 #[test]
 fn get_put_field() -> anyhow::Result<()> {
@@ -82,6 +196,397 @@ fn get_put_field() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// ICONST_1/ICONST_0, GETFIELD <field>, PUTFIELD <field> of `boolean` fields
+#[test]
+fn get_put_field_boolean() -> anyhow::Result<()> {
+    let code = "static class Flags { boolean a; boolean b; int c; }
+
+        // Object's hashCode() returns the instance's address
+        public static int store_constants() {
+            Flags f = new Flags();
+            f.a = true;
+            f.b = false;
+            f.c = -1;
+            return f.hashCode();
+        }
+        public static boolean load_true() { Flags f = new Flags(); f.a = true; return f.a; }
+        public static boolean load_false() { Flags f = new Flags(); f.b = true; f.b = false; return f.b; }";
+
+    // Check boolean constants are stored as the integer constants 1 and 0, without conversion
+    let (_, functions) = compile_code_functions(code, 1)?;
+    let store_constants = functions
+        .iter()
+        .find(|f| f.id.to_string() == "Test.store_constants()I")
+        .unwrap();
+    let stored_constants = (store_constants.code.as_ref().unwrap().windows(2))
+        .filter_map(|window| match window {
+            [I(WASMInstruction::I32Const(value)), Instruction::PutField(id)] => {
+                Some((id.name.to_string(), *value))
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        stored_constants,
+        [
+            (String::from("a"), 1),
+            (String::from("b"), 0),
+            (String::from("c"), -1)
+        ]
+    );
+
+    let module = construct_code_module(code)?;
+    let module = Module::new(&WASM_ENGINE, module.finish())?;
+    let linker = Linker::new(&WASM_ENGINE);
+    let mut store = Store::new(&WASM_ENGINE, 0);
+    let instance = linker.instantiate(&mut store, &module)?;
+
+    let store_constants =
+        instance.get_typed_func::<(), i32, _>(&mut store, "Test.store_constants()I")?;
+    let load_true = instance.get_typed_func::<(), i32, _>(&mut store, "Test.load_true()Z")?;
+    let load_false = instance.get_typed_func::<(), i32, _>(&mut store, "Test.load_false()Z")?;
+    let memory = instance.get_memory(&mut store, "memory").unwrap();
+
+    // Check booleans read back as exactly 1 and 0
+    assert_eq!(load_true.call(&mut store, ())?, 1);
+    assert_eq!(load_false.call(&mut store, ())?, 0);
+
+    // Check each boolean occupies a single byte after the 4 byte header, holding exactly 1 or 0,
+    // and `c` is padded to the next 4 byte boundary, without clobbering neighbouring fields
+    let p = store_constants.call(&mut store, ())? as usize;
+    let data = memory.data(&store);
+    let word =
+        |offset: usize| i32::from_le_bytes(data[p + offset..p + offset + 4].try_into().unwrap());
+    assert_eq!(data[p + 4], 1); // a
+    assert_eq!(data[p + 5], 0); // b
+    assert_eq!(data[p + 6..p + 8], [0, 0]); // padding
+    assert_eq!(word(8), -1); // c
+
+    Ok(())
+}
+
+/// GETFIELD <field>, PUTFIELD <field> of narrow `byte`, `short` and `char` fields
+#[test]
+fn get_put_field_narrow() -> anyhow::Result<()> {
+    let module = construct_code_module(
+        "byte b;
+        short s;
+        char c;
+        int i;
+
+        public static int store(int x) {
+            Test t = new Test();
+            t.b = (byte) x; t.s = (short) x; t.c = (char) x; t.i = x;
+            return t.hashCode();
+        }
+        public static byte copy_byte(byte x) { Test t = new Test(); t.b = x; return t.b; }
+        public static short copy_short(short x) { Test t = new Test(); t.s = x; return t.s; }
+        public static char copy_char(char x) { Test t = new Test(); t.c = x; return t.c; }",
+    )?;
+    let module = Module::new(&WASM_ENGINE, module.finish())?;
+    let linker = Linker::new(&WASM_ENGINE);
+    let mut store = Store::new(&WASM_ENGINE, 0);
+    let instance = linker.instantiate(&mut store, &module)?;
+
+    let store_fields = instance.get_typed_func::<i32, i32, _>(&mut store, "Test.store(I)I")?;
+    let copy_byte = instance.get_typed_func::<i32, i32, _>(&mut store, "Test.copy_byte(B)B")?;
+    let copy_short = instance.get_typed_func::<i32, i32, _>(&mut store, "Test.copy_short(S)S")?;
+    let copy_char = instance.get_typed_func::<i32, i32, _>(&mut store, "Test.copy_char(C)C")?;
+    let memory = instance.get_memory(&mut store, "memory").unwrap();
+
+    // Check `byte`s and `short`s are sign extended when loaded, and `char`s zero extended
+    assert_eq!(copy_byte.call(&mut store, 42)?, 42);
+    assert_eq!(copy_byte.call(&mut store, -1)?, -1);
+    assert_eq!(copy_short.call(&mut store, -1000)?, -1000);
+    assert_eq!(copy_char.call(&mut store, 0xFFFF)?, 0xFFFF);
+
+    // Check fields are packed at their natural alignment after the 4 byte header: `b` at 4, `s` at
+    // 6, `c` at 8 and `i` padded to 12, without clobbering each other
+    let p = store_fields.call(&mut store, 0x12345678)? as usize;
+    let data = memory.data(&store);
+    assert_eq!(
+        data[p + 4..p + 16],
+        [0x78, 0, 0x78, 0x56, 0x78, 0x56, 0, 0, 0x78, 0x56, 0x34, 0x12]
+    );
+
+    Ok(())
+}
+
+/// GETFIELD <field>, PUTFIELD <field> with --null-checks
+#[test]
+fn get_put_field_null_checks() -> anyhow::Result<()> {
+    let render_options = RenderOptions {
+        null_checks: true,
+        ..RenderOptions::default()
+    };
+    let module = construct_code_module_with_options(
+        "int i;
+        long l;
+
+        public static int get_int(Test t) { return t.i; }
+        public static void put_int(Test t, int i) { t.i = i; }
+        public static void put_long(Test t, long l) { t.l = l; }
+        public static long copy_long(long l) { Test t = new Test(); t.l = l; return t.l; }",
+        CompileOptions::default(),
+        render_options,
+    )?;
+    let module = Module::new(&WASM_ENGINE, module.finish())?;
+    let linker = Linker::new(&WASM_ENGINE);
+    let mut store = Store::new(&WASM_ENGINE, 0);
+    let instance = linker.instantiate(&mut store, &module)?;
+
+    let get_int = instance.get_typed_func::<i32, i32, _>(&mut store, "Test.get_int(LTest;)I")?;
+    let put_int =
+        instance.get_typed_func::<(i32, i32), (), _>(&mut store, "Test.put_int(LTest;I)V")?;
+    let put_long =
+        instance.get_typed_func::<(i32, i64), (), _>(&mut store, "Test.put_long(LTest;J)V")?;
+    let copy_long = instance.get_typed_func::<i64, i64, _>(&mut store, "Test.copy_long(J)J")?;
+
+    // Check non-null accesses still work
+    assert_eq!(copy_long.call(&mut store, 42)?, 42);
+
+    // Check null accesses trap
+    let res = get_int.call(&mut store, 0).unwrap_err();
+    assert_eq!(res.trap_code(), Some(TrapCode::UnreachableCodeReached));
+    let res = put_int.call(&mut store, (0, 1)).unwrap_err();
+    assert_eq!(res.trap_code(), Some(TrapCode::UnreachableCodeReached));
+    let res = put_long.call(&mut store, (0, 1)).unwrap_err();
+    assert_eq!(res.trap_code(), Some(TrapCode::UnreachableCodeReached));
+
+    Ok(())
+}
+
+/// GETFIELD <field>, PUTFIELD <field> (8 byte fields following 4 byte fields)
+#[test]
+fn get_put_field_wide_after_narrow() -> anyhow::Result<()> {
+    let module = construct_code_module(
+        "static class D { int a; double b; }
+
+        int a;
+        long b;
+
+        public static Test make(int a, long b) { Test t = new Test(); t.a = a; t.b = b; return t; }
+        public static long get_b(Test t) { return t.b; }
+        public static D make_d(int a, double b) { D d = new D(); d.a = a; d.b = b; return d; }
+        public static double get_d_b(D d) { return d.b; }",
+    )?;
+    let wasm_bytes = module.finish();
+
+    // Check 8 byte fields are accessed with naturally aligned (`align: 3`) memory arguments, at
+    // offset 8: the 4 byte header and 4 byte `a` field place them on an 8 byte boundary, as the
+    // heap starts 8 byte aligned. Note wasmprinter omits `align=` for natural alignments.
+    let wat = wasmprinter::print_bytes(&wasm_bytes)?;
+    assert!(wat.contains("i64.store offset=8\n"));
+    assert!(wat.contains("i64.load offset=8\n"));
+    assert!(wat.contains("f64.store offset=8\n"));
+    assert!(wat.contains("f64.load offset=8\n"));
+    assert!(!wat.contains("align="));
+
+    let module = Module::new(&WASM_ENGINE, wasm_bytes)?;
+    let linker = Linker::new(&WASM_ENGINE);
+    let mut store = Store::new(&WASM_ENGINE, 0);
+    let instance = linker.instantiate(&mut store, &module)?;
+
+    let make = instance.get_typed_func::<(i32, i64), i32, _>(&mut store, "Test.make(IJ)LTest;")?;
+    let get_b = instance.get_typed_func::<i32, i64, _>(&mut store, "Test.get_b(LTest;)J")?;
+    let make_d =
+        instance.get_typed_func::<(i32, f64), i32, _>(&mut store, "Test.make_d(ID)LTest$D;")?;
+    let get_d_b = instance.get_typed_func::<i32, f64, _>(&mut store, "Test.get_d_b(LTest$D;)D")?;
+    let memory = instance.get_memory(&mut store, "memory").unwrap();
+
+    // Use values with distinct bytes in both halves, so truncated or shifted accesses are detected
+    let b = 0x0123_4567_89AB_CDEF_i64;
+    let d_b = -1234.5678e123;
+    let t = make.call(&mut store, (-1, b))?;
+    let d = make_d.call(&mut store, (-1, d_b))?;
+    assert_eq!(get_b.call(&mut store, t)?, b);
+    assert_eq!(get_d_b.call(&mut store, d)?, d_b);
+
+    // Check each instance is 8 byte aligned, so the fields are too, and the full 8 bytes are
+    // written after `a` without clobbering it
+    let data = memory.data(&store);
+    for (p, bytes) in [(t, b.to_le_bytes()), (d, d_b.to_le_bytes())] {
+        let p = p as usize;
+        assert_eq!(p % 8, 0);
+        assert_eq!(data[p + 4..p + 8], (-1_i32).to_le_bytes());
+        assert_eq!(data[p + 8..p + 16], bytes);
+    }
+
+    Ok(())
+}
+
+/// GETFIELD <field>, PUTFIELD <field> (8 byte fields padded to 8 byte boundaries)
+#[test]
+fn get_put_field_wide_padded() -> anyhow::Result<()> {
+    let module = construct_code_module(
+        "static class P { int a; int c; }
+        static class C extends P { double b; }
+
+        int a;
+        int c;
+        long b;
+
+        public static Test make(int a, int c, long b) {
+            Test t = new Test(); t.a = a; t.c = c; t.b = b; return t;
+        }
+        public static long get_b(Test t) { return t.b; }
+        public static C make_c(int a, int c, double b) {
+            C o = new C(); o.a = a; o.c = c; o.b = b; return o;
+        }
+        public static double get_c_b(C o) { return o.b; }",
+    )?;
+    let wasm_bytes = module.finish();
+
+    // Check 8 byte fields are padded to offset 16, rather than directly following the 4 byte
+    // header and two 4 byte fields at offset 12, in both the same class and a subclass
+    let wat = wasmprinter::print_bytes(&wasm_bytes)?;
+    assert!(wat.contains("i64.store offset=16\n"));
+    assert!(wat.contains("i64.load offset=16\n"));
+    assert!(wat.contains("f64.store offset=16\n"));
+    assert!(wat.contains("f64.load offset=16\n"));
+    assert!(!wat.contains("offset=12"));
+
+    let module = Module::new(&WASM_ENGINE, wasm_bytes)?;
+    let linker = Linker::new(&WASM_ENGINE);
+    let mut store = Store::new(&WASM_ENGINE, 0);
+    let instance = linker.instantiate(&mut store, &module)?;
+
+    let make =
+        instance.get_typed_func::<(i32, i32, i64), i32, _>(&mut store, "Test.make(IIJ)LTest;")?;
+    let get_b = instance.get_typed_func::<i32, i64, _>(&mut store, "Test.get_b(LTest;)J")?;
+    let make_c = instance
+        .get_typed_func::<(i32, i32, f64), i32, _>(&mut store, "Test.make_c(IID)LTest$C;")?;
+    let get_c_b = instance.get_typed_func::<i32, f64, _>(&mut store, "Test.get_c_b(LTest$C;)D")?;
+    let memory = instance.get_memory(&mut store, "memory").unwrap();
+
+    let b = 0x0123_4567_89AB_CDEF_i64;
+    let c_b = -1234.5678e123;
+    let t = make.call(&mut store, (-1, -2, b))?;
+    let o = make_c.call(&mut store, (-1, -2, c_b))?;
+    assert_eq!(get_b.call(&mut store, t)?, b);
+    assert_eq!(get_c_b.call(&mut store, o)?, c_b);
+
+    // Check the padding is left untouched, and both instances (sized 24 bytes) are 8 byte aligned
+    let data = memory.data(&store);
+    assert_eq!(o - t, 4 + 4 + 4 + 4 + 8);
+    for (p, bytes) in [(t, b.to_le_bytes()), (o, c_b.to_le_bytes())] {
+        let p = p as usize;
+        assert_eq!(p % 8, 0);
+        assert_eq!(data[p + 4..p + 8], (-1_i32).to_le_bytes());
+        assert_eq!(data[p + 8..p + 12], (-2_i32).to_le_bytes());
+        assert_eq!(data[p + 12..p + 16], [0; 4]);
+        assert_eq!(data[p + 16..p + 24], bytes);
+    }
+
+    Ok(())
+}
+
+/// NEW <class>, GETFIELD <field>, PUTFIELD <field> with --uniform-headers
+#[test]
+fn get_put_field_uniform_headers() -> anyhow::Result<()> {
+    let render_options = RenderOptions {
+        uniform_headers: true,
+        ..RenderOptions::default()
+    };
+    let module = construct_code_module_with_options(
+        "int i;
+        long l;
+
+        public static Test make(int i, long l) { Test t = new Test(); t.i = i; t.l = l; return t; }
+        public static long sum(int i, long l) { Test t = make(i, l); return t.i + t.l; }
+        public static boolean is_test(Object o) { if (o instanceof Test) { return true; } return false; }",
+        CompileOptions::default(),
+        render_options,
+    )?;
+    let module = Module::new(&WASM_ENGINE, module.finish())?;
+    let linker = Linker::new(&WASM_ENGINE);
+    let mut store = Store::new(&WASM_ENGINE, 0);
+    let instance = linker.instantiate(&mut store, &module)?;
+
+    let make = instance.get_typed_func::<(i32, i64), i32, _>(&mut store, "Test.make(IJ)LTest;")?;
+    let sum = instance.get_typed_func::<(i32, i64), i64, _>(&mut store, "Test.sum(IJ)J")?;
+    let is_test =
+        instance.get_typed_func::<i32, i32, _>(&mut store, "Test.is_test(Ljava/lang/Object;)Z")?;
+    let memory = instance.get_memory(&mut store, "memory").unwrap();
+
+    // Check existing field accesses still work
+    assert_eq!(sum.call(&mut store, (1, 2))?, 3);
+
+    // Check fields start after the 8 byte header, and the virtual class ID is still first
+    let p1 = make.call(&mut store, (42, -1))?;
+    let p2 = make.call(&mut store, (7, 5))?;
+    assert_eq!(p2 - p1, 8 + 4 + 4 + 8); // header + i + padding + l
+    let read_i32 = |data: &[u8], p: usize| i32::from_le_bytes(data[p..p + 4].try_into().unwrap());
+    let data = memory.data(&store);
+    let p1 = p1 as usize;
+    assert_eq!(read_i32(data, p1 + 4), 0); // Reserved length
+    assert_eq!(read_i32(data, p1 + 8), 42); // i
+    assert_eq!(
+        i64::from_le_bytes(data[p1 + 16..p1 + 24].try_into().unwrap()),
+        -1
+    ); // l, padded to an 8 byte boundary
+    assert_eq!(is_test.call(&mut store, p1 as i32)?, 1);
+
+    Ok(())
+}
+
+/// GETFIELD <field> with --cache-receivers
+#[test]
+fn get_field_cached_receiver() -> anyhow::Result<()> {
+    let (classes, functions) = compile_code_functions_with_options(
+        "static class Point {
+            int x; int y;
+            Point(int x, int y) { this.x = x; this.y = y; }
+        }
+
+        Point a;
+        Test(Point a) { this.a = a; }
+        int sum() { return a.x + a.y; }
+
+        public static int sum(int x, int y) { return new Test(new Point(x, y)).sum(); }",
+        1,
+        CompileOptions {
+            cache_receivers: true,
+            ..CompileOptions::default()
+        },
+    )?;
+
+    // Check `this.a` is only loaded once, with the second access using a scratch local
+    let sum = functions
+        .iter()
+        .find(|f| f.id.to_string() == "Test.sum()I")
+        .unwrap();
+    let code = sum.code.as_ref().unwrap();
+    let field_accesses = code
+        .iter()
+        .filter(|instruction| matches!(instruction, Instruction::GetField(_)))
+        .count();
+    assert_eq!(field_accesses, 3); // a, x, y
+    let this_loads = code
+        .iter()
+        .filter(|instruction| matches!(instruction, I(WASMInstruction::LocalGet(0))))
+        .count();
+    assert_eq!(this_loads, 1);
+    assert!(code
+        .iter()
+        .any(|instruction| matches!(instruction, I(WASMInstruction::LocalTee(1)))));
+
+    // Check result is still computed correctly
+    let classes = Arc::new(classes);
+    let virtual_table = construct_virtual_table(None, &classes)?;
+    let module = render_module(classes, virtual_table, functions, RenderOptions::default())?;
+    let module = Module::new(&WASM_ENGINE, module.finish())?;
+    let linker = Linker::new(&WASM_ENGINE);
+    let mut store = Store::new(&WASM_ENGINE, 0);
+    let instance = linker.instantiate(&mut store, &module)?;
+
+    let sum = instance.get_typed_func::<(i32, i32), i32, _>(&mut store, "Test.sum(II)I")?;
+    assert_eq!(sum.call(&mut store, (1, 2))?, 3);
+    assert_eq!(sum.call(&mut store, (-5, 12))?, 7);
+
+    Ok(())
+}
+
 /// GETFIELD <field>, PUTFIELD <field>
 #[test]
 fn get_put_field_hidden() -> anyhow::Result<()> {
@@ -110,6 +615,279 @@ fn get_put_field_hidden() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// GETFIELD <field>, PUTFIELD <field> of volatile fields with --threads
+#[test]
+fn get_put_field_volatile_threads() -> anyhow::Result<()> {
+    let code = "volatile int i;
+        volatile long l;
+        volatile double d;
+        volatile float f;
+        int plain;
+        volatile byte b;
+
+        public static long sum(int i, long l, float f, double d) {
+            Test t = new Test();
+            t.i = i; t.l = l; t.f = f; t.d = d; t.plain = i; t.b = (byte) i;
+            return t.i + t.l + (long) t.f + (long) t.d + t.plain + t.b;
+        }";
+    let render = |threads| -> anyhow::Result<Vec<u8>> {
+        let render_options = RenderOptions {
+            threads,
+            ..RenderOptions::default()
+        };
+        let module =
+            construct_code_module_with_options(code, CompileOptions::default(), render_options)?;
+        Ok(module.finish())
+    };
+
+    // Check volatile fields are accessed normally without threads...
+    let wasm = render(false)?;
+    let wat = wasmprinter::print_bytes(&wasm)?;
+    assert!(!wat.contains("atomic"));
+
+    // ...but with atomic loads and stores with them, reinterpreting floating point values and sign
+    // extending narrow signed values
+    let wasm = render(true)?;
+    let wat = wasmprinter::print_bytes(&wasm)?;
+    assert!(Module::new(&WASM_ENGINE, &wasm).is_err());
+    for instruction in [
+        "i32.atomic.load offset=4",
+        "i32.atomic.store offset=4",
+        "i64.atomic.load offset=8",
+        "i64.atomic.store offset=8",
+        "i64.reinterpret_f64\n    i64.atomic.store offset=16",
+        "i64.atomic.load offset=16\n    f64.reinterpret_i64",
+        "i32.reinterpret_f32\n    i32.atomic.store offset=24",
+        "i32.atomic.load offset=24\n    f32.reinterpret_i32",
+        "i32.atomic.store8 offset=32",
+        "i32.atomic.load8_u offset=32\n    i32.extend8_s",
+    ] {
+        assert!(wat.contains(instruction), "{}", instruction);
+    }
+    // Non-volatile fields should still be accessed normally
+    assert!(wat.contains("i32.load offset=28"));
+    assert!(wat.contains("i32.store offset=28"));
+
+    let mut config = Config::new();
+    config.wasm_threads(true);
+    let engine = Engine::new(&config)?;
+    let module = Module::new(&engine, wasm)?;
+    let linker = Linker::new(&engine);
+    let mut store = Store::new(&engine, 0);
+    let instance = linker.instantiate(&mut store, &module)?;
+
+    let sum =
+        instance.get_typed_func::<(i32, i64, f32, f64), i64, _>(&mut store, "Test.sum(IJFD)J")?;
+    assert_eq!(
+        sum.call(&mut store, (1, 2, 3.5, 4.5))?,
+        1 + 2 + 3 + 4 + 1 + 1
+    );
+    assert_eq!(
+        sum.call(&mut store, (-1, 2, 3.5, 4.5))?,
+        -1 + 2 + 3 + 4 - 1 - 1
+    );
+
+    Ok(())
+}
+
+/// INVOKESPECIAL <init> chained through multiple superclass constructors
+#[test]
+fn constructor_chaining() -> anyhow::Result<()> {
+    let module = construct_code_module(
+        "static class A {
+            int a;
+            A(int a) { this.a = a; } // Implicit Object super(), dropped
+        }
+        static class B extends A {
+            int b;
+            B(int a, int b) { super(a); this.b = b; }
+        }
+        static class C extends B {
+            int c;
+            C(int a, int b, int c) { super(a, b); this.c = c; }
+            C(int n) { this(n, n * 2, n * 3); }
+        }
+
+        public static int get_a(int a, int b, int c) { return new C(a, b, c).a; }
+        public static int get_b(int a, int b, int c) { return new C(a, b, c).b; }
+        public static int get_c(int a, int b, int c) { return new C(a, b, c).c; }
+        public static int sum_delegated(int n) { C c = new C(n); return c.a + c.b + c.c; }",
+    )?;
+    let module = Module::new(&WASM_ENGINE, module.finish())?;
+    let linker = Linker::new(&WASM_ENGINE);
+    let mut store = Store::new(&WASM_ENGINE, 0);
+    let instance = linker.instantiate(&mut store, &module)?;
+
+    let get_a =
+        instance.get_typed_func::<(i32, i32, i32), i32, _>(&mut store, "Test.get_a(III)I")?;
+    let get_b =
+        instance.get_typed_func::<(i32, i32, i32), i32, _>(&mut store, "Test.get_b(III)I")?;
+    let get_c =
+        instance.get_typed_func::<(i32, i32, i32), i32, _>(&mut store, "Test.get_c(III)I")?;
+    let sum_delegated =
+        instance.get_typed_func::<i32, i32, _>(&mut store, "Test.sum_delegated(I)I")?;
+
+    // Check fields set by each constructor in the chain are initialised
+    assert_eq!(get_a.call(&mut store, (1, 2, 3))?, 1);
+    assert_eq!(get_b.call(&mut store, (1, 2, 3))?, 2);
+    assert_eq!(get_c.call(&mut store, (1, 2, 3))?, 3);
+    assert_eq!(sum_delegated.call(&mut store, 5)?, 30);
+
+    Ok(())
+}
+
+/// NEW <class>, DUP, INVOKESPECIAL <init> of default constructors only calling Object.<init>
+#[test]
+fn new_default_constructor() -> anyhow::Result<()> {
+    let module = construct_code_module(
+        "static class Sub extends Test {
+            int m() { return 2; } // Default constructor only calls Test.<init>
+        }
+
+        int m() { return 1; } // Default constructor only calls Object.<init>, dropped
+
+        public static Test make() { return new Test(); }
+        public static int call() { return new Test().m(); }
+        public static int call_stored() { Test t = new Test(); return t.m(); }
+        public static int call_sub() { Test t = new Sub(); return t.m(); }",
+    )?;
+    let module = Module::new(&WASM_ENGINE, module.finish())?;
+    let linker = Linker::new(&WASM_ENGINE);
+    let mut store = Store::new(&WASM_ENGINE, 0);
+    let instance = linker.instantiate(&mut store, &module)?;
+
+    let make = instance.get_typed_func::<(), i32, _>(&mut store, "Test.make()LTest;")?;
+    let call = instance.get_typed_func::<(), i32, _>(&mut store, "Test.call()I")?;
+    let call_stored = instance.get_typed_func::<(), i32, _>(&mut store, "Test.call_stored()I")?;
+    let call_sub = instance.get_typed_func::<(), i32, _>(&mut store, "Test.call_sub()I")?;
+
+    // Check the duplicated reference survives the constructor call, and each is a new instance
+    let first = make.call(&mut store, ())?;
+    let second = make.call(&mut store, ())?;
+    assert_ne!(first, 0);
+    assert_ne!(second, 0);
+    assert_ne!(first, second);
+
+    // Check methods can be dispatched on the constructed instances
+    assert_eq!(call.call(&mut store, ())?, 1);
+    assert_eq!(call_stored.call(&mut store, ())?, 1);
+    assert_eq!(call_sub.call(&mut store, ())?, 2);
+
+    Ok(())
+}
+
+/// NEW <class> of a class that wasn't loaded, failing to render with a descriptive error
+#[test]
+fn new_unloaded_class() -> anyhow::Result<()> {
+    let (mut classes, mut functions) = compile_code_functions(
+        "static class A {}
+
+        public static Object make() { return new A(); }",
+        1,
+    )?;
+    // Drop `A` as if it were never included in the input
+    classes.remove(&str_arc("Test$A"));
+    functions.retain(|function| function.id.class_name.as_str() != "Test$A");
+    let classes = Arc::new(classes);
+    let virtual_table = construct_virtual_table(None, &classes)?;
+
+    let result = render_module(classes, virtual_table, functions, RenderOptions::default());
+    let err = match result {
+        Ok(_) => panic!("Expected rendering to fail"),
+        Err(err) => err,
+    };
+    let message = format!("{:#}", err);
+    assert!(
+        message.contains("Unable to render Test.make()"),
+        "{}",
+        message
+    );
+    assert!(
+        message.contains("Unknown class Test$A; did you forget to include it?"),
+        "{}",
+        message
+    );
+
+    Ok(())
+}
+
+/// GETFIELD <field> of a field inherited from a superclass that wasn't loaded, failing to render
+/// with a descriptive error
+#[test]
+fn get_field_unloaded_superclass() -> anyhow::Result<()> {
+    let (classes, mut functions) = compile_code_functions(
+        "static class A { int x; }
+        static class B extends A { int y; }
+
+        public static int get(B b) { return b.x; }",
+        1,
+    )?;
+    // Construct the virtual table with all classes, then drop `A` as if it were never included in
+    // the classes being rendered
+    let classes = Arc::new(classes);
+    let virtual_table = construct_virtual_table(None, &classes)?;
+    let mut classes = (*classes).clone();
+    classes.remove(&str_arc("Test$A"));
+    functions.retain(|function| function.id.class_name.as_str() != "Test$A");
+
+    let result = render_module(
+        Arc::new(classes),
+        virtual_table,
+        functions,
+        RenderOptions::default(),
+    );
+    let err = match result {
+        Ok(_) => panic!("Expected rendering to fail"),
+        Err(err) => err,
+    };
+    let message = format!("{:#}", err);
+    assert!(
+        message.contains("Unable to render Test.get(LTest$B;)I"),
+        "{}",
+        message
+    );
+    assert!(
+        message.contains(
+            "Unknown class Test$A while resolving field Test$B.xI; did you forget to include it?"
+        ),
+        "{}",
+        message
+    );
+
+    Ok(())
+}
+
+/// INVOKESPECIAL <method> calling superclass implementations
+#[test]
+fn invoke_special_super() -> anyhow::Result<()> {
+    let module = construct_code_module(
+        "static class A { int m(int n) { return n + 1; } }
+        static class B extends A { int m(int n) { return super.m(n) * 10; } }
+        static class C extends B {}
+        static class D extends C { int m(int n) { return super.m(n) + 100; } } // C inherits B.m
+
+        public static int call_a(int n) { return new A().m(n); }
+        public static int call_b(int n) { return new B().m(n); }
+        public static int call_d(int n) { return new D().m(n); }",
+    )?;
+    let module = Module::new(&WASM_ENGINE, module.finish())?;
+    let linker = Linker::new(&WASM_ENGINE);
+    let mut store = Store::new(&WASM_ENGINE, 0);
+    let instance = linker.instantiate(&mut store, &module)?;
+
+    let call_a = instance.get_typed_func::<i32, i32, _>(&mut store, "Test.call_a(I)I")?;
+    let call_b = instance.get_typed_func::<i32, i32, _>(&mut store, "Test.call_b(I)I")?;
+    let call_d = instance.get_typed_func::<i32, i32, _>(&mut store, "Test.call_d(I)I")?;
+
+    // Check super calls run the superclass's implementation, not the overriding one
+    assert_eq!(call_a.call(&mut store, 2)?, 3);
+    assert_eq!(call_b.call(&mut store, 2)?, 30);
+    // Check super calls to a superclass inheriting the method run the inherited implementation
+    assert_eq!(call_d.call(&mut store, 2)?, 130);
+
+    Ok(())
+}
+
 /// INVOKESTATIC <method>
 #[test]
 fn invoke_static() -> anyhow::Result<()> {
@@ -137,6 +915,115 @@ fn invoke_static() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// INVOKESTATIC <method> of static methods inherited from, or hidden by, subclasses
+#[test]
+fn invoke_static_inherited() -> anyhow::Result<()> {
+    let module = construct_code_module(
+        "static class A {
+            static int f() { return 1; }
+            static int g(int n) { return n * 2; }
+        }
+        static class B extends A { static int f() { return 2; } } // Hides A.f, inherits A.g
+        static class C extends B {} // Inherits B.f and A.g
+
+        public static int a_f() { return A.f(); }
+        public static int b_f() { return B.f(); }
+        public static int c_f() { return C.f(); }
+        public static int b_g(int n) { return B.g(n); }
+        public static int c_g(int n) { return C.g(n); }",
+    )?;
+    let module = Module::new(&WASM_ENGINE, module.finish())?;
+    let linker = Linker::new(&WASM_ENGINE);
+    let mut store = Store::new(&WASM_ENGINE, 0);
+    let instance = linker.instantiate(&mut store, &module)?;
+
+    let a_f = instance.get_typed_func::<(), i32, _>(&mut store, "Test.a_f()I")?;
+    let b_f = instance.get_typed_func::<(), i32, _>(&mut store, "Test.b_f()I")?;
+    let c_f = instance.get_typed_func::<(), i32, _>(&mut store, "Test.c_f()I")?;
+    let b_g = instance.get_typed_func::<i32, i32, _>(&mut store, "Test.b_g(I)I")?;
+    let c_g = instance.get_typed_func::<i32, i32, _>(&mut store, "Test.c_g(I)I")?;
+
+    // Check hidden static methods are resolved by static type
+    assert_eq!(a_f.call(&mut store, ())?, 1);
+    assert_eq!(b_f.call(&mut store, ())?, 2);
+    assert_eq!(c_f.call(&mut store, ())?, 2);
+    // Check inherited static methods call the superclass's implementation
+    assert_eq!(b_g.call(&mut store, 3)?, 6);
+    assert_eq!(c_g.call(&mut store, 3)?, 6);
+
+    Ok(())
+}
+
+/// INVOKESTATIC <method>, INVOKEVIRTUAL <method> in tail position with `--tail-calls`
+#[test]
+fn invoke_tail_calls() -> anyhow::Result<()> {
+    let code = "static class Counter {
+            int countdown(int n) { if (n == 0) return 0; return this.countdown(n - 1); }
+        }
+
+        static long factorial(int n, long acc) {
+            if (n <= 1) return acc;
+            return factorial(n - 1, acc * n);
+        }
+        public static long factorial(int n) { return factorial(n, 1); }
+        public static int countdown(int n) { return new Counter().countdown(n); }";
+    let compile = |tail_calls| -> anyhow::Result<_> {
+        let options = CompileOptions {
+            tail_calls,
+            ..CompileOptions::default()
+        };
+        let (classes, functions) = compile_code_functions_with_options(code, 1, options)?;
+        let classes = Arc::new(classes);
+        let virtual_table = construct_virtual_table(None, &classes)?;
+        Ok((classes, virtual_table, functions))
+    };
+
+    // Check recursive calls immediately followed by returns become tail calls
+    let (classes, virtual_table, functions) = compile(true)?;
+    let code_of = |id: &str| {
+        let func = functions.iter().find(|f| f.id.to_string() == id).unwrap();
+        func.code.as_ref().unwrap()
+    };
+    assert!(code_of("Test.factorial(IJ)J")
+        .iter()
+        .any(|instruction| matches!(instruction, Instruction::ReturnCallStatic(_))));
+    assert!(code_of("Test$Counter.countdown(I)I")
+        .iter()
+        .any(|instruction| matches!(instruction, Instruction::ReturnCallVirtual(_))));
+    let module = render_module(classes, virtual_table, functions, RenderOptions::default())?;
+    // wasmtime doesn't support the tail call proposal yet, so we can only check the module's valid
+    // with it enabled, and that `return_call` instructions are used without it
+    let wasm = module.finish();
+    let features = TargetFeatures {
+        tail_call: true,
+        ..TargetFeatures::default()
+    };
+    validate_functions_with_features(&wasm, features)?;
+    assert!(validate_functions(&wasm).is_err());
+
+    // Check deep recursion overflows the stack without tail calls
+    let (classes, virtual_table, functions) = compile(false)?;
+    assert!(!functions.iter().any(CompiledFunction::has_tail_calls));
+    let module = render_module(classes, virtual_table, functions, RenderOptions::default())?;
+    let module = Module::new(&WASM_ENGINE, module.finish())?;
+    let linker = Linker::new(&WASM_ENGINE);
+    let mut store = Store::new(&WASM_ENGINE, 0);
+    let instance = linker.instantiate(&mut store, &module)?;
+
+    let factorial = instance.get_typed_func::<i32, i64, _>(&mut store, "Test.factorial(I)J")?;
+    let countdown = instance.get_typed_func::<i32, i32, _>(&mut store, "Test.countdown(I)I")?;
+    assert_eq!(factorial.call(&mut store, 1)?, 1);
+    assert_eq!(factorial.call(&mut store, 5)?, 120);
+    assert_eq!(factorial.call(&mut store, 20)?, 2432902008176640000);
+    assert_eq!(countdown.call(&mut store, 10)?, 0);
+    let res = factorial.call(&mut store, 1_000_000).unwrap_err();
+    assert_eq!(res.trap_code(), Some(TrapCode::StackOverflow));
+    let res = countdown.call(&mut store, 1_000_000).unwrap_err();
+    assert_eq!(res.trap_code(), Some(TrapCode::StackOverflow));
+
+    Ok(())
+}
+
 /// INVOKEVIRTUAL <method>
 #[test]
 fn invoke_virtual() -> anyhow::Result<()> {
@@ -183,3 +1070,494 @@ fn invoke_virtual() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+/// INVOKEVIRTUAL <method> returning long
+#[test]
+fn invoke_virtual_long() -> anyhow::Result<()> {
+    let module = construct_code_module(
+        "static abstract class Scaler {
+            abstract long scale(long x);
+        }
+
+        static class Doubler extends Scaler {
+            long scale(long x) { return x * 2; }
+        }
+
+        static class Squarer extends Scaler {
+            long scale(long x) { return x * x; }
+        }
+
+        static Scaler make(int kind) {
+            if (kind == 0) { return new Doubler(); }
+            return new Squarer();
+        }
+
+        public static long scale(int kind, long x) { return make(kind).scale(x); }",
+    )?;
+    let wasm_bytes = module.finish();
+
+    // Check the dispatcher's type includes the i64 result, i.e. `[x: i64] -> [i64]`, excluding the
+    // implicit `this`
+    let wat = wasmprinter::print_bytes(&wasm_bytes)?;
+    assert!(wat.contains("(func $!Dispatcher_i64_i64 "));
+
+    let module = Module::new(&WASM_ENGINE, wasm_bytes)?;
+    let linker = Linker::new(&WASM_ENGINE);
+    let mut store = Store::new(&WASM_ENGINE, 0);
+    let instance = linker.instantiate(&mut store, &module)?;
+
+    let scale = instance.get_typed_func::<(i32, i64), i64, _>(&mut store, "Test.scale(IJ)J")?;
+    // Use values outside of i32's range, so truncated results would be detected
+    assert_eq!(scale.call(&mut store, (0, 3_000_000_000))?, 6_000_000_000);
+    assert_eq!(
+        scale.call(&mut store, (1, 3_000_000_000))?,
+        9_000_000_000_000_000_000
+    );
+    assert_eq!(scale.call(&mut store, (1, -5))?, 25);
+
+    Ok(())
+}
+
+/// ARETURN, ACONST_NULL
+#[test]
+fn return_references() -> anyhow::Result<()> {
+    let module = construct_code_module(
+        "static class Box {
+            int v;
+            Box(int v) { this.v = v; }
+            Box self() { return this; }
+            Box none() { return null; }
+        }
+
+        static Box make(int v) {
+            if (v < 0) { return null; }
+            return new Box(v);
+        }
+
+        // Note if's returning values aren't yet supported, hence the explicit returns.
+        public static int make_value(int v) { return make(v).v; }
+        public static boolean make_null(int v) { if (make(v) == null) { return true; } return false; }
+        public static int self_value(int v) { return new Box(v).self().v; }
+        public static boolean none_null(int v) { if (new Box(v).none() == null) { return true; } return false; }",
+    )?;
+    let wasm_bytes = module.finish();
+
+    // Check reference-returning virtual methods are dispatched as `[] -> [i32]`, excluding the
+    // implicit `this`
+    let wat = wasmprinter::print_bytes(&wasm_bytes)?;
+    assert!(wat.contains("(func $!Dispatcher__i32 "));
+
+    let module = Module::new(&WASM_ENGINE, wasm_bytes)?;
+    let linker = Linker::new(&WASM_ENGINE);
+    let mut store = Store::new(&WASM_ENGINE, 0);
+    let instance = linker.instantiate(&mut store, &module)?;
+
+    let make_value = instance.get_typed_func::<i32, i32, _>(&mut store, "Test.make_value(I)I")?;
+    let make_null = instance.get_typed_func::<i32, i32, _>(&mut store, "Test.make_null(I)Z")?;
+    let self_value = instance.get_typed_func::<i32, i32, _>(&mut store, "Test.self_value(I)I")?;
+    let none_null = instance.get_typed_func::<i32, i32, _>(&mut store, "Test.none_null(I)Z")?;
+
+    // Check direct calls return references...
+    assert_eq!(make_value.call(&mut store, 42)?, 42);
+    assert_eq!(make_null.call(&mut store, 42)?, 0);
+    // ...and null
+    assert_eq!(make_null.call(&mut store, -1)?, 1);
+    // Check virtual calls return references and null
+    assert_eq!(self_value.call(&mut store, 7)?, 7);
+    assert_eq!(none_null.call(&mut store, 7)?, 1);
+
+    Ok(())
+}
+
+/// INVOKEVIRTUAL <method> through a superclass-typed receiver
+#[test]
+fn invoke_virtual_superclass_receiver() -> anyhow::Result<()> {
+    let code = "static class Animal {
+            int legs() { return 4; }
+            int speak() { return 1; }
+        }
+        static class Dog extends Animal {
+            // Declared before the override, so would take its offset if offsets weren't shared
+            int fetch() { return 3; }
+            @Override
+            int speak() { return 2; }
+        }
+        static class Puppy extends Dog {}
+
+        public static int animal_speak() { Animal a = new Animal(); return a.speak(); }
+        public static int dog_speak() { Animal a = new Dog(); return a.speak(); }
+        public static int puppy_speak() { Animal a = new Puppy(); return a.speak(); }
+        public static int dog_legs() { Animal a = new Dog(); return a.legs(); }
+        public static int dog_fetch() { Dog d = new Puppy(); return d.fetch(); }";
+
+    // Check overriding methods share their overridden method's offset
+    let (classes, _) = compile_code_functions(code, 1)?;
+    let classes = Arc::new(classes);
+    let virtual_table = construct_virtual_table(None, &classes)?;
+    let speak_offset = |class_name: &str| {
+        let class = &classes[&str_arc(class_name)];
+        let speak = class
+            .methods
+            .iter()
+            .find(|m| *m.id.name == "speak")
+            .unwrap();
+        virtual_table.get_method_virtual_offset(&speak.id)
+    };
+    assert_eq!(speak_offset("Test$Animal"), speak_offset("Test$Dog"));
+
+    // Check calls through `Animal` references dispatch to the runtime class's implementation
+    let module = construct_code_module(code)?;
+    let module = Module::new(&WASM_ENGINE, module.finish())?;
+    let linker = Linker::new(&WASM_ENGINE);
+    let mut store = Store::new(&WASM_ENGINE, 0);
+    let instance = linker.instantiate(&mut store, &module)?;
+
+    let animal_speak = instance.get_typed_func::<(), i32, _>(&mut store, "Test.animal_speak()I")?;
+    let dog_speak = instance.get_typed_func::<(), i32, _>(&mut store, "Test.dog_speak()I")?;
+    let puppy_speak = instance.get_typed_func::<(), i32, _>(&mut store, "Test.puppy_speak()I")?;
+    let dog_legs = instance.get_typed_func::<(), i32, _>(&mut store, "Test.dog_legs()I")?;
+    let dog_fetch = instance.get_typed_func::<(), i32, _>(&mut store, "Test.dog_fetch()I")?;
+
+    assert_eq!(animal_speak.call(&mut store, ())?, 1);
+    assert_eq!(dog_speak.call(&mut store, ())?, 2);
+    assert_eq!(puppy_speak.call(&mut store, ())?, 2);
+    assert_eq!(dog_legs.call(&mut store, ())?, 4);
+    assert_eq!(dog_fetch.call(&mut store, ())?, 3);
+
+    Ok(())
+}
+
+/// INVOKEVIRTUAL <method> of abstract methods with --abstract-behavior
+#[test]
+fn invoke_virtual_abstract_behaviour() -> anyhow::Result<()> {
+    let code = "static abstract class Shape { abstract int area(); abstract void reset(); }
+        static class Square extends Shape { int area() { return 4; } void reset() {} }
+
+        public static Shape make() { return new Square(); }
+        public static int area(Shape s) { return s.area(); }
+        public static void reset(Shape s) { s.reset(); }";
+
+    for abstract_behaviour in [AbstractBehaviour::Trap, AbstractBehaviour::Default] {
+        let (classes, functions) = compile_code_functions(code, 1)?;
+        let classes = Arc::new(classes);
+        let virtual_table = construct_virtual_table(None, &classes)?;
+        let shape_id = virtual_table.get_virtual_class_id(&str_arc("Test$Shape"))?;
+        let module = render_module(
+            classes,
+            virtual_table,
+            functions,
+            RenderOptions {
+                abstract_behaviour,
+                ..RenderOptions::default()
+            },
+        )?;
+        let module = Module::new(&WASM_ENGINE, module.finish())?;
+        let linker = Linker::new(&WASM_ENGINE);
+        let mut store = Store::new(&WASM_ENGINE, 0);
+        let instance = linker.instantiate(&mut store, &module)?;
+
+        let make = instance.get_typed_func::<(), i32, _>(&mut store, "Test.make()LTest$Shape;")?;
+        let area =
+            instance.get_typed_func::<i32, i32, _>(&mut store, "Test.area(LTest$Shape;)I")?;
+        let reset =
+            instance.get_typed_func::<i32, (), _>(&mut store, "Test.reset(LTest$Shape;)V")?;
+        let memory = instance.get_memory(&mut store, "memory").unwrap();
+
+        // Check implementations are still called
+        let s = make.call(&mut store, ())?;
+        assert_eq!(area.call(&mut store, s)?, 4);
+
+        // Abstract methods can only be called if there's a bug, so simulate one by replacing the
+        // instance's virtual class ID with the abstract class's
+        memory.write(&mut store, s as usize, &shape_id.to_le_bytes())?;
+        let area_res = area.call(&mut store, s);
+        let reset_res = reset.call(&mut store, s);
+        match abstract_behaviour {
+            AbstractBehaviour::Trap => {
+                let trap_code = area_res.unwrap_err().trap_code();
+                assert_eq!(trap_code, Some(TrapCode::UnreachableCodeReached));
+                let trap_code = reset_res.unwrap_err().trap_code();
+                assert_eq!(trap_code, Some(TrapCode::UnreachableCodeReached));
+            }
+            AbstractBehaviour::Default => {
+                assert_eq!(area_res?, 0);
+                reset_res?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Abstract classes only instantiated through subclasses, with inherited fields and methods
+#[test]
+fn abstract_class_inherited() -> anyhow::Result<()> {
+    let (classes, functions) = compile_code_functions(
+        "static abstract class Shape {
+            int scale;
+            abstract int area();
+            int scaledArea() { return scale * area(); }
+        }
+
+        static class Rect extends Shape {
+            int w;
+            int h;
+            Rect(int w, int h) { this.w = w; this.h = h; }
+            int area() { return w * h; }
+        }
+
+        public static Shape make(int scale, int w, int h) { Rect r = new Rect(w, h); r.scale = scale; return r; }
+        public static int scale(Shape s) { return s.scale; }
+        public static int area(Shape s) { return s.area(); }
+        public static int scaled_area(Shape s) { return s.scaledArea(); }",
+        1,
+    )?;
+    let classes = Arc::new(classes);
+    let virtual_table = construct_virtual_table(None, &classes)?;
+    let shape_id = virtual_table.get_virtual_class_id(&str_arc("Test$Shape"))?;
+    let module = render_module(classes, virtual_table, functions, RenderOptions::default())?;
+    let module = Module::new(&WASM_ENGINE, module.finish())?;
+    let linker = Linker::new(&WASM_ENGINE);
+    let mut store = Store::new(&WASM_ENGINE, 0);
+    let instance = linker.instantiate(&mut store, &module)?;
+
+    let make = instance
+        .get_typed_func::<(i32, i32, i32), i32, _>(&mut store, "Test.make(III)LTest$Shape;")?;
+    let scale = instance.get_typed_func::<i32, i32, _>(&mut store, "Test.scale(LTest$Shape;)I")?;
+    let area = instance.get_typed_func::<i32, i32, _>(&mut store, "Test.area(LTest$Shape;)I")?;
+    let scaled_area =
+        instance.get_typed_func::<i32, i32, _>(&mut store, "Test.scaled_area(LTest$Shape;)I")?;
+    let memory = instance.get_memory(&mut store, "memory").unwrap();
+
+    // Check the inherited field is laid out separately from the subclass's fields, and the
+    // inherited concrete method dispatches to the subclass's implementation
+    let r = make.call(&mut store, (5, 2, 3))?;
+    assert_eq!(scale.call(&mut store, r)?, 5);
+    assert_eq!(area.call(&mut store, r)?, 6);
+    assert_eq!(scaled_area.call(&mut store, r)?, 30);
+    let other = make.call(&mut store, (-1, 4, 4))?;
+    assert_eq!(scaled_area.call(&mut store, other)?, -16);
+    assert_eq!(scaled_area.call(&mut store, r)?, 30);
+
+    // Check the abstract method is rendered as `unreachable`, by replacing the instance's virtual
+    // class ID with the abstract class's
+    memory.write(&mut store, r as usize, &shape_id.to_le_bytes())?;
+    let trap_code = area.call(&mut store, r).unwrap_err().trap_code();
+    assert_eq!(trap_code, Some(TrapCode::UnreachableCodeReached));
+    // Inherited fields and concrete methods still belong to the abstract class itself
+    assert_eq!(scale.call(&mut store, r)?, 5);
+    let trap_code = scaled_area.call(&mut store, r).unwrap_err().trap_code();
+    assert_eq!(trap_code, Some(TrapCode::UnreachableCodeReached));
+
+    Ok(())
+}
+
+/// INVOKEVIRTUAL <method> with --null-checks
+#[test]
+fn invoke_virtual_null_checks() -> anyhow::Result<()> {
+    let render_options = RenderOptions {
+        null_checks: true,
+        ..RenderOptions::default()
+    };
+    let module = construct_code_module_with_options(
+        "static class Counter {
+            int count;
+            int increment(int n) { count += n; return count; }
+        }
+
+        public static int increment_new(int n) { return new Counter().increment(n); }
+        public static int increment(Counter counter, int n) { return counter.increment(n); }",
+        CompileOptions::default(),
+        render_options,
+    )?;
+    let module = Module::new(&WASM_ENGINE, module.finish())?;
+    let linker = Linker::new(&WASM_ENGINE);
+    let mut store = Store::new(&WASM_ENGINE, 0);
+    let instance = linker.instantiate(&mut store, &module)?;
+
+    let increment_new =
+        instance.get_typed_func::<i32, i32, _>(&mut store, "Test.increment_new(I)I")?;
+    let increment = instance
+        .get_typed_func::<(i32, i32), i32, _>(&mut store, "Test.increment(LTest$Counter;I)I")?;
+
+    // Check non-null calls still dispatch correctly
+    assert_eq!(increment_new.call(&mut store, 3)?, 3);
+
+    // Check null calls trap
+    let res = increment.call(&mut store, (0, 1)).unwrap_err();
+    assert_eq!(res.trap_code(), Some(TrapCode::UnreachableCodeReached));
+
+    Ok(())
+}
+
+/// INVOKEVIRTUAL java/lang/Object.{equals,hashCode,getClass}
+#[test]
+fn invoke_virtual_object_intrinsics() -> anyhow::Result<()> {
+    let module = construct_code_module(
+        "static class A {}
+        static class B {}
+
+        public static boolean equals_self() { A a = new A(); return a.equals(a); }
+        public static boolean equals_other() { return new A().equals(new A()); }
+        public static boolean equals_object() { Object a = new A(); return a.equals(a); }
+        public static int hash_code() { return new A().hashCode(); }
+        public static int hash_code_stable() { A a = new A(); return a.hashCode() - a.hashCode(); }
+        public static Class get_class_a() { return new A().getClass(); }
+        public static Class get_class_b() { Object b = new B(); return b.getClass(); }",
+    )?;
+    let module = Module::new(&WASM_ENGINE, module.finish())?;
+    let linker = Linker::new(&WASM_ENGINE);
+    let mut store = Store::new(&WASM_ENGINE, 0);
+    let instance = linker.instantiate(&mut store, &module)?;
+
+    let equals_self = instance.get_typed_func::<(), i32, _>(&mut store, "Test.equals_self()Z")?;
+    let equals_other = instance.get_typed_func::<(), i32, _>(&mut store, "Test.equals_other()Z")?;
+    let equals_object =
+        instance.get_typed_func::<(), i32, _>(&mut store, "Test.equals_object()Z")?;
+    let hash_code = instance.get_typed_func::<(), i32, _>(&mut store, "Test.hash_code()I")?;
+    let hash_code_stable =
+        instance.get_typed_func::<(), i32, _>(&mut store, "Test.hash_code_stable()I")?;
+    let get_class_a =
+        instance.get_typed_func::<(), i32, _>(&mut store, "Test.get_class_a()Ljava/lang/Class;")?;
+    let get_class_b =
+        instance.get_typed_func::<(), i32, _>(&mut store, "Test.get_class_b()Ljava/lang/Class;")?;
+
+    // Check equals compares references for identity
+    assert_eq!(equals_self.call(&mut store, ())?, 1);
+    assert_eq!(equals_other.call(&mut store, ())?, 0);
+    assert_eq!(equals_object.call(&mut store, ())?, 1);
+
+    // Check hash codes are non-zero and stable
+    assert_ne!(hash_code.call(&mut store, ())?, 0);
+    assert_eq!(hash_code_stable.call(&mut store, ())?, 0);
+
+    // Check classes are the same for instances of the same class, and different otherwise
+    let class_a = get_class_a.call(&mut store, ())?;
+    assert_eq!(get_class_a.call(&mut store, ())?, class_a);
+    assert_ne!(get_class_b.call(&mut store, ())?, class_a);
+
+    Ok(())
+}
+
+/// POP after intrinsic calls whose results are ignored
+#[test]
+fn intrinsic_results_popped() -> anyhow::Result<()> {
+    let render_options = RenderOptions {
+        null_checks: true,
+        ..RenderOptions::default()
+    };
+    let module = construct_code_module_with_options(
+        "static class A {}
+
+        public static int ignore_results(float f, double d) {
+            A a = new A();
+            a.equals(a);
+            a.hashCode();
+            a.getClass();
+            Float.floatToIntBits(f);
+            Float.floatToRawIntBits(f);
+            Double.doubleToLongBits(d);
+            Double.longBitsToDouble(1L);
+            return 1;
+        }",
+        CompileOptions::default(),
+        render_options,
+    )?;
+    let wasm = module.finish();
+    validate_functions(&wasm)?;
+    let module = Module::new(&WASM_ENGINE, wasm)?;
+    let linker = Linker::new(&WASM_ENGINE);
+    let mut store = Store::new(&WASM_ENGINE, 0);
+    let instance = linker.instantiate(&mut store, &module)?;
+
+    let ignore_results =
+        instance.get_typed_func::<(f32, f64), i32, _>(&mut store, "Test.ignore_results(FD)I")?;
+    assert_eq!(ignore_results.call(&mut store, (1.5, f64::NAN))?, 1);
+
+    Ok(())
+}
+
+/// Classes rendered to separate modules, then merged
+#[test]
+fn merge_modules() -> anyhow::Result<()> {
+    let (classes, functions) = compile_code_functions(
+        "static class Shape { int area() { return 0; } }
+        static class Square extends Shape {
+            int side;
+            Square(int side) { this.side = side; }
+            int area() { return Test.square(side); }
+        }
+
+        static int square(int x) { return x * x; }
+        public static int area(int side) { Shape s = new Square(side); return s.area(); }",
+        1,
+    )?;
+    let classes = Arc::new(classes);
+    let virtual_table = construct_virtual_table(None, &classes)?;
+
+    // Render `Test`'s methods separately from the shapes', importing methods from the other module
+    let (test_functions, shape_functions) = functions
+        .into_iter()
+        .partition(|function| function.id.class_name.as_str() == "Test");
+    let render = |functions| -> anyhow::Result<OutputModule> {
+        let mut module = OutputModule::new();
+        let mut renderer =
+            Renderer::new(Arc::clone(&classes), Arc::clone(&virtual_table), functions);
+        renderer.import_missing = true;
+        let function_indices = renderer.render_all(&mut module)?;
+        virtual_table.render(&mut module, &function_indices);
+        Ok(module)
+    };
+    let test_module = render(test_functions)?;
+    let shape_module = render(shape_functions)?;
+
+    // Check merging resolves calls in both directions, including through the virtual table
+    let module = test_module.merge(shape_module)?;
+    let module = Module::new(&WASM_ENGINE, module.finish())?;
+    assert_eq!(module.imports().len(), 0);
+    let linker = Linker::new(&WASM_ENGINE);
+    let mut store = Store::new(&WASM_ENGINE, 0);
+    let instance = linker.instantiate(&mut store, &module)?;
+
+    let area = instance.get_typed_func::<i32, i32, _>(&mut store, "Test.area(I)I")?;
+    assert_eq!(area.call(&mut store, 3)?, 9);
+    assert_eq!(area.call(&mut store, -4)?, 16);
+
+    Ok(())
+}
+
+/// Enum classes, including their synthetic `$VALUES` array, static constant fields, `values()`,
+/// `<clinit>`, and the synthetic switch map class generated for switching on them
+#[test]
+#[ignore = "requires static fields, arrays, strings, java/lang/Enum and n-way branches"]
+fn enum_switch() -> anyhow::Result<()> {
+    let module = construct_code_module(
+        "enum Colour {
+            RED, GREEN;
+            int code() { return ordinal() == 0 ? 10 : 20; }
+        }
+
+        public static int describe(int i) {
+            switch (Colour.values()[i]) {
+                case RED: return 1;
+                case GREEN: return 2;
+                default: return 0;
+            }
+        }
+        public static int code(int i) { return Colour.values()[i].code(); }",
+    )?;
+    let module = Module::new(&WASM_ENGINE, module.finish())?;
+    let linker = Linker::new(&WASM_ENGINE);
+    let mut store = Store::new(&WASM_ENGINE, 0);
+    let instance = linker.instantiate(&mut store, &module)?;
+
+    let describe = instance.get_typed_func::<i32, i32, _>(&mut store, "Test.describe(I)I")?;
+    let code = instance.get_typed_func::<i32, i32, _>(&mut store, "Test.code(I)I")?;
+
+    assert_eq!(describe.call(&mut store, 0)?, 1);
+    assert_eq!(describe.call(&mut store, 1)?, 2);
+    assert_eq!(code.call(&mut store, 0)?, 10);
+    assert_eq!(code.call(&mut store, 1)?, 20);
+
+    Ok(())
+}
@@ -2,21 +2,27 @@
 
 use crate::class::load_class;
 use crate::function::structure::ControlFlowGraph;
-use crate::output::BuiltinFunction;
+use crate::function::{CompileOptions, CompiledFunction};
+use crate::output::{BuiltinFunction, RenderOptions};
 use crate::scheduler::SerialScheduler;
+use crate::timings::SharedDuration;
 use crate::{
-    collect_functions, compile_functions, construct_virtual_table, render_module, Class, Module,
+    check_errors, collect_functions, compile_functions, construct_virtual_table, render_module,
+    Class, Module,
 };
 use data_encoding::HEXLOWER;
+use log::{LevelFilter, Log, Metadata, Record};
 use sha1::{Digest, Sha1};
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::path::PathBuf;
 use std::process::Command;
 use std::sync::mpsc::channel;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, Once};
 use std::{env, fs};
 use wasm_encoder::Export;
-use wasmtime::Engine;
+use wasmparser::{Name, NameSectionReader, Parser, Payload, ValidPayload, Validator, WasmFeatures};
+use wasmtime::{AsContextMut, Config, Engine, Instance};
 
 const CACHE_DIR: &str = ".cache";
 
@@ -51,10 +57,19 @@ lazy_static! {
 /// }
 /// ```
 pub fn load_many_code(code: &str) -> anyhow::Result<HashMap<String, Class>> {
+    load_many_code_with_args(code, &[])
+}
+
+/// Compiles Java code, passing additional arguments to `javac` (e.g. `["--release", "8"]`),
+/// returning paths to all compiled `.class` files.
+///
+/// See [`load_many_code`] for details on caching and the template `code` is placed in.
+pub fn javac_code_with_args(code: &str, args: &[&str]) -> anyhow::Result<Vec<PathBuf>> {
     // Check if we've already compiled this code (global mutex ensures cache is in consistent state)
     let javac_guard = JAVAC_MUTEX.lock().unwrap();
     let java = format!("public class Test {{\n{}\n}}", code);
-    let hash = sha1_digest(&java);
+    // Include arguments in cache key, as these affect the compiled output
+    let hash = sha1_digest(&format!("{}{}", args.join(" "), java));
     let cache = cache_path(&hash);
     if !cache.exists() {
         // If not, compile it. First, write Java code...
@@ -62,6 +77,7 @@ pub fn load_many_code(code: &str) -> anyhow::Result<HashMap<String, Class>> {
         fs::write(cache.join("Test.java"), java)?;
         // ...then run `javac` on it
         let result = Command::new("javac")
+            .args(args)
             .arg("Test.java")
             .current_dir(&cache)
             .output()?;
@@ -76,17 +92,31 @@ pub fn load_many_code(code: &str) -> anyhow::Result<HashMap<String, Class>> {
     }
     drop(javac_guard); // Unlock JAVAC_MUTEX
 
-    // Load all .class files in cache directory
-    let mut classes = HashMap::new();
+    // Find all .class files in cache directory
+    let mut paths = vec![];
     for file in fs::read_dir(&cache)? {
         let path = file?.path();
         if let Some("class") = path.extension().and_then(|s| s.to_str()) {
-            let name = path.file_stem().and_then(|s| s.to_str()).map(String::from);
-            let class = load_class(&path)?;
-            classes.insert(name.unwrap(), class);
+            paths.push(path);
         }
     }
+    Ok(paths)
+}
 
+/// Compiles, loads and parses Java code, passing additional arguments to `javac` (e.g.
+/// `["--release", "8"]`), returning a map of class names to parsed classes.
+///
+/// See [`load_many_code`] for details on caching and the template `code` is placed in.
+pub fn load_many_code_with_args(
+    code: &str,
+    args: &[&str],
+) -> anyhow::Result<HashMap<String, Class>> {
+    let mut classes = HashMap::new();
+    for path in javac_code_with_args(code, args)? {
+        let name = path.file_stem().and_then(|s| s.to_str()).map(String::from);
+        let class = load_class(&path, false)?;
+        classes.insert(name.unwrap(), class);
+    }
     Ok(classes)
 }
 
@@ -136,6 +166,155 @@ lazy_static! {
     pub static ref WASM_ENGINE: Engine = Engine::default();
 }
 
+/// WebAssembly proposals a module requires that aren't enabled by default, so [`WASM_ENGINE`] and
+/// [`validate_functions`] would reject it. wasmtime doesn't support the exception handling
+/// proposal yet, so it can't be enabled here.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct TargetFeatures {
+    pub threads: bool,
+    pub memory64: bool,
+    pub multi_memory: bool,
+    /// wasmtime doesn't support the tail call proposal yet either, so this only affects
+    /// [`validate_functions_with_features`], and [`engine_with_features`] will still reject modules
+    /// using it.
+    pub tail_call: bool,
+}
+
+impl TargetFeatures {
+    /// Returns wasmparser's default features with these proposals enabled.
+    fn wasm_features(self) -> WasmFeatures {
+        WasmFeatures {
+            threads: self.threads,
+            memory64: self.memory64,
+            multi_memory: self.multi_memory,
+            tail_call: self.tail_call,
+            ..WasmFeatures::default()
+        }
+    }
+}
+
+/// Constructs a wasmtime engine with `features` enabled, for instantiating modules
+/// [`WASM_ENGINE`] would reject.
+pub fn engine_with_features(features: TargetFeatures) -> Engine {
+    let mut config = Config::new();
+    config
+        .wasm_threads(features.threads)
+        .wasm_memory64(features.memory64)
+        .wasm_multi_memory(features.multi_memory);
+    Engine::new(&config).unwrap()
+}
+
+/// Returns the address the next block allocated on `instance`'s heap will start at. Allocation is
+/// deterministic (see the allocate built-in's `heap_base`), so this can be used to predict the
+/// addresses of new objects. The module must allocate and have been finished with
+/// [`Module::emit_debug_globals`] set, so the bump allocator's next pointer is exported.
+pub fn next_allocation_address(mut store: impl AsContextMut, instance: &Instance) -> i32 {
+    let heap_next = instance
+        .get_global(&mut store, "heap_next")
+        .expect("Module must allocate and emit debug globals");
+    heap_next.get(&mut store).unwrap_i32()
+}
+
+thread_local! {
+    /// Log messages recorded on this thread by [`capture_logs`], if currently capturing.
+    static CAPTURED_LOGS: RefCell<Option<Vec<String>>> = const { RefCell::new(None) };
+}
+
+/// Logger recording messages to [`CAPTURED_LOGS`]. Tests run in parallel on different threads, so
+/// this allows each test to only see its own messages.
+struct CaptureLogger;
+
+impl Log for CaptureLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        CAPTURED_LOGS.with(|logs| logs.borrow().is_some())
+    }
+
+    fn log(&self, record: &Record) {
+        CAPTURED_LOGS.with(|logs| {
+            if let Some(logs) = logs.borrow_mut().as_mut() {
+                logs.push(record.args().to_string());
+            }
+        });
+    }
+
+    fn flush(&self) {}
+}
+
+static CAPTURE_LOGGER: CaptureLogger = CaptureLogger;
+static CAPTURE_LOGGER_INIT: Once = Once::new();
+
+/// Calls `f`, returning its result and all messages logged on this thread while it was running.
+pub fn capture_logs<R>(f: impl FnOnce() -> R) -> (R, Vec<String>) {
+    CAPTURE_LOGGER_INIT.call_once(|| {
+        log::set_logger(&CAPTURE_LOGGER).expect("Unable to set capture logger");
+        log::set_max_level(LevelFilter::Trace);
+    });
+    CAPTURED_LOGS.with(|logs| *logs.borrow_mut() = Some(vec![]));
+    let result = f();
+    let logs = CAPTURED_LOGS.with(|logs| logs.borrow_mut().take().unwrap());
+    (result, logs)
+}
+
+/// Validates a finished WebAssembly module, validating each function body independently and
+/// reporting the index and debug name of the first invalid function. wasmtime only reports the
+/// byte offset of validation errors, which makes it hard to find the function with incorrect
+/// stack effects.
+pub fn validate_functions(wasm: &[u8]) -> anyhow::Result<()> {
+    validate_functions_with_features(wasm, TargetFeatures::default())
+}
+
+/// Validates a finished WebAssembly module with `features` enabled, reporting the first invalid
+/// function. See [`validate_functions`] for more details.
+pub fn validate_functions_with_features(
+    wasm: &[u8],
+    features: TargetFeatures,
+) -> anyhow::Result<()> {
+    let mut validator = Validator::new();
+    validator.wasm_features(features.wasm_features());
+    let mut import_count = 0;
+    let mut functions = vec![];
+    let mut names = HashMap::new();
+    for payload in Parser::new(0).parse_all(wasm) {
+        let payload = payload?;
+        match &payload {
+            // All imports are functions
+            Payload::ImportSection(reader) => import_count = reader.get_count(),
+            Payload::CustomSection {
+                name: "name",
+                data,
+                data_offset,
+                ..
+            } => {
+                let mut reader = NameSectionReader::new(data, *data_offset)?;
+                while !reader.eof() {
+                    if let Name::Function(function_names) = reader.read()? {
+                        let mut function_names = function_names.get_map()?;
+                        for _ in 0..function_names.get_count() {
+                            let naming = function_names.read()?;
+                            names.insert(naming.index, naming.name);
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+        // Validates everything but function bodies, which are returned for validating later
+        if let ValidPayload::Func(function_validator, body) = validator.payload(&payload)? {
+            functions.push((function_validator, body));
+        }
+    }
+
+    // Validate function bodies once all debug names are known
+    for (i, (mut function_validator, body)) in functions.into_iter().enumerate() {
+        let index = import_count + i as u32;
+        if let Err(e) = function_validator.validate(&body) {
+            let name = names.get(&index).copied().unwrap_or("<unnamed>");
+            bail!("Function {} ({}) is invalid: {}", index, name, e);
+        }
+    }
+    Ok(())
+}
+
 /// Constructs a WebAssembly module exporting the specified built-in functions.
 pub fn construct_builtin_module(builtins: &[BuiltinFunction]) -> Module {
     let mut module = Module::new();
@@ -151,7 +330,12 @@ pub fn construct_builtin_module(builtins: &[BuiltinFunction]) -> Module {
     module
 }
 
-/// Compiles, loads and parses Java code, then compiles it to WebAssembly, returning a module.
+/// Parsed classes and their compiled functions, returned by [`compile_code_functions`].
+pub type CompiledCode = (HashMap<Arc<String>, Class>, Vec<CompiledFunction>);
+
+/// Compiles, loads and parses Java code, then compiles each of its functions, returning all parsed
+/// classes and compiled functions. Up to `max_errors` function compilation failures will be
+/// reported together (see [`check_errors`]).
 ///
 /// Compilation will be cached. `code` may include methods or fields and will be placed inside the
 /// following template:
@@ -161,7 +345,35 @@ pub fn construct_builtin_module(builtins: &[BuiltinFunction]) -> Module {
 ///     // `code` goes here
 /// }
 /// ```
-pub fn construct_code_module(code: &str) -> anyhow::Result<Module> {
+pub fn compile_code_functions(code: &str, max_errors: usize) -> anyhow::Result<CompiledCode> {
+    compile_code_functions_with_options(code, max_errors, CompileOptions::default())
+}
+
+/// Compiles, loads and parses Java code, then compiles each of its functions, returning all parsed
+/// classes and compiled functions. `options` controls how each function is compiled (see
+/// [`compile_functions`]).
+///
+/// See [`compile_code_functions`] for more details.
+pub fn compile_code_functions_with_options(
+    code: &str,
+    max_errors: usize,
+    options: CompileOptions,
+) -> anyhow::Result<CompiledCode> {
+    let (compiled, errors) = compile_code_results(code, max_errors, options)?;
+    check_errors(errors)?;
+    Ok(compiled)
+}
+
+/// Compiles, loads and parses Java code, then compiles each of its functions, returning all parsed
+/// classes and successfully compiled functions, along with up to `max_errors` function
+/// compilation failures (see [`collect_functions`]).
+///
+/// See [`compile_code_functions`] for more details.
+pub fn compile_code_results(
+    code: &str,
+    max_errors: usize,
+    options: CompileOptions,
+) -> anyhow::Result<(CompiledCode, Vec<anyhow::Error>)> {
     // Load classes from code
     let classes = load_many_code(code)?;
     let class_count = classes.len();
@@ -174,18 +386,52 @@ pub fn construct_code_module(code: &str) -> anyhow::Result<Module> {
 
     // Compile all functions
     let schd = SerialScheduler {};
-    let (classes, function_count, function_rx) =
-        compile_functions(&schd, None, class_count, class_rx)?;
+    let (classes, function_count, function_rx) = compile_functions(
+        &schd,
+        None,
+        options,
+        &SharedDuration::default(),
+        class_count,
+        class_rx,
+    )?;
+
+    // Collect function compilation results
+    let (functions, errors) = collect_functions(function_count, function_rx, max_errors);
+
+    Ok(((classes, functions), errors))
+}
+
+/// Compiles, loads and parses Java code, then compiles it to WebAssembly, returning a module.
+///
+/// Compilation will be cached. `code` may include methods or fields and will be placed inside the
+/// following template:
+///
+/// ```java
+/// public class Test {
+///     // `code` goes here
+/// }
+/// ```
+pub fn construct_code_module(code: &str) -> anyhow::Result<Module> {
+    construct_code_module_with_options(code, CompileOptions::default(), RenderOptions::default())
+}
+
+/// Compiles, loads and parses Java code, then compiles it to WebAssembly, returning a module.
+/// `compile_options` controls how each function is compiled (see [`compile_functions`]), and
+/// `render_options` how the module is rendered (see [`render_module`]).
+///
+/// See [`construct_code_module`] for more details.
+pub fn construct_code_module_with_options(
+    code: &str,
+    compile_options: CompileOptions,
+    render_options: RenderOptions,
+) -> anyhow::Result<Module> {
+    // Load classes and compile all functions
+    let (classes, functions) = compile_code_functions_with_options(code, 1, compile_options)?;
 
     // Construct virtual method table containing virtual class and method IDs
     let classes = Arc::new(classes);
     let virtual_table = construct_virtual_table(None, &classes)?;
 
-    // Collect function compilation results
-    let functions = collect_functions(function_count, function_rx)?;
-
     // Render functions and virtual table to WebAssembly module
-    let module = render_module(classes, virtual_table, functions);
-
-    Ok(module)
+    render_module(classes, virtual_table, functions, render_options)
 }
@@ -1,4 +1,9 @@
-use crate::tests::{construct_code_module, WASM_ENGINE};
+use crate::function::CompileOptions;
+use crate::output::RenderOptions;
+use crate::tests::{
+    compile_code_functions_with_options, construct_code_module, construct_code_module_with_options,
+    WASM_ENGINE,
+};
 use itertools::Itertools;
 use wasmtime::{Linker, Module, Store};
 
@@ -173,3 +178,68 @@ fn a_i_f_l_d_load_store_wide() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+/// ISTORE <local>, ILOAD <local> with --coalesce-locals
+#[test]
+fn coalesce_locals() -> anyhow::Result<()> {
+    let code = "public static int chain(int x) {
+            int a = x + 1;
+            int b = a * 2;
+            int c = b - 3;
+            int d = c * c;
+            int e = d + a;
+            return e;
+        }
+        public static int loop(int n) {
+            int sum = 0;
+            for (int i = 0; i < n; i++) {
+                int square = i * i;
+                sum += square;
+            }
+            int doubled = sum * 2;
+            return doubled;
+        }";
+
+    // Check fewer locals are used when coalescing: in chain(), `a` is live until `e` is computed,
+    // so `b`, `c`, `d` and `e` can share one other local. In loop(), `sum`, `i` and `square` may
+    // be carried around the loop so can't share locals (javac already reuses `i`'s slot for
+    // `doubled`).
+    let locals_len = |coalesce_locals| -> anyhow::Result<Vec<usize>> {
+        let options = CompileOptions {
+            coalesce_locals,
+            ..CompileOptions::default()
+        };
+        let (_, functions) = compile_code_functions_with_options(code, 1, options)?;
+        let lens = functions
+            .iter()
+            .filter(|f| f.id.name.as_str() != "<init>")
+            .sorted_by_key(|f| f.id.name.to_string())
+            .map(|f| f.locals.as_ref().unwrap().len())
+            .collect();
+        Ok(lens)
+    };
+    assert_eq!(locals_len(false)?, [6, 4]); // chain(), loop()
+    assert_eq!(locals_len(true)?, [3, 4]);
+
+    // Check coalesced locals still produce the correct values
+    let options = CompileOptions {
+        coalesce_locals: true,
+        ..CompileOptions::default()
+    };
+    let module = construct_code_module_with_options(code, options, RenderOptions::default())?;
+    let module = Module::new(&WASM_ENGINE, module.finish())?;
+    let linker = Linker::new(&WASM_ENGINE);
+    let mut store = Store::new(&WASM_ENGINE, 0);
+    let instance = linker.instantiate(&mut store, &module)?;
+
+    let chain = instance.get_typed_func::<i32, i32, _>(&mut store, "Test.chain(I)I")?;
+    let r#loop = instance.get_typed_func::<i32, i32, _>(&mut store, "Test.loop(I)I")?;
+
+    // a = 4, b = 8, c = 5, d = 25, e = 29
+    assert_eq!(chain.call(&mut store, 3)?, 29);
+    // 2 * (0 + 1 + 4 + 9)
+    assert_eq!(r#loop.call(&mut store, 4)?, 28);
+    assert_eq!(r#loop.call(&mut store, 0)?, 0);
+
+    Ok(())
+}
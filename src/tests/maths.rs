@@ -1,4 +1,4 @@
-use crate::tests::{construct_code_module, WASM_ENGINE};
+use crate::tests::{construct_code_module, validate_functions, WASM_ENGINE};
 use wasmtime::{Linker, Module, Store};
 
 macro_rules! assert_int {
@@ -154,7 +154,9 @@ fn maths() -> anyhow::Result<()> {
         public static int iinc(int a) { a += 1; return a; }
         public static int iinc_wide(int a) { a += 9000; return a; }",
     )?;
-    let module = Module::new(&WASM_ENGINE, module.finish())?;
+    let wasm = module.finish();
+    validate_functions(&wasm)?;
+    let module = Module::new(&WASM_ENGINE, wasm)?;
     let linker = Linker::new(&WASM_ENGINE);
     let mut store = Store::new(&WASM_ENGINE, 0);
     let instance = linker.instantiate(&mut store, &module)?;
@@ -172,3 +174,201 @@ fn maths() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+/// FREM, DREM matching the JVM's semantics: the result has the dividend's sign, and is NaN if the
+/// divisor is zero or the dividend is infinite
+#[test]
+fn rem_semantics() -> anyhow::Result<()> {
+    let module = construct_code_module(
+        "public static float frem(float a, float b) { return a % b; }
+        public static double drem(double a, double b) { return a % b; }",
+    )?;
+    let module = Module::new(&WASM_ENGINE, module.finish())?;
+    let linker = Linker::new(&WASM_ENGINE);
+    let mut store = Store::new(&WASM_ENGINE, 0);
+    let instance = linker.instantiate(&mut store, &module)?;
+
+    let frem = instance.get_typed_func::<(f32, f32), f32, _>(&mut store, "Test.frem(FF)F")?;
+    let drem = instance.get_typed_func::<(f64, f64), f64, _>(&mut store, "Test.drem(DD)D")?;
+
+    // Check the result has the sign of the dividend, not the divisor
+    assert_eq!(frem.call(&mut store, (5.0, 3.0))?, 2.0);
+    assert_eq!(frem.call(&mut store, (-5.0, 3.0))?, -2.0);
+    assert_eq!(frem.call(&mut store, (5.0, -3.0))?, 2.0);
+    assert_eq!(
+        frem.call(&mut store, (-0.0, 3.0))?.to_bits(),
+        (-0.0f32).to_bits()
+    );
+    assert_eq!(drem.call(&mut store, (5.0, 3.0))?, 2.0);
+    assert_eq!(drem.call(&mut store, (-5.0, 3.0))?, -2.0);
+    assert_eq!(drem.call(&mut store, (5.0, -3.0))?, 2.0);
+    assert_eq!(
+        drem.call(&mut store, (-0.0, 3.0))?.to_bits(),
+        (-0.0f64).to_bits()
+    );
+
+    // Check infinite dividends and zero divisors produce NaN
+    assert!(frem.call(&mut store, (f32::INFINITY, 1.0))?.is_nan());
+    assert!(frem.call(&mut store, (1.0, 0.0))?.is_nan());
+    assert!(drem.call(&mut store, (f64::INFINITY, 1.0))?.is_nan());
+    assert!(drem.call(&mut store, (1.0, 0.0))?.is_nan());
+
+    Ok(())
+}
+
+/// INVOKESTATIC java/lang/Float.{floatToIntBits,floatToRawIntBits,intBitsToFloat},
+/// INVOKESTATIC java/lang/Double.{doubleToLongBits,doubleToRawLongBits,longBitsToDouble}
+#[test]
+fn bit_intrinsics() -> anyhow::Result<()> {
+    let module = construct_code_module(
+        "public static float f_round_trip(float a) { return Float.intBitsToFloat(Float.floatToIntBits(a)); }
+        public static int f_raw_round_trip(int a) { return Float.floatToRawIntBits(Float.intBitsToFloat(a)); }
+        public static int f_bits(float a) { return Float.floatToIntBits(a); }
+
+        public static double d_round_trip(double a) { return Double.longBitsToDouble(Double.doubleToLongBits(a)); }
+        public static long d_raw_round_trip(long a) { return Double.doubleToRawLongBits(Double.longBitsToDouble(a)); }
+        public static long d_bits(double a) { return Double.doubleToLongBits(a); }",
+    )?;
+    let module = Module::new(&WASM_ENGINE, module.finish())?;
+    let linker = Linker::new(&WASM_ENGINE);
+    let mut store = Store::new(&WASM_ENGINE, 0);
+    let instance = linker.instantiate(&mut store, &module)?;
+
+    let f_round_trip =
+        instance.get_typed_func::<f32, f32, _>(&mut store, "Test.f_round_trip(F)F")?;
+    let f_raw_round_trip =
+        instance.get_typed_func::<i32, i32, _>(&mut store, "Test.f_raw_round_trip(I)I")?;
+    let f_bits = instance.get_typed_func::<f32, i32, _>(&mut store, "Test.f_bits(F)I")?;
+    let d_round_trip =
+        instance.get_typed_func::<f64, f64, _>(&mut store, "Test.d_round_trip(D)D")?;
+    let d_raw_round_trip =
+        instance.get_typed_func::<i64, i64, _>(&mut store, "Test.d_raw_round_trip(J)J")?;
+    let d_bits = instance.get_typed_func::<f64, i64, _>(&mut store, "Test.d_bits(D)J")?;
+
+    // Check values round trip with identical bits, including NaNs
+    for a in [1.5, -0.0, f32::INFINITY, f32::NAN] {
+        assert_eq!(f_round_trip.call(&mut store, a)?.to_bits(), a.to_bits());
+    }
+    for a in [1.5, -0.0, f64::INFINITY, f64::NAN] {
+        assert_eq!(d_round_trip.call(&mut store, a)?.to_bits(), a.to_bits());
+    }
+
+    // Check raw bits are preserved, including non-canonical NaNs
+    assert_eq!(f_raw_round_trip.call(&mut store, 0x7fc00001)?, 0x7fc00001);
+    let nan = 0x7ff8000000000001;
+    assert_eq!(d_raw_round_trip.call(&mut store, nan)?, nan);
+
+    // Check non-raw bits collapse NaNs to the canonical NaN
+    assert_eq!(f_bits.call(&mut store, 1.5)?, 0x3fc00000);
+    assert_eq!(
+        f_bits.call(&mut store, f32::from_bits(0xffc00001))?,
+        0x7fc00000
+    );
+    assert_eq!(d_bits.call(&mut store, 1.5)?, 0x3ff8000000000000);
+    let nan = f64::from_bits(0xfff8000000000001);
+    assert_eq!(d_bits.call(&mut store, nan)?, 0x7ff8000000000000);
+
+    Ok(())
+}
+
+/// INVOKESTATIC java/lang/Math.{min,max} (float, double)
+#[test]
+fn min_max_intrinsics() -> anyhow::Result<()> {
+    let module = construct_code_module(
+        "public static float f_min(float a, float b) { return Math.min(a, b); }
+        public static float f_max(float a, float b) { return Math.max(a, b); }
+        public static double d_min(double a, double b) { return Math.min(a, b); }
+        public static double d_max(double a, double b) { return Math.max(a, b); }",
+    )?;
+    let module = Module::new(&WASM_ENGINE, module.finish())?;
+    let linker = Linker::new(&WASM_ENGINE);
+    let mut store = Store::new(&WASM_ENGINE, 0);
+    let instance = linker.instantiate(&mut store, &module)?;
+
+    let f_min = instance.get_typed_func::<(f32, f32), f32, _>(&mut store, "Test.f_min(FF)F")?;
+    let f_max = instance.get_typed_func::<(f32, f32), f32, _>(&mut store, "Test.f_max(FF)F")?;
+    let d_min = instance.get_typed_func::<(f64, f64), f64, _>(&mut store, "Test.d_min(DD)D")?;
+    let d_max = instance.get_typed_func::<(f64, f64), f64, _>(&mut store, "Test.d_max(DD)D")?;
+
+    // Check ordinary values, and -0.0 is treated as less than 0.0 in either order
+    assert_eq!(f_min.call(&mut store, (1.5, -2.0))?, -2.0);
+    assert_eq!(f_max.call(&mut store, (1.5, -2.0))?, 1.5);
+    for (a, b) in [(0.0, -0.0), (-0.0, 0.0)] {
+        assert_eq!(
+            f_min.call(&mut store, (a, b))?.to_bits(),
+            (-0.0f32).to_bits()
+        );
+        assert_eq!(f_max.call(&mut store, (a, b))?.to_bits(), 0.0f32.to_bits());
+    }
+    assert_eq!(d_min.call(&mut store, (1.5, -2.0))?, -2.0);
+    assert_eq!(d_max.call(&mut store, (1.5, -2.0))?, 1.5);
+    for (a, b) in [(0.0, -0.0), (-0.0, 0.0)] {
+        assert_eq!(
+            d_min.call(&mut store, (a, b))?.to_bits(),
+            (-0.0f64).to_bits()
+        );
+        assert_eq!(d_max.call(&mut store, (a, b))?.to_bits(), 0.0f64.to_bits());
+    }
+
+    // Check NaN is returned if either value is NaN, even if the other is infinite
+    for (a, b) in [
+        (f32::NAN, 1.0),
+        (1.0, f32::NAN),
+        (f32::NAN, f32::NEG_INFINITY),
+    ] {
+        assert!(f_min.call(&mut store, (a, b))?.is_nan());
+        assert!(f_max.call(&mut store, (a, b))?.is_nan());
+    }
+    for (a, b) in [(f64::NAN, 1.0), (1.0, f64::NAN), (f64::INFINITY, f64::NAN)] {
+        assert!(d_min.call(&mut store, (a, b))?.is_nan());
+        assert!(d_max.call(&mut store, (a, b))?.is_nan());
+    }
+
+    Ok(())
+}
+
+/// I2B, I2C, I2S, ISHR, IUSHR of narrowed values
+#[test]
+fn narrowing_shifts() -> anyhow::Result<()> {
+    let module = construct_code_module(
+        "public static char ushr_char(int x) { return (char) (x >>> 4); }
+        public static char shr_char(int x) { return (char) (x >> 4); }
+        public static int ushr_loaded_char(int x) { char c = (char) x; return c >>> 4; }
+        public static int shr_loaded_char(int x) { char c = (char) x; return c >> 4; }
+        public static byte shr_byte(int x) { return (byte) (x >> 4); }
+        public static short shr_short(int x) { return (short) (x >> 4); }",
+    )?;
+    let wasm = module.finish();
+    validate_functions(&wasm)?;
+    let module = Module::new(&WASM_ENGINE, wasm)?;
+    let linker = Linker::new(&WASM_ENGINE);
+    let mut store = Store::new(&WASM_ENGINE, 0);
+    let instance = linker.instantiate(&mut store, &module)?;
+
+    let ushr_char = instance.get_typed_func::<i32, i32, _>(&mut store, "Test.ushr_char(I)C")?;
+    let shr_char = instance.get_typed_func::<i32, i32, _>(&mut store, "Test.shr_char(I)C")?;
+    let ushr_loaded_char =
+        instance.get_typed_func::<i32, i32, _>(&mut store, "Test.ushr_loaded_char(I)I")?;
+    let shr_loaded_char =
+        instance.get_typed_func::<i32, i32, _>(&mut store, "Test.shr_loaded_char(I)I")?;
+    let shr_byte = instance.get_typed_func::<i32, i32, _>(&mut store, "Test.shr_byte(I)B")?;
+    let shr_short = instance.get_typed_func::<i32, i32, _>(&mut store, "Test.shr_short(I)S")?;
+
+    // High bit set, so >> and >>> differ before narrowing, but not in the low 16 bits kept
+    let x = 0xf2348678_u32 as i32;
+    assert_eq!(ushr_char.call(&mut store, x)?, 0x4867);
+    assert_eq!(shr_char.call(&mut store, x)?, 0x4867);
+    let x = 0xf234f678_u32 as i32;
+    assert_eq!(ushr_char.call(&mut store, x)?, 0x4f67);
+    assert_eq!(shr_char.call(&mut store, x)?, 0x4f67);
+
+    // Loaded chars are zero-extended, so shifting never brings in set bits
+    assert_eq!(ushr_loaded_char.call(&mut store, x)?, 0x0f67);
+    assert_eq!(shr_loaded_char.call(&mut store, x)?, 0x0f67);
+
+    // Bytes and shorts are sign-extended
+    assert_eq!(shr_byte.call(&mut store, 0x0f80)?, -8);
+    assert_eq!(shr_short.call(&mut store, 0x000f_8000)?, -2048);
+
+    Ok(())
+}
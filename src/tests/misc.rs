@@ -1,5 +1,39 @@
-use crate::tests::{construct_code_module, WASM_ENGINE};
-use wasmtime::{Linker, Module, Store, TrapCode};
+use crate::class::{load_class, FunctionType};
+use crate::function::{
+    compile_single, Assertions, CompileFunctionJob, CompileOptions, Function, Instruction,
+    UnsupportedBehaviour,
+};
+use crate::inspect::inspect_class;
+use crate::options::Options;
+use crate::output::{
+    diff_modules, report_unsupported, size_report, summarise_module, AssertMode, DemoMain,
+    RenderOptions, TrapKind, SOURCE_FILES_SECTION, TRAP_IMPORT,
+};
+use crate::scheduler::{Job, SerialScheduler, AUTO_PARALLEL_MIN_FUNCTIONS};
+use crate::tests::{
+    cache_path, capture_logs, compile_code_functions, compile_code_results, construct_code_module,
+    construct_code_module_with_options, engine_with_features, javac_code_with_args, load_code,
+    load_many_code, validate_functions, validate_functions_with_features, TargetFeatures,
+    WASM_ENGINE,
+};
+use crate::timings::SharedDuration;
+use crate::{
+    check_errors, check_roundtrip_wat, collect_functions, compile_functions,
+    construct_virtual_table, optimise_module, render_module, run, write_module,
+    Module as OutputModule,
+};
+use clap::Parser;
+use classfile_parser::method_info::MethodAccessFlags;
+use itertools::Itertools;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs;
+use std::sync::mpsc::channel;
+use std::sync::Arc;
+use std::time::Duration;
+use wasm_encoder::{Function as WASMFunction, Instruction as WASMInstruction, ValType};
+use wasmparser::{BinaryReader, Name, NameSectionReader, Parser as WasmParser, Payload};
+use wasmtime::{Caller, Linker, Module, Store, TrapCode};
 
 /// DUP
 #[test]
@@ -24,6 +58,92 @@ fn dup() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// DUP (float)
+#[test]
+fn dup_float() -> anyhow::Result<()> {
+    let module = construct_code_module(
+        "public static float chain(float x) {
+            float a, b;
+            a = b = x * 2; // DUP here, to store in both local variables
+            return a + b;
+        }",
+    )?;
+    let module = Module::new(&WASM_ENGINE, module.finish())?;
+    let linker = Linker::new(&WASM_ENGINE);
+    let mut store = Store::new(&WASM_ENGINE, 0);
+    let instance = linker.instantiate(&mut store, &module)?;
+
+    let chain = instance.get_typed_func::<f32, f32, _>(&mut store, "Test.chain(F)F")?;
+    assert_eq!(chain.call(&mut store, 1.5)?, 6.0);
+
+    Ok(())
+}
+
+/// DUP2 (long, double)
+#[test]
+fn dup2_category_2() -> anyhow::Result<()> {
+    let module = construct_code_module(
+        "public static long chain_long(long x) {
+            long a, b;
+            a = b = x * 2; // DUP2 here, to store in both local variables
+            return a + b * 3;
+        }
+
+        public static double chain_double(double x) {
+            double a, b;
+            a = b = x * 2; // DUP2 here, to store in both local variables
+            return a - b / 4;
+        }",
+    )?;
+    let module = Module::new(&WASM_ENGINE, module.finish())?;
+    let linker = Linker::new(&WASM_ENGINE);
+    let mut store = Store::new(&WASM_ENGINE, 0);
+    let instance = linker.instantiate(&mut store, &module)?;
+
+    let chain_long = instance.get_typed_func::<i64, i64, _>(&mut store, "Test.chain_long(J)J")?;
+    let chain_double =
+        instance.get_typed_func::<f64, f64, _>(&mut store, "Test.chain_double(D)D")?;
+    assert_eq!(chain_long.call(&mut store, 1 << 40)?, 8 << 40);
+    assert_eq!(chain_double.call(&mut store, 1.5)?, 2.25);
+
+    Ok(())
+}
+
+/// DUP at a merge point
+#[test]
+fn dup_stack_map() -> anyhow::Result<()> {
+    let code = "public static float select(boolean c, float x, float y) {
+            float a, b;
+            a = b = c ? x : y; // DUP here, at the start of the conditional's follow block
+            return a + b;
+        }";
+    let (_, functions) = compile_code_functions(code, 1)?;
+
+    // The duplicated value is pushed in both branches, so its type is only known from the stack
+    // map frame at the follow block
+    let select = functions
+        .iter()
+        .find(|f| f.id.to_string() == "Test.select(ZFF)F")
+        .unwrap();
+    let select_code = select.code.as_ref().unwrap();
+    assert!(select_code.contains(&Instruction::Dup(ValType::F32)));
+    assert!(!select_code.contains(&Instruction::Dup(ValType::I32)));
+
+    // Check the duplicated value is used correctly
+    let module = construct_code_module(code)?;
+    let module = Module::new(&WASM_ENGINE, module.finish())?;
+    let linker = Linker::new(&WASM_ENGINE);
+    let mut store = Store::new(&WASM_ENGINE, 0);
+    let instance = linker.instantiate(&mut store, &module)?;
+
+    let select =
+        instance.get_typed_func::<(i32, f32, f32), f32, _>(&mut store, "Test.select(ZFF)F")?;
+    assert_eq!(select.call(&mut store, (1, 2.0, 3.0))?, 4.0);
+    assert_eq!(select.call(&mut store, (0, 2.0, 3.0))?, 6.0);
+
+    Ok(())
+}
+
 /// ATHROW, RETURN
 #[test]
 fn assert() -> anyhow::Result<()> {
@@ -47,6 +167,155 @@ fn assert() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// ATHROW, RETURN with --assume-assertions
+#[test]
+fn assert_assume_assertions() -> anyhow::Result<()> {
+    for assertions in [Assertions::Enabled, Assertions::Disabled] {
+        let compile_options = CompileOptions {
+            assertions,
+            ..CompileOptions::default()
+        };
+        let module = construct_code_module_with_options(
+            "static boolean fail() { return false; }
+            public static int assert_fail(int a) { assert fail(); return a; }",
+            compile_options,
+            RenderOptions::default(),
+        )?;
+        let module = Module::new(&WASM_ENGINE, module.finish())?;
+        let linker = Linker::new(&WASM_ENGINE);
+        let mut store = Store::new(&WASM_ENGINE, 0);
+        let instance = linker.instantiate(&mut store, &module)?;
+
+        let assert_fail =
+            instance.get_typed_func::<i32, i32, _>(&mut store, "Test.assert_fail(I)I")?;
+        let res = assert_fail.call(&mut store, 42);
+        match assertions {
+            // Check assertion body is run, trapping as it fails
+            Assertions::Enabled => {
+                let res = res.unwrap_err();
+                assert_eq!(res.trap_code(), Some(TrapCode::UnreachableCodeReached));
+            }
+            // Check assertion body is skipped
+            Assertions::Disabled => assert_eq!(res?, 42),
+        }
+    }
+
+    Ok(())
+}
+
+/// --assert-mode host
+#[test]
+fn assert_mode_host() -> anyhow::Result<()> {
+    let code = "public static void assert_eq(int a, int b) { assert a == b; }
+        public static void assert_object(Object o, int n) { assert n > 0 : o; }
+        public static void assert_int(int n) { assert n > 0 : n; }";
+    let module = construct_code_module_with_options(
+        code,
+        CompileOptions::default(),
+        RenderOptions {
+            assert_mode: AssertMode::Host,
+            ..RenderOptions::default()
+        },
+    )?;
+    let module = Module::new(&WASM_ENGINE, module.finish())?;
+
+    // Record the detail message references the host is called with
+    let mut linker = Linker::new(&WASM_ENGINE);
+    linker.func_wrap(
+        "imports",
+        "onAssertionFailure",
+        |mut caller: Caller<'_, Vec<i32>>, message: i32| caller.data_mut().push(message),
+    )?;
+    let mut store = Store::new(&WASM_ENGINE, vec![]);
+    let instance = linker.instantiate(&mut store, &module)?;
+
+    let assert_eq =
+        instance.get_typed_func::<(i32, i32), (), _>(&mut store, "Test.assert_eq(II)V")?;
+    let assert_object = instance.get_typed_func::<(i32, i32), (), _>(
+        &mut store,
+        "Test.assert_object(Ljava/lang/Object;I)V",
+    )?;
+    let assert_int = instance.get_typed_func::<i32, (), _>(&mut store, "Test.assert_int(I)V")?;
+
+    // Check host isn't called if assertions pass
+    assert_eq.call(&mut store, (1, 1))?;
+    assert_object.call(&mut store, (1234, 1))?;
+    assert_int.call(&mut store, 1)?;
+    assert!(store.data().is_empty());
+
+    // Check host is called before trapping if assertions fail, with the message if it's an object
+    let res = assert_eq.call(&mut store, (1, 2)).unwrap_err();
+    assert_eq!(res.trap_code(), Some(TrapCode::UnreachableCodeReached));
+    let res = assert_object.call(&mut store, (1234, 0)).unwrap_err();
+    assert_eq!(res.trap_code(), Some(TrapCode::UnreachableCodeReached));
+    let res = assert_int.call(&mut store, -1).unwrap_err();
+    assert_eq!(res.trap_code(), Some(TrapCode::UnreachableCodeReached));
+    assert_eq!(store.data(), &[0, 1234, 0]);
+
+    Ok(())
+}
+
+/// --rich-traps
+#[test]
+fn rich_traps() -> anyhow::Result<()> {
+    let code = "int i;
+        int get() { return i; }
+
+        public static int get_int(Test t) { return t.i; }
+        public static int call_get(Test t) { return t.get(); }
+        public static void assert_positive(int n) { assert n > 0; }";
+    let module = construct_code_module_with_options(
+        code,
+        CompileOptions::default(),
+        RenderOptions {
+            null_checks: true,
+            rich_traps: true,
+            ..RenderOptions::default()
+        },
+    )?;
+    let module = Module::new(&WASM_ENGINE, module.finish())?;
+
+    // Record the codes the host is called with
+    let mut linker = Linker::new(&WASM_ENGINE);
+    linker.func_wrap(
+        "imports",
+        TRAP_IMPORT,
+        |mut caller: Caller<'_, Vec<i32>>, code: i32| caller.data_mut().push(code),
+    )?;
+    let mut store = Store::new(&WASM_ENGINE, vec![]);
+    let instance = linker.instantiate(&mut store, &module)?;
+
+    let get_int = instance.get_typed_func::<i32, i32, _>(&mut store, "Test.get_int(LTest;)I")?;
+    let call_get = instance.get_typed_func::<i32, i32, _>(&mut store, "Test.call_get(LTest;)I")?;
+    let assert_positive =
+        instance.get_typed_func::<i32, (), _>(&mut store, "Test.assert_positive(I)V")?;
+
+    // Check host isn't called if nothing traps
+    assert_positive.call(&mut store, 1)?;
+    assert!(store.data().is_empty());
+
+    // Check null field accesses call the host with the null pointer code before trapping
+    let res = get_int.call(&mut store, 0).unwrap_err();
+    assert_eq!(res.trap_code(), Some(TrapCode::UnreachableCodeReached));
+    assert_eq!(store.data(), &[TrapKind::NullPointer.code()]);
+
+    // Check null virtual calls (checked by the dispatcher) and failed assertions do too
+    let res = call_get.call(&mut store, 0).unwrap_err();
+    assert_eq!(res.trap_code(), Some(TrapCode::UnreachableCodeReached));
+    let res = assert_positive.call(&mut store, -1).unwrap_err();
+    assert_eq!(res.trap_code(), Some(TrapCode::UnreachableCodeReached));
+    assert_eq!(
+        store.data(),
+        &[
+            TrapKind::NullPointer.code(),
+            TrapKind::NullPointer.code(),
+            TrapKind::Throw.code()
+        ]
+    );
+
+    Ok(())
+}
+
 #[test]
 fn native() -> anyhow::Result<()> {
     let module = construct_code_module(
@@ -248,3 +517,1241 @@ fn advanced_kitchen_sink_example() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn deduplicate_identical_functions() -> anyhow::Result<()> {
+    let module = construct_code_module(
+        "public static int succ_a(int x) { return x + 1; }
+        public static int succ_b(int x) { return x + 1; }
+        public static int pred(int x) { return x - 1; }",
+    )?;
+    // Implicit constructor, `succ_a`/`succ_b` sharing a body, `pred`, and the super ID function
+    assert_eq!(module.codes.len(), 4);
+    let module = Module::new(&WASM_ENGINE, module.finish())?;
+    let linker = Linker::new(&WASM_ENGINE);
+    let mut store = Store::new(&WASM_ENGINE, 0);
+    let instance = linker.instantiate(&mut store, &module)?;
+
+    // Check both methods are still exported
+    let succ_a = instance.get_typed_func::<i32, i32, _>(&mut store, "Test.succ_a(I)I")?;
+    let succ_b = instance.get_typed_func::<i32, i32, _>(&mut store, "Test.succ_b(I)I")?;
+    let pred = instance.get_typed_func::<i32, i32, _>(&mut store, "Test.pred(I)I")?;
+    assert_eq!(succ_a.call(&mut store, 1)?, 2);
+    assert_eq!(succ_b.call(&mut store, 1)?, 2);
+    assert_eq!(pred.call(&mut store, 1)?, 0);
+
+    Ok(())
+}
+
+/// SourceFile attribute
+#[test]
+fn source_files_section() -> anyhow::Result<()> {
+    let code = "static class Inner { int get() { return 1; } }
+        public static int get() { return new Inner().get() + 1; }";
+    // Returns the debug name and source file of each function in the source files section
+    let source_files = |wasm: &[u8]| -> anyhow::Result<Vec<(String, String)>> {
+        let mut names = HashMap::new();
+        let mut source_files = vec![];
+        for payload in WasmParser::new(0).parse_all(wasm) {
+            match payload? {
+                Payload::CustomSection {
+                    name: "name",
+                    data,
+                    data_offset,
+                    ..
+                } => {
+                    let mut reader = NameSectionReader::new(data, data_offset)?;
+                    while !reader.eof() {
+                        if let Name::Function(function_names) = reader.read()? {
+                            let mut function_names = function_names.get_map()?;
+                            for _ in 0..function_names.get_count() {
+                                let naming = function_names.read()?;
+                                names.insert(naming.index, naming.name);
+                            }
+                        }
+                    }
+                }
+                Payload::CustomSection {
+                    name: SOURCE_FILES_SECTION,
+                    data,
+                    ..
+                } => {
+                    let mut reader = BinaryReader::new(data);
+                    for _ in 0..reader.read_var_u32()? {
+                        let index = reader.read_var_u32()?;
+                        source_files.push((index, reader.read_string()?));
+                    }
+                }
+                _ => {}
+            }
+        }
+        let source_files = source_files
+            .into_iter()
+            .map(|(index, source_file)| (names[&index].to_string(), source_file.to_string()));
+        Ok(source_files.collect())
+    };
+
+    // Check user-defined functions record the file their class was compiled from, but built-ins
+    // and dispatchers don't. Both constructors share a body, so only have one function.
+    let wasm = construct_code_module(code)?.finish();
+    let expected = ["Test.<init>__V", "Test.get__I", "Test$Inner.get__I"];
+    let expected = expected
+        .iter()
+        .map(|&name| (String::from(name), String::from("Test.java")))
+        .collect::<Vec<_>>();
+    assert_eq!(source_files(&wasm)?, expected);
+
+    // Check the section is omitted if no source files were recorded
+    let (classes, mut functions) = compile_code_functions(code, 1)?;
+    for function in &mut functions {
+        function.source_file = None;
+    }
+    let classes = Arc::new(classes);
+    let virtual_table = construct_virtual_table(None, &classes)?;
+    let module = render_module(classes, virtual_table, functions, RenderOptions::default())?;
+    assert_eq!(source_files(&module.finish())?, []);
+
+    Ok(())
+}
+
+/// --on-unsupported stub, --on-unsupported trap
+#[test]
+fn on_unsupported() -> anyhow::Result<()> {
+    // Static fields aren't supported, so the PUTSTATIC instruction can't be compiled
+    let code = "static class Box { int value; }
+        static int sink;
+
+        public static Box make() { return new Box(); }
+        public static int get(Box box) { return box.value; }
+        public static void run(Box box) { box.value = 1; sink = 2; box.value = 3; }";
+
+    // Check functions containing unsupported instructions fail to compile by default
+    let err = construct_code_module(code).map(|_| ()).unwrap_err();
+    assert_eq!(
+        format!("{}", err),
+        "Unable to compile Test.run(LTest$Box;)V"
+    );
+
+    for (on_unsupported, expected) in [
+        (UnsupportedBehaviour::Trap, 1),
+        (UnsupportedBehaviour::Stub, 3),
+    ] {
+        let compile_options = CompileOptions {
+            on_unsupported,
+            ..CompileOptions::default()
+        };
+        let module =
+            construct_code_module_with_options(code, compile_options, RenderOptions::default())?;
+        let module = Module::new(&WASM_ENGINE, module.finish())?;
+        let linker = Linker::new(&WASM_ENGINE);
+        let mut store = Store::new(&WASM_ENGINE, 0);
+        let instance = linker.instantiate(&mut store, &module)?;
+
+        let make = instance.get_typed_func::<(), i32, _>(&mut store, "Test.make()LTest$Box;")?;
+        let get = instance.get_typed_func::<i32, i32, _>(&mut store, "Test.get(LTest$Box;)I")?;
+        let run = instance.get_typed_func::<i32, (), _>(&mut store, "Test.run(LTest$Box;)V")?;
+
+        // Check instructions before the unsupported one run, and only stubs run those after
+        let box_ref = make.call(&mut store, ())?;
+        let res = run.call(&mut store, box_ref);
+        if on_unsupported == UnsupportedBehaviour::Trap {
+            let trap_code = res.unwrap_err().trap_code();
+            assert_eq!(trap_code, Some(TrapCode::UnreachableCodeReached));
+        } else {
+            res?;
+        }
+        assert_eq!(get.call(&mut store, box_ref)?, expected);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn max_errors() -> anyhow::Result<()> {
+    // Arrays aren't supported, so each of these methods will fail to compile
+    let code = "static int a() { return new int[1].length; }
+        static int b() { return new int[2].length; }
+        static int c() { return new int[3].length; }";
+
+    // Check only the first error is reported by default
+    let err = compile_code_functions(code, 1).unwrap_err();
+    assert_eq!(format!("{}", err), "Unable to compile Test.a()I");
+
+    // Check errors are accumulated up to the limit
+    let err = compile_code_functions(code, 2).unwrap_err();
+    let message = format!("{}", err);
+    assert!(message.starts_with("Unable to compile 2 functions:"));
+    assert!(message.contains("Test.a()I: Newarray instruction unimplemented (Array)"));
+    assert!(message.contains("Test.b()I: Newarray instruction unimplemented (Array)"));
+    assert!(!message.contains("Test.c()I"));
+
+    // Check all errors are reported with no limit
+    let err = compile_code_functions(code, 0).unwrap_err();
+    let message = format!("{}", err);
+    assert!(message.starts_with("Unable to compile 3 functions:"));
+    assert!(message.contains("Test.c()I: Newarray instruction unimplemented (Array)"));
+
+    Ok(())
+}
+
+#[test]
+fn max_errors_closed_receiver() -> anyhow::Result<()> {
+    let class = load_code(
+        "static int a() { return new int[1].length; }
+        static int b() { return new int[2].length; }",
+    )?;
+    let (function_tx, function_rx) = channel();
+    let job = |function: &Arc<Function>| CompileFunctionJob {
+        function: Arc::clone(function),
+        graphs_dir: None,
+        options: CompileOptions::default(),
+        result_tx: function_tx.clone(),
+        compile_duration: SharedDuration::default(),
+    };
+    let mut functions = class.methods.iter().filter(|f| *f.id.name != "<init>");
+
+    // Stop waiting for results after the first error, dropping the receiver
+    job(functions.next().unwrap()).process();
+    let (_, errors) = collect_functions(2, function_rx, 1);
+    assert_eq!(errors.len(), 1);
+
+    // Check workers still compiling functions don't panic when sending their results
+    job(functions.next().unwrap()).process();
+
+    Ok(())
+}
+
+#[test]
+fn error_names_method() -> anyhow::Result<()> {
+    // Endless loops can't be structured, so only `spin` will fail to compile, before any
+    // instructions are visited
+    let code = "static int add(int a, int b) { return a + b; }
+        static void spin() { while (true) {} }
+        static int sub(int a, int b) { return a - b; }";
+
+    // Check the error identifies the method by class, name and descriptor, with the cause
+    let err = compile_code_functions(code, 0).unwrap_err();
+    assert_eq!(format!("{}", err), "Unable to compile Test.spin()V");
+    assert_eq!(
+        format!("{:#}", err),
+        "Unable to compile Test.spin()V: Endless loops are not yet supported"
+    );
+
+    Ok(())
+}
+
+/// try/finally
+#[test]
+fn try_finally() -> anyhow::Result<()> {
+    let module = construct_code_module(
+        "static native void sideEffect();
+        public static int run() { try { return 1; } finally { sideEffect(); } }
+        public static int add(int a, int b) { int c; try { c = a + b; } finally { sideEffect(); } return c; }",
+    )?;
+    let module = Module::new(&WASM_ENGINE, module.finish())?;
+
+    // Count calls to the native method from `finally` blocks
+    let mut linker = Linker::new(&WASM_ENGINE);
+    linker.func_wrap(
+        "imports",
+        "Test.sideEffect()V",
+        |mut caller: Caller<'_, i32>| *caller.data_mut() += 1,
+    )?;
+    let mut store = Store::new(&WASM_ENGINE, 0);
+    let instance = linker.instantiate(&mut store, &module)?;
+
+    let run = instance.get_typed_func::<(), i32, _>(&mut store, "Test.run()I")?;
+    let add = instance.get_typed_func::<(i32, i32), i32, _>(&mut store, "Test.add(II)I")?;
+
+    // Check `finally` blocks run when returning from, and falling out of, `try` blocks
+    assert_eq!(run.call(&mut store, ())?, 1);
+    assert_eq!(*store.data(), 1);
+    assert_eq!(add.call(&mut store, (1, 2))?, 3);
+    assert_eq!(*store.data(), 2);
+
+    Ok(())
+}
+
+#[test]
+fn catch_clauses_unimplemented() -> anyhow::Result<()> {
+    // Exception tables are parsed, but exceptions can't be caught yet
+    let code = "static int divide(int x) { try { return 1 / x; } catch (ArithmeticException e) { return -1; } }";
+    let err = compile_code_functions(code, 0).unwrap_err();
+    assert_eq!(
+        format!("{:#}", err),
+        "Unable to compile Test.divide(I)I: Catch clauses unimplemented (Exception)"
+    );
+
+    Ok(())
+}
+
+/// --keep-going
+#[test]
+fn keep_going() -> anyhow::Result<()> {
+    // Arrays aren't supported, so `length` will fail to compile
+    let code = "public static int add(int a, int b) { return a + b; }
+        public static int length() { return new int[1].length; }
+        public static int sub(int a, int b) { return a - b; }";
+
+    // Check the failed function is replaced with a stub instead of failing
+    let options = CompileOptions {
+        keep_going: true,
+        ..CompileOptions::default()
+    };
+    let module = construct_code_module_with_options(code, options, RenderOptions::default())?;
+    let module = Module::new(&WASM_ENGINE, module.finish())?;
+    let linker = Linker::new(&WASM_ENGINE);
+    let mut store = Store::new(&WASM_ENGINE, 0);
+    let instance = linker.instantiate(&mut store, &module)?;
+
+    // Check functions that compiled still run, and the stub traps
+    let add = instance.get_typed_func::<(i32, i32), i32, _>(&mut store, "Test.add(II)I")?;
+    let length = instance.get_typed_func::<(), i32, _>(&mut store, "Test.length()I")?;
+    let sub = instance.get_typed_func::<(i32, i32), i32, _>(&mut store, "Test.sub(II)I")?;
+    assert_eq!(add.call(&mut store, (1, 2))?, 3);
+    assert_eq!(sub.call(&mut store, (5, 3))?, 2);
+    let trap_code = length.call(&mut store, ()).unwrap_err().trap_code();
+    assert_eq!(trap_code, Some(TrapCode::UnreachableCodeReached));
+
+    Ok(())
+}
+
+/// montera inspect
+#[test]
+fn inspect() -> anyhow::Result<()> {
+    let class = load_code(
+        "static int add(int a, int b) { return a + b; }
+        static int length() { return new int[4].length; }
+        static native int external(int a);
+        static int run(int x) { try { return 1 / x; } catch (ArithmeticException e) { return -1; } }",
+    )?;
+    let output = inspect_class(&class);
+
+    // Check every method is listed, including the constructor, with all unsupported instructions
+    // (not just the first) and features that can't be replaced
+    assert!(output.starts_with("Test.<init>()V\n"));
+    assert!(output.contains("Test.add(II)I\n  flags: STATIC\n  code: yes\n  unsupported: none\n"));
+    assert!(output.contains(
+        "Test.length()I\n  flags: STATIC\n  code: yes\n  unsupported: Newarray instruction (Array), Arraylength instruction (Array)\n"
+    ));
+    assert!(output.contains(
+        "Test.external(I)I\n  flags: STATIC | NATIVE\n  code: no\n  unsupported: none\n"
+    ));
+    assert!(output.contains(
+        "Test.run(I)I\n  flags: STATIC\n  code: yes\n  unsupported: Catch clauses (Exception)\n"
+    ));
+    assert_eq!(output.matches("  flags: ").count(), 5);
+
+    Ok(())
+}
+
+/// --report-unsupported-json
+#[test]
+fn report_unsupported_json() -> anyhow::Result<()> {
+    // Arrays and catch clauses aren't supported, but `add` compiles
+    let code = "static int add(int a, int b) { return a + b; }
+        static int length() { return new int[4].length; }
+        static int sum(int[] a) { return a[0] + a[1]; }
+        static int run(int x) { try { return 1 / x; } catch (ArithmeticException e) { return -1; } }";
+    let expected = r#"{
+  "methods": [
+    {"method": "Test.length()I", "unsupported": [{"description": "Newarray instruction", "category": "Array"}, {"description": "Arraylength instruction", "category": "Array"}]},
+    {"method": "Test.run(I)I", "unsupported": [{"description": "Catch clauses", "category": "Exception"}]},
+    {"method": "Test.sum([I)I", "unsupported": [{"description": "Iaload instruction", "category": "Array"}, {"description": "Iaload instruction", "category": "Array"}]}
+  ],
+  "categories": [
+    {"category": "Array", "count": 4},
+    {"category": "Exception", "count": 1}
+  ]
+}
+"#;
+
+    // Check replaced instructions and failed functions are both reported, with every replaced
+    // instruction listed
+    let options = CompileOptions {
+        on_unsupported: UnsupportedBehaviour::Trap,
+        ..CompileOptions::default()
+    };
+    let ((_, functions), errors) = compile_code_results(code, 0, options)?;
+    assert_eq!(errors.len(), 1);
+    assert_eq!(report_unsupported(&functions, &errors), expected);
+
+    // Check functions compiled to stubs report the feature that caused them to fail
+    let options = CompileOptions {
+        keep_going: true,
+        ..CompileOptions::default()
+    };
+    let ((_, functions), errors) = compile_code_results(code, 0, options)?;
+    assert!(errors.is_empty());
+    let report = report_unsupported(&functions, &errors);
+    assert!(report.contains(
+        r#"{"method": "Test.length()I", "unsupported": [{"description": "Newarray instruction", "category": "Array"}]}"#
+    ));
+    assert!(report.contains(r#"{"category": "Exception", "count": 1}"#));
+
+    Ok(())
+}
+
+#[test]
+fn timings_populated() -> anyhow::Result<()> {
+    let paths = javac_code_with_args(
+        "int a;
+        Test(int a) { this.a = a; }
+
+        int get() { return a; }
+
+        public static int sum(int n) {
+            int total = 0;
+            for (int i = 0; i < n; i++) {
+                if (i % 2 == 0) { total += new Test(i).get(); } else { total -= i; }
+            }
+            return total;
+        }",
+        &[],
+    )?;
+    let output_dir = cache_path("timings");
+    let mut args = vec![
+        String::from("montera"),
+        String::from("--optimise"),
+        String::from("--output-dir"),
+        output_dir.display().to_string(),
+    ];
+    args.extend(paths.iter().map(|path| path.display().to_string()));
+
+    // Run the main entrypoint's pipeline, recording timings
+    let timings = run(Options::try_parse_from(args)?)?;
+
+    // Check every phase took some time
+    for (name, duration) in timings.phases() {
+        assert!(duration > Duration::ZERO, "{} took no time", name);
+    }
+
+    Ok(())
+}
+
+/// --scheduler auto
+#[test]
+fn scheduler_auto() -> anyhow::Result<()> {
+    let run_auto = |code: &str, key: &str| -> anyhow::Result<Vec<String>> {
+        let paths = javac_code_with_args(code, &[])?;
+        let output_dir = cache_path(key);
+        let mut args = vec![
+            String::from("montera"),
+            String::from("--scheduler"),
+            String::from("auto"),
+            String::from("--output-dir"),
+            output_dir.display().to_string(),
+        ];
+        args.extend(paths.iter().map(|path| path.display().to_string()));
+        let opts = Options::try_parse_from(args)?;
+        let (result, logs) = capture_logs(|| run(opts));
+        result?;
+        Ok(logs)
+    };
+
+    // Check a single function (plus the implicit constructor) is compiled serially...
+    let logs = run_auto("static int one() { return 1; }", "scheduler_auto_serial")?;
+    assert!(logs.iter().any(|log| log == "Using 1 worker..."));
+
+    // ...but many functions are compiled in parallel, if enabled
+    let code = (0..AUTO_PARALLEL_MIN_FUNCTIONS)
+        .map(|i| format!("static int f{0}() {{ return {0}; }}", i))
+        .join("\n");
+    let logs = run_auto(&code, "scheduler_auto_parallel")?;
+    let serial = logs.iter().any(|log| log == "Using 1 worker...");
+    let parallel_enabled = cfg!(any(
+        feature = "parallel_scheduler",
+        feature = "rayon_scheduler"
+    ));
+    assert_eq!(serial, !parallel_enabled);
+
+    Ok(())
+}
+
+/// Parallel rendering
+#[test]
+fn parallel_render_identical() -> anyhow::Result<()> {
+    // Include plenty of functions with different dependencies, so they're split across threads
+    // and built-ins/dispatchers are required in different orders
+    let code = "abstract static class Shape { abstract double area(); }
+        static class Square extends Shape {
+            long side;
+            Square(long side) { this.side = side; }
+            double area() { return side * side; }
+            int bigger(Square other) {
+                if (side > other.side) return 1;
+                return 0;
+            }
+        }
+        static class Circle extends Shape {
+            float radius;
+            Circle(float radius) { this.radius = radius; }
+            double area() { return 3.14 * radius * radius; }
+            float mod(float x) { return radius % x; }
+            int countdown(int n) {
+                if (n == 0) return 0;
+                return countdown(n - 1);
+            }
+        }
+        public static int allocate(float x) {
+            Shape shape = new Circle(x);
+            if (shape instanceof Square) return 1;
+            return (int) (x % 2);
+        }
+        public static double area(Shape shape) { return shape.area(); }
+        public static boolean isSquare(Shape shape) { return shape instanceof Square; }
+        public static double rem(double a, double b) { return a % b; }
+        public static int cmp(float a, float b) {
+            if (a < b) return 1;
+            return 0;
+        }
+        public static boolean same(Shape a, Shape b) { return a.equals(b); }";
+
+    // Tail calls to dispatchers are patched too
+    let compile_options = CompileOptions {
+        tail_calls: true,
+        ..CompileOptions::default()
+    };
+    let render = |render_threads| -> anyhow::Result<Vec<u8>> {
+        let module = construct_code_module_with_options(
+            code,
+            compile_options,
+            RenderOptions {
+                null_checks: true,
+                render_threads,
+                ..RenderOptions::default()
+            },
+        )?;
+        Ok(module.finish())
+    };
+
+    // Check rendering across any number of threads produces identical bytes to rendering serially
+    let serial = render(1)?;
+    let features = TargetFeatures {
+        tail_call: true,
+        ..TargetFeatures::default()
+    };
+    validate_functions_with_features(&serial, features)?;
+    assert!(wasmprinter::print_bytes(&serial)?.contains("return_call"));
+    for render_threads in [2, 3, 8, 64] {
+        assert!(
+            render(render_threads)? == serial,
+            "{} threads",
+            render_threads
+        );
+    }
+
+    Ok(())
+}
+
+/// --scheduler serial, --scheduler parallel
+#[cfg(feature = "parallel_scheduler")]
+#[test]
+fn serial_parallel_schedulers_identical() -> anyhow::Result<()> {
+    use crate::scheduler::{Scheduler, WorkerScheduler};
+
+    let code = "static abstract class Animal {
+            int legs;
+            Animal(int legs) { this.legs = legs; }
+            abstract long noise(long volume);
+            int legs() { return legs; }
+        }
+        static class Dog extends Animal {
+            Dog() { super(4); }
+            long noise(long volume) { return volume * 2; }
+        }
+        static class Bird extends Animal {
+            double wingspan;
+            Bird(double wingspan) { super(2); this.wingspan = wingspan; }
+            long noise(long volume) { return volume + legs; }
+        }
+        static class Parrot extends Bird {
+            Parrot() { super(0.5); }
+            long noise(long volume) { return volume * volume; }
+        }
+        static class Counter {
+            int count;
+            void add(Animal a) { count += a.legs(); }
+        }
+
+        public static long total(int n, long volume) {
+            Counter counter = new Counter();
+            long total = 0;
+            for (int i = 0; i < n; i++) {
+                Animal a;
+                if (i % 3 == 0) { a = new Dog(); } else if (i % 3 == 1) { a = new Bird(1.5); } else { a = new Parrot(); }
+                counter.add(a);
+                total += a.noise(volume);
+            }
+            return total + counter.count;
+        }
+        public static boolean is_bird(Animal a) { if (a instanceof Bird) { return true; } return false; }";
+
+    // Compile and render the same classes like the main entrypoint, with jobs scheduled by `schd`
+    let compile = |schd: Box<dyn Scheduler>, render_threads| -> anyhow::Result<Vec<u8>> {
+        let classes = load_many_code(code)?;
+        let class_count = classes.len();
+        let (class_tx, class_rx) = channel();
+        for (_, class) in classes.into_iter() {
+            class_tx.send(Ok(class))?;
+        }
+        drop(class_tx);
+        let (classes, function_count, function_rx) = compile_functions(
+            &schd,
+            None,
+            CompileOptions::default(),
+            &SharedDuration::default(),
+            class_count,
+            class_rx,
+        )?;
+        let (functions, errors) = collect_functions(function_count, function_rx, 1);
+        check_errors(errors)?;
+        let classes = Arc::new(classes);
+        let virtual_table = construct_virtual_table(None, &classes)?;
+        let module = render_module(
+            classes,
+            virtual_table,
+            functions,
+            RenderOptions {
+                render_threads,
+                ..RenderOptions::default()
+            },
+        )?;
+        Ok(module.finish())
+    };
+
+    // Check compiling in parallel produces byte-identical output to compiling serially, even
+    // though functions finish compiling in a different order each time
+    let serial = compile(Box::new(SerialScheduler {}), 1)?;
+    validate_functions(&serial)?;
+    for workers in [2, 4, 8] {
+        let parallel = compile(Box::new(WorkerScheduler::new(workers)), workers)?;
+        assert!(parallel == serial, "{} workers", workers);
+    }
+
+    Ok(())
+}
+
+/// --roundtrip-wat
+#[test]
+fn roundtrip_wat() -> anyhow::Result<()> {
+    // Include virtual calls, fields, loops and conditionals, so the module contains tables,
+    // elements, memory, globals and names
+    let module = construct_code_module(
+        "static abstract class Shape {
+            int scale;
+            Shape(int scale) { this.scale = scale; }
+            abstract long area();
+        }
+
+        static class Square extends Shape {
+            Square(int scale) { super(scale); }
+            long area() { return (long) scale * scale; }
+        }
+
+        static class Triangle extends Shape {
+            Triangle(int scale) { super(scale); }
+            long area() { return (long) scale * scale / 2; }
+        }
+
+        public static long total(int n) {
+            long total = 0;
+            for (int i = 0; i < n; i++) {
+                Shape s;
+                if (i % 2 == 0) { s = new Square(i); } else { s = new Triangle(i); }
+                total += s.area();
+            }
+            return total;
+        }",
+    )?;
+    let wasm_bytes = module.finish();
+
+    // Check writing with round-tripping enabled succeeds
+    let output_dir = cache_path("roundtrip_wat");
+    fs::create_dir_all(&output_dir)?;
+    write_module(&output_dir.join("Test"), &wasm_bytes, "wasm", "wat", true)?;
+    let wat = fs::read_to_string(output_dir.join("Test.wat"))?;
+    check_roundtrip_wat(&wasm_bytes, &wat)?;
+
+    // Check differences between the text and binary are detected
+    let tampered = wat.replace("i64.mul", "i64.add");
+    assert_ne!(tampered, wat);
+    let err = check_roundtrip_wat(&wasm_bytes, &tampered).unwrap_err();
+    assert!(err.to_string().starts_with("Rendered text differs"));
+
+    Ok(())
+}
+
+/// --dump-json-module
+#[test]
+fn dump_json_module() -> anyhow::Result<()> {
+    let module = construct_code_module(
+        "static class Counter {
+            int n;
+            int add(int x) { n += x; return n; }
+        }
+
+        public static int count(int x) {
+            Counter c = new Counter();
+            c.add(x);
+            return c.add(1);
+        }",
+    )?;
+    let summary = summarise_module(&module.finish())?;
+
+    // Check functions are listed by their name section names with their signatures
+    assert!(summary.contains(r#"{"name": "Test.count_I_I", "type": "[i32] -> [i32]"}"#));
+    assert!(summary.contains(r#"{"name": "Test$Counter.add_I_I", "type": "[i32, i32] -> [i32]"}"#));
+    // Check only the public static method is exported
+    assert!(summary.contains(
+        r#"  "exports": [
+    {"name": "Test.count(I)I", "function": "Test.count_I_I"}
+  ],"#
+    ));
+    // Check allocating and calling a virtual method required built-ins
+    assert!(summary.contains(
+        r#"  "builtins": [
+    "!Allocate",
+    "!Dispatcher_i32_i32"
+  ],"#
+    ));
+    assert!(summary.contains(r#""imports": []"#));
+    assert!(summary.contains(r#""virtual_table": ["#));
+    Ok(())
+}
+
+/// --diff
+#[test]
+fn diff_modules_changed_function() -> anyhow::Result<()> {
+    let baseline = construct_code_module(
+        "public static int old(int x) { return x - 1; }
+        public static int square(int x) { return x * x; }
+        public static int twice(int x) { return square(x) + square(x); }",
+    )?
+    .finish();
+    let new = construct_code_module(
+        "public static int square(int x) { return x * x + 1; }
+        public static int twice(int x) { return square(x) + square(x); }
+        public static long added(long x) { return x; }",
+    )?
+    .finish();
+
+    // Check modules are identical to themselves
+    assert!(diff_modules(&baseline, &baseline)?.is_empty());
+
+    // Check only the modified function is changed, even though removing a function shifted the
+    // indices of those after it, changing `twice`'s call instructions' bytes. Functions are matched
+    // by their name section names.
+    let diff = diff_modules(&new, &baseline)?;
+    assert_eq!(diff.added, ["Test.added_J_J"]);
+    assert_eq!(diff.removed, ["Test.old_I_I"]);
+    assert_eq!(diff.changed, ["Test.square_I_I"]);
+    assert_eq!(
+        diff.to_string(),
+        "+ Test.added_J_J\n- Test.old_I_I\n~ Test.square_I_I"
+    );
+    Ok(())
+}
+
+/// --size-report
+#[test]
+fn size_report_functions() -> anyhow::Result<()> {
+    let wasm_bytes = construct_code_module(
+        "public static int small(int x) { return x; }
+        public static int large(int x, int y) {
+            int total = 0;
+            while (x < y) { total += x * y; x++; }
+            return total;
+        }",
+    )?
+    .finish();
+    let report = size_report(&wasm_bytes)?;
+
+    // Check both functions are listed with plausible sizes, largest first
+    let size = |name: &str| {
+        let function = report.functions.iter().find(|(n, _)| n == name);
+        function
+            .map(|(_, size)| *size)
+            .expect("Function not in report")
+    };
+    let small = size("Test.small_I_I");
+    let large = size("Test.large_II_I");
+    assert!(small > 0);
+    assert!(large > small);
+    let sizes = report.functions.iter().map(|(_, size)| *size);
+    assert!(sizes.clone().zip(sizes.skip(1)).all(|(a, b)| a >= b));
+
+    // Check the code section contains every body, and the report lists each on its own line
+    let total: usize = report.functions.iter().map(|(_, size)| size).sum();
+    assert!(report.code_size > total);
+    let text = report.to_string();
+    assert!(text.starts_with(&format!("Code section: {} byte(s)\n", report.code_size)));
+    assert_eq!(text.lines().count(), report.functions.len() + 1);
+    Ok(())
+}
+
+/// --output-dir
+#[test]
+fn output_dir() -> anyhow::Result<()> {
+    let dir = cache_path("output_dir");
+    if dir.exists() {
+        fs::remove_dir_all(&dir)?;
+    }
+    let args = [
+        "montera",
+        "--output-dir",
+        dir.to_str().unwrap(),
+        "--graphs",
+        "graphs",
+        "--graphs-format",
+        "graphml",
+        "--dump-json-module",
+        "summary.json",
+        "Test.class",
+    ];
+    let opts = Options::try_parse_from(args)?;
+
+    // Check the output directory can't be combined with an output path, but replaces it
+    let mut conflicting = args.to_vec();
+    conflicting.extend(["-o", "Test.wasm"]);
+    assert!(Options::try_parse_from(conflicting).is_err());
+    assert!(Options::try_parse_from(["montera", "Test.class"]).is_err());
+    let module_path = opts.module_path().unwrap();
+    assert_eq!(module_path, dir.join("module"));
+
+    // Write every output the same way as the main entrypoint
+    let graphs_root_dir = opts.artifact_path(opts.graphs_root_dir.as_ref().unwrap());
+    let classes = load_many_code("public static int f(int x) { return x + 1; }")?;
+    let class_count = classes.len();
+    let (class_tx, class_rx) = channel();
+    for (_, class) in classes.into_iter() {
+        class_tx.send(Ok(class))?;
+    }
+    drop(class_tx);
+    let options = CompileOptions {
+        graphs_format: opts.graphs_format,
+        ..CompileOptions::default()
+    };
+    let schd = SerialScheduler {};
+    let (classes, function_count, function_rx) = compile_functions(
+        &schd,
+        Some(&graphs_root_dir),
+        options,
+        &SharedDuration::default(),
+        class_count,
+        class_rx,
+    )?;
+    let (functions, errors) = collect_functions(function_count, function_rx, 1);
+    check_errors(errors)?;
+    let classes = Arc::new(classes);
+    let virtual_table = construct_virtual_table(None, &classes)?;
+    let wasm_bytes =
+        render_module(classes, virtual_table, functions, RenderOptions::default())?.finish();
+    write_module(&module_path, &wasm_bytes, "wasm", "wat", false)?;
+    let opt_wasm_bytes = optimise_module(&wasm_bytes)?;
+    write_module(&module_path, &opt_wasm_bytes, "opt.wasm", "opt.wat", false)?;
+    let summary_path = opts.artifact_path(opts.dump_json_module.as_ref().unwrap());
+    fs::write(summary_path, summarise_module(&wasm_bytes)?)?;
+
+    // Check all outputs were collected in the output directory
+    for name in [
+        "module.wasm",
+        "module.wat",
+        "module.opt.wasm",
+        "module.opt.wat",
+        "summary.json",
+    ] {
+        assert!(dir.join(name).is_file(), "{} not written", name);
+    }
+    let graphs_dir = dir.join("graphs").join("Test.f(I)I");
+    assert!(fs::read_dir(graphs_dir)?.next().is_some());
+
+    Ok(())
+}
+
+/// ACC_SYNTHETIC, ACC_BRIDGE
+#[test]
+fn synthetic_bridge_not_exported() -> anyhow::Result<()> {
+    let (classes, mut functions) = compile_code_functions(
+        "public static int synthetic(int x) { return x + 1; }
+        public static int bridge(int x) { return x * 2; }
+        public static int both(int x) { return bridge(synthetic(x)); }",
+        1,
+    )?;
+    // javac won't generate public static synthetic or bridge methods from source, so set the flags
+    // on regular methods instead
+    for function in &mut functions {
+        match function.id.name.as_str() {
+            "synthetic" => function.flags |= MethodAccessFlags::SYNTHETIC,
+            "bridge" => function.flags |= MethodAccessFlags::BRIDGE,
+            _ => {}
+        }
+    }
+    let classes = Arc::new(classes);
+    let virtual_table = construct_virtual_table(None, &classes)?;
+    let wasm_bytes =
+        render_module(classes, virtual_table, functions, RenderOptions::default())?.finish();
+
+    // Check compiler-generated methods are still compiled, but not exported
+    let wat = wasmprinter::print_bytes(&wasm_bytes)?;
+    assert!(wat.contains("(func $Test.synthetic_I_I"));
+    assert!(wat.contains("(func $Test.bridge_I_I"));
+    assert!(!wat.contains(r#"(export "Test.synthetic(I)I""#));
+    assert!(!wat.contains(r#"(export "Test.bridge(I)I""#));
+
+    let module = Module::new(&WASM_ENGINE, &wasm_bytes)?;
+    let linker = Linker::new(&WASM_ENGINE);
+    let mut store = Store::new(&WASM_ENGINE, 0);
+    let instance = linker.instantiate(&mut store, &module)?;
+
+    let both = instance.get_typed_func::<i32, i32, _>(&mut store, "Test.both(I)I")?;
+    assert_eq!(both.call(&mut store, 20)?, 42);
+
+    Ok(())
+}
+
+/// --compat-mode
+#[test]
+fn compat_mode_missing_code() -> anyhow::Result<()> {
+    // javac always generates code for concrete methods, so hand-write a class file containing
+    // `public static int stub(int)` without a Code attribute
+    #[rustfmt::skip]
+    let data: &[u8] = &[
+        0xCA, 0xFE, 0xBA, 0xBE, 0x00, 0x00, 0x00, 0x34, // Magic, Java 8
+        0x00, 0x07, // Constant pool count
+        0x01, 0x00, 0x04, b'T', b'e', b's', b't', // #1 Utf8 "Test"
+        0x07, 0x00, 0x01, // #2 Class #1
+        0x01, 0x00, 0x10, b'j', b'a', b'v', b'a', b'/', b'l', b'a', b'n', b'g', b'/',
+        b'O', b'b', b'j', b'e', b'c', b't', // #3 Utf8 "java/lang/Object"
+        0x07, 0x00, 0x03, // #4 Class #3
+        0x01, 0x00, 0x04, b's', b't', b'u', b'b', // #5 Utf8 "stub"
+        0x01, 0x00, 0x04, b'(', b'I', b')', b'I', // #6 Utf8 "(I)I"
+        0x00, 0x21, // ACC_PUBLIC | ACC_SUPER
+        0x00, 0x02, // This class
+        0x00, 0x04, // Super class
+        0x00, 0x00, // Interfaces count
+        0x00, 0x00, // Fields count
+        0x00, 0x01, // Methods count
+        0x00, 0x09, 0x00, 0x05, 0x00, 0x06, 0x00, 0x00, // ACC_PUBLIC | ACC_STATIC stub(I)I
+        0x00, 0x00, // Attributes count
+    ];
+    let path = cache_path("compat_mode").join("Test.class");
+    fs::create_dir_all(path.parent().unwrap())?;
+    fs::write(&path, data)?;
+
+    // Check class is rejected without compat mode
+    let err = load_class(&path, false).unwrap_err();
+    assert!(format!("{:#}", err).contains("Unable to find code"));
+
+    // Check method is compiled to a stub with compat mode
+    let class = load_class(&path, true)?;
+    let (class_tx, class_rx) = channel();
+    class_tx.send(Ok(class))?;
+    drop(class_tx);
+    let schd = SerialScheduler {};
+    let (classes, function_count, function_rx) = compile_functions(
+        &schd,
+        None,
+        CompileOptions::default(),
+        &SharedDuration::default(),
+        1,
+        class_rx,
+    )?;
+    let (functions, errors) = collect_functions(function_count, function_rx, 1);
+    check_errors(errors)?;
+    let classes = Arc::new(classes);
+    let virtual_table = construct_virtual_table(None, &classes)?;
+    let wasm_bytes =
+        render_module(classes, virtual_table, functions, RenderOptions::default())?.finish();
+
+    let module = Module::new(&WASM_ENGINE, &wasm_bytes)?;
+    let linker = Linker::new(&WASM_ENGINE);
+    let mut store = Store::new(&WASM_ENGINE, 0);
+    let instance = linker.instantiate(&mut store, &module)?;
+
+    let stub = instance.get_typed_func::<i32, i32, _>(&mut store, "Test.stub(I)I")?;
+    let trap = stub.call(&mut store, 1).unwrap_err();
+    assert_eq!(trap.trap_code(), Some(TrapCode::UnreachableCodeReached));
+
+    Ok(())
+}
+
+#[test]
+fn module_info_skipped() -> anyhow::Result<()> {
+    // Hand-write the module descriptor javac generates for `module test {}`, so we don't need to
+    // compile a whole module
+    #[rustfmt::skip]
+    let data: &[u8] = &[
+        0xCA, 0xFE, 0xBA, 0xBE, 0x00, 0x00, 0x00, 0x35, // Magic, Java 9
+        0x00, 0x08, // Constant pool count
+        0x07, 0x00, 0x02, // #1 Class #2
+        0x01, 0x00, 0x0B, b'm', b'o', b'd', b'u', b'l', b'e', b'-', b'i', b'n', b'f',
+        b'o', // #2 Utf8 "module-info"
+        0x01, 0x00, 0x06, b'M', b'o', b'd', b'u', b'l', b'e', // #3 Utf8 "Module"
+        0x13, 0x00, 0x05, // #4 Module #5
+        0x01, 0x00, 0x04, b't', b'e', b's', b't', // #5 Utf8 "test"
+        0x13, 0x00, 0x07, // #6 Module #7
+        0x01, 0x00, 0x09, b'j', b'a', b'v', b'a', b'.', b'b', b'a', b's',
+        b'e', // #7 Utf8 "java.base"
+        0x80, 0x00, // ACC_MODULE
+        0x00, 0x01, // This class
+        0x00, 0x00, // Super class (none)
+        0x00, 0x00, // Interfaces count
+        0x00, 0x00, // Fields count
+        0x00, 0x00, // Methods count
+        0x00, 0x01, // Attributes count
+        0x00, 0x03, 0x00, 0x00, 0x00, 0x16, // Module attribute, length
+        0x00, 0x04, 0x00, 0x00, 0x00, 0x00, // Module test, flags, version
+        0x00, 0x01, 0x00, 0x06, 0x80, 0x00, 0x00, 0x00, // Requires ACC_MANDATED java.base
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // Exports, opens, uses, provides counts
+    ];
+    let path = cache_path("module_info").join("module-info.class");
+    fs::create_dir_all(path.parent().unwrap())?;
+    fs::write(&path, data)?;
+
+    // Check the descriptor loads alongside a real class...
+    let module_info = load_class(&path, false)?;
+    assert!(module_info.is_descriptor());
+    let class = load_code("static int one() { return 1; }")?;
+    assert!(!class.is_descriptor());
+    let (class_tx, class_rx) = channel();
+    class_tx.send(Ok(module_info))?;
+    class_tx.send(Ok(class))?;
+    drop(class_tx);
+
+    // ...but only the real class is compiled
+    let schd = SerialScheduler {};
+    let (classes, function_count, function_rx) = compile_functions(
+        &schd,
+        None,
+        CompileOptions::default(),
+        &SharedDuration::default(),
+        2,
+        class_rx,
+    )?;
+    assert_eq!(classes.len(), 1);
+    assert_eq!(*classes.values().next().unwrap().class_name, "Test");
+    assert_eq!(function_count, 2); // Implicit constructor and one()
+    let (functions, errors) = collect_functions(function_count, function_rx, 1);
+    check_errors(errors)?;
+    assert_eq!(functions.len(), 2);
+
+    Ok(())
+}
+
+#[test]
+fn annotation_skipped() -> anyhow::Result<()> {
+    let classes = load_many_code(
+        "@java.lang.annotation.Retention(java.lang.annotation.RetentionPolicy.RUNTIME)
+        @interface Marker { int value() default 1; }
+
+        @Marker(2)
+        static int one() { return 1; }",
+    )?;
+    let annotation = &classes["Test$Marker"];
+    assert!(annotation.is_annotation());
+    assert!(!classes["Test"].is_annotation());
+
+    // Check the annotation loads alongside a real class, but only the real class is compiled
+    let (class_tx, class_rx) = channel();
+    for (_, class) in classes {
+        class_tx.send(Ok(class))?;
+    }
+    drop(class_tx);
+    let schd = SerialScheduler {};
+    let (classes, function_count, function_rx) = compile_functions(
+        &schd,
+        None,
+        CompileOptions::default(),
+        &SharedDuration::default(),
+        2,
+        class_rx,
+    )?;
+    assert_eq!(classes.len(), 1);
+    assert_eq!(*classes.values().next().unwrap().class_name, "Test");
+    assert_eq!(function_count, 2); // Implicit constructor and one()
+    let (functions, errors) = collect_functions(function_count, function_rx, 1);
+    check_errors(errors)?;
+    assert_eq!(functions.len(), 2);
+
+    Ok(())
+}
+
+/// --demo-main <method>:<arg>
+#[test]
+fn demo_main() -> anyhow::Result<()> {
+    let (classes, functions) = compile_code_functions(
+        "static int helper(int x) { return x + 1; }
+        static int twice(int x) { return helper(x) * 2 - 2; }",
+        1,
+    )?;
+    let classes = Arc::new(classes);
+    let virtual_table = construct_virtual_table(None, &classes)?;
+    let demo_main: DemoMain = "Test.twice(I)I:-42".parse()?;
+    demo_main.check(&functions)?;
+    let wasm_bytes = render_module(
+        classes,
+        virtual_table,
+        functions,
+        RenderOptions {
+            demo_main: Some(demo_main),
+            ..RenderOptions::default()
+        },
+    )?
+    .finish();
+
+    // Check the WASI import is declared, and _start calls the target
+    let wat = wasmprinter::print_bytes(&wasm_bytes)?;
+    assert!(wat.contains(r#"(import "wasi_snapshot_preview1" "fd_write""#));
+    assert!(wat.contains(r#"(export "_start""#));
+    let start = wat.split("(func $!DemoStart").nth(1).unwrap();
+    assert!(start.contains("call $Test.twice_I_I"));
+
+    // Check the result is printed to standard output
+    let module = Module::new(&WASM_ENGINE, &wasm_bytes)?;
+    let mut linker = Linker::new(&WASM_ENGINE);
+    linker.func_wrap(
+        "wasi_snapshot_preview1",
+        "fd_write",
+        |mut caller: Caller<'_, Vec<u8>>, fd: i32, iovs: i32, iovs_len: i32, nwritten: i32| {
+            assert_eq!(fd, 1);
+            assert_eq!(iovs_len, 1);
+            let memory = caller.get_export("memory").unwrap().into_memory().unwrap();
+            let mut iov = [0; 8];
+            memory.read(&caller, iovs as usize, &mut iov).unwrap();
+            let base = i32::from_le_bytes(iov[0..4].try_into().unwrap()) as usize;
+            let len = i32::from_le_bytes(iov[4..8].try_into().unwrap()) as usize;
+            let mut bytes = vec![0; len];
+            memory.read(&caller, base, &mut bytes).unwrap();
+            caller.data_mut().extend(bytes);
+            memory
+                .write(&mut caller, nwritten as usize, &(len as i32).to_le_bytes())
+                .unwrap();
+            0
+        },
+    )?;
+    let mut store = Store::new(&WASM_ENGINE, vec![]);
+    let instance = linker.instantiate(&mut store, &module)?;
+    let start = instance.get_typed_func::<(), (), _>(&mut store, "_start")?;
+    start.call(&mut store, ())?;
+    assert_eq!(String::from_utf8(store.into_data())?, "-84\n");
+
+    Ok(())
+}
+
+/// Per-function validation errors, naming the invalid function
+#[test]
+fn validate_functions_reports_name() -> anyhow::Result<()> {
+    let mut module = OutputModule::new();
+    let func_type = Arc::new(FunctionType {
+        params: vec![],
+        results: vec![ValType::I32],
+    });
+    let type_index = module.ensure_type(&func_type);
+    for (name, instructions) in [
+        ("valid", &[WASMInstruction::I32Const(1)][..]),
+        (
+            "broken",
+            &[WASMInstruction::I32Const(1), WASMInstruction::I32Add][..],
+        ),
+    ] {
+        let mut f = WASMFunction::new(vec![]);
+        for instruction in instructions {
+            f.instruction(instruction);
+        }
+        f.instruction(&WASMInstruction::End);
+        module.functions.function(type_index);
+        module.codes.function(&f);
+        module
+            .function_names
+            .append(module.next_function_index, name);
+        module.next_function_index += 1;
+    }
+
+    let err = validate_functions(&module.finish()).unwrap_err();
+    assert!(err
+        .to_string()
+        .starts_with("Function 1 (broken) is invalid"));
+
+    Ok(())
+}
+
+/// Instantiating modules using non-default WebAssembly proposals
+#[test]
+fn target_features() -> anyhow::Result<()> {
+    let wasm = wat::parse_str(
+        r#"(module
+            (memory (export "memory") i64 1)
+            (func (export "roundtrip") (param i64 i32) (result i32)
+                local.get 0
+                local.get 1
+                i32.store
+                local.get 0
+                i32.load))"#,
+    )?;
+
+    // Check the default validator and engine reject 64-bit memories...
+    assert!(validate_functions(&wasm).is_err());
+    assert!(Module::new(&WASM_ENGINE, &wasm).is_err());
+
+    // ...but they're accepted with memory64 enabled
+    let features = TargetFeatures {
+        memory64: true,
+        ..TargetFeatures::default()
+    };
+    validate_functions_with_features(&wasm, features)?;
+    let engine = engine_with_features(features);
+    let module = Module::new(&engine, &wasm)?;
+    let linker = Linker::new(&engine);
+    let mut store = Store::new(&engine, 0);
+    let instance = linker.instantiate(&mut store, &module)?;
+
+    let roundtrip = instance.get_typed_func::<(i64, i32), i32, _>(&mut store, "roundtrip")?;
+    assert_eq!(roundtrip.call(&mut store, (16, 42))?, 42);
+
+    Ok(())
+}
+
+/// Compiling a single parsed method directly, without a scheduler
+#[test]
+fn compile_single_function() -> anyhow::Result<()> {
+    let class = load_code(
+        "static int add_one(int n) { return n + 1; }
+        static int add_two(int n) { return add_one(add_one(n)); }",
+    )?;
+    let function = |name: &str| {
+        let function = class.methods.iter().find(|f| f.id.name.as_str() == name);
+        Arc::clone(function.unwrap())
+    };
+
+    // Check simple instructions are produced directly...
+    let add_one = compile_single(&function("add_one"))?;
+    assert_eq!(add_one.id.to_string(), "Test.add_one(I)I");
+    assert_eq!(
+        add_one.code.unwrap(),
+        [
+            Instruction::I(WASMInstruction::LocalGet(0)),
+            Instruction::I(WASMInstruction::I32Const(1)),
+            Instruction::I(WASMInstruction::I32Add),
+            Instruction::I(WASMInstruction::Return),
+            Instruction::I(WASMInstruction::End),
+        ]
+    );
+    assert_eq!(add_one.locals.unwrap().len(), 1);
+
+    // ...and calls are left as pseudo-instructions for the renderer to resolve
+    let add_two = compile_single(&function("add_two"))?;
+    let add_one_id = function("add_one").id.clone();
+    assert_eq!(
+        add_two.code.unwrap(),
+        [
+            Instruction::I(WASMInstruction::LocalGet(0)),
+            Instruction::CallStatic(add_one_id.clone()),
+            Instruction::CallStatic(add_one_id),
+            Instruction::I(WASMInstruction::Return),
+            Instruction::I(WASMInstruction::End),
+        ]
+    );
+
+    Ok(())
+}
@@ -0,0 +1,130 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Time spent in each phase of the compilation pipeline, printed with `--print-timings`.
+///
+/// Class loading and function compilation are performed in parallel by worker threads, so their
+/// durations are the sum of the time spent by each individual job, not elapsed wall-clock time.
+#[derive(Debug, Default)]
+pub struct Timings {
+    /// Total time spent reading and parsing class files. See [`crate::class::LoadClassJob`].
+    pub load: Duration,
+    /// Total time spent compiling functions. See [`crate::function::CompileFunctionJob`].
+    pub compile: Duration,
+    /// Time spent constructing the virtual method table.
+    pub virtual_table: Duration,
+    /// Time spent rendering functions and the virtual method table to a WebAssembly module.
+    pub render: Duration,
+    /// Time spent optimising the WebAssembly module with Binaryen, if enabled.
+    pub optimise: Duration,
+    /// Time spent writing WebAssembly modules to disk.
+    pub write: Duration,
+}
+
+impl Timings {
+    /// Calls `f`, adding the time it took to `phase` and returning its result.
+    pub fn time<T>(phase: &mut Duration, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        *phase += start.elapsed();
+        result
+    }
+
+    /// Returns the name and duration of each phase, in the order they're performed. Phases ending
+    /// in `*` are aggregated across worker threads.
+    pub fn phases(&self) -> [(&'static str, Duration); 6] {
+        [
+            ("Class loading*", self.load),
+            ("Function compilation*", self.compile),
+            ("Virtual table construction", self.virtual_table),
+            ("Rendering", self.render),
+            ("Optimisation", self.optimise),
+            ("Writing", self.write),
+        ]
+    }
+}
+
+/// Duration shared between jobs, so time spent by each worker thread on a phase can be accumulated.
+/// Cloning this returns another handle to the same duration.
+#[derive(Debug, Default, Clone)]
+pub struct SharedDuration(Arc<Mutex<Duration>>);
+
+impl SharedDuration {
+    /// Calls `f`, adding the time it took to this duration and returning its result.
+    pub fn time<T>(&self, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        *self.0.lock().unwrap() += start.elapsed();
+        result
+    }
+
+    /// Returns the total time accumulated so far.
+    pub fn get(&self) -> Duration {
+        *self.0.lock().unwrap()
+    }
+}
+
+impl fmt::Display for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:<28} {:>12}", "Phase", "Time (ms)")?;
+        for (name, duration) in self.phases() {
+            let millis = duration.as_secs_f64() * 1000.0;
+            writeln!(f, "{:<28} {:>12.3}", name, millis)?;
+        }
+        write!(f, "* aggregated across worker threads")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::timings::{SharedDuration, Timings};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn time_accumulates() {
+        let mut phase = Duration::ZERO;
+        let result = Timings::time(&mut phase, || {
+            thread::sleep(Duration::from_millis(1));
+            42
+        });
+        assert_eq!(result, 42);
+        assert!(phase >= Duration::from_millis(1));
+        let first = phase;
+        Timings::time(&mut phase, || thread::sleep(Duration::from_millis(1)));
+        assert!(phase >= first + Duration::from_millis(1));
+    }
+
+    #[test]
+    fn shared_duration_accumulates() {
+        let duration = SharedDuration::default();
+        let handles: Vec<_> = (0..2)
+            .map(|_| {
+                let duration = duration.clone();
+                thread::spawn(move || duration.time(|| thread::sleep(Duration::from_millis(1))))
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert!(duration.get() >= Duration::from_millis(2));
+    }
+
+    #[test]
+    fn display_table() {
+        let timings = Timings {
+            load: Duration::from_micros(1500),
+            compile: Duration::from_millis(20),
+            ..Timings::default()
+        };
+        let table = timings.to_string();
+        let lines: Vec<_> = table.lines().collect();
+        assert_eq!(lines.len(), 8);
+        assert!(lines[1].starts_with("Class loading*"));
+        assert!(lines[1].ends_with(" 1.500"));
+        assert!(lines[2].starts_with("Function compilation*"));
+        assert!(lines[2].ends_with(" 20.000"));
+        assert!(lines[6].ends_with(" 0.000"));
+    }
+}
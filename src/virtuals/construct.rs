@@ -20,7 +20,7 @@ impl VirtualTable {
 
         // Add all possible methods that could be called on a class to the tree
         let root = inheritance_tree.entry.unwrap();
-        populate_tree_methods(classes, &mut inheritance_tree, root, vec![]);
+        populate_tree_methods(classes, &mut inheritance_tree, root);
 
         // Build the class_indices map with entries mapping class names to IDs in the graph and
         // virtual class IDs into the final WebAssembly table
@@ -68,52 +68,60 @@ pub fn construct_inheritance_tree(classes: &HashMap<Arc<String>, Class>) -> Grap
 /// To build this, we copy all methods from the superclass, checking if the current class overrides
 /// them. We then add all new methods defined in that class. Methods declared abstract have no
 /// implementation but are still included.
+///
+/// Classes are visited using an explicit worklist starting at `root` instead of recursion, so deep
+/// inheritance chains can't overflow the stack.
 pub fn populate_tree_methods(
     classes: &HashMap<Arc<String>, Class>,
     g: &mut Graph<VirtualClass>,
-    current_id: NodeId,
-    mut current_methods: Vec<MethodId>,
+    root: NodeId,
 ) {
-    let class_name = &g[current_id].value.class_name;
-
-    // Build a list of methods implemented/overridden by this class. Overridden methods
-    // will already exist in the methods list, but should be updated to point to this class.
-    // This ensures they share the same index all the way down the inheritance tree, allowing
-    // this index to be used for dynamic dispatch. class_name may be "java/lang/Object" which
-    // won't have an entry in classes, hence the `let Some(...)`.
-    if let Some(class) = classes.get(class_name) {
-        for method in &class.methods {
-            if *method.id.name == "<init>" {
-                // Ignore constructors, classes always (potentially implicitly) define their own
-                // and they have special handling via the invokespecial JVM instruction
-                continue;
-            }
-            let existing = current_methods
-                .iter_mut()
-                .find(|m| m.name == method.id.name && m.descriptor == method.id.descriptor);
-            match existing {
-                // If `methods` already contains a method with the same name and descriptor,
-                // update it to point to this class' implementation instead
-                Some(existing) => existing.class_name = Arc::clone(class_name),
-                // Otherwise, add it to the end of `methods` (MethodIds are a collection of Arcs so clone is cheap)
-                None => current_methods.push(method.id.clone()),
+    // Each entry contains a class to visit, and the methods inherited from its superclass
+    let mut worklist: Vec<(NodeId, Vec<MethodId>)> = vec![(root, vec![])];
+    while let Some((current_id, mut current_methods)) = worklist.pop() {
+        let class_name = &g[current_id].value.class_name;
+
+        // Build a list of methods implemented/overridden by this class. Overridden methods
+        // will already exist in the methods list, but should be updated to point to this class.
+        // This ensures they share the same index all the way down the inheritance tree, allowing
+        // this index to be used for dynamic dispatch. class_name may be "java/lang/Object" which
+        // won't have an entry in classes, hence the `let Some(...)`.
+        if let Some(class) = classes.get(class_name) {
+            for method in &class.methods {
+                if *method.id.name == "<init>" {
+                    // Ignore constructors, classes always (potentially implicitly) define their own
+                    // and they have special handling via the invokespecial JVM instruction
+                    continue;
+                }
+                let existing = current_methods
+                    .iter_mut()
+                    .find(|m| m.name == method.id.name && m.descriptor == method.id.descriptor);
+                match existing {
+                    // If `methods` already contains a method with the same name and descriptor,
+                    // update it to point to this class' implementation instead
+                    Some(existing) => existing.class_name = Arc::clone(class_name),
+                    // Otherwise, add it to the end of `methods` (MethodIds are a collection of Arcs
+                    // so clone is cheap)
+                    None => current_methods.push(method.id.clone()),
+                }
             }
         }
-    }
 
-    // Populate methods for all child classes, using this class' methods as a base
-    // (clone() required here as mutable borrow passed to populate_tree_methods each iteration)
-    for &subclass_id in &g[current_id].successors.clone() {
-        populate_tree_methods(classes, g, subclass_id, current_methods.clone());
-    }
+        // Populate methods for all child classes, using this class' methods as a base
+        for &subclass_id in &g[current_id].successors {
+            worklist.push((subclass_id, current_methods.clone()));
+        }
 
-    g[current_id].value.methods = current_methods;
+        g[current_id].value.methods = current_methods;
+    }
 }
 
 /// Assign a unique virtual class ID to each class.
 ///
 /// Because classes are added to the tree in lexicographic order, virtual class IDs will be
-/// alphabetic, with `java/lang/Object` always having ID 0.
+/// alphabetic, with `java/lang/Object` always having ID 0. Each class reserves a table element for
+/// its constant super ID function at its ID, followed by one for each of its methods (see
+/// [`VirtualTable::super_id_function_offset`]).
 pub fn index_tree(g: &Graph<VirtualClass>) -> HashMap<Arc<String>, VirtualClassIndex> {
     let mut class_indices = HashMap::new();
 
@@ -133,7 +141,7 @@ pub fn index_tree(g: &Graph<VirtualClass>) -> HashMap<Arc<String>, VirtualClassI
 #[cfg(test)]
 mod tests {
     use crate::class::{
-        FieldDescriptor, MethodDescriptor, MethodId, ReturnDescriptor, JAVA_LANG_OBJECT,
+        Class, FieldDescriptor, MethodDescriptor, MethodId, ReturnDescriptor, JAVA_LANG_OBJECT,
     };
     use crate::tests::{load_many_code, str_arc};
     use crate::VirtualTable;
@@ -251,7 +259,7 @@ mod tests {
         assert_eq!(methods_van[3].class_name, class_car);
 
         // Check get_virtual_class_id returns correct value
-        assert_eq!(table.get_virtual_class_id(&class_van), 11);
+        assert_eq!(table.get_virtual_class_id(&class_van)?, 11);
 
         // Check get_method_virtual_offset returns correct value
         let id = MethodId {
@@ -266,4 +274,38 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn from_classes_deep_inheritance() -> anyhow::Result<()> {
+        // Construct a 10,000 level inheritance chain below a class defining a single method, by
+        // copying a subclass that doesn't define any virtual methods and renaming it
+        let mut fixtures =
+            load_many_code("int m() { return 1; }\n\nstatic class Sub extends Test {}")?;
+        let base = fixtures.remove("Test").unwrap();
+        let sub = fixtures.remove("Test$Sub").unwrap();
+        let base_name = Arc::clone(&base.class_name);
+        let mut classes = HashMap::new();
+        let mut super_class_name = Arc::clone(&base_name);
+        for i in 0..10_000 {
+            let class_name = Arc::new(format!("Test$C{}", i));
+            let class = Class {
+                class_name: Arc::clone(&class_name),
+                super_class_name,
+                ..sub.clone()
+            };
+            classes.insert(Arc::clone(&class_name), class);
+            super_class_name = class_name;
+        }
+        classes.insert(Arc::clone(&base_name), base);
+        let table = VirtualTable::from_classes(&Arc::new(classes));
+
+        // Check the deepest class inherits the base class's implementation
+        let index = &table.class_indices[&super_class_name];
+        let methods = &table.inheritance_tree[index.node].value.methods;
+        assert_eq!(methods.len(), 1);
+        assert_eq!(*methods[0].name, "m");
+        assert_eq!(methods[0].class_name, base_name);
+
+        Ok(())
+    }
 }
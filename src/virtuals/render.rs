@@ -41,7 +41,8 @@ impl VirtualTable {
             // Render the function returning the superclass' virtual ID for this class
             // TODO: extract out into separate function, maybe move to output module so we
             //  can keep visibility on Module's fields pub(super)
-            let super_id = self.get_virtual_class_id(&class.super_class_name);
+            let super_id = (self.get_virtual_class_id(&class.super_class_name))
+                .expect("Superclasses should be in the inheritance tree");
             let mut f = WASMFunction::new(vec![]);
             f.instruction(&WASMInstruction::I32Const(super_id))
                 .instruction(&WASMInstruction::End);
@@ -52,11 +53,14 @@ impl VirtualTable {
             out.function_names
                 .append(super_id_index, &format!("!Super_{}", class.class_name));
 
-            // Add indices to table in output module
+            // Add indices to table in output module, starting with the super ID function
+            let super_id_offset = (self.super_id_function_offset(&class.class_name))
+                .expect("Classes should be in the inheritance tree");
+            debug_assert_eq!(super_id_offset, offset as i32);
             let function_indices = once(super_id_index).chain(method_indices).collect_vec();
             out.elements.active(
                 None,
-                &WASMInstruction::I32Const(offset as i32),
+                &WASMInstruction::I32Const(super_id_offset),
                 ValType::FuncRef,
                 Elements::Functions(&function_indices),
             );
@@ -11,6 +11,10 @@ use wasm_encoder::MemArg;
 
 /// Number of bytes required to store virtual class ID before fields begin.
 pub const VIRTUAL_CLASS_ID_SIZE: u32 = 4;
+/// Number of bytes required to store the header before fields begin with `--uniform-headers`. This
+/// contains the virtual class ID, followed by 4 bytes reserved for an array length or flags, so
+/// objects and arrays share the same layout.
+pub const UNIFORM_HEADER_SIZE: u32 = 8;
 /// Location of virtual class ID relative to instance pointers.
 pub const VIRTUAL_CLASS_ID_MEM_ARG: MemArg = MemArg {
     offset: 0,
@@ -100,26 +104,65 @@ pub struct VirtualTable {
 }
 
 impl VirtualTable {
-    /// Returns the virtual class ID for a class included in this virtual table.
+    /// Returns the virtual class ID for a class included in this virtual table, or an error if
+    /// the class wasn't loaded.
     ///
     /// This will be included in the first 4 bytes of all instances of this class.
     /// All table method offsets will be relative to this ID.
-    pub fn get_virtual_class_id(&self, class_name: &Arc<String>) -> i32 {
-        i32::try_from(self.class_indices[class_name].id)
-            .expect("Virtual class ID exceeded i32 bounds")
+    pub fn get_virtual_class_id(&self, class_name: &Arc<String>) -> anyhow::Result<i32> {
+        let index = self.class_indices.get(class_name).ok_or_else(|| {
+            anyhow!(
+                "Unknown class {}; did you forget to include it?",
+                class_name
+            )
+        })?;
+        Ok(i32::try_from(index.id).expect("Virtual class ID exceeded i32 bounds"))
+    }
+
+    /// Returns the index in the virtual table of a class's constant super ID function, or an error
+    /// if the class wasn't loaded. This function returns the virtual class ID of the class's
+    /// superclass, and is called by the [`InstanceOf`] built-in to walk up the inheritance tree.
+    ///
+    /// Super ID functions are always the first table element for each class, so this is the same as
+    /// the class's virtual class ID. Method offsets start after it.
+    ///
+    /// [`InstanceOf`]: crate::output::BuiltinFunction::InstanceOf
+    pub fn super_id_function_offset(&self, class_name: &Arc<String>) -> anyhow::Result<i32> {
+        self.get_virtual_class_id(class_name)
     }
 
     /// Returns the virtual method offset for a method included in this virtual table.
     ///
     /// This offset will be relative to a virtual class ID.
     pub fn get_method_virtual_offset(&self, id: &MethodId) -> i32 {
+        self.find_method_virtual_offset(id)
+            .expect("Method not included in virtual table")
+    }
+
+    /// Returns the virtual method offset for a method if it's included in this virtual table, or
+    /// `None` if neither the method's class nor any of its superclasses define it.
+    pub fn find_method_virtual_offset(&self, id: &MethodId) -> Option<i32> {
         let node_id = self.class_indices[&id.class_name].node;
         let methods = &self.inheritance_tree[node_id].value.methods;
+        let position = methods
+            .iter()
+            .position(|method| method.name == id.name && method.descriptor == id.descriptor)?;
+        Some(position as i32 + 1) // +1 for super_id() function
+    }
+
+    /// Returns the method providing the implementation called when the method `id` is called on
+    /// `id.class_name` without dynamic dispatch, or `None` if neither the method's class nor any of
+    /// its superclasses define it.
+    ///
+    /// This is used to resolve `invokespecial` calls to superclass methods (e.g. `super.m()`), and
+    /// `invokestatic` calls to inherited static methods (e.g. `B.f()` where only `A` defines `f`),
+    /// where the named class may inherit the method from one of its own superclasses.
+    pub fn find_method_implementation(&self, id: &MethodId) -> Option<&MethodId> {
+        let node_id = self.class_indices.get(&id.class_name)?.node;
+        let methods = &self.inheritance_tree[node_id].value.methods;
         methods
             .iter()
-            .position(|method| method.name == id.name && method.descriptor == id.descriptor)
-            .unwrap() as i32
-            + 1 // for super_id() function
+            .find(|method| method.name == id.name && method.descriptor == id.descriptor)
     }
 
     /// Converts the inheritance tree used to construct the virtual method table to the
@@ -131,6 +174,7 @@ impl VirtualTable {
             omit_node_ids: true,
             omit_branch_ids: true,
             subgraph: None,
+            label_instructions: false,
         })
     }
 